@@ -30,7 +30,8 @@ use std::iter::FromIterator;
 use std::cmp::Ordering;
 use std::error::Error;
 use std::sync::Arc;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use rustc_serialize::hex::{FromHex, ToHex};
 
 use clap::{App, Arg, SubCommand};
@@ -47,8 +48,11 @@ use kvproto::debugpb::*;
 use kvproto::debugpb::DB as DBType;
 use kvproto::debugpb_grpc::DebugClient;
 use tikv::util::{self, escape, unescape};
+use tikv::util::rocksdb::ttl;
 use tikv::raftstore::store::{keys, Engines};
-use tikv::raftstore::store::debug::{Debugger, RegionInfo};
+use tikv::raftstore::store::debug::{BackupManifest, Debugger, RegionInfo};
+use tikv::server::extpb::{BackupRequest, DebugExtClient, ImportModeRequest, ResolvedTsRequest,
+                          SwitchImportModeRequest};
 use tikv::storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_WRITE};
 
 fn perror_and_exit<E: Error>(prefix: &str, e: E) -> ! {
@@ -76,8 +80,11 @@ fn new_debug_executor(
         (Some(remote), None) => {
             let env = Arc::new(Environment::new(1));
             let channel = ChannelBuilder::new(env).connect(remote);
-            let client = DebugClient::new(channel);
-            Box::new(client) as Box<DebugExecutor>
+            let debugger = RemoteDebugger {
+                client: DebugClient::new(channel.clone()),
+                ext_client: DebugExtClient::new(channel),
+            };
+            Box::new(debugger) as Box<DebugExecutor>
         }
         _ => unreachable!(),
     }
@@ -89,6 +96,20 @@ trait DebugExecutor {
         println!("value: {}", escape(&value));
     }
 
+    fn dump_expire_ts(&self, key: Vec<u8>) {
+        let value = self.get_value_by_key(CF_DEFAULT, keys::data_key(&key));
+        let expire_ts = ttl::get_expire_ts(&value)
+            .unwrap_or_else(|e| perror_and_exit("ttl::get_expire_ts", e));
+        let now = ttl::current_ts();
+        if expire_ts == 0 {
+            println!("expire ts: 0 (never expires)");
+        } else if expire_ts <= now {
+            println!("expire ts: {} (expired)", expire_ts);
+        } else {
+            println!("expire ts: {} (expires in {}s)", expire_ts, expire_ts - now);
+        }
+    }
+
     fn dump_region_size(&self, region: u64, cfs: Vec<&str>) -> usize {
         let sizes = self.get_region_size(region, cfs);
         let mut total_size = 0;
@@ -323,6 +344,33 @@ trait DebugExecutor {
         self.do_compact(db, cf, from, to);
     }
 
+    fn dump_resolved_ts(&self, region: u64) {
+        println!("region {} resolved ts: {}", region, self.get_resolved_ts(region));
+    }
+
+    fn import_mode(&self, mode: Option<&str>, timeout: Duration) {
+        if let Some(mode) = mode {
+            self.do_switch_import_mode(mode == "import", timeout);
+        }
+        let mode = if self.get_import_mode() { "import" } else { "normal" };
+        println!("import mode: {}", mode);
+    }
+
+    fn backup(&self, from: Vec<u8>, to: Vec<u8>, ts: u64, path: &str) {
+        let manifest = self.do_backup(from, to, ts, path);
+        for file in &manifest.files {
+            println!(
+                "region {} [{}, {}): {} keys in {}",
+                file.region_id,
+                file.start_key,
+                file.end_key,
+                file.kv_count,
+                file.name.as_ref().map_or("no file", |n| n.as_str())
+            );
+        }
+        println!("backup of {} regions at {} saved to {}", manifest.files.len(), ts, path);
+    }
+
     fn get_all_meta_regions(&self) -> Vec<u64>;
 
     fn get_value_by_key(&self, cf: &str, key: Vec<u8>) -> Vec<u8>;
@@ -333,6 +381,10 @@ trait DebugExecutor {
 
     fn get_raft_log(&self, region: u64, index: u64) -> Entry;
 
+    fn get_resolved_ts(&self, region: u64) -> u64;
+
+    fn get_import_mode(&self) -> bool;
+
     fn get_mvcc_infos(
         &self,
         from: Vec<u8>,
@@ -341,10 +393,19 @@ trait DebugExecutor {
     ) -> Box<Stream<Item = (Vec<u8>, MvccInfo), Error = String>>;
 
     fn do_compact(&self, db: DBType, cf: &str, from: Vec<u8>, to: Vec<u8>);
+
+    fn do_backup(&self, from: Vec<u8>, to: Vec<u8>, ts: u64, path: &str) -> BackupManifest;
+
+    fn do_switch_import_mode(&self, import: bool, timeout: Duration);
 }
 
+/// Executes the debug commands on a running TiKV through gRPC.
+struct RemoteDebugger {
+    client: DebugClient,
+    ext_client: DebugExtClient,
+}
 
-impl DebugExecutor for DebugClient {
+impl DebugExecutor for RemoteDebugger {
     fn get_all_meta_regions(&self) -> Vec<u64> {
         unimplemented!();
     }
@@ -354,7 +415,7 @@ impl DebugExecutor for DebugClient {
         req.set_db(DBType::KV);
         req.set_cf(cf.to_owned());
         req.set_key(key);
-        self.get(req)
+        self.client.get(req)
             .unwrap_or_else(|e| perror_and_exit("DebugClient::get", e))
             .take_value()
     }
@@ -364,7 +425,7 @@ impl DebugExecutor for DebugClient {
         let mut req = RegionSizeRequest::new();
         req.set_cfs(RepeatedField::from_vec(cfs));
         req.set_region_id(region);
-        self.region_size(req)
+        self.client.region_size(req)
             .unwrap_or_else(|e| perror_and_exit("DebugClient::region_size", e))
             .take_entries()
             .into_iter()
@@ -375,7 +436,7 @@ impl DebugExecutor for DebugClient {
     fn get_region_info(&self, region: u64) -> RegionInfo {
         let mut req = RegionInfoRequest::new();
         req.set_region_id(region);
-        let mut resp = self.region_info(req)
+        let mut resp = self.client.region_info(req)
             .unwrap_or_else(|e| perror_and_exit("DebugClient::region_info", e));
 
         let mut region_info = RegionInfo::default();
@@ -395,11 +456,26 @@ impl DebugExecutor for DebugClient {
         let mut req = RaftLogRequest::new();
         req.set_region_id(region);
         req.set_log_index(index);
-        self.raft_log(req)
+        self.client.raft_log(req)
             .unwrap_or_else(|e| perror_and_exit("DebugClient::raft_log", e))
             .take_entry()
     }
 
+    fn get_resolved_ts(&self, region: u64) -> u64 {
+        let req = ResolvedTsRequest { region_id: region };
+        self.ext_client
+            .resolved_ts(req)
+            .unwrap_or_else(|e| perror_and_exit("DebugExtClient::resolved_ts", e))
+            .resolved_ts
+    }
+
+    fn get_import_mode(&self) -> bool {
+        self.ext_client
+            .import_mode(ImportModeRequest {})
+            .unwrap_or_else(|e| perror_and_exit("DebugExtClient::import_mode", e))
+            .import
+    }
+
     fn get_mvcc_infos(
         &self,
         from: Vec<u8>,
@@ -411,7 +487,7 @@ impl DebugExecutor for DebugClient {
         req.set_to_key(to);
         req.set_limit(limit);
         Box::new(
-            self.scan_mvcc(req)
+            self.client.scan_mvcc(req)
                 .map_err(|e| e.to_string())
                 .map(|mut resp| (resp.take_key(), resp.take_info())),
         ) as Box<Stream<Item = (Vec<u8>, MvccInfo), Error = String>>
@@ -423,10 +499,33 @@ impl DebugExecutor for DebugClient {
         req.set_cf(cf.to_owned());
         req.set_from_key(from);
         req.set_to_key(to);
-        self.compact(req)
+        self.client.compact(req)
             .unwrap_or_else(|e| perror_and_exit("DebugClient::compact", e));
         println!("success!");
     }
+
+    fn do_backup(&self, from: Vec<u8>, to: Vec<u8>, ts: u64, path: &str) -> BackupManifest {
+        let req = BackupRequest {
+            start_key: from,
+            end_key: to,
+            backup_ts: ts,
+            path: path.to_owned(),
+        };
+        self.ext_client
+            .backup(req)
+            .unwrap_or_else(|e| perror_and_exit("DebugExtClient::backup", e))
+            .manifest
+    }
+
+    fn do_switch_import_mode(&self, import: bool, timeout: Duration) {
+        let req = SwitchImportModeRequest {
+            import: import,
+            timeout_secs: timeout.as_secs(),
+        };
+        self.ext_client
+            .switch_import_mode(req)
+            .unwrap_or_else(|e| perror_and_exit("DebugExtClient::switch_import_mode", e));
+    }
 }
 
 impl DebugExecutor for Debugger {
@@ -458,6 +557,16 @@ impl DebugExecutor for Debugger {
             .unwrap_or_else(|e| perror_and_exit("Debugger::raft_log", e))
     }
 
+    fn get_resolved_ts(&self, region: u64) -> u64 {
+        self.resolved_ts(region)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::resolved_ts", e))
+    }
+
+    fn get_import_mode(&self) -> bool {
+        self.is_import_mode()
+            .unwrap_or_else(|e| perror_and_exit("Debugger::is_import_mode", e))
+    }
+
     fn get_mvcc_infos(
         &self,
         from: Vec<u8>,
@@ -476,6 +585,16 @@ impl DebugExecutor for Debugger {
             .unwrap_or_else(|e| perror_and_exit("Debugger::compact", e));
         println!("success!");
     }
+
+    fn do_backup(&self, from: Vec<u8>, to: Vec<u8>, ts: u64, path: &str) -> BackupManifest {
+        self.backup(&from, &to, ts, Path::new(path))
+            .unwrap_or_else(|e| perror_and_exit("Debugger::backup", e))
+    }
+
+    fn do_switch_import_mode(&self, import: bool, timeout: Duration) {
+        self.switch_import_mode(import, timeout)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::switch_import_mode", e));
+    }
 }
 
 fn main() {
@@ -651,6 +770,17 @@ fn main() {
                         .help("set the query raw key, in escaped form"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("ttl")
+                .about("print the expire time of a raw key written with storage.enable-ttl")
+                .arg(
+                    Arg::with_name("key")
+                        .required(true)
+                        .short("k")
+                        .takes_value(true)
+                        .help("set the raw key, in escaped form"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("mvcc")
                 .about("print the mvcc value")
@@ -738,6 +868,74 @@ fn main() {
                         .takes_value(true)
                         .help("set the end raw key, in escaped form"),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("resolved-ts")
+                .about("print the resolved ts of a region, only on a running TiKV")
+                .arg(
+                    Arg::with_name("region")
+                        .short("r")
+                        .required(true)
+                        .takes_value(true)
+                        .help("set the region id"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-mode")
+                .about("print or switch the import mode, only on a running TiKV")
+                .arg(
+                    Arg::with_name("mode")
+                        .short("m")
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(&["import", "normal"])
+                        .help("switch to the mode, if not specified, print the current mode"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .takes_value(true)
+                        .default_value("600")
+                        .help("seconds before the import mode is left unless it's renewed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("back up the versions visible at a ts in a range into sst files")
+                .arg(
+                    Arg::with_name("from")
+                        .short("f")
+                        .long("from")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("set the start raw key, in escaped form"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .short("t")
+                        .long("to")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("set the end raw key, in escaped form, empty means unbounded"),
+                )
+                .arg(
+                    Arg::with_name("ts")
+                        .long("ts")
+                        .required(true)
+                        .takes_value(true)
+                        .help("the ts at which the versions are backed up"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .short("p")
+                        .long("path")
+                        .required(true)
+                        .takes_value(true)
+                        .help(
+                            "the directory to save the sst files and the manifest, \
+                             which is on the remote host with --host",
+                        ),
+                ),
         );
     let matches = app.clone().get_matches();
 
@@ -766,6 +964,9 @@ fn main() {
         let cf = matches.value_of("cf").unwrap();
         let key = unescape(matches.value_of("key").unwrap());
         debug_executor.dump_value(cf, key);
+    } else if let Some(matches) = matches.subcommand_matches("ttl") {
+        let key = unescape(matches.value_of("key").unwrap());
+        debug_executor.dump_expire_ts(key);
     } else if let Some(matches) = matches.subcommand_matches("raft") {
         if let Some(matches) = matches.subcommand_matches("log") {
             let (id, index) = if let Some(key) = matches.value_of("key") {
@@ -819,6 +1020,19 @@ fn main() {
         let from_key = matches.value_of("from").map(|k| unescape(k));
         let to_key = matches.value_of("to").map(|k| unescape(k));
         debug_executor.compact(db_type, cf, from_key, to_key);
+    } else if let Some(matches) = matches.subcommand_matches("resolved-ts") {
+        let region = matches.value_of("region").unwrap().parse().unwrap();
+        debug_executor.dump_resolved_ts(region);
+    } else if let Some(matches) = matches.subcommand_matches("import-mode") {
+        let mode = matches.value_of("mode");
+        let timeout = matches.value_of("timeout").unwrap().parse().unwrap();
+        debug_executor.import_mode(mode, Duration::from_secs(timeout));
+    } else if let Some(matches) = matches.subcommand_matches("backup") {
+        let from = unescape(matches.value_of("from").unwrap());
+        let to = unescape(matches.value_of("to").unwrap());
+        let ts = matches.value_of("ts").unwrap().parse().unwrap();
+        let path = matches.value_of("path").unwrap();
+        debug_executor.backup(from, to, ts, path);
     } else {
         let _ = app.print_help();
     }
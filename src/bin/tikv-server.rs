@@ -63,12 +63,15 @@ use tikv::util::logger::{self, StderrLogger};
 use tikv::util::file_log::RotatingFileLogger;
 use tikv::util::transport::SendCh;
 use tikv::util::worker::FutureWorker;
-use tikv::storage::DEFAULT_ROCKSDB_SUB_DIR;
-use tikv::server::{create_raft_storage, Node, Server, DEFAULT_CLUSTER_ID};
+use tikv::storage::{CF_DEFAULT, CF_WRITE, DATA_CFS, DEFAULT_ROCKSDB_SUB_DIR};
+use tikv::storage::mvcc::GcSafePoint;
+use tikv::server::{create_raft_storage, CdcService, GcManager, Node, PdSafePointProvider,
+                   Server, DEFAULT_CLUSTER_ID};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::resolve;
-use tikv::raftstore::store::{self, Engines, SnapManager};
+use tikv::raftstore::store::{self, Engines, ResolvedTsTracker, SnapManager};
 use tikv::pd::{PdClient, RpcClient};
+use tikv::cdc::CdcHub;
 use tikv::util::time::Monitor;
 use tikv::util::rocksdb::metrics_flusher::{MetricsFlusher, DEFAULT_FLUSER_INTERVAL};
 
@@ -169,13 +172,37 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
 
     // Create kv engine, storage.
     let kv_db_opts = cfg.rocksdb.build_opt();
-    let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    let mut kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    if cfg.storage.enable_ttl {
+        // Raw values can be stored in any of the data cfs.
+        for cf_opts in &mut kv_cfs_opts {
+            if DATA_CFS.iter().any(|cf| *cf == cf_opts.cf()) {
+                cf_opts.enable_ttl();
+            }
+        }
+    }
+    let gc_safe_point = Arc::new(GcSafePoint::new());
+    if cfg.storage.gc_enable_compaction_filter {
+        for cf_opts in &mut kv_cfs_opts {
+            if cf_opts.cf() == CF_WRITE || cf_opts.cf() == CF_DEFAULT {
+                cf_opts.enable_gc_compaction_filter(gc_safe_point.clone());
+            }
+        }
+    }
     let kv_engine = Arc::new(
         rocksdb_util::new_engine_opt(db_path.to_str().unwrap(), kv_db_opts, kv_cfs_opts)
             .unwrap_or_else(|s| fatal!("failed to create kv engine: {:?}", s)),
     );
-    let mut storage = create_raft_storage(raft_router.clone(), kv_engine.clone(), &cfg.storage)
-        .unwrap_or_else(|e| fatal!("failed to create raft stroage: {:?}", e));
+    gc_safe_point
+        .bind(&kv_engine)
+        .unwrap_or_else(|s| fatal!("failed to load gc safe point: {:?}", s));
+    let resolved_ts = ResolvedTsTracker::new();
+    let mut storage = create_raft_storage(
+        raft_router.clone(),
+        kv_engine.clone(),
+        resolved_ts.clone(),
+        &cfg.storage,
+    ).unwrap_or_else(|e| fatal!("failed to create raft stroage: {:?}", e));
 
     // Create raft engine.
     let raft_db_opts = cfg.raftdb.build_opt();
@@ -200,6 +227,7 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
     );
 
     // Create server
+    let cdc_hub = CdcHub::new();
     let mut server = Server::new(
         &cfg.server,
         cfg.raft_store.region_split_size.0 as usize,
@@ -209,11 +237,20 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         snap_mgr.clone(),
         pd_worker.scheduler(),
         Some(engines.clone()),
+        resolved_ts.clone(),
+        Some(CdcService::new(cdc_hub.clone(), engines.kv_engine.clone())),
     ).unwrap_or_else(|e| fatal!("failed to create server: {:?}", e));
     let trans = server.transport();
 
     // Create node.
-    let mut node = Node::new(&mut event_loop, &cfg.server, &cfg.raft_store, pd_client);
+    let mut node = Node::new(
+        &mut event_loop,
+        &cfg.server,
+        &cfg.raft_store,
+        pd_client.clone(),
+        resolved_ts,
+        cdc_hub,
+    );
     node.start(
         event_loop,
         engines.clone(),
@@ -230,6 +267,27 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         fatal!("failed to start storage, error: {:?}", e);
     }
 
+    // Start gc manager if it's enabled.
+    let mut gc_manager = None;
+    if cfg.storage.gc_safe_point_lifetime.0 > Duration::from_secs(0) {
+        let lifetime = cfg.storage.gc_safe_point_lifetime.0;
+        let provider = PdSafePointProvider::new(pd_client, lifetime);
+        let mut m = GcManager::new(
+            node.id(),
+            kv_engine.clone(),
+            storage.clone(),
+            provider,
+            &cfg.storage,
+        );
+        if cfg.storage.gc_enable_compaction_filter {
+            m.set_compaction_filter_safe_point(gc_safe_point);
+        }
+        if let Err(e) = m.start() {
+            fatal!("failed to start gc manager, error: {:?}", e);
+        }
+        gc_manager = Some(m);
+    }
+
     let mut metrics_flusher = MetricsFlusher::new(
         engines.clone(),
         Duration::from_millis(DEFAULT_FLUSER_INTERVAL),
@@ -251,6 +309,10 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         .stop()
         .unwrap_or_else(|e| fatal!("failed to stop server: {:?}", e));
 
+    if let Some(mut m) = gc_manager {
+        m.stop();
+    }
+
     metrics_flusher.stop();
 
     node.stop()
@@ -0,0 +1,417 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Change data capture of the committed rows.
+//!
+//! A committed row is made of the value put into the default CF by the prewrite, unless it's
+//! short enough to be kept in the lock, and the write record put into the write CF by the
+//! commit, which takes the short value over from the lock. `CdcObserver` watches the writes
+//! applied to the subscribed regions and matches the two, the changes of a region are sent to
+//! its subscribers in the order they are applied.
+//!
+//! A subscriber catches up with the rows committed before it subscribes by an incremental scan,
+//! so a row committed around the subscription may be delivered twice. The changes are captured
+//! by a worker off the apply thread. If they can't be captured, or a subscriber falls too far
+//! behind, its stream fails and it has to subscribe again and catch up the same way.
+//!
+//! The clients subscribe through the `ChangeData` service of `server::extpb`, which does both.
+
+mod observer;
+
+pub use self::observer::{CdcObserver, Runner as CdcRunner, Task as CdcTask};
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Async, Poll, Stream};
+use futures::sync::mpsc::{self, Receiver, Sender};
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+use rocksdb::DB;
+
+use raftstore::{Error, Result};
+use raftstore::store::{keys, Iterable, Peekable};
+use storage::{Key, CF_DEFAULT, CF_RAFT, CF_WRITE};
+use storage::mvcc::{Write, WriteType};
+use storage::types::split_encoded_key_on_ts;
+use util::collections::HashMap;
+use util::escape;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowOp {
+    Put,
+    Delete,
+}
+
+/// A row committed in a region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowChange {
+    pub region_id: u64,
+    /// The raw key of the row.
+    pub key: Vec<u8>,
+    pub op: RowOp,
+    /// The value put, `None` for deletes.
+    pub value: Option<Vec<u8>>,
+    pub start_ts: u64,
+    pub commit_ts: u64,
+}
+
+// The number of changes buffered for a subscriber before it's deregistered.
+const SINK_CAPACITY: usize = 10240;
+
+struct Downstream {
+    id: usize,
+    sink: Sender<RowChange>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Downstream {
+    /// Makes the stream of the subscriber fail after the buffered changes. The subscriber must
+    /// be removed from the hub afterwards.
+    fn fail(&self, reason: String) {
+        *self.error.lock().unwrap() = Some(reason);
+    }
+}
+
+/// The stream of the changes of a subscription.
+///
+/// It ends when the subscriber unsubscribes, and fails if the subscriber is deregistered by the
+/// hub, after which the subscriber should resync from its checkpoint by `incremental_scan`.
+pub struct ChangeStream {
+    rx: Receiver<RowChange>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Stream for ChangeStream {
+    type Item = RowChange;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<RowChange>, Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Some(change))) => Ok(Async::Ready(Some(change))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // The error is set before the sink is dropped.
+            Ok(Async::Ready(None)) | Err(_) => match self.error.lock().unwrap().take() {
+                Some(reason) => Err(box_err!(reason)),
+                None => Ok(Async::Ready(None)),
+            },
+        }
+    }
+}
+
+/// `CdcHub` keeps the subscribers of the regions on a store.
+#[derive(Clone)]
+pub struct CdcHub {
+    downstreams: Arc<Mutex<HashMap<u64, Vec<Downstream>>>>,
+    next_id: Arc<AtomicUsize>,
+    capacity: usize,
+}
+
+impl CdcHub {
+    pub fn new() -> CdcHub {
+        CdcHub::with_capacity(SINK_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> CdcHub {
+        CdcHub {
+            downstreams: Arc::default(),
+            next_id: Arc::default(),
+            capacity: capacity,
+        }
+    }
+
+    /// Subscribes the rows committed in the region from now on, returns the id of the
+    /// subscription and the stream of the changes.
+    pub fn subscribe(&self, region_id: u64) -> (usize, ChangeStream) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let error = Arc::new(Mutex::new(None));
+        self.downstreams
+            .lock()
+            .unwrap()
+            .entry(region_id)
+            .or_insert_with(Vec::new)
+            .push(Downstream {
+                id: id,
+                sink: tx,
+                error: error.clone(),
+            });
+        (id, ChangeStream { rx: rx, error: error })
+    }
+
+    pub fn unsubscribe(&self, region_id: u64, id: usize) {
+        self.retain(region_id, |d| d.id != id);
+    }
+
+    pub fn is_subscribed(&self, region_id: u64) -> bool {
+        self.downstreams.lock().unwrap().contains_key(&region_id)
+    }
+
+    /// Deregisters all the subscribers of the region, whose streams fail with `reason`.
+    pub fn deregister(&self, region_id: u64, reason: &str) {
+        self.retain(region_id, |d| {
+            d.fail(reason.to_owned());
+            false
+        });
+    }
+
+    /// Sends the changes to the subscribers of the region. The closed ones are removed, and the
+    /// ones which can't buffer the changes are deregistered.
+    fn broadcast(&self, region_id: u64, changes: &[RowChange]) {
+        self.retain(region_id, |d| {
+            for c in changes {
+                if let Err(e) = d.sink.try_send(c.clone()) {
+                    if e.is_full() {
+                        d.fail(format!("subscriber {} falls behind", d.id));
+                    }
+                    return false;
+                }
+            }
+            true
+        });
+    }
+
+    fn retain<F>(&self, region_id: u64, mut f: F)
+    where
+        F: FnMut(&mut Downstream) -> bool,
+    {
+        let mut downstreams = self.downstreams.lock().unwrap();
+        let is_empty = match downstreams.get_mut(&region_id) {
+            Some(ds) => {
+                let kept: Vec<_> = ds.drain(..)
+                    .filter_map(|mut d| if f(&mut d) { Some(d) } else { None })
+                    .collect();
+                *ds = kept;
+                ds.is_empty()
+            }
+            None => return,
+        };
+        if is_empty {
+            downstreams.remove(&region_id);
+        }
+    }
+}
+
+/// Scans the rows of the region committed after `checkpoint_ts`, which are passed to `f` in
+/// the order of the keys.
+pub fn incremental_scan<F>(db: &DB, region_id: u64, checkpoint_ts: u64, mut f: F) -> Result<()>
+where
+    F: FnMut(RowChange),
+{
+    let state_key = keys::region_state_key(region_id);
+    let mut state = match db.get_msg_cf::<RegionLocalState>(CF_RAFT, &state_key)? {
+        Some(state) => state,
+        None => return Err(Error::RegionNotFound(region_id)),
+    };
+    if state.get_state() != PeerState::Normal {
+        return Err(Error::RegionNotFound(region_id));
+    }
+    let region = state.take_region();
+
+    let start_key = keys::enc_start_key(&region);
+    let end_key = keys::enc_end_key(&region);
+    db.scan_cf(CF_WRITE, &start_key, &end_key, false, &mut |key, value| {
+        let (encoded_key, commit_ts) = box_try!(split_encoded_key_on_ts(keys::origin_key(key)));
+        if commit_ts <= checkpoint_ts {
+            return Ok(true);
+        }
+        let write = box_try!(Write::parse(value));
+        let change = new_row_change(region_id, encoded_key, commit_ts, write, |key, start_ts| {
+            load_value(db, key, start_ts)
+        })?;
+        if let Some(change) = change {
+            f(change);
+        }
+        Ok(true)
+    })
+}
+
+/// Makes the change of a write record, `get_value` is called for the value which is not short.
+fn new_row_change<F>(
+    region_id: u64,
+    encoded_key: &[u8],
+    commit_ts: u64,
+    write: Write,
+    get_value: F,
+) -> Result<Option<RowChange>>
+where
+    F: FnOnce(&[u8], u64) -> Result<Vec<u8>>,
+{
+    let (op, value) = match write.write_type {
+        WriteType::Put => {
+            let value = match write.short_value {
+                Some(value) => value,
+                None => get_value(encoded_key, write.start_ts)?,
+            };
+            (RowOp::Put, Some(value))
+        }
+        WriteType::Delete => (RowOp::Delete, None),
+        WriteType::Lock | WriteType::Rollback => return Ok(None),
+    };
+    let key = box_try!(Key::from_encoded(encoded_key.to_vec()).raw());
+    Ok(Some(RowChange {
+        region_id: region_id,
+        key: key,
+        op: op,
+        value: value,
+        start_ts: write.start_ts,
+        commit_ts: commit_ts,
+    }))
+}
+
+fn load_value(db: &DB, encoded_key: &[u8], start_ts: u64) -> Result<Vec<u8>> {
+    let key = Key::from_encoded(encoded_key.to_vec()).append_ts(start_ts);
+    match db.get_value_cf(CF_DEFAULT, &keys::data_key(key.encoded()))? {
+        Some(value) => Ok(value.to_vec()),
+        None => Err(box_err!(
+            "value of key {} at {} is missing",
+            escape(encoded_key),
+            start_ts
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Stream;
+    use kvproto::metapb::Region;
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use raftstore::store::Mutable;
+    use storage::ALL_CFS;
+    use storage::types::make_key;
+    use util::rocksdb;
+    use super::*;
+
+    fn new_change(key: &[u8], value: Option<&[u8]>, start_ts: u64, commit_ts: u64) -> RowChange {
+        RowChange {
+            region_id: 1,
+            key: key.to_vec(),
+            op: if value.is_some() {
+                RowOp::Put
+            } else {
+                RowOp::Delete
+            },
+            value: value.map(|v| v.to_vec()),
+            start_ts: start_ts,
+            commit_ts: commit_ts,
+        }
+    }
+
+    #[test]
+    fn test_hub() {
+        let hub = CdcHub::new();
+        assert!(!hub.is_subscribed(1));
+        let (id1, rx1) = hub.subscribe(1);
+        let (id2, rx2) = hub.subscribe(1);
+        assert_ne!(id1, id2);
+        assert!(hub.is_subscribed(1));
+
+        let changes = vec![new_change(b"k1", Some(b"v1"), 1, 2), new_change(b"k2", None, 3, 4)];
+        hub.broadcast(1, &changes);
+        // Changes of other regions are ignored.
+        hub.broadcast(2, &changes);
+
+        // The closed subscriber is removed when broadcasting.
+        drop(rx2);
+        hub.broadcast(1, &changes[..1]);
+        assert_eq!(hub.downstreams.lock().unwrap()[&1].len(), 1);
+
+        hub.unsubscribe(1, id1);
+        assert!(!hub.is_subscribed(1));
+        let received: Vec<_> = rx1.wait().map(|c| c.unwrap()).collect();
+        assert_eq!(
+            received,
+            vec![changes[0].clone(), changes[1].clone(), changes[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_hub_deregister() {
+        let hub = CdcHub::with_capacity(1);
+        let (_, rx1) = hub.subscribe(1);
+        let (_, rx2) = hub.subscribe(2);
+
+        // The subscriber which can't buffer the changes is deregistered.
+        let changes: Vec<_> = (0..3)
+            .map(|i| new_change(b"k", Some(b"v"), i * 2 + 1, i * 2 + 2))
+            .collect();
+        hub.broadcast(1, &changes);
+        assert!(!hub.is_subscribed(1));
+        let results: Vec<_> = rx1.wait().collect();
+        assert!(results.last().unwrap().is_err());
+        let received: Vec<_> = results.into_iter().filter_map(|r| r.ok()).collect();
+        assert!(!received.is_empty() && received.len() < changes.len());
+        assert_eq!(received[..], changes[..received.len()]);
+
+        hub.deregister(2, "capture failed");
+        assert!(!hub.is_subscribed(2));
+        let mut rx2 = rx2.wait();
+        assert!(rx2.next().unwrap().is_err());
+        assert!(rx2.next().is_none());
+    }
+
+    #[test]
+    fn test_incremental_scan() {
+        let path = TempDir::new("test_cdc_scan").unwrap();
+        let db = rocksdb::new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap();
+        let default_cf = db.cf_handle(CF_DEFAULT).unwrap();
+        let write_cf = db.cf_handle(CF_WRITE).unwrap();
+        let raft_cf = db.cf_handle(CF_RAFT).unwrap();
+
+        let mut region = Region::new();
+        region.set_id(1);
+        region.set_end_key(make_key(b"k4").encoded().to_vec());
+        let mut state = RegionLocalState::new();
+        state.set_region(region);
+        db.put_msg_cf(raft_cf, &keys::region_state_key(1), &state)
+            .unwrap();
+
+        let writes: Vec<(&[u8], Write, u64)> = vec![
+            (b"k1", Write::new(WriteType::Put, 5, None), 6),
+            (b"k1", Write::new(WriteType::Put, 1, Some(b"v0".to_vec())), 2),
+            (b"k2", Write::new(WriteType::Rollback, 7, None), 7),
+            (b"k2", Write::new(WriteType::Delete, 8, None), 9),
+            (b"k3", Write::new(WriteType::Put, 10, Some(b"v3".to_vec())), 11),
+            (b"k5", Write::new(WriteType::Put, 10, Some(b"v5".to_vec())), 11),
+        ];
+        for (k, write, commit_ts) in writes {
+            let key = make_key(k).append_ts(commit_ts);
+            db.put_cf(write_cf, &keys::data_key(key.encoded()), &write.to_bytes())
+                .unwrap();
+        }
+        let key = make_key(b"k1").append_ts(5);
+        db.put_cf(default_cf, &keys::data_key(key.encoded()), b"v1")
+            .unwrap();
+
+        let mut changes = vec![];
+        incremental_scan(&db, 1, 2, |c| changes.push(c)).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                new_change(b"k1", Some(b"v1"), 5, 6),
+                new_change(b"k2", None, 8, 9),
+                new_change(b"k3", Some(b"v3"), 10, 11),
+            ]
+        );
+
+        // The value of a long row is missing.
+        let mut changes = vec![];
+        let key = make_key(b"k1").append_ts(5);
+        db.delete_cf(default_cf, &keys::data_key(key.encoded()))
+            .unwrap();
+        assert!(incremental_scan(&db, 1, 2, |c| changes.push(c)).is_err());
+
+        assert!(incremental_scan(&db, 2, 0, |c| changes.push(c)).is_err());
+    }
+}
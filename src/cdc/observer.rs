@@ -0,0 +1,286 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+use kvproto::raft_cmdpb::{CmdType, Request};
+use protobuf::RepeatedField;
+use rocksdb::DB;
+
+use raftstore::Result;
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use storage::{Key, CF_DEFAULT, CF_WRITE};
+use storage::mvcc::Write;
+use storage::types::split_encoded_key_on_ts;
+use util::collections::HashMap;
+use util::worker::{Runnable, Scheduler};
+use super::{load_value, new_row_change, CdcHub, RowChange};
+
+/// The requests applied to a subscribed region.
+pub struct Task {
+    region_id: u64,
+    requests: Vec<Request>,
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "capture {} requests of region {}",
+            self.requests.len(),
+            self.region_id
+        )
+    }
+}
+
+/// `CdcObserver` hands the requests applied to the subscribed regions over to the cdc worker,
+/// so that the apply thread doesn't read the engine for them.
+pub struct CdcObserver {
+    hub: CdcHub,
+    scheduler: Mutex<Scheduler<Task>>,
+}
+
+impl CdcObserver {
+    pub fn new(hub: CdcHub, scheduler: Scheduler<Task>) -> CdcObserver {
+        CdcObserver {
+            hub: hub,
+            scheduler: Mutex::new(scheduler),
+        }
+    }
+}
+
+impl Coprocessor for CdcObserver {}
+
+impl RegionObserver for CdcObserver {
+    fn post_apply_query(&self, ctx: &mut ObserverContext, requests: &RepeatedField<Request>) {
+        let region_id = ctx.region().get_id();
+        if !self.hub.is_subscribed(region_id) {
+            return;
+        }
+        let task = Task {
+            region_id: region_id,
+            requests: requests.to_vec(),
+        };
+        if let Err(e) = self.scheduler.lock().unwrap().schedule(task) {
+            error!("[region {}] failed to schedule cdc task: {:?}", region_id, e);
+            self.hub.deregister(region_id, "cdc worker is stopped");
+        }
+    }
+}
+
+/// `Runner` matches the values prewritten in the subscribed regions with their commits, and
+/// sends the committed rows to the `CdcHub`.
+pub struct Runner {
+    hub: CdcHub,
+    engine: Arc<DB>,
+    // region id -> default CF key -> value, the values prewritten in the subscribed regions.
+    prewrites: HashMap<u64, HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl Runner {
+    pub fn new(hub: CdcHub, engine: Arc<DB>) -> Runner {
+        Runner {
+            hub: hub,
+            engine: engine,
+            prewrites: HashMap::default(),
+        }
+    }
+
+    fn handle_requests(&mut self, region_id: u64, requests: &[Request]) -> Result<Vec<RowChange>> {
+        let hub = &self.hub;
+        self.prewrites.retain(|id, _| hub.is_subscribed(*id));
+        if !hub.is_subscribed(region_id) {
+            return Ok(vec![]);
+        }
+        let prewrites = self.prewrites
+            .entry(region_id)
+            .or_insert_with(HashMap::default);
+
+        let mut changes = vec![];
+        for req in requests {
+            match req.get_cmd_type() {
+                CmdType::Put => {
+                    let put = req.get_put();
+                    match cf_name(put.get_cf()) {
+                        CF_DEFAULT => {
+                            prewrites.insert(put.get_key().to_vec(), put.get_value().to_vec());
+                        }
+                        CF_WRITE => {
+                            let (key, commit_ts) = box_try!(split_encoded_key_on_ts(put.get_key()));
+                            let write = box_try!(Write::parse(put.get_value()));
+                            // The values prewritten before the subscription are written to the
+                            // engine already, as a commit is proposed after the prewrite is
+                            // acknowledged.
+                            let engine = &self.engine;
+                            let change =
+                                new_row_change(region_id, key, commit_ts, write, |key, start_ts| {
+                                    let default_key =
+                                        Key::from_encoded(key.to_vec()).append_ts(start_ts);
+                                    match prewrites.remove(default_key.encoded()) {
+                                        Some(value) => Ok(value),
+                                        None => load_value(engine, key, start_ts),
+                                    }
+                                })?;
+                            changes.extend(change);
+                        }
+                        _ => {}
+                    }
+                }
+                // The value is deleted when the transaction is rolled back.
+                CmdType::Delete if cf_name(req.get_delete().get_cf()) == CF_DEFAULT => {
+                    prewrites.remove(req.get_delete().get_key());
+                }
+                _ => {}
+            }
+        }
+        Ok(changes)
+    }
+}
+
+fn cf_name(cf: &str) -> &str {
+    if cf.is_empty() { CF_DEFAULT } else { cf }
+}
+
+impl Runnable<Task> for Runner {
+    fn run(&mut self, task: Task) {
+        let region_id = task.region_id;
+        match self.handle_requests(region_id, &task.requests) {
+            Ok(ref changes) if changes.is_empty() => {}
+            Ok(changes) => self.hub.broadcast(region_id, &changes),
+            Err(e) => {
+                // The subscribers resync from their checkpoints instead of missing the changes.
+                error!("[region {}] failed to capture changes: {:?}", region_id, e);
+                self.hub
+                    .deregister(region_id, &format!("failed to capture changes: {:?}", e));
+                self.prewrites.remove(&region_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Stream;
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use raftstore::store::keys;
+    use storage::ALL_CFS;
+    use storage::mvcc::WriteType;
+    use storage::types::make_key;
+    use util::rocksdb;
+    use super::*;
+    use super::super::RowOp;
+
+    fn new_put(cf: &str, key: Key, value: Vec<u8>) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_cf(cf.to_owned());
+        req.mut_put().set_key(key.encoded().to_vec());
+        req.mut_put().set_value(value);
+        req
+    }
+
+    fn new_delete(cf: &str, key: Key) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Delete);
+        req.mut_delete().set_cf(cf.to_owned());
+        req.mut_delete().set_key(key.encoded().to_vec());
+        req
+    }
+
+    fn new_commit(key: &[u8], write: Write, commit_ts: u64) -> Request {
+        new_put(CF_WRITE, make_key(key).append_ts(commit_ts), write.to_bytes())
+    }
+
+    #[test]
+    fn test_cdc_runner() {
+        let path = TempDir::new("test_cdc_runner").unwrap();
+        let engine = Arc::new(rocksdb::new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        let hub = CdcHub::new();
+        let mut runner = Runner::new(hub.clone(), engine.clone());
+        let mut apply = |requests: Vec<Request>| {
+            runner.run(Task {
+                region_id: 1,
+                requests: requests,
+            });
+            runner.prewrites.clone()
+        };
+
+        // Changes are not captured before the subscription.
+        apply(vec![new_put("", make_key(b"k1").append_ts(1), b"v0".to_vec())]);
+        apply(vec![new_commit(b"k1", Write::new(WriteType::Put, 1, None), 2)]);
+        // The value prewritten before the subscription is read from the engine.
+        let key = make_key(b"k2").append_ts(3);
+        let default_cf = engine.cf_handle(CF_DEFAULT).unwrap();
+        engine
+            .put_cf(default_cf, &keys::data_key(key.encoded()), b"v2")
+            .unwrap();
+
+        let (id, rx) = hub.subscribe(1);
+        apply(vec![
+            new_put(CF_DEFAULT, make_key(b"k1").append_ts(5), b"v1".to_vec()),
+            new_put(CF_DEFAULT, make_key(b"k3").append_ts(5), b"v3".to_vec()),
+        ]);
+        apply(vec![
+            new_commit(b"k2", Write::new(WriteType::Put, 3, None), 4),
+            new_commit(b"k4", Write::new(WriteType::Delete, 3, None), 4),
+        ]);
+        apply(vec![
+            new_commit(b"k1", Write::new(WriteType::Put, 5, None), 6),
+            new_commit(b"k5", Write::new(WriteType::Put, 5, Some(b"v5".to_vec())), 6),
+            new_commit(b"k5", Write::new(WriteType::Lock, 5, None), 6),
+        ]);
+        let prewrites = apply(vec![
+            new_delete("", make_key(b"k3").append_ts(5)),
+            new_commit(b"k3", Write::new(WriteType::Rollback, 5, None), 5),
+        ]);
+        assert!(prewrites[&1].is_empty());
+
+        let changes: Vec<_> = rx.wait()
+            .take(4)
+            .map(|c| {
+                let c = c.unwrap();
+                (c.key, c.op, c.value, c.start_ts, c.commit_ts)
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (b"k2".to_vec(), RowOp::Put, Some(b"v2".to_vec()), 3, 4),
+                (b"k4".to_vec(), RowOp::Delete, None, 3, 4),
+                (b"k1".to_vec(), RowOp::Put, Some(b"v1".to_vec()), 5, 6),
+                (b"k5".to_vec(), RowOp::Put, Some(b"v5".to_vec()), 5, 6),
+            ]
+        );
+
+        hub.unsubscribe(1, id);
+        let prewrites = apply(vec![new_put("", make_key(b"k1").append_ts(9), b"v0".to_vec())]);
+        assert!(prewrites.is_empty());
+
+        // The subscriber is deregistered if the changes can't be captured, instead of missing
+        // them silently.
+        let (_, rx) = hub.subscribe(1);
+        apply(vec![new_put("", make_key(b"k6").append_ts(7), b"v6".to_vec())]);
+        let prewrites = apply(vec![
+            new_commit(b"k6", Write::new(WriteType::Put, 7, None), 8),
+            new_commit(b"k7", Write::new(WriteType::Put, 7, None), 8),
+        ]);
+        assert!(prewrites.is_empty());
+        assert!(!hub.is_subscribed(1));
+        let mut rx = rx.wait();
+        assert!(rx.next().unwrap().is_err());
+        assert!(rx.next().is_none());
+    }
+}
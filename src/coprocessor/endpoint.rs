@@ -449,6 +449,10 @@ impl BatchRunnable<Task> for Host {
                         on_error(e, req);
                         continue;
                     }
+                    if let Some(start_ts) = req.start_ts {
+                        // The one-phase commits in the region must commit after the read.
+                        self.engine.update_max_read_ts(req.req.get_context(), start_ts);
+                    }
                     let key = {
                         let ctx = req.req.get_context();
                         (
@@ -0,0 +1,244 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Builder};
+use std::time::{Duration, Instant};
+
+use rocksdb::{ColumnFamilyOptions, DB};
+
+use util::rocksdb::get_cf_handle;
+use super::Result;
+
+const RETRY_INTERVAL_SECS: u64 = 1;
+
+/// The options of a CF tuned by the import mode.
+#[derive(Debug, Clone, PartialEq)]
+struct ImportModeCFOptions {
+    disable_auto_compactions: bool,
+    level0_slowdown_writes_trigger: u32,
+    level0_stop_writes_trigger: u32,
+    soft_pending_compaction_bytes_limit: u64,
+    hard_pending_compaction_bytes_limit: u64,
+}
+
+impl ImportModeCFOptions {
+    fn import_mode() -> ImportModeCFOptions {
+        ImportModeCFOptions {
+            disable_auto_compactions: true,
+            // Writes are never slowed down or stopped by the L0 files or pending compactions.
+            level0_slowdown_writes_trigger: 1 << 30,
+            level0_stop_writes_trigger: 1 << 30,
+            soft_pending_compaction_bytes_limit: 0,
+            hard_pending_compaction_bytes_limit: 0,
+        }
+    }
+
+    fn from_options(opts: &ColumnFamilyOptions) -> ImportModeCFOptions {
+        ImportModeCFOptions {
+            disable_auto_compactions: opts.get_disable_auto_compactions(),
+            level0_slowdown_writes_trigger: opts.get_level_zero_slowdown_writes_trigger(),
+            level0_stop_writes_trigger: opts.get_level_zero_stop_writes_trigger(),
+            soft_pending_compaction_bytes_limit: opts.get_soft_pending_compaction_bytes_limit(),
+            hard_pending_compaction_bytes_limit: opts.get_hard_pending_compaction_bytes_limit(),
+        }
+    }
+
+    fn set_options(&self, db: &DB, cf: &str) -> Result<()> {
+        let handle = get_cf_handle(db, cf)?;
+        let disable_auto_compactions = self.disable_auto_compactions.to_string();
+        let level0_slowdown_writes_trigger = self.level0_slowdown_writes_trigger.to_string();
+        let level0_stop_writes_trigger = self.level0_stop_writes_trigger.to_string();
+        let soft_limit = self.soft_pending_compaction_bytes_limit.to_string();
+        let hard_limit = self.hard_pending_compaction_bytes_limit.to_string();
+        let opts = [
+            ("disable_auto_compactions", disable_auto_compactions.as_str()),
+            ("level0_slowdown_writes_trigger", level0_slowdown_writes_trigger.as_str()),
+            ("level0_stop_writes_trigger", level0_stop_writes_trigger.as_str()),
+            ("soft_pending_compaction_bytes_limit", soft_limit.as_str()),
+            ("hard_pending_compaction_bytes_limit", hard_limit.as_str()),
+        ];
+        db.set_options_cf(handle, &opts)?;
+        Ok(())
+    }
+}
+
+fn set_all_options(db: &DB, options: &[(String, ImportModeCFOptions)]) -> Result<()> {
+    for &(ref cf, ref opts) in options {
+        opts.set_options(db, cf)?;
+    }
+    Ok(())
+}
+
+struct Inner {
+    // The options of the CFs before entering the import mode, `None` in the normal mode.
+    normal_options: Option<Vec<(String, ImportModeCFOptions)>>,
+    deadline: Instant,
+    checker_running: bool,
+}
+
+/// `ImportModeSwitcher` tunes all the CFs of a DB for the throughput of bulk loads, with auto
+/// compactions off and no write stalls. The import mode is left automatically when it's not
+/// renewed in time, so a crashed loader can't leave the DB in it.
+#[derive(Clone)]
+pub struct ImportModeSwitcher {
+    db: Arc<DB>,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ImportModeSwitcher {
+    pub fn new(db: Arc<DB>) -> ImportModeSwitcher {
+        ImportModeSwitcher {
+            db: db,
+            inner: Arc::new(Mutex::new(Inner {
+                normal_options: None,
+                deadline: Instant::now(),
+                checker_running: false,
+            })),
+        }
+    }
+
+    pub fn is_import_mode(&self) -> bool {
+        self.inner.lock().unwrap().normal_options.is_some()
+    }
+
+    /// Enters the import mode, or renews it if it's entered already. The normal mode is
+    /// restored after `timeout` unless the import mode is renewed again.
+    pub fn enter_import_mode(&self, timeout: Duration) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.normal_options.is_none() {
+            let mut normal_options = Vec::new();
+            for cf in self.db.cf_names() {
+                let handle = get_cf_handle(&self.db, cf)?;
+                let opts = ImportModeCFOptions::from_options(&self.db.get_options_cf(handle));
+                normal_options.push((cf.to_owned(), opts));
+            }
+            let import_options: Vec<_> = normal_options
+                .iter()
+                .map(|&(ref cf, _)| (cf.clone(), ImportModeCFOptions::import_mode()))
+                .collect();
+            inner.normal_options = Some(normal_options);
+            if let Err(e) = set_all_options(&self.db, &import_options) {
+                if let Err(e) = self.restore(&mut inner) {
+                    error!("failed to restore the normal mode: {:?}", e);
+                }
+                return Err(e);
+            }
+            info!("enter import mode");
+        }
+        inner.deadline = Instant::now() + timeout;
+        if !inner.checker_running {
+            let switcher = self.clone();
+            Builder::new()
+                .name(thd_name!("import-mode"))
+                .spawn(move || switcher.check_deadline())?;
+            inner.checker_running = true;
+        }
+        Ok(())
+    }
+
+    pub fn enter_normal_mode(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        self.restore(&mut inner)
+    }
+
+    fn restore(&self, inner: &mut Inner) -> Result<()> {
+        let normal_options = match inner.normal_options.take() {
+            Some(options) => options,
+            None => return Ok(()),
+        };
+        if let Err(e) = set_all_options(&self.db, &normal_options) {
+            inner.normal_options = Some(normal_options);
+            return Err(e);
+        }
+        info!("enter normal mode");
+        Ok(())
+    }
+
+    fn check_deadline(&self) {
+        loop {
+            let wait = {
+                let mut inner = self.inner.lock().unwrap();
+                let now = Instant::now();
+                if inner.normal_options.is_none() {
+                    inner.checker_running = false;
+                    return;
+                } else if now < inner.deadline {
+                    inner.deadline - now
+                } else {
+                    warn!("import mode is not renewed in time");
+                    match self.restore(&mut inner) {
+                        Ok(()) => {
+                            inner.checker_running = false;
+                            return;
+                        }
+                        Err(e) => {
+                            error!("failed to restore the normal mode: {:?}", e);
+                            Duration::from_secs(RETRY_INTERVAL_SECS)
+                        }
+                    }
+                }
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use storage::{CF_DEFAULT, CF_WRITE};
+    use util::rocksdb;
+    use super::*;
+
+    fn cf_options(db: &DB, cf: &str) -> ImportModeCFOptions {
+        let handle = get_cf_handle(db, cf).unwrap();
+        ImportModeCFOptions::from_options(&db.get_options_cf(handle))
+    }
+
+    #[test]
+    fn test_import_mode_switcher() {
+        let temp_dir = TempDir::new("test_import_mode_switcher").unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let db = Arc::new(rocksdb::new_engine(path, &[CF_DEFAULT, CF_WRITE]).unwrap());
+        let normal_options = cf_options(&db, CF_WRITE);
+        assert_ne!(normal_options, ImportModeCFOptions::import_mode());
+
+        let switcher = ImportModeSwitcher::new(db.clone());
+        assert!(!switcher.is_import_mode());
+        switcher.enter_normal_mode().unwrap();
+
+        switcher.enter_import_mode(Duration::from_secs(10)).unwrap();
+        assert!(switcher.is_import_mode());
+        for cf in &[CF_DEFAULT, CF_WRITE] {
+            assert_eq!(cf_options(&db, cf), ImportModeCFOptions::import_mode());
+        }
+        // Renewing the import mode doesn't lose the normal options.
+        switcher.enter_import_mode(Duration::from_secs(10)).unwrap();
+        switcher.enter_normal_mode().unwrap();
+        assert!(!switcher.is_import_mode());
+        assert_eq!(cf_options(&db, CF_WRITE), normal_options);
+
+        // The normal mode is restored when the import mode is not renewed in time.
+        switcher.enter_import_mode(Duration::from_millis(200)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        switcher.enter_import_mode(Duration::from_millis(200)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(switcher.is_import_mode());
+        thread::sleep(Duration::from_millis(300));
+        assert!(!switcher.is_import_mode());
+        assert_eq!(cf_options(&db, CF_WRITE), normal_options);
+        assert!(!switcher.inner.lock().unwrap().checker_running);
+    }
+}
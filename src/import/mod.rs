@@ -0,0 +1,50 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support of bulk loads.
+//!
+//! `ImportModeSwitcher` tunes the engine for the throughput of the loads.
+
+mod import_mode;
+
+pub use self::import_mode::ImportModeSwitcher;
+
+use std::error;
+use std::io;
+use std::result;
+
+quick_error!{
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: io::Error) {
+            from()
+            cause(err)
+            description(err.description())
+            display("Io {}", err)
+        }
+        // RocksDb uses plain string as the error.
+        RocksDb(msg: String) {
+            from()
+            description("RocksDb error")
+            display("RocksDb {}", msg)
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
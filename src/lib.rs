@@ -86,5 +86,7 @@ pub mod raftstore;
 pub mod pd;
 pub mod server;
 pub mod coprocessor;
+pub mod cdc;
+pub mod import;
 
 pub use storage::Storage;
@@ -25,7 +25,7 @@ use kvproto::pdpb::{self, Member};
 use util::{Either, HandyRwLock};
 use util::time::duration_to_sec;
 use pd::PdFuture;
-use super::{Error, PdClient, RegionStat, Result, REQUEST_TIMEOUT};
+use super::{Error, PdClient, RegionStat, Result, REQUEST_TIMEOUT, TSO_LOGICAL_BITS};
 use super::util::{check_resp_header, sync_request, validate_endpoints, Inner, LeaderClient};
 use super::metrics::*;
 
@@ -368,4 +368,51 @@ impl PdClient for RpcClient {
             .request(req, executor, LEADER_CHANGE_RETRY)
             .execute()
     }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        let timer = Instant::now();
+
+        let mut req = pdpb::TsoRequest::new();
+        req.set_header(self.header());
+        req.set_count(1);
+
+        let executor = move |client: &RwLock<Inner>, req: pdpb::TsoRequest| {
+            let (sender, receiver) = match client.rl().client.tso() {
+                Ok(stream) => stream,
+                Err(e) => return Box::new(future::err(Error::Grpc(e))) as PdFuture<_>,
+            };
+            Box::new(
+                sender
+                    .send((req, WriteFlags::default()))
+                    .and_then(|sender| {
+                        // Keep the sender alive until the response is received.
+                        receiver
+                            .into_future()
+                            .map(move |(resp, _)| {
+                                drop(sender);
+                                resp
+                            })
+                            .map_err(|(e, _)| e)
+                    })
+                    .map_err(Error::Grpc)
+                    .and_then(move |resp| {
+                        PD_REQUEST_HISTOGRAM_VEC
+                            .with_label_values(&["get_tso"])
+                            .observe(duration_to_sec(timer.elapsed()));
+                        let resp = match resp {
+                            Some(resp) => resp,
+                            None => return Err(Error::Other(box_err!("tso stream is closed"))),
+                        };
+                        check_resp_header(resp.get_header())?;
+                        let ts = resp.get_timestamp();
+                        Ok(((ts.get_physical() as u64) << TSO_LOGICAL_BITS) |
+                            ts.get_logical() as u64)
+                    }),
+            ) as PdFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
 }
@@ -144,6 +144,12 @@ pub trait PdClient: Send + Sync {
 
     // Report pd the split region.
     fn report_split(&self, left: metapb::Region, right: metapb::Region) -> PdFuture<()>;
+
+    // Get a timestamp from the timestamp oracle of pd.
+    fn get_tso(&self) -> PdFuture<u64>;
 }
 
 const REQUEST_TIMEOUT: u64 = 2; // 2s
+
+// The bits of the logical part in a timestamp.
+pub const TSO_LOGICAL_BITS: u64 = 18;
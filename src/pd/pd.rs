@@ -72,6 +72,8 @@ pub enum Task {
     },
     ReadStats { read_stats: HashMap<u64, FlowStatistics>, },
     DestroyPeer { region_id: u64 },
+    // (region id, term) of the leaders
+    ResolvedTs { regions: Vec<(u64, u64)> },
 }
 
 pub struct StoreStat {
@@ -150,6 +152,9 @@ impl Display for Task {
                 write!(f, "get the read statistics {:?}", read_stats)
             }
             Task::DestroyPeer { ref region_id } => write!(f, "destroy peer {}", region_id),
+            Task::ResolvedTs { ref regions } => {
+                write!(f, "get ts to advance resolved ts of regions {:?}", regions)
+            }
         }
     }
 }
@@ -452,6 +457,25 @@ impl<T: PdClient> Runner<T> {
         }
     }
 
+    fn handle_resolved_ts(&self, handle: &Handle, regions: Vec<(u64, u64)>) {
+        let ch = self.ch.clone();
+        let f = self.pd_client.get_tso().then(move |resp| {
+            match resp {
+                Ok(ts) => {
+                    if let Err(e) = ch.try_send(Msg::AdvanceResolvedTs {
+                        regions: regions,
+                        ts: ts,
+                    }) {
+                        error!("send advance resolved ts err {:?}", e);
+                    }
+                }
+                Err(e) => debug!("get ts for resolved ts failed {:?}", e),
+            }
+            Ok(())
+        });
+        handle.spawn(f);
+    }
+
     fn handle_destory_peer(&mut self, region_id: u64) {
         match self.region_peers.remove(&region_id) {
             None => return,
@@ -530,6 +554,7 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
             Task::ValidatePeer { region, peer } => self.handle_validate_peer(handle, region, peer),
             Task::ReadStats { read_stats } => self.handle_read_stats(read_stats),
             Task::DestroyPeer { region_id } => self.handle_destory_peer(region_id),
+            Task::ResolvedTs { regions } => self.handle_resolved_ts(handle, regions),
         };
     }
 }
@@ -79,6 +79,10 @@ pub struct Progress {
     // When a leader receives a reply, the previous inflights should
     // be freed by calling inflights.freeTo.
     pub ins: Inflights,
+
+    // is_learner is true if the progress is of a learner, which receives the log like
+    // a follower but never votes, so it's not counted in the quorum.
+    pub is_learner: bool,
 }
 
 
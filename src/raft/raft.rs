@@ -81,6 +81,12 @@ pub struct Config {
     /// peer is private and only used for testing right now.
     pub peers: Vec<u64>,
 
+    /// learners contains the IDs of the learner nodes, which receive the log
+    /// from the leader but never vote. ConfState doesn't record the learners,
+    /// so it should be set whenever the raft is started. The learners are kept
+    /// when a snapshot is restored unless the snapshot makes them voters.
+    pub learners: Vec<u64>,
+
     /// ElectionTick is the number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
@@ -149,6 +155,12 @@ impl Config {
             ));
         }
 
+        if self.learners.iter().any(|id| self.peers.contains(id)) {
+            return Err(Error::ConfigInvalid(
+                "a node can't be both a peer and a learner".to_owned(),
+            ));
+        }
+
         if self.max_inflight_msgs == 0 {
             return Err(Error::ConfigInvalid(
                 "max inflight messages must be greater than 0".to_owned(),
@@ -287,7 +299,7 @@ impl<T: Storage> Raft<T> {
             raft_log: raft_log,
             max_inflight: c.max_inflight_msgs,
             max_msg_size: c.max_size_per_msg,
-            prs: FlatMap::with_capacity(peers.len()),
+            prs: FlatMap::with_capacity(peers.len() + c.learners.len()),
             state: StateRole::Follower,
             check_quorum: c.check_quorum,
             pre_vote: c.pre_vote,
@@ -311,6 +323,14 @@ impl<T: Storage> Raft<T> {
         for p in peers {
             r.prs.insert(*p, new_progress(1, r.max_inflight));
         }
+        for p in &c.learners {
+            if r.prs.contains_key(p) {
+                panic!("{} node {} is in both peers and learners", c.tag, p);
+            }
+            let mut pr = new_progress(1, r.max_inflight);
+            pr.is_learner = true;
+            r.prs.insert(*p, pr);
+        }
         if rs.hard_state != HardState::new() {
             r.load_state(rs.hard_state);
         }
@@ -320,10 +340,11 @@ impl<T: Storage> Raft<T> {
         let term = r.term;
         r.become_follower(term, INVALID_ID);
         info!(
-            "{} newRaft [peers: {:?}, term: {:?}, commit: {}, applied: {}, last_index: {}, \
-             last_term: {}]",
+            "{} newRaft [peers: {:?}, learners: {:?}, term: {:?}, commit: {}, applied: {}, \
+             last_index: {}, last_term: {}]",
             r.tag,
             r.nodes(),
+            r.learner_nodes(),
             r.term,
             r.raft_log.committed,
             r.raft_log.get_applied(),
@@ -378,7 +399,7 @@ impl<T: Storage> Raft<T> {
     }
 
     fn quorum(&self) -> usize {
-        quorum(self.prs.len())
+        quorum(self.prs.values().filter(|p| !p.is_learner).count())
     }
 
     // for testing leader lease
@@ -398,9 +419,24 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout
     }
 
+    /// Returns the ids of the voters.
     pub fn nodes(&self) -> Vec<u64> {
-        let mut nodes = Vec::with_capacity(self.prs.len());
-        nodes.extend(self.prs.keys());
+        let mut nodes: Vec<_> = self.prs
+            .iter()
+            .filter(|&(_, p)| !p.is_learner)
+            .map(|(id, _)| *id)
+            .collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the ids of the learners.
+    pub fn learner_nodes(&self) -> Vec<u64> {
+        let mut nodes: Vec<_> = self.prs
+            .iter()
+            .filter(|&(_, p)| p.is_learner)
+            .map(|(id, _)| *id)
+            .collect();
         nodes.sort();
         nodes
     }
@@ -599,15 +635,17 @@ impl<T: Storage> Raft<T> {
     // the commit index changed (in which case the caller should call
     // r.bcast_append).
     pub fn maybe_commit(&mut self) -> bool {
+        let voters = self.prs.values().filter(|p| !p.is_learner).count();
         let mut mis_arr = [0; 5];
         let mut mis_vec;
-        let mis = if self.prs.len() <= 5 {
-            &mut mis_arr[..self.prs.len()]
+        let mis = if voters <= 5 {
+            &mut mis_arr[..voters]
         } else {
-            mis_vec = vec![0; self.prs.len()];
+            mis_vec = vec![0; voters];
             mis_vec.as_mut_slice()
         };
-        for (i, pr) in self.prs.values().enumerate() {
+        // learners don't count in the quorum.
+        for (i, pr) in self.prs.values().filter(|p| !p.is_learner).enumerate() {
             mis[i] = pr.matched;
         }
         // reverse sort
@@ -632,7 +670,9 @@ impl<T: Storage> Raft<T> {
         let (last_index, max_inflight) = (self.raft_log.last_index(), self.max_inflight);
         let self_id = self.id;
         for (id, p) in &mut self.prs {
+            let is_learner = p.is_learner;
             *p = new_progress(last_index + 1, max_inflight);
+            p.is_learner = is_learner;
             if id == &self_id {
                 p.matched = last_index;
             }
@@ -801,7 +841,8 @@ impl<T: Storage> Raft<T> {
             }
             return;
         }
-        let ids: Vec<_> = self.prs.keys().cloned().collect();
+        // learners never vote.
+        let ids = self.nodes();
         for id in ids {
             if id == self.id {
                 continue;
@@ -956,7 +997,13 @@ impl<T: Storage> Raft<T> {
 
 
         match m.get_msg_type() {
-            MessageType::MsgHup => if self.state != StateRole::Leader {
+            MessageType::MsgHup => if self.is_learner() {
+                warn!(
+                    "{} is a learner and can't campaign at term {}",
+                    self.tag,
+                    self.term
+                );
+            } else if self.state != StateRole::Leader {
                 let ents = self.raft_log
                     .slice(
                         self.raft_log.applied + 1,
@@ -1160,6 +1207,14 @@ impl<T: Storage> Raft<T> {
             );
             return;
         }
+        if self.prs[&lead_transferee].is_learner {
+            debug!(
+                "{} ignored transferring leadership to learner {}",
+                self.tag,
+                lead_transferee
+            );
+            return;
+        }
         // Transfer leadership to third party.
         info!(
             "{} [term {}] starts to transfer leadership to {}",
@@ -1242,7 +1297,10 @@ impl<T: Storage> Raft<T> {
                     }
                 }
 
-                if self.read_only.option != ReadOnlyOption::Safe || m.get_context().is_empty() {
+                // the acks of learners don't count in the quorum.
+                if self.read_only.option != ReadOnlyOption::Safe || m.get_context().is_empty() ||
+                    self.prs[&m.get_from()].is_learner
+                {
                     return;
                 }
 
@@ -1395,12 +1453,20 @@ impl<T: Storage> Raft<T> {
                             }
                         }
                     }
-                } else {
+                } else if m.get_from() == INVALID_ID || m.get_from() == self.id {
                     let rs = ReadState {
                         index: self.raft_log.committed,
                         request_ctx: m.take_entries()[0].take_data(),
                     };
                     self.read_states.push(rs);
+                } else {
+                    // the read is forwarded by a learner.
+                    let mut to_send = Message::new();
+                    to_send.set_to(m.get_from());
+                    to_send.set_msg_type(MessageType::MsgReadIndexResp);
+                    to_send.set_index(self.raft_log.committed);
+                    to_send.set_entries(m.take_entries());
+                    self.send(to_send);
                 }
                 return;
             }
@@ -1709,8 +1775,15 @@ impl<T: Storage> Raft<T> {
             meta.get_index(),
             meta.get_term()
         );
-        self.prs = FlatMap::with_capacity(meta.get_conf_state().get_nodes().len());
-        for &n in meta.get_conf_state().get_nodes() {
+        // ConfState doesn't record the learners, so the known ones which are not voters
+        // in the snapshot are kept as learners.
+        let nodes = meta.get_conf_state().get_nodes();
+        let learners: Vec<u64> = self.learner_nodes()
+            .into_iter()
+            .filter(|id| !nodes.contains(id))
+            .collect();
+        self.prs = FlatMap::with_capacity(nodes.len() + learners.len());
+        for &n in nodes {
             let next_idx = self.raft_log.last_index() + 1;
             let matched = if n == self.id { next_idx - 1 } else { 0 };
             self.set_progress(n, matched, next_idx);
@@ -1721,6 +1794,18 @@ impl<T: Storage> Raft<T> {
                 self.prs[&n]
             );
         }
+        for id in learners {
+            let next_idx = self.raft_log.last_index() + 1;
+            let matched = if id == self.id { next_idx - 1 } else { 0 };
+            self.set_progress(id, matched, next_idx);
+            self.prs.get_mut(&id).unwrap().is_learner = true;
+            info!(
+                "{} restored progress of learner {} [{:?}]",
+                self.tag,
+                id,
+                self.prs[&id]
+            );
+        }
         None
     }
 
@@ -1742,28 +1827,53 @@ impl<T: Storage> Raft<T> {
     }
 
     // promotable indicates whether state machine can be promoted to leader,
-    // which is true when its own id is in progress list.
+    // which is true when its own id is in progress list and it's not a learner.
     pub fn promotable(&self) -> bool {
-        self.prs.contains_key(&self.id)
+        self.prs.get(&self.id).map_or(false, |p| !p.is_learner)
     }
 
+    // is_learner indicates whether the local node is a learner.
+    pub fn is_learner(&self) -> bool {
+        self.prs.get(&self.id).map_or(false, |p| p.is_learner)
+    }
+
+    // add_node adds a voter, or promotes the learner to a voter if it's a learner.
     pub fn add_node(&mut self, id: u64) {
         self.pending_conf = false;
-        if self.prs.contains_key(&id) {
+        if let Some(pr) = self.prs.get_mut(&id) {
             // Ignore any redundant addNode calls (which can happen because the
             // initial bootstrapping entries are applied twice).
+            if pr.is_learner {
+                info!("{} promotes learner {} to voter", self.tag, id);
+                pr.is_learner = false;
+            }
+            return;
+        }
+        let last_index = self.raft_log.last_index();
+        self.set_progress(id, 0, last_index + 1);
+    }
+
+    // add_learner adds a learner, which receives the log but never votes. A voter can't be
+    // demoted to a learner.
+    pub fn add_learner(&mut self, id: u64) {
+        self.pending_conf = false;
+        if let Some(pr) = self.prs.get(&id) {
+            if !pr.is_learner {
+                warn!("{} ignored adding voter {} as a learner", self.tag, id);
+            }
             return;
         }
         let last_index = self.raft_log.last_index();
         self.set_progress(id, 0, last_index + 1);
+        self.prs.get_mut(&id).unwrap().is_learner = true;
     }
 
     pub fn remove_node(&mut self, id: u64) {
         self.del_progress(id);
         self.pending_conf = false;
 
-        // do not try to commit or abort transferring if there are no nodes in the cluster.
-        if self.prs.is_empty() {
+        // do not try to commit or abort transferring if there are no voters in the cluster.
+        if self.prs.values().all(|p| p.is_learner) {
             return;
         }
 
@@ -1845,7 +1955,7 @@ impl<T: Storage> Raft<T> {
                 continue;
             }
 
-            if p.recent_active {
+            if p.recent_active && !p.is_learner {
                 act += 1;
             }
 
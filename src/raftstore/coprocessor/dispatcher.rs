@@ -13,7 +13,7 @@
 
 use super::{ObserverContext, RegionObserver, Result};
 
-use kvproto::raft_cmdpb::RaftCmdRequest;
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
 use kvproto::metapb::Region;
 
 struct ObserverEntry {
@@ -98,6 +98,20 @@ impl CoprocessorHost {
         }
     }
 
+    /// Call all post apply hook until bypass is set to true.
+    pub fn post_apply(&self, region: &Region, req: &RaftCmdRequest, resp: &RaftCmdResponse) {
+        if req.has_admin_request() || resp.get_header().has_error() {
+            return;
+        }
+        let mut ctx = ObserverContext::new(region);
+        for entry in &self.registry.observers {
+            entry.observer.post_apply_query(&mut ctx, req.get_requests());
+            if ctx.bypass {
+                break;
+            }
+        }
+    }
+
     pub fn shutdown(&self) {
         for entry in &self.registry.observers {
             entry.observer.stop();
@@ -113,7 +127,8 @@ mod test {
     use protobuf::RepeatedField;
 
     use kvproto::metapb::Region;
-    use kvproto::raft_cmdpb::{AdminRequest, RaftCmdRequest, Request};
+    use kvproto::errorpb;
+    use kvproto::raft_cmdpb::{AdminRequest, RaftCmdRequest, RaftCmdResponse, Request};
 
     struct TestCoprocessor {
         bypass: Arc<AtomicBool>,
@@ -165,6 +180,11 @@ mod test {
             self.called.fetch_add(3, Ordering::SeqCst);
             ctx.bypass = self.bypass.load(Ordering::SeqCst);
         }
+
+        fn post_apply_query(&self, ctx: &mut ObserverContext, _: &RepeatedField<Request>) {
+            self.called.fetch_add(4, Ordering::SeqCst);
+            ctx.bypass = self.bypass.load(Ordering::SeqCst);
+        }
     }
 
     fn share_bool() -> Arc<AtomicBool> {
@@ -229,6 +249,15 @@ mod test {
         host.pre_apply(&region, &mut query_req);
         assert_all!(&[&called1, &called2], &[0, 5]);
 
+        host.post_apply(&region, &query_req, &RaftCmdResponse::new());
+        assert_all!(&[&called1, &called2], &[0, 9]);
+
+        // post_apply is ignored when the request fails.
+        let mut err_resp = RaftCmdResponse::new();
+        err_resp.mut_header().set_error(errorpb::Error::new());
+        host.post_apply(&region, &query_req, &err_resp);
+        assert_all!(&[&called1, &called2], &[0, 9]);
+
         set_all!(&[&bypass2], false);
         set_all!(&[&called2], 0);
 
@@ -69,4 +69,9 @@ pub trait RegionObserver: Coprocessor {
     ///
     /// Please note that improper implementation can lead to data inconsistency.
     fn pre_apply_query(&self, _: &mut ObserverContext, _: &mut RepeatedField<Request>) {}
+
+    /// Hook to call after read/write request is applied successfully.
+    ///
+    /// Please note that the writes may not be written to the engine yet.
+    fn post_apply_query(&self, _: &mut ObserverContext, _: &RepeatedField<Request>) {}
 }
@@ -89,6 +89,9 @@ pub struct Config {
 
     pub report_region_flow_interval: ReadableDuration,
 
+    // Interval to advance the resolved ts of the leader regions with a timestamp from pd.
+    pub resolved_ts_advance_interval: ReadableDuration,
+
     // The lease provided by a successfully proposed and applied entry.
     pub raft_store_max_leader_lease: ReadableDuration,
 
@@ -138,6 +141,7 @@ impl Default for Config {
             // We should turn on this only in our tests.
             consistency_check_interval: ReadableDuration::secs(0),
             report_region_flow_interval: ReadableDuration::minutes(1),
+            resolved_ts_advance_interval: ReadableDuration::secs(1),
             raft_store_max_leader_lease: ReadableDuration::secs(9),
             right_derive_when_split: true,
             allow_remove_leader: false,
@@ -13,23 +13,37 @@
 
 use std::{error, result};
 use std::cmp::Ordering;
+use std::fs;
+use std::io::Write as IoWrite;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use protobuf::RepeatedField;
+use serde_json;
 
 use rocksdb::{Kv, SeekKey, DB};
-use kvproto::kvrpcpb::{LockInfo, MvccInfo, Op, ValueInfo, WriteInfo};
+use kvproto::kvrpcpb::{IsolationLevel, LockInfo, MvccInfo, Op, ValueInfo, WriteInfo};
 use kvproto::debugpb::DB as DBType;
 use kvproto::eraftpb::Entry;
+use kvproto::metapb::Region;
 use kvproto::raft_serverpb::*;
 
-use raftstore::store::{keys, Engines, Iterable, Peekable};
+use import::ImportModeSwitcher;
+use raftstore::coprocessor::RegionSnapshot;
+use raftstore::store::{keys, Engines, Iterable, Peekable, ResolvedTsTracker};
 use raftstore::store::engine::IterOption;
 use storage::{is_short_value, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::engine::{ScanMode, Statistics};
 use storage::types::{truncate_ts, Key};
-use storage::mvcc::{Lock, Write, WriteType};
+use storage::mvcc::{Lock, MvccReader, Write, WriteType};
 use util::escape;
-use util::rocksdb::{compact_range, get_cf_handle};
+use util::file::{calc_crc32, file_exists, get_file_size};
+use util::rocksdb::{compact_range, get_cf_handle, new_sst_writer};
+
+/// The name of the manifest file in a backup directory, which is written after all the SST
+/// files of the backup.
+pub const BACKUP_MANIFEST_FILE: &str = "backup.manifest";
 
 pub type Result<T> = result::Result<T, Error>;
 type DBIterator = ::rocksdb::DBIterator<Arc<DB>>;
@@ -75,14 +89,56 @@ impl RegionInfo {
     }
 }
 
+/// The manifest of a backup, keys are raw keys in escaped form and an empty end key means
+/// unbounded.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BackupManifest {
+    pub backup_ts: u64,
+    pub start_key: String,
+    pub end_key: String,
+    pub files: Vec<BackupFile>,
+}
+
+/// The data of a region in a backup. The SST file holds the raw keys and values visible at
+/// the backup ts in the range, it's absent when there is no such key.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BackupFile {
+    pub region_id: u64,
+    pub region_version: u64,
+    pub start_key: String,
+    pub end_key: String,
+    pub name: Option<String>,
+    pub kv_count: u64,
+    pub size: u64,
+    pub checksum: u32,
+}
+
 #[derive(Clone)]
 pub struct Debugger {
     engines: Engines,
+    resolved_ts: Option<ResolvedTsTracker>,
+    import_mode: Option<ImportModeSwitcher>,
 }
 
 impl Debugger {
     pub fn new(engines: Engines) -> Debugger {
-        Debugger { engines }
+        Debugger {
+            engines,
+            resolved_ts: None,
+            import_mode: None,
+        }
+    }
+
+    /// Set the resolved ts tracker of a running store.
+    pub fn set_resolved_ts(&mut self, resolved_ts: ResolvedTsTracker) {
+        self.resolved_ts = Some(resolved_ts);
+    }
+
+    /// Set the import mode switcher of the KV engine of a running store.
+    pub fn set_import_mode_switcher(&mut self, switcher: ImportModeSwitcher) {
+        self.import_mode = Some(switcher);
     }
 
     /// Get all regions holding region meta data from raft CF in KV storage.
@@ -166,6 +222,42 @@ impl Debugger {
         }
     }
 
+    pub fn resolved_ts(&self, region_id: u64) -> Result<u64> {
+        let resolved_ts = match self.resolved_ts {
+            Some(ref resolved_ts) => resolved_ts,
+            None => {
+                return Err(Error::InvalidArgument(
+                    "resolved ts is only tracked by a running store".to_owned(),
+                ))
+            }
+        };
+        resolved_ts
+            .resolved_ts(region_id)
+            .ok_or_else(|| Error::NotFound(format!("resolved ts for region {}", region_id)))
+    }
+
+    fn import_mode_switcher(&self) -> Result<&ImportModeSwitcher> {
+        self.import_mode.as_ref().ok_or_else(|| {
+            Error::InvalidArgument("import mode is only switched on a running store".to_owned())
+        })
+    }
+
+    pub fn is_import_mode(&self) -> Result<bool> {
+        Ok(self.import_mode_switcher()?.is_import_mode())
+    }
+
+    /// Switch the KV engine to the import mode, which is left after `timeout` unless it's
+    /// switched again, or back to the normal mode.
+    pub fn switch_import_mode(&self, import: bool, timeout: Duration) -> Result<()> {
+        let switcher = self.import_mode_switcher()?;
+        if import {
+            box_try!(switcher.enter_import_mode(timeout));
+        } else {
+            box_try!(switcher.enter_normal_mode());
+        }
+        Ok(())
+    }
+
     pub fn region_size<T: AsRef<str>>(
         &self,
         region_id: u64,
@@ -219,6 +311,167 @@ impl Debugger {
         compact_range(db, handle, start, end, false);
         Ok(())
     }
+
+    /// Backs up the versions visible at `ts` in the raw key range [start, end) of the regions
+    /// on this store into SST files under `dir`. A running store only backs up the regions
+    /// whose resolved ts has reached `ts`, otherwise the backup fails on the locks before
+    /// `ts`. The ranges of the regions are recorded in the manifest, so the backups taken on
+    /// several stores can be put together.
+    pub fn backup(&self, start: &[u8], end: &[u8], ts: u64, dir: &Path) -> Result<BackupManifest> {
+        if ts == 0 {
+            return Err(Error::InvalidArgument("backup ts must be positive".to_owned()));
+        }
+        if !end.is_empty() && start >= end {
+            return Err(Error::InvalidArgument(format!(
+                "invalid range [{}, {})",
+                escape(start),
+                escape(end)
+            )));
+        }
+        let manifest_path = dir.join(BACKUP_MANIFEST_FILE);
+        if file_exists(&manifest_path) {
+            return Err(Error::InvalidArgument(
+                format!("backup exists in {}", dir.display()),
+            ));
+        }
+        box_try!(fs::create_dir_all(dir));
+
+        let start_key = encode_backup_key(start);
+        let end_key = encode_backup_key(end);
+        let mut manifest = BackupManifest {
+            backup_ts: ts,
+            start_key: escape(start),
+            end_key: escape(end),
+            files: vec![],
+        };
+        for region_id in self.get_all_meta_regions()? {
+            let region = match self.get_backup_region(region_id, ts)? {
+                Some(region) => region,
+                None => continue,
+            };
+            if let Some(region) = clip_region(&region, &start_key, &end_key) {
+                manifest.files.push(self.backup_region(region, ts, dir)?);
+            }
+        }
+
+        let tmp_path = dir.join(format!("{}.tmp", BACKUP_MANIFEST_FILE));
+        let data = box_try!(serde_json::to_vec_pretty(&manifest));
+        {
+            let mut f = box_try!(fs::File::create(&tmp_path));
+            box_try!(f.write_all(&data));
+            box_try!(f.sync_all());
+        }
+        box_try!(fs::rename(&tmp_path, &manifest_path));
+        Ok(manifest)
+    }
+
+    /// Gets the region to back up at `ts`, `None` if the peer is not in normal state.
+    fn get_backup_region(&self, region_id: u64, ts: u64) -> Result<Option<Region>> {
+        let region_state_key = keys::region_state_key(region_id);
+        let mut state = match box_try!(
+            self.engines
+                .kv_engine
+                .get_msg_cf::<RegionLocalState>(CF_RAFT, &region_state_key)
+        ) {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        if state.get_state() != PeerState::Normal {
+            return Ok(None);
+        }
+        let region = state.take_region();
+        let resolved_ts = match self.resolved_ts {
+            Some(ref resolved_ts) => resolved_ts,
+            None => return Ok(Some(region)),
+        };
+        match resolved_ts.region_for_read(region_id, region.get_region_epoch(), ts) {
+            Some(region) => Ok(Some(region)),
+            None => Err(box_err!(
+                "data of region {} at {} is not complete yet",
+                region_id,
+                ts
+            )),
+        }
+    }
+
+    fn backup_region(&self, region: Region, ts: u64, dir: &Path) -> Result<BackupFile> {
+        let db = &self.engines.kv_engine;
+        let mut file = BackupFile {
+            region_id: region.get_id(),
+            region_version: region.get_region_epoch().get_version(),
+            start_key: decode_backup_key(region.get_start_key())?,
+            end_key: decode_backup_key(region.get_end_key())?,
+            ..Default::default()
+        };
+        let name = format!("{}_{}_{}.sst", file.region_id, file.region_version, ts);
+        let path = dir.join(&name);
+
+        let start_key = Key::from_encoded(region.get_start_key().to_vec());
+        let snap = RegionSnapshot::from_raw(db.clone(), region);
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            &snap,
+            &mut statistics,
+            Some(ScanMode::Forward),
+            false,
+            None,
+            IsolationLevel::SI,
+        );
+        let mut writer = None;
+        let mut key = start_key;
+        while let Some((k, v)) = box_try!(reader.seek(key, ts)) {
+            if writer.is_none() {
+                let path = path.to_str().unwrap();
+                writer = Some(box_try!(new_sst_writer(db, CF_DEFAULT, path)));
+            }
+            let raw_key = box_try!(k.raw());
+            box_try!(writer.as_mut().unwrap().put(&raw_key, &v));
+            file.kv_count += 1;
+            key = k.append_ts(0);
+        }
+
+        if let Some(mut writer) = writer {
+            box_try!(writer.finish());
+            file.size = box_try!(get_file_size(&path));
+            file.checksum = box_try!(calc_crc32(&path));
+            file.name = Some(name);
+        }
+        Ok(file)
+    }
+}
+
+fn encode_backup_key(key: &[u8]) -> Vec<u8> {
+    if key.is_empty() {
+        return vec![];
+    }
+    Key::from_raw(key).encoded().to_vec()
+}
+
+fn decode_backup_key(key: &[u8]) -> Result<String> {
+    if key.is_empty() {
+        return Ok(String::new());
+    }
+    let key = box_try!(Key::from_encoded(key.to_vec()).raw());
+    Ok(escape(&key))
+}
+
+/// Clips the range of the region to the encoded range [start, end), `None` if they don't
+/// overlap. An empty end key means unbounded.
+fn clip_region(region: &Region, start: &[u8], end: &[u8]) -> Option<Region> {
+    let (region_start, region_end) = (region.get_start_key(), region.get_end_key());
+    if (!region_end.is_empty() && start >= region_end) ||
+        (!end.is_empty() && region_start >= end)
+    {
+        return None;
+    }
+    let mut region = region.clone();
+    if start > region_start {
+        region.set_start_key(start.to_vec());
+    }
+    if !end.is_empty() && (region_end.is_empty() || end < region_end) {
+        region.set_end_key(end.to_vec());
+    }
+    Some(region)
 }
 
 pub struct MvccInfoIterator {
@@ -396,7 +649,7 @@ pub fn validate_db_and_cf(db: DBType, cf: &str) -> Result<()> {
 mod tests {
     use std::sync::Arc;
 
-    use rocksdb::{ColumnFamilyOptions, DBOptions, Writable};
+    use rocksdb::{ColumnFamilyOptions, DBOptions, IngestExternalFileOptions, Writable};
     use kvproto::metapb;
     use kvproto::eraftpb::EntryType;
     use tempdir::TempDir;
@@ -552,6 +805,46 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_resolved_ts() {
+        let mut debugger = new_debugger();
+        match debugger.resolved_ts(1) {
+            Err(Error::InvalidArgument(_)) => (),
+            _ => panic!("expect Error::InvalidArgument(_)"),
+        }
+
+        let tracker = ResolvedTsTracker::new();
+        debugger.set_resolved_ts(tracker.clone());
+        match debugger.resolved_ts(1) {
+            Err(Error::NotFound(_)) => (),
+            _ => panic!("expect Error::NotFound(_)"),
+        }
+
+        let mut region = metapb::Region::new();
+        region.set_id(1);
+        tracker.register(region, vec![]);
+        tracker.advance(1, 10);
+        assert_eq!(debugger.resolved_ts(1).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_switch_import_mode() {
+        let mut debugger = new_debugger();
+        let timeout = Duration::from_secs(10);
+        match debugger.switch_import_mode(true, timeout) {
+            Err(Error::InvalidArgument(_)) => (),
+            _ => panic!("expect Error::InvalidArgument(_)"),
+        }
+
+        let switcher = ImportModeSwitcher::new(debugger.engines.kv_engine.clone());
+        debugger.set_import_mode_switcher(switcher);
+        assert!(!debugger.is_import_mode().unwrap());
+        debugger.switch_import_mode(true, timeout).unwrap();
+        assert!(debugger.is_import_mode().unwrap());
+        debugger.switch_import_mode(false, timeout).unwrap();
+        assert!(!debugger.is_import_mode().unwrap());
+    }
+
     #[test]
     fn test_region_size() {
         let debugger = new_debugger();
@@ -606,7 +899,7 @@ mod tests {
         for &(prefix, tp, value, version) in &cf_lock_data {
             let encoded_key = Key::from_raw(prefix);
             let key = keys::data_key(encoded_key.encoded().as_slice());
-            let lock = Lock::new(tp, value.to_vec(), version, 0, None);
+            let lock = Lock::new(tp, value.to_vec(), version, 0, None, 0);
             let value = lock.to_bytes();
             engine
                 .put_cf(lock_cf, key.as_slice(), value.as_slice())
@@ -637,4 +930,130 @@ mod tests {
         }
         assert_eq!(count, 7);
     }
+    fn put_region(engine: &DB, id: u64, start: &[u8], end: &[u8], state: PeerState) {
+        let mut region = metapb::Region::new();
+        region.set_id(id);
+        if !start.is_empty() {
+            region.set_start_key(Key::from_raw(start).encoded().to_vec());
+        }
+        if !end.is_empty() {
+            region.set_end_key(Key::from_raw(end).encoded().to_vec());
+        }
+        let mut region_state = RegionLocalState::new();
+        region_state.set_region(region);
+        region_state.set_state(state);
+        let cf_raft = engine.cf_handle(CF_RAFT).unwrap();
+        engine
+            .put_msg_cf(cf_raft, &keys::region_state_key(id), &region_state)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_backup() {
+        let mut debugger = new_debugger();
+        let engine = debugger.engines.kv_engine.clone();
+        put_region(&engine, 1, b"", b"k3", PeerState::Normal);
+        put_region(&engine, 2, b"k3", b"", PeerState::Normal);
+        put_region(&engine, 3, b"k3", b"", PeerState::Tombstone);
+
+        let write_cf = engine.cf_handle(CF_WRITE).unwrap();
+        let writes: Vec<(&[u8], WriteType, Option<&[u8]>, u64, u64)> = vec![
+            (b"k1", WriteType::Put, Some(b"v1"), 5, 10),
+            (b"k2", WriteType::Put, None, 5, 10),
+            (b"k2", WriteType::Put, Some(b"v2"), 25, 30),
+            (b"k3", WriteType::Put, Some(b"v3"), 5, 10),
+            (b"k3", WriteType::Delete, None, 15, 20),
+            (b"k4", WriteType::Put, Some(b"v4"), 5, 10),
+            (b"k4", WriteType::Rollback, None, 15, 15),
+            (b"k5", WriteType::Put, Some(b"v5"), 5, 10),
+        ];
+        for (k, tp, short_value, start_ts, commit_ts) in writes {
+            let key = keys::data_key(Key::from_raw(k).append_ts(commit_ts).encoded());
+            let write = Write::new(tp, start_ts, short_value.map(|v| v.to_vec()));
+            engine.put_cf(write_cf, &key, &write.to_bytes()).unwrap();
+        }
+        let key = keys::data_key(Key::from_raw(b"k2").append_ts(5).encoded());
+        engine.put(&key, b"long v2").unwrap();
+        // The lock after the backup ts is ignored.
+        let lock_cf = engine.cf_handle(CF_LOCK).unwrap();
+        let key = keys::data_key(Key::from_raw(b"k4").encoded());
+        let lock = Lock::new(LockType::Put, b"k4".to_vec(), 25, 0, None, 0);
+        engine.put_cf(lock_cf, &key, &lock.to_bytes()).unwrap();
+
+        let tmp = TempDir::new("test_backup").unwrap();
+        let dir = tmp.path().join("backup");
+        debugger.backup(b"", b"", 0, &dir).unwrap_err();
+        debugger.backup(b"k5", b"k2", 20, &dir).unwrap_err();
+
+        let manifest = debugger.backup(b"k2", b"k5", 20, &dir).unwrap();
+        assert_eq!(manifest.backup_ts, 20);
+        assert_eq!(manifest.start_key, "k2");
+        assert_eq!(manifest.end_key, "k5");
+        assert_eq!(manifest.files.len(), 2);
+        let ranges: Vec<_> = manifest
+            .files
+            .iter()
+            .map(|f| (f.region_id, f.start_key.as_str(), f.end_key.as_str(), f.kv_count))
+            .collect();
+        assert_eq!(ranges, vec![(1, "k2", "k3", 1), (2, "k3", "k5", 1)]);
+        let f = fs::File::open(dir.join(BACKUP_MANIFEST_FILE)).unwrap();
+        let saved: BackupManifest = serde_json::from_reader(f).unwrap();
+        assert_eq!(saved, manifest);
+        // The backup can't be overwritten.
+        debugger.backup(b"k2", b"k5", 20, &dir).unwrap_err();
+
+        let restore_dir = tmp.path().join("restore");
+        let restored = rocksdb_util::new_engine(restore_dir.to_str().unwrap(), &[CF_DEFAULT])
+            .unwrap();
+        for f in &manifest.files {
+            let path = dir.join(f.name.as_ref().unwrap());
+            assert_eq!(get_file_size(&path).unwrap(), f.size);
+            assert_eq!(calc_crc32(&path).unwrap(), f.checksum);
+            let handle = restored.cf_handle(CF_DEFAULT).unwrap();
+            let opt = IngestExternalFileOptions::new();
+            restored
+                .ingest_external_file_cf(handle, &opt, &[path.to_str().unwrap()])
+                .unwrap();
+        }
+        assert_eq!(&*restored.get(b"k2").unwrap().unwrap(), b"long v2");
+        assert_eq!(&*restored.get(b"k4").unwrap().unwrap(), b"v4");
+        assert!(restored.get(b"k1").unwrap().is_none());
+        assert!(restored.get(b"k3").unwrap().is_none());
+
+        // A region without any visible key has no file.
+        let manifest = debugger
+            .backup(b"k3", b"k4", 20, &tmp.path().join("empty"))
+            .unwrap();
+        assert_eq!(manifest.files.len(), 1);
+        assert!(manifest.files[0].name.is_none());
+
+        // The lock before the backup ts fails the backup.
+        let lock = Lock::new(LockType::Put, b"k4".to_vec(), 15, 0, None, 0);
+        engine.put_cf(lock_cf, &key, &lock.to_bytes()).unwrap();
+        debugger
+            .backup(b"k2", b"k5", 20, &tmp.path().join("locked"))
+            .unwrap_err();
+        debugger
+            .backup(b"k2", b"k5", 10, &tmp.path().join("before_lock"))
+            .unwrap();
+
+        // A running store only backs up the regions resolved at the backup ts.
+        let tracker = ResolvedTsTracker::new();
+        debugger.set_resolved_ts(tracker.clone());
+        debugger
+            .backup(b"", b"k3", 20, &tmp.path().join("untracked"))
+            .unwrap_err();
+        let mut state: RegionLocalState = engine
+            .get_msg_cf(CF_RAFT, &keys::region_state_key(1))
+            .unwrap()
+            .unwrap();
+        tracker.register(state.take_region(), vec![]);
+        tracker.advance(1, 20);
+        debugger
+            .backup(b"", b"k3", 20, &tmp.path().join("resolved"))
+            .unwrap();
+        debugger
+            .backup(b"", b"k3", 21, &tmp.path().join("unresolved"))
+            .unwrap_err();
+    }
 }
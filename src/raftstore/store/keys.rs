@@ -46,6 +46,8 @@ pub const REGION_META_PREFIX: u8 = 0x03;
 pub const REGION_META_PREFIX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
 pub const REGION_META_MIN_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
 pub const REGION_META_MAX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX + 1];
+// The safe point of the GC compaction filter of the store.
+pub const GC_SAFE_POINT_KEY: &'static [u8] = &[LOCAL_PREFIX, 0x04];
 
 // Following are the suffix after the local prefix.
 // For region id
@@ -70,6 +70,12 @@ lazy_static! {
             &["type"]
         ).unwrap();
 
+    pub static ref STORE_MIN_RESOLVED_TS_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_raftstore_min_resolved_ts",
+            "The physical time (ms) of the minimal resolved ts of the regions."
+        ).unwrap();
+
     pub static ref STORE_SNAPSHOT_TRAFFIC_GAUGE_VEC: GaugeVec =
         register_gauge_vec!(
             "tikv_raftstore_snapshot_traffic_total",
@@ -28,6 +28,7 @@ mod snap;
 mod worker;
 mod metrics;
 mod local_metrics;
+mod resolved_ts;
 
 pub use self::msg::{BatchCallback, Callback, Msg, SignificantMsg, Tick};
 pub use self::store::{create_event_loop, Engines, Store, StoreChannel, StoreStat};
@@ -37,6 +38,7 @@ pub use self::peer::{Peer, PeerStat};
 pub use self::bootstrap::{bootstrap_store, clear_prepare_bootstrap, clear_prepare_bootstrap_state,
                           prepare_bootstrap, write_prepare_bootstrap};
 pub use self::engine::{Iterable, Mutable, Peekable};
+pub use self::resolved_ts::{ResolvedTsTracker, TxnChange};
 pub use self::peer_storage::{do_snapshot, CacheQueryStats, PeerStorage, SnapState,
                              RAFT_INIT_LOG_INDEX, RAFT_INIT_LOG_TERM};
 pub use self::snap::{check_abort, copy_snapshot, ApplyOptions, SnapEntry, SnapKey, SnapManager,
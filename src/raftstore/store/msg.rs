@@ -35,6 +35,7 @@ pub enum Tick {
     SnapGc,
     CompactLockCf,
     ConsistencyCheck,
+    ResolvedTs,
 }
 
 #[derive(Debug, PartialEq)]
@@ -57,6 +58,8 @@ pub enum Msg {
         send_time: Instant,
         request: RaftCmdRequest,
         callback: Callback,
+        // The ts of the read if a follower may serve it by the read index from the leader.
+        follower_read: Option<u64>,
     },
 
     BatchRaftSnapCmds {
@@ -86,6 +89,10 @@ pub enum Msg {
 
     // For region size
     ApproximateRegionSize { region_id: u64, region_size: u64 },
+
+    // For resolved ts
+    // (region id, term) of the leaders the ts is allocated for
+    AdvanceResolvedTs { regions: Vec<(u64, u64)>, ts: u64 },
 }
 
 impl fmt::Debug for Msg {
@@ -121,6 +128,12 @@ impl fmt::Debug for Msg {
                 region_id,
                 region_size
             ),
+            Msg::AdvanceResolvedTs { ref regions, ts } => write!(
+                fmt,
+                "Advance resolved ts [regions: {:?}, ts: {}]",
+                regions,
+                ts
+            ),
         }
     }
 }
@@ -131,6 +144,20 @@ impl Msg {
             send_time: Instant::now(),
             request: request,
             callback: callback,
+            follower_read: None,
+        }
+    }
+
+    pub fn new_follower_read_cmd(
+        request: RaftCmdRequest,
+        read_ts: u64,
+        callback: Callback,
+    ) -> Msg {
+        Msg::RaftCmd {
+            send_time: Instant::now(),
+            request: request,
+            callback: callback,
+            follower_read: Some(read_ts),
         }
     }
 
@@ -15,7 +15,7 @@ use std::sync::Arc;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::{cmp, mem, slice};
+use std::{cmp, mem};
 use std::time::{Duration, Instant};
 
 use time::Timespec;
@@ -45,6 +45,7 @@ use util::collections::{FlatMap, FlatMapValues as Values, HashSet};
 use pd::{PdTask, INVALID_ID};
 
 use super::store::{Store, StoreStat};
+use super::resolved_ts::ResolvedTsTracker;
 use super::peer_storage::{write_peer_state, ApplySnapResult, InvokeContext, PeerStorage};
 use super::util;
 use super::msg::Callback;
@@ -59,17 +60,32 @@ const DEFAULT_APPEND_WB_SIZE: usize = 4 * 1024;
 
 struct ReadIndexRequest {
     id: u64,
+    // The ts of a follower read, which the leader records as a read served in the region.
+    read_ts: u64,
     cmds: Vec<(RaftCmdRequest, Callback)>,
     renew_lease_time: Timespec,
+    // The index returned by raft, `None` before the read is ready.
+    read_index: Option<u64>,
 }
 
-impl ReadIndexRequest {
-    fn binary_id(&self) -> &[u8] {
-        unsafe {
-            let id = &self.id as *const u64 as *const u8;
-            slice::from_raw_parts(id, 8)
-        }
+// The context of a read index is made of the ids of the read and the peer, so that it's unique
+// among the reads forwarded to the leader by the followers, which are dropped if duplicated.
+// The ts of the read follows, so that the leader knows the reads served by the followers.
+fn read_index_ctx(id: u64, peer_id: u64, read_ts: u64) -> Vec<u8> {
+    let fields: [u64; 3] = [id, peer_id, read_ts];
+    let ctx: [u8; 24] = unsafe { mem::transmute(fields) };
+    ctx.to_vec()
+}
+
+// Returns the read ts in the context of a read index.
+fn read_ts_of_read_index_ctx(ctx: &[u8]) -> Option<u64> {
+    if ctx.len() != 24 {
+        return None;
     }
+    let mut fields = [0u8; 24];
+    fields.copy_from_slice(ctx);
+    let fields: [u64; 3] = unsafe { mem::transmute(fields) };
+    Some(fields[2])
 }
 
 impl Drop for ReadIndexRequest {
@@ -93,6 +109,19 @@ impl ReadIndexQueue {
         self.id_allocator
     }
 
+    // Answers the reads which are not ready and asked no later than `expired_time` with
+    // StaleCommand. The reads are asked in order, so the expired ones are at the front.
+    fn clear_expired(&mut self, expired_time: Timespec, term: u64) {
+        while self.reads.len() > self.ready_cnt &&
+            self.reads[self.ready_cnt].renew_lease_time <= expired_time
+        {
+            let mut read = self.reads.remove(self.ready_cnt).unwrap();
+            for (_, cb) in read.cmds.drain(..) {
+                apply::notify_stale_req(term, cb);
+            }
+        }
+    }
+
     fn clear_uncommitted(&mut self, term: u64) {
         for mut read in self.reads.drain(self.ready_cnt..) {
             for (_, cb) in read.cmds.drain(..) {
@@ -209,6 +238,7 @@ pub struct Peer {
     // Record the last instant of each peer's heartbeat response.
     pub peer_heartbeats: FlatMap<u64, Instant>,
     coprocessor_host: Arc<CoprocessorHost>,
+    resolved_ts: ResolvedTsTracker,
     /// an inaccurate difference in region size since last reset.
     pub size_diff_hint: u64,
     /// delete keys' count since last reset.
@@ -345,6 +375,7 @@ impl Peer {
             peer_cache: RefCell::new(peer_cache),
             peer_heartbeats: FlatMap::default(),
             coprocessor_host: store.coprocessor_host.clone(),
+            resolved_ts: store.resolved_ts.clone(),
             size_diff_hint: 0,
             delete_keys_hint: 0,
             approximate_size: None,
@@ -535,6 +566,14 @@ impl Peer {
         if self.is_leader() && m.get_from() != INVALID_ID {
             self.peer_heartbeats.insert(m.get_from(), Instant::now());
         }
+        if self.is_leader() && m.get_msg_type() == MessageType::MsgReadIndex {
+            // The read is served by the follower at the read index, so one-phase commits must
+            // commit after it, even if the leader drops the request.
+            let ctx = m.get_entries().first().map(|e| e.get_data());
+            if let Some(read_ts) = ctx.and_then(read_ts_of_read_index_ctx) {
+                self.resolved_ts.update_max_read_ts(self.region_id, read_ts);
+            }
+        }
         self.raft_group.step(m)?;
         Ok(())
     }
@@ -650,6 +689,8 @@ impl Peer {
                         self.tag,
                         next_expired_time
                     );
+                    // The reads served by the previous leader are unknown to this peer.
+                    self.resolved_ts.invalidate_max_read_ts(self.region_id);
                     self.heartbeat_pd(worker)
                 }
                 StateRole::Follower => {
@@ -678,6 +719,28 @@ impl Peer {
         self.get_store().applied_index_term == self.term()
     }
 
+    /// Whether the ready read at `read_index` can be served. A follower has to apply the log
+    /// up to the read index, which is the commit index of the leader when it's asked.
+    fn ready_to_handle_read_index(&self, read_index: u64) -> bool {
+        if self.is_leader() {
+            self.ready_to_handle_read()
+        } else {
+            !self.is_applying_snapshot() && self.get_store().applied_index() >= read_index
+        }
+    }
+
+    /// Whether the peer is a leader holding the lease, which has applied all the writes
+    /// acknowledged by the previous leaders.
+    pub fn is_applied_leader_in_lease(&self) -> bool {
+        if !self.is_leader() || !self.ready_to_handle_read() || !self.raft_group.raft.in_lease() {
+            return false;
+        }
+        match self.leader_lease_expired_time {
+            Some(Either::Left(safe_expired_time)) => monotonic_raw_now() <= safe_expired_time,
+            _ => false,
+        }
+    }
+
     pub fn take_apply_proposals(&mut self) -> Option<RegionProposal> {
         if self.apply_proposals.is_empty() {
             return None;
@@ -832,26 +895,40 @@ impl Peer {
 
     fn apply_reads(&mut self, ready: &Ready) {
         let mut propose_time = None;
-        if self.ready_to_handle_read() {
-            for state in &ready.read_states {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                for (req, cb) in read.cmds.drain(..) {
-                    // TODO: we should add test case that a split happens before pending
-                    // read-index is handled. To do this we need to control async-apply
-                    // procedure precisely.
-                    cb(self.handle_read(req));
+        let peer_id = self.peer_id();
+        let is_leader = self.is_leader();
+        for state in &ready.read_states {
+            // The leader may drop the reads forwarded by a follower, so the read states of a
+            // follower can skip some reads, which are kept until they expire.
+            let pos = {
+                let reads = &self.pending_reads.reads;
+                (self.pending_reads.ready_cnt..reads.len())
+                    .find(|&i| {
+                        state.request_ctx == read_index_ctx(reads[i].id, peer_id, reads[i].read_ts)
+                    })
+            };
+            let pos = match pos {
+                Some(pos) => pos,
+                None => {
+                    assert!(!is_leader, "{} read state {:?} is missing", self.tag, state);
+                    // The read has expired and been answered already.
+                    continue;
                 }
+            };
+            assert!(!is_leader || pos == self.pending_reads.ready_cnt);
+            let mut read = self.pending_reads.reads.remove(pos).unwrap();
+            read.read_index = Some(state.index);
+            if is_leader {
                 propose_time = Some(read.renew_lease_time);
             }
-        } else {
-            for state in &ready.read_states {
-                let read = &self.pending_reads.reads[self.pending_reads.ready_cnt];
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                self.pending_reads.ready_cnt += 1;
-                propose_time = Some(read.renew_lease_time);
-            }
+            let ready_cnt = self.pending_reads.ready_cnt;
+            self.pending_reads.reads.insert(ready_cnt, read);
+            self.pending_reads.ready_cnt += 1;
         }
+        // TODO: we should add test case that a split happens before pending
+        // read-index is handled. To do this we need to control async-apply
+        // procedure precisely.
+        self.handle_ready_reads();
 
         // Note that only after handle read_states can we identify what requests are
         // actually stale.
@@ -907,14 +984,21 @@ impl Peer {
             self.mark_to_be_checked(groups);
         }
 
-        if self.pending_reads.ready_cnt > 0 && self.ready_to_handle_read() {
-            for _ in 0..self.pending_reads.ready_cnt {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                for (req, cb) in read.cmds.drain(..) {
-                    cb(self.handle_read(req));
-                }
+        self.handle_ready_reads();
+    }
+
+    /// Serves the ready reads in order until one of them can't be served yet.
+    fn handle_ready_reads(&mut self) {
+        while self.pending_reads.ready_cnt > 0 {
+            let read_index = self.pending_reads.reads[0].read_index.unwrap();
+            if !self.ready_to_handle_read_index(read_index) {
+                return;
+            }
+            let mut read = self.pending_reads.reads.pop_front().unwrap();
+            self.pending_reads.ready_cnt -= 1;
+            for (req, cb) in read.cmds.drain(..) {
+                cb(self.handle_read(req));
             }
-            self.pending_reads.ready_cnt = 0;
         }
     }
 
@@ -1000,7 +1084,7 @@ impl Peer {
         None
     }
 
-    /// Propose a request.
+    /// Propose a request. `read_ts` is the ts of a follower read, or 0.
     ///
     /// Return true means the request has been proposed successfully.
     pub fn propose(
@@ -1008,6 +1092,7 @@ impl Peer {
         cb: Callback,
         req: RaftCmdRequest,
         mut err_resp: RaftCmdResponse,
+        read_ts: u64,
         metrics: &mut RaftProposeMetrics,
     ) -> bool {
         if self.pending_remove {
@@ -1023,7 +1108,7 @@ impl Peer {
                 self.read_local(req, cb, metrics);
                 return false;
             }
-            Ok(RequestPolicy::ReadIndex) => return self.read_index(req, cb, metrics),
+            Ok(RequestPolicy::ReadIndex) => return self.read_index(req, cb, read_ts, metrics),
             Ok(RequestPolicy::ProposeNormal) => self.propose_normal(req, metrics),
             Ok(RequestPolicy::ProposeTransferLeader) => {
                 return self.propose_transfer_leader(req, cb, metrics)
@@ -1290,11 +1375,15 @@ impl Peer {
         &mut self,
         req: RaftCmdRequest,
         cb: Callback,
+        read_ts: u64,
         metrics: &mut RaftProposeMetrics,
     ) -> bool {
         metrics.read_index += 1;
 
         let renew_lease_time = monotonic_raw_now();
+        if !self.is_leader() {
+            return self.follower_read_index(req, cb, read_ts, renew_lease_time);
+        }
         if let Some(read) = self.pending_reads.reads.back_mut() {
             if read.renew_lease_time + self.cfg.raft_store_max_leader_lease() > renew_lease_time {
                 read.cmds.push((req, cb));
@@ -1306,9 +1395,10 @@ impl Peer {
         let last_pending_read_count = self.raft_group.raft.pending_read_count();
         let last_ready_read_count = self.raft_group.raft.ready_read_count();
 
+        // The reads of the leader are recorded by the storage, the read ts is not needed.
         let id = self.pending_reads.next_id();
-        let ctx: [u8; 8] = unsafe { mem::transmute(id) };
-        self.raft_group.read_index(ctx.to_vec());
+        let ctx = read_index_ctx(id, self.peer_id(), 0);
+        self.raft_group.read_index(ctx);
 
         let pending_read_count = self.raft_group.raft.pending_read_count();
         let ready_read_count = self.raft_group.raft.ready_read_count();
@@ -1323,8 +1413,10 @@ impl Peer {
 
         self.pending_reads.reads.push_back(ReadIndexRequest {
             id: id,
+            read_ts: 0,
             cmds: vec![(req, cb)],
             renew_lease_time: renew_lease_time,
+            read_index: None,
         });
 
         match self.leader_lease_expired_time {
@@ -1348,6 +1440,50 @@ impl Peer {
         true
     }
 
+    /// Asks the leader for the read index of a follower read. Unlike the leader, a follower
+    /// can't piggyback a read on a pending one, which may get a read index older than the read.
+    fn follower_read_index(
+        &mut self,
+        req: RaftCmdRequest,
+        cb: Callback,
+        read_ts: u64,
+        renew_lease_time: Timespec,
+    ) -> bool {
+        let leader_id = self.leader_id();
+        if leader_id == INVALID_ID {
+            let mut resp = cmd_resp::new_error(Error::NotLeader(self.region_id, None));
+            cmd_resp::bind_term(&mut resp, self.term());
+            cb(resp);
+            return false;
+        }
+
+        // The request is forwarded to the leader, and the read states are ready when the
+        // leader responds. A request dropped by the leader is cleared when the leader changes
+        // or when it expires.
+        let id = self.pending_reads.next_id();
+        let ctx = read_index_ctx(id, self.peer_id(), read_ts);
+        self.raft_group.read_index(ctx);
+        self.pending_reads.reads.push_back(ReadIndexRequest {
+            id: id,
+            read_ts: read_ts,
+            cmds: vec![(req, cb)],
+            renew_lease_time: renew_lease_time,
+            read_index: None,
+        });
+        true
+    }
+
+    /// Answers the follower reads which get no read index from the leader in the max leader
+    /// lease with StaleCommand, because the leader may drop the forwarded requests.
+    pub fn clear_expired_follower_reads(&mut self) {
+        if self.is_leader() || self.pending_reads.reads.len() == self.pending_reads.ready_cnt {
+            return;
+        }
+        let expired_time = monotonic_raw_now() - self.cfg.raft_store_max_leader_lease();
+        let term = self.term();
+        self.pending_reads.clear_expired(expired_time, term);
+    }
+
     fn propose_normal(
         &mut self,
         mut req: RaftCmdRequest,
@@ -1482,6 +1618,18 @@ impl Peer {
     }
 }
 
+/// Whether the request only reads, so that a follower can serve it by the read index from
+/// the leader when it's sent as a follower read.
+pub fn is_read_request(req: &RaftCmdRequest) -> bool {
+    if req.has_admin_request() || req.has_status_request() || req.get_requests().is_empty() {
+        return false;
+    }
+    req.get_requests().iter().all(|r| match r.get_cmd_type() {
+        CmdType::Get | CmdType::Snap => true,
+        _ => false,
+    })
+}
+
 pub fn check_epoch(region: &metapb::Region, req: &RaftCmdRequest) -> Result<()> {
     let (mut check_ver, mut check_conf_ver) = (false, false);
     if req.has_admin_request() {
@@ -0,0 +1,422 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking of the timestamps below which the data of a region is complete on a replica.
+//!
+//! A transaction gets its commit ts after all of its prewrites have been applied, so once a
+//! replica has applied a commit at `commit_ts`, every transaction committed no later than it
+//! has its locks in the replica already. Reading at a ts not greater than the maximum applied
+//! commit ts therefore either sees the committed versions or runs into the locks, as it would
+//! on the leader. The ts is further bounded by the outstanding locks so that such reads don't
+//! block on them.
+//!
+//! Without new commits the ts would stay still, so the leader advances it with timestamps
+//! from pd as well: a transaction can only get a commit ts not greater than a timestamp
+//! allocated before that if all of its prewrites have been acknowledged, thus applied on a
+//! leader which has applied an entry of its own term.
+//!
+//! One-phase commits write no locks, so they are not covered by the resolved ts. Instead, the
+//! tracker keeps the max ts of the reads served in a region, including the coprocessor reads,
+//! and a one-phase commit must commit after it. The reads served by a previous leader are
+//! unknown to a new one, so the max read ts becomes unknown on leader changes until it is
+//! synced with a timestamp from pd allocated after the peer becomes the leader.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use rocksdb::DB;
+use kvproto::metapb::{Region, RegionEpoch};
+
+use raftstore::Result;
+use raftstore::store::keys;
+use raftstore::store::engine::Iterable;
+use storage::CF_LOCK;
+use storage::mvcc::Lock;
+use util::HandyRwLock;
+use util::collections::HashMap;
+
+/// A change of the transactions in a region made by an applied write.
+#[derive(Debug, PartialEq)]
+pub enum TxnChange {
+    /// A lock of the transaction started at `ts` is written on the encoded `key`.
+    Lock { key: Vec<u8>, ts: u64 },
+    /// The lock on the encoded `key` is removed.
+    Unlock { key: Vec<u8> },
+    /// A transaction is committed at `commit_ts`.
+    Commit { commit_ts: u64 },
+}
+
+#[derive(Debug)]
+struct Resolver {
+    region: Region,
+    // encoded key -> start ts of the lock
+    locks: HashMap<Vec<u8>, u64>,
+    // start ts -> count of the locks
+    lock_ts: BTreeMap<u64, usize>,
+    // The maximum applied commit ts or advanced ts.
+    max_ts: u64,
+    // The max ts of the reads served, valid only if it's synced.
+    max_read_ts: u64,
+    max_read_ts_synced: bool,
+}
+
+impl Resolver {
+    fn new(region: Region, max_ts: u64) -> Resolver {
+        Resolver {
+            region: region,
+            locks: HashMap::default(),
+            lock_ts: BTreeMap::new(),
+            max_ts: max_ts,
+            max_read_ts: 0,
+            max_read_ts_synced: false,
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        let end_key = self.region.get_end_key();
+        key >= self.region.get_start_key() && (end_key.is_empty() || key < end_key)
+    }
+
+    fn track_lock(&mut self, key: Vec<u8>, ts: u64) {
+        if !self.contains(&key) {
+            return;
+        }
+        if let Some(old_ts) = self.locks.insert(key, ts) {
+            self.untrack_ts(old_ts);
+        }
+        *self.lock_ts.entry(ts).or_insert(0) += 1;
+    }
+
+    fn untrack_lock(&mut self, key: &[u8]) {
+        if let Some(ts) = self.locks.remove(key) {
+            self.untrack_ts(ts);
+        }
+    }
+
+    fn untrack_ts(&mut self, ts: u64) {
+        let remove = match self.lock_ts.get_mut(&ts) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if remove {
+            self.lock_ts.remove(&ts);
+        }
+    }
+
+    fn apply(&mut self, change: TxnChange) {
+        match change {
+            TxnChange::Lock { key, ts } => self.track_lock(key, ts),
+            TxnChange::Unlock { key } => self.untrack_lock(&key),
+            TxnChange::Commit { commit_ts } => self.advance(commit_ts),
+        }
+    }
+
+    fn advance(&mut self, ts: u64) {
+        self.max_ts = cmp::max(self.max_ts, ts);
+    }
+
+    fn set_region(&mut self, region: Region) {
+        self.region = region;
+        let out_of_range: Vec<_> = self.locks
+            .keys()
+            .filter(|k| !self.contains(k))
+            .cloned()
+            .collect();
+        for key in out_of_range {
+            self.untrack_lock(&key);
+        }
+    }
+
+    fn resolved_ts(&self) -> u64 {
+        match self.lock_ts.keys().next() {
+            Some(&min_lock_ts) => cmp::min(self.max_ts, min_lock_ts.saturating_sub(1)),
+            None => self.max_ts,
+        }
+    }
+}
+
+/// `ResolvedTsTracker` keeps the resolved ts of the regions on a store, which is shared between
+/// the apply worker feeding it and the readers.
+#[derive(Clone, Default)]
+pub struct ResolvedTsTracker {
+    regions: Arc<RwLock<HashMap<u64, Resolver>>>,
+}
+
+impl ResolvedTsTracker {
+    pub fn new() -> ResolvedTsTracker {
+        ResolvedTsTracker::default()
+    }
+
+    /// Starts tracking `region` with its outstanding `locks`. The maximum ts seen by a previous
+    /// registration is kept, as it stays valid for newer data of the region, and so is the max
+    /// read ts.
+    pub fn register(&self, region: Region, locks: Vec<(Vec<u8>, u64)>) {
+        let mut regions = self.regions.wl();
+        let (max_ts, max_read_ts, max_read_ts_synced) = regions
+            .get(&region.get_id())
+            .map_or((0, 0, false), |r| {
+                (r.max_ts, r.max_read_ts, r.max_read_ts_synced)
+            });
+        let mut resolver = Resolver::new(region, max_ts);
+        resolver.max_read_ts = max_read_ts;
+        resolver.max_read_ts_synced = max_read_ts_synced;
+        for (key, ts) in locks {
+            resolver.track_lock(key, ts);
+        }
+        regions.insert(resolver.region.get_id(), resolver);
+    }
+
+    pub fn deregister(&self, region_id: u64) {
+        self.regions.wl().remove(&region_id);
+    }
+
+    /// Updates the range and epoch of a tracked region, the locks out of the new range are
+    /// dropped.
+    pub fn update_region(&self, region: &Region) {
+        if let Some(resolver) = self.regions.wl().get_mut(&region.get_id()) {
+            resolver.set_region(region.clone());
+        }
+    }
+
+    /// Applies the changes of transactions, which must have been written to the engine.
+    pub fn apply(&self, region_id: u64, changes: Vec<TxnChange>) {
+        if let Some(resolver) = self.regions.wl().get_mut(&region_id) {
+            for change in changes {
+                resolver.apply(change);
+            }
+        }
+    }
+
+    /// Advances the region with `ts`, which must be allocated after all the transactions
+    /// acknowledged so far have their prewrites applied in the region.
+    pub fn advance(&self, region_id: u64, ts: u64) {
+        if let Some(resolver) = self.regions.wl().get_mut(&region_id) {
+            resolver.advance(ts);
+        }
+    }
+
+    /// Records a read served in the region at `ts`.
+    pub fn update_max_read_ts(&self, region_id: u64, ts: u64) {
+        if let Some(resolver) = self.regions.wl().get_mut(&region_id) {
+            resolver.max_read_ts = cmp::max(resolver.max_read_ts, ts);
+        }
+    }
+
+    /// Returns the max ts of the reads served in the region, or `None` if it's unknown.
+    pub fn max_read_ts(&self, region_id: u64) -> Option<u64> {
+        match self.regions.rl().get(&region_id) {
+            Some(r) if r.max_read_ts_synced => Some(r.max_read_ts),
+            _ => None,
+        }
+    }
+
+    /// Marks the max read ts of the region unknown, as the peer becomes the leader and the
+    /// reads served by the previous leader are not recorded.
+    pub fn invalidate_max_read_ts(&self, region_id: u64) {
+        if let Some(resolver) = self.regions.wl().get_mut(&region_id) {
+            resolver.max_read_ts_synced = false;
+        }
+    }
+
+    /// Syncs the max read ts of the region with `ts`, which must be allocated after the peer
+    /// becomes the leader.
+    pub fn sync_max_read_ts(&self, region_id: u64, ts: u64) {
+        if let Some(resolver) = self.regions.wl().get_mut(&region_id) {
+            resolver.max_read_ts = cmp::max(resolver.max_read_ts, ts);
+            resolver.max_read_ts_synced = true;
+        }
+    }
+
+    pub fn resolved_ts(&self, region_id: u64) -> Option<u64> {
+        self.regions.rl().get(&region_id).map(|r| r.resolved_ts())
+    }
+
+    /// Returns the minimal resolved ts of all the tracked regions.
+    pub fn min_resolved_ts(&self) -> Option<u64> {
+        self.regions.rl().values().map(|r| r.resolved_ts()).min()
+    }
+
+    /// Returns the region if the data at `read_ts` is complete in it and its version is still
+    /// the one of `epoch`.
+    pub fn region_for_read(
+        &self,
+        region_id: u64,
+        epoch: &RegionEpoch,
+        read_ts: u64,
+    ) -> Option<Region> {
+        let regions = self.regions.rl();
+        match regions.get(&region_id) {
+            Some(r)
+                if r.region.get_region_epoch().get_version() == epoch.get_version() &&
+                    read_ts <= r.resolved_ts() =>
+            {
+                Some(r.region.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Scans the outstanding locks of `region`.
+pub fn scan_locks(db: &DB, region: &Region) -> Result<Vec<(Vec<u8>, u64)>> {
+    let start_key = keys::enc_start_key(region);
+    let end_key = keys::enc_end_key(region);
+    let mut locks = vec![];
+    db.scan_cf(CF_LOCK, &start_key, &end_key, false, &mut |key, value| {
+        let lock = box_try!(Lock::parse(value));
+        locks.push((keys::origin_key(key).to_vec(), lock.ts));
+        Ok(true)
+    })?;
+    Ok(locks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_region(id: u64, start_key: &[u8], end_key: &[u8], version: u64) -> Region {
+        let mut region = Region::new();
+        region.set_id(id);
+        region.set_start_key(start_key.to_vec());
+        region.set_end_key(end_key.to_vec());
+        region.mut_region_epoch().set_version(version);
+        region
+    }
+
+    fn lock(key: &[u8], ts: u64) -> TxnChange {
+        TxnChange::Lock {
+            key: key.to_vec(),
+            ts: ts,
+        }
+    }
+
+    fn unlock(key: &[u8]) -> TxnChange {
+        TxnChange::Unlock { key: key.to_vec() }
+    }
+
+    fn commit(commit_ts: u64) -> TxnChange {
+        TxnChange::Commit {
+            commit_ts: commit_ts,
+        }
+    }
+
+    #[test]
+    fn test_resolved_ts() {
+        let tracker = ResolvedTsTracker::new();
+        assert_eq!(tracker.resolved_ts(1), None);
+        tracker.register(new_region(1, b"", b"", 1), vec![]);
+        assert_eq!(tracker.resolved_ts(1), Some(0));
+
+        tracker.apply(1, vec![lock(b"a", 10), lock(b"b", 10), commit(5)]);
+        assert_eq!(tracker.resolved_ts(1), Some(5));
+        tracker.apply(1, vec![commit(20)]);
+        assert_eq!(tracker.resolved_ts(1), Some(9));
+        tracker.apply(1, vec![unlock(b"a"), lock(b"c", 15)]);
+        assert_eq!(tracker.resolved_ts(1), Some(9));
+        tracker.apply(1, vec![unlock(b"b")]);
+        assert_eq!(tracker.resolved_ts(1), Some(14));
+        // A lock overwritten by a newer transaction.
+        tracker.apply(1, vec![lock(b"c", 18)]);
+        assert_eq!(tracker.resolved_ts(1), Some(17));
+        tracker.apply(1, vec![unlock(b"c"), unlock(b"d")]);
+        assert_eq!(tracker.resolved_ts(1), Some(20));
+
+        // Changes of unknown regions are ignored.
+        tracker.apply(2, vec![commit(30)]);
+        assert_eq!(tracker.resolved_ts(2), None);
+
+        // Registering again resets the locks only.
+        tracker.register(new_region(1, b"", b"", 1), vec![(b"a".to_vec(), 12)]);
+        assert_eq!(tracker.resolved_ts(1), Some(11));
+        tracker.deregister(1);
+        assert_eq!(tracker.resolved_ts(1), None);
+    }
+
+    #[test]
+    fn test_update_region() {
+        let tracker = ResolvedTsTracker::new();
+        tracker.register(new_region(1, b"", b"", 1), vec![]);
+        tracker.apply(1, vec![lock(b"a", 10), lock(b"m", 5), commit(20)]);
+        assert_eq!(tracker.resolved_ts(1), Some(4));
+
+        tracker.update_region(&new_region(1, b"", b"k", 2));
+        assert_eq!(tracker.resolved_ts(1), Some(9));
+        // The keys out of the region are ignored.
+        tracker.apply(1, vec![lock(b"x", 3)]);
+        assert_eq!(tracker.resolved_ts(1), Some(9));
+    }
+
+    #[test]
+    fn test_advance() {
+        let tracker = ResolvedTsTracker::new();
+        assert_eq!(tracker.min_resolved_ts(), None);
+        tracker.register(new_region(1, b"", b"k", 1), vec![(b"a".to_vec(), 10)]);
+        tracker.register(new_region(2, b"k", b"", 1), vec![]);
+        tracker.advance(1, 30);
+        tracker.advance(2, 30);
+        assert_eq!(tracker.resolved_ts(1), Some(9));
+        assert_eq!(tracker.resolved_ts(2), Some(30));
+        assert_eq!(tracker.min_resolved_ts(), Some(9));
+
+        // The ts never goes backward.
+        tracker.advance(2, 20);
+        tracker.apply(2, vec![commit(25)]);
+        assert_eq!(tracker.resolved_ts(2), Some(30));
+        tracker.apply(1, vec![unlock(b"a")]);
+        assert_eq!(tracker.min_resolved_ts(), Some(30));
+    }
+
+    #[test]
+    fn test_max_read_ts() {
+        let tracker = ResolvedTsTracker::new();
+        tracker.register(new_region(1, b"", b"", 1), vec![]);
+        tracker.update_max_read_ts(1, 10);
+        assert_eq!(tracker.max_read_ts(1), None);
+        tracker.sync_max_read_ts(1, 5);
+        assert_eq!(tracker.max_read_ts(1), Some(10));
+        tracker.update_max_read_ts(1, 20);
+        tracker.update_max_read_ts(1, 15);
+        assert_eq!(tracker.max_read_ts(1), Some(20));
+
+        // It's kept by the registrations, but lost on leader changes.
+        tracker.register(new_region(1, b"", b"", 2), vec![]);
+        assert_eq!(tracker.max_read_ts(1), Some(20));
+        tracker.invalidate_max_read_ts(1);
+        tracker.update_max_read_ts(1, 25);
+        assert_eq!(tracker.max_read_ts(1), None);
+        tracker.sync_max_read_ts(1, 30);
+        assert_eq!(tracker.max_read_ts(1), Some(30));
+
+        tracker.update_max_read_ts(2, 10);
+        assert_eq!(tracker.max_read_ts(2), None);
+    }
+
+    #[test]
+    fn test_region_for_read() {
+        let tracker = ResolvedTsTracker::new();
+        let region = new_region(1, b"", b"", 2);
+        tracker.register(region.clone(), vec![]);
+        tracker.apply(1, vec![commit(10)]);
+
+        let epoch = region.get_region_epoch();
+        assert_eq!(tracker.region_for_read(1, epoch, 10), Some(region.clone()));
+        assert_eq!(tracker.region_for_read(1, epoch, 11), None);
+        assert_eq!(tracker.region_for_read(2, epoch, 10), None);
+        let stale_epoch = new_region(1, b"", b"", 1).take_region_epoch();
+        assert_eq!(tracker.region_for_read(1, &stale_epoch, 10), None);
+    }
+}
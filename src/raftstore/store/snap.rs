@@ -221,34 +221,13 @@ use std::time::Instant;
 use crc::crc32::{self, Digest, Hasher32};
 use protobuf::RepeatedField;
 use kvproto::raft_serverpb::{SnapshotCFFile, SnapshotMeta};
-use rocksdb::{DBCompressionType, EnvOptions, IngestExternalFileOptions, SstFileWriter};
+use rocksdb::{IngestExternalFileOptions, SstFileWriter};
 use util::rocksdb;
 use util::time::duration_to_sec;
-use util::file::{delete_file_if_exist, file_exists, get_file_size};
-use util::rocksdb::get_fastest_supported_compression_type;
+use util::file::{calc_crc32, delete_file_if_exist, file_exists, get_file_size};
 
 pub const SNAPSHOT_VERSION: u64 = 2;
 const META_FILE_SUFFIX: &'static str = ".meta";
-const DIGEST_BUFFER_SIZE: usize = 10240;
-
-
-fn calc_crc32(p: &PathBuf) -> io::Result<u32> {
-    let mut digest = Digest::new(crc32::IEEE);
-    let mut f = OpenOptions::new().read(true).open(&p)?;
-    let mut buf = vec![0; DIGEST_BUFFER_SIZE];
-    loop {
-        match f.read(&mut buf[..]) {
-            Ok(0) => {
-                return Ok(digest.sum32());
-            }
-            Ok(n) => {
-                digest.write(&buf[..n]);
-            }
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
-            Err(err) => return Err(err),
-        }
-    }
-}
 
 fn gen_snapshot_meta(cf_files: &[CfFile]) -> RaftStoreResult<SnapshotMeta> {
     let mut meta = Vec::with_capacity(cf_files.len());
@@ -503,16 +482,8 @@ impl Snap {
                     .open(&cf_file.tmp_path)?;
                 cf_file.file = Some(f);
             } else {
-                let handle = snap.cf_handle(cf_file.cf)?;
-                let mut io_options = snap.get_db().get_options_cf(handle).clone();
-                io_options.compression(get_fastest_supported_compression_type());
-                // in rocksdb 5.5.1, SstFileWriter will try to use bottommost_compression and
-                // compression_per_level first, so to make sure our specified compression type
-                // being used, we must set them empty or disabled.
-                io_options.compression_per_level(&[]);
-                io_options.bottommost_compression(DBCompressionType::Disable);
-                let mut writer = SstFileWriter::new(EnvOptions::new(), io_options);
-                box_try!(writer.open(cf_file.tmp_path.as_path().to_str().unwrap()));
+                let path = cf_file.tmp_path.as_path().to_str().unwrap();
+                let writer = box_try!(rocksdb::new_sst_writer(&snap.get_db(), cf_file.cf, path));
                 cf_file.sst_writer = Some(writer);
             }
         }
@@ -33,7 +33,7 @@ use kvproto::eraftpb::{ConfChangeType, MessageType};
 use kvproto::pdpb::StoreStats;
 use util::{escape, rocksdb};
 use util::time::{duration_to_sec, SlowTimer};
-use pd::{PdClient, PdRunner, PdTask};
+use pd::{PdClient, PdRunner, PdTask, TSO_LOGICAL_BITS};
 use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, RaftCmdRequest, RaftCmdResponse,
                           StatusCmdType, StatusResponse};
 use protobuf::Message;
@@ -47,6 +47,7 @@ use util::collections::{HashMap, HashSet};
 use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::coprocessor::split_observer::SplitObserver;
+use cdc::{CdcHub, CdcObserver, CdcRunner, CdcTask};
 use super::worker::{ApplyRunner, ApplyTask, ApplyTaskRes, CompactRunner, CompactTask,
                     ConsistencyCheckRunner, ConsistencyCheckTask, RaftlogGcRunner, RaftlogGcTask,
                     RegionRunner, RegionTask, SplitCheckRunner, SplitCheckTask};
@@ -62,6 +63,7 @@ use super::cmd_resp::{bind_term, new_error};
 use super::transport::Transport;
 use super::metrics::*;
 use super::local_metrics::RaftMetrics;
+use super::resolved_ts::ResolvedTsTracker;
 
 type Key = Vec<u8>;
 
@@ -141,11 +143,14 @@ pub struct Store<T, C: 'static> {
     consistency_check_worker: Worker<ConsistencyCheckTask>,
     pub apply_worker: Worker<ApplyTask>,
     apply_res_receiver: Option<StdReceiver<ApplyTaskRes>>,
+    cdc_worker: Worker<CdcTask>,
 
     trans: T,
     pd_client: Arc<C>,
 
     pub coprocessor_host: Arc<CoprocessorHost>,
+    pub resolved_ts: ResolvedTsTracker,
+    cdc: CdcHub,
 
     snap_mgr: SnapManager,
 
@@ -187,6 +192,8 @@ impl<T, C> Store<T, C> {
         pd_client: Arc<C>,
         mgr: SnapManager,
         pd_worker: FutureWorker<PdTask>,
+        resolved_ts: ResolvedTsTracker,
+        cdc: CdcHub,
     ) -> Result<Store<T, C>> {
         // TODO: we can get cluster meta regularly too later.
         cfg.validate()?;
@@ -194,11 +201,15 @@ impl<T, C> Store<T, C> {
         let sendch = SendCh::new(ch.sender, "raftstore");
         let tag = format!("[store {}]", meta.get_id());
 
+        let cdc_worker = Worker::new("cdc worker");
         let mut coprocessor_host = CoprocessorHost::new();
         // TODO load coprocessors from configuration
         coprocessor_host
             .registry
             .register_observer(100, box SplitObserver);
+        coprocessor_host
+            .registry
+            .register_observer(200, box CdcObserver::new(cdc.clone(), cdc_worker.scheduler()));
 
         let mut s = Store {
             cfg: Rc::new(cfg),
@@ -217,11 +228,14 @@ impl<T, C> Store<T, C> {
             consistency_check_worker: Worker::new("consistency check worker"),
             apply_worker: Worker::new("apply worker"),
             apply_res_receiver: None,
+            cdc_worker: cdc_worker,
             region_ranges: BTreeMap::new(),
             pending_snapshot_regions: vec![],
             trans: trans,
             pd_client: pd_client,
             coprocessor_host: Arc::new(coprocessor_host),
+            resolved_ts: resolved_ts,
+            cdc: cdc,
             snap_mgr: mgr,
             raft_metrics: RaftMetrics::default(),
             entry_cache_metries: Rc::new(RefCell::new(CacheQueryStats::default())),
@@ -493,6 +507,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_snap_mgr_gc_tick(event_loop);
         self.register_compact_lock_cf_tick(event_loop);
         self.register_consistency_check_tick(event_loop);
+        self.register_resolved_ts_tick(event_loop);
 
         let split_check_runner = SplitCheckRunner::new(
             self.kv_engine.clone(),
@@ -530,6 +545,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 .start(consistency_check_runner)
         );
 
+        let cdc_runner = CdcRunner::new(self.cdc.clone(), self.kv_engine.clone());
+        box_try!(self.cdc_worker.start(cdc_runner));
+
         let (tx, rx) = mpsc::channel();
         let apply_runner = ApplyRunner::new(self, tx, self.cfg.sync_log);
         self.apply_res_receiver = Some(rx);
@@ -556,6 +574,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         handles.push(self.pd_worker.stop());
         handles.push(self.consistency_check_worker.stop());
         handles.push(self.apply_worker.stop());
+        handles.push(self.cdc_worker.stop());
 
         for h in handles {
             if let Some(h) = h {
@@ -598,6 +617,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             if peer.raft_group.tick() {
                 peer.mark_to_be_checked(&mut self.pending_raft_groups);
             }
+            peer.clear_expired_follower_reads();
 
             // If this peer detects the leader is missing for a long long time,
             // it should consider itself as a stale peer which is removed from
@@ -1529,6 +1549,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     fn pre_propose_raft_command(
         &mut self,
         msg: &RaftCmdRequest,
+        follower_read: bool,
     ) -> Result<Option<RaftCmdResponse>> {
         self.validate_store_id(msg)?;
         if msg.has_status_request() {
@@ -1536,12 +1557,17 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             let resp = self.execute_status_command(msg)?;
             return Ok(Some(resp));
         }
-        self.validate_region(msg)?;
+        self.validate_region(msg, follower_read)?;
         Ok(None)
     }
 
-    fn propose_raft_command(&mut self, msg: RaftCmdRequest, cb: Callback) {
-        match self.pre_propose_raft_command(&msg) {
+    fn propose_raft_command(
+        &mut self,
+        msg: RaftCmdRequest,
+        cb: Callback,
+        follower_read: Option<u64>,
+    ) {
+        match self.pre_propose_raft_command(&msg, follower_read.is_some()) {
             Ok(Some(resp)) => {
                 cb.call_box((resp,));
                 return;
@@ -1563,7 +1589,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let peer = self.region_peers.get_mut(&region_id).unwrap();
         let term = peer.term();
         bind_term(&mut resp, term);
-        if peer.propose(cb, msg, resp, &mut self.raft_metrics.propose) {
+        let read_ts = follower_read.unwrap_or(0);
+        if peer.propose(cb, msg, resp, read_ts, &mut self.raft_metrics.propose) {
             peer.mark_to_be_checked(&mut self.pending_raft_groups);
         }
 
@@ -1580,7 +1607,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         BATCH_SNAPSHOT_COMMANDS.observe(size as f64);
         let mut ret = Vec::with_capacity(size);
         for msg in batch {
-            match self.pre_propose_raft_command(&msg) {
+            match self.pre_propose_raft_command(&msg, false) {
                 Ok(Some(resp)) => {
                     ret.push(Some(resp));
                     continue;
@@ -1607,7 +1634,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         Ok(())
     }
 
-    fn validate_region(&self, msg: &RaftCmdRequest) -> Result<()> {
+    fn validate_region(&self, msg: &RaftCmdRequest, follower_read: bool) -> Result<()> {
         let region_id = msg.get_header().get_region_id();
         let peer_id = msg.get_header().get_peer().get_id();
 
@@ -1615,7 +1642,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             Some(peer) => peer,
             None => return Err(Error::RegionNotFound(region_id)),
         };
-        if !peer.is_leader() {
+        // A follower serves the reads sent as follower reads by the read index.
+        if !peer.is_leader() && !(follower_read && peer::is_read_request(msg)) {
             return Err(Error::NotLeader(
                 region_id,
                 peer.get_peer_from_cache(peer.leader_id()),
@@ -2276,6 +2304,51 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_consistency_check_tick(event_loop);
     }
 
+    fn register_resolved_ts_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if let Err(e) = register_timer(
+            event_loop,
+            Tick::ResolvedTs,
+            self.cfg.resolved_ts_advance_interval.as_millis(),
+        ) {
+            error!("{} register resolved ts tick err: {:?}", self.tag, e);
+        };
+    }
+
+    fn on_resolved_ts_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        if let Some(ts) = self.resolved_ts.min_resolved_ts() {
+            STORE_MIN_RESOLVED_TS_GAUGE.set((ts >> TSO_LOGICAL_BITS) as f64);
+        }
+
+        let regions: Vec<_> = self.region_peers
+            .iter()
+            .filter(|&(_, peer)| peer.is_applied_leader_in_lease())
+            .map(|(&region_id, peer)| (region_id, peer.term()))
+            .collect();
+        if !regions.is_empty() {
+            let task = PdTask::ResolvedTs { regions: regions };
+            if let Err(e) = self.pd_worker.schedule(task) {
+                error!("{} failed to get ts for resolved ts: {:?}", self.tag, e);
+            }
+        }
+
+        self.register_resolved_ts_tick(event_loop);
+    }
+
+    fn on_advance_resolved_ts(&self, regions: Vec<(u64, u64)>, ts: u64) {
+        for (region_id, term) in regions {
+            // The writes acknowledged before the ts was allocated are known to be applied only
+            // if the peer is still a leader of the region, and the reads served by the other
+            // leaders are older than the ts only if the peer has been the leader since then.
+            let is_leader = self.region_peers.get(&region_id).map_or(false, |peer| {
+                peer.is_applied_leader_in_lease() && peer.term() == term
+            });
+            if is_leader {
+                self.resolved_ts.advance(region_id, ts);
+                self.resolved_ts.sync_max_read_ts(region_id, ts);
+            }
+        }
+    }
+
     fn on_ready_compute_hash(&mut self, region: metapb::Region, index: u64, snap: EngineSnapshot) {
         let region_id = region.get_id();
         self.region_peers
@@ -2425,12 +2498,13 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
                 send_time,
                 request,
                 callback,
+                follower_read,
             } => {
                 self.raft_metrics
                     .propose
                     .request_wait_time
                     .observe(duration_to_sec(send_time.elapsed()) as f64);
-                self.propose_raft_command(request, callback)
+                self.propose_raft_command(request, callback, follower_read)
             }
             // For now, it is only called by batch snapshot.
             Msg::BatchRaftSnapCmds {
@@ -2473,6 +2547,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
                 region_id,
                 region_size,
             } => self.on_approximate_region_size(region_id, region_size),
+            Msg::AdvanceResolvedTs { regions, ts } => self.on_advance_resolved_ts(regions, ts),
         }
     }
 
@@ -2488,6 +2563,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Tick::SnapGc => self.on_snap_mgr_gc(event_loop),
             Tick::CompactLockCf => self.on_compact_lock_cf(event_loop),
             Tick::ConsistencyCheck => self.on_consistency_check_tick(event_loop),
+            Tick::ResolvedTs => self.on_resolved_ts_tick(event_loop),
         }
         slow_log!(t, "{} handle timeout {:?}", self.tag, timeout);
     }
@@ -16,6 +16,7 @@ use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::collections::VecDeque;
+use std::mem;
 
 use rocksdb::{Writable, WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
@@ -31,7 +32,9 @@ use util::worker::Runnable;
 use util::{escape, rocksdb};
 use util::time::{duration_to_sec, SlowTimer};
 use util::collections::{HashMap, HashMapEntry as MapEntry};
-use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT};
+use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::mvcc::{Lock, Write, WriteType};
+use storage::types::split_encoded_key_on_ts;
 use raftstore::{Error, Result};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::store::{cmd_resp, keys, util, Store};
@@ -40,6 +43,7 @@ use raftstore::store::engine::{Mutable, Peekable, Snapshot};
 use raftstore::store::peer_storage::{self, compact_raft_log, write_initial_apply_state,
                                      write_peer_state};
 use raftstore::store::peer::{check_epoch, parse_data_at, Peer};
+use raftstore::store::resolved_ts::{self, ResolvedTsTracker, TxnChange};
 use raftstore::store::metrics::*;
 
 use super::metrics::*;
@@ -167,22 +171,34 @@ pub enum ExecResult {
 
 struct ApplyContext<'a> {
     pub host: &'a CoprocessorHost,
+    pub resolved_ts: &'a ResolvedTsTracker,
     pub wb: Option<WriteBatch>,
     pub cbs: Vec<(Callback, RaftCmdResponse)>,
     pub wb_last_bytes: u64,
     pub wb_last_keys: u64,
     pub sync_log: bool,
+    // The transaction changes of the regions, published after the write batch is written.
+    pub txn_changes: Vec<(u64, Vec<TxnChange>)>,
 }
 
 impl<'a> ApplyContext<'a> {
-    fn new(host: &CoprocessorHost) -> ApplyContext {
+    fn new(host: &'a CoprocessorHost, resolved_ts: &'a ResolvedTsTracker) -> ApplyContext<'a> {
         ApplyContext {
             host: host,
+            resolved_ts: resolved_ts,
             wb: Some(WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE)),
             cbs: vec![],
             wb_last_bytes: 0,
             wb_last_keys: 0,
             sync_log: false,
+            txn_changes: vec![],
+        }
+    }
+
+    /// Publishes the transaction changes, must be called after the write batch is written.
+    pub fn publish_txn_changes(&mut self) {
+        for (region_id, changes) in self.txn_changes.drain(..) {
+            self.resolved_ts.apply(region_id, changes);
         }
     }
 
@@ -250,6 +266,45 @@ pub fn notify_stale_req(term: u64, cb: Callback) {
     cb(resp);
 }
 
+/// Starts tracking the resolved ts of `region` with the locks in the engine.
+fn register_resolved_ts(db: &DB, tracker: &ResolvedTsTracker, region: &Region) {
+    // An uninitialized region has no data yet.
+    if region.get_peers().is_empty() {
+        return;
+    }
+    match resolved_ts::scan_locks(db, region) {
+        Ok(locks) => tracker.register(region.clone(), locks),
+        Err(e) => {
+            error!(
+                "[region {}] failed to scan locks for resolved ts: {:?}",
+                region.get_id(),
+                e
+            );
+            tracker.deregister(region.get_id());
+        }
+    }
+}
+
+/// Returns the commit ts of a put to the write cf, or `None` if it's a rollback.
+fn parse_commit_ts(key: &[u8], value: &[u8]) -> Option<u64> {
+    let commit_ts = match split_encoded_key_on_ts(key) {
+        Ok((_, ts)) => ts,
+        Err(_) => return None,
+    };
+    match Write::parse(value) {
+        Ok(ref write) if write.write_type != WriteType::Rollback => Some(commit_ts),
+        _ => None,
+    }
+}
+
+/// Returns true if the result changes the range or the epoch of the region.
+fn changes_region(res: &ExecResult) -> bool {
+    match *res {
+        ExecResult::ChangePeer(_) | ExecResult::SplitRegion { .. } => true,
+        _ => false,
+    }
+}
+
 fn should_flush_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
     // When encounter ComputeHash cmd, we must flush the write batch to engine immediately.
     if cmd.has_admin_request() &&
@@ -294,6 +349,8 @@ pub struct ApplyDelegate {
     term: u64,
     pending_cmds: PendingCmdQueue,
     metrics: ApplyMetrics,
+    // the transaction changes of the applied commands.
+    txn_changes: Vec<TxnChange>,
 }
 
 impl ApplyDelegate {
@@ -322,6 +379,7 @@ impl ApplyDelegate {
             term: reg.term,
             pending_cmds: Default::default(),
             metrics: Default::default(),
+            txn_changes: vec![],
         }
     }
 
@@ -367,12 +425,21 @@ impl ApplyDelegate {
             self.write_apply_state(apply_ctx.wb_mut());
         }
 
+        self.stash_txn_changes(apply_ctx);
+
         self.update_metrics(apply_ctx);
         apply_ctx.mark_last_bytes_and_keys();
 
         results
     }
 
+    fn stash_txn_changes(&mut self, apply_ctx: &mut ApplyContext) {
+        if !self.txn_changes.is_empty() {
+            let changes = mem::replace(&mut self.txn_changes, vec![]);
+            apply_ctx.txn_changes.push((self.region_id(), changes));
+        }
+    }
+
     fn update_metrics(&mut self, apply_ctx: &ApplyContext) {
         self.metrics.written_bytes += apply_ctx.delta_bytes();
         self.metrics.written_keys += apply_ctx.delta_keys();
@@ -421,6 +488,10 @@ impl ApplyDelegate {
                         panic!("{} failed to write to engine, error: {:?}", self.tag, e)
                     });
 
+                // The locks must be tracked before the writes are acknowledged.
+                self.stash_txn_changes(apply_ctx);
+                apply_ctx.publish_txn_changes();
+
                 // call callback
                 for (cb, resp) in apply_ctx.cbs.drain(..) {
                     cb(resp);
@@ -525,6 +596,7 @@ impl ApplyDelegate {
         let cmd_cb = self.find_cb(index, term, &cmd);
         apply_ctx.host.pre_apply(&self.region, &mut cmd);
         let (mut resp, exec_result) = self.apply_raft_cmd(apply_ctx.wb_mut(), index, term, &cmd);
+        apply_ctx.host.post_apply(&self.region, &cmd, &resp);
 
         debug!("{} applied command at log index {}", self.tag, index);
 
@@ -533,7 +605,6 @@ impl ApplyDelegate {
             Some(cb) => cb,
         };
 
-        // TODO: Involve post apply hook.
         // TODO: if we have exec_result, maybe we should return this callback too. Outer
         // store will call it after handing exec result.
         cmd_resp::bind_term(&mut resp, self.term);
@@ -563,6 +634,7 @@ impl ApplyDelegate {
         let (resp, exec_result) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
             // clear dirty values.
             ctx.wb.rollback_to_save_point().unwrap();
+            ctx.txn_changes.clear();
             match e {
                 Error::StaleEpoch(..) => info!("{} stale epoch err: {:?}", self.tag, e),
                 _ => error!("{} execute raft command err: {:?}", self.tag, e),
@@ -572,6 +644,7 @@ impl ApplyDelegate {
 
         ctx.apply_state.set_applied_index(index);
 
+        self.txn_changes.append(&mut ctx.txn_changes);
         self.apply_state = ctx.apply_state;
         self.applied_index_term = term;
 
@@ -655,6 +728,7 @@ impl ApplyDelegate {
             req: req,
             index: index,
             term: term,
+            txn_changes: vec![],
         }
     }
 }
@@ -665,6 +739,7 @@ struct ExecContext<'a> {
     req: &'a RaftCmdRequest,
     index: u64,
     term: u64,
+    txn_changes: Vec<TxnChange>,
 }
 
 // Here we implement all commands.
@@ -1001,9 +1076,10 @@ impl ApplyDelegate {
 
     fn exec_write_cmd(
         &mut self,
-        ctx: &ExecContext,
+        ctx: &mut ExecContext,
     ) -> Result<(RaftCmdResponse, Option<ExecResult>)> {
-        let requests = ctx.req.get_requests();
+        let cmd = ctx.req;
+        let requests = cmd.get_requests();
         let mut responses = Vec::with_capacity(requests.len());
 
         let mut ranges = vec![];
@@ -1043,12 +1119,12 @@ impl ApplyDelegate {
         Ok((resp, exec_res))
     }
 
-    fn handle_put(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
-        let (key, value) = (req.get_put().get_key(), req.get_put().get_value());
-        check_data_key(key, &self.region)?;
+    fn handle_put(&mut self, ctx: &mut ExecContext, req: &Request) -> Result<Response> {
+        let (origin_key, value) = (req.get_put().get_key(), req.get_put().get_value());
+        check_data_key(origin_key, &self.region)?;
 
         let resp = Response::new();
-        let key = keys::data_key(key);
+        let key = keys::data_key(origin_key);
         self.metrics.size_diff_hint += key.len() as i64;
         self.metrics.size_diff_hint += value.len() as i64;
         if req.get_put().has_cf() {
@@ -1057,6 +1133,18 @@ impl ApplyDelegate {
             if cf == CF_LOCK {
                 self.metrics.lock_cf_written_bytes += key.len() as u64;
                 self.metrics.lock_cf_written_bytes += value.len() as u64;
+                if let Ok(lock) = Lock::parse(value) {
+                    ctx.txn_changes.push(TxnChange::Lock {
+                        key: origin_key.to_vec(),
+                        ts: lock.ts,
+                    });
+                }
+            } else if cf == CF_WRITE {
+                if let Some(commit_ts) = parse_commit_ts(origin_key, value) {
+                    ctx.txn_changes.push(TxnChange::Commit {
+                        commit_ts: commit_ts,
+                    });
+                }
             }
             // TODO: check whether cf exists or not.
             rocksdb::get_cf_handle(&self.engine, cf)
@@ -1085,11 +1173,11 @@ impl ApplyDelegate {
         Ok(resp)
     }
 
-    fn handle_delete(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
-        let key = req.get_delete().get_key();
-        check_data_key(key, &self.region)?;
+    fn handle_delete(&mut self, ctx: &mut ExecContext, req: &Request) -> Result<Response> {
+        let origin_key = req.get_delete().get_key();
+        check_data_key(origin_key, &self.region)?;
 
-        let key = keys::data_key(key);
+        let key = keys::data_key(origin_key);
         // since size_diff_hint is not accurate, so we just skip calculate the value size.
         self.metrics.size_diff_hint -= key.len() as i64;
         let resp = Response::new();
@@ -1105,6 +1193,9 @@ impl ApplyDelegate {
             if cf == CF_LOCK {
                 // delete is a kind of write for RocksDB.
                 self.metrics.lock_cf_written_bytes += key.len() as u64;
+                ctx.txn_changes.push(TxnChange::Unlock {
+                    key: origin_key.to_vec(),
+                });
             } else {
                 self.metrics.delete_keys_hint += 1;
             }
@@ -1421,22 +1512,26 @@ pub struct Runner {
     delegates: HashMap<u64, ApplyDelegate>,
     notifier: Sender<TaskRes>,
     sync_log: bool,
+    resolved_ts: ResolvedTsTracker,
     tag: String,
 }
 
 impl Runner {
     pub fn new<T, C>(store: &Store<T, C>, notifier: Sender<TaskRes>, sync_log: bool) -> Runner {
+        let db = store.kv_engine();
         let mut delegates =
             HashMap::with_capacity_and_hasher(store.get_peers().len(), Default::default());
         for (&region_id, p) in store.get_peers() {
+            register_resolved_ts(&db, &store.resolved_ts, p.region());
             delegates.insert(region_id, ApplyDelegate::from_peer(p));
         }
         Runner {
-            db: store.kv_engine(),
+            db: db,
             host: store.coprocessor_host.clone(),
             delegates: delegates,
             notifier: notifier,
             sync_log: sync_log,
+            resolved_ts: store.resolved_ts.clone(),
             tag: format!("[store {}]", store.store_id()),
         }
     }
@@ -1445,7 +1540,7 @@ impl Runner {
         let t = SlowTimer::new();
 
         let mut applys_res = Vec::with_capacity(applys.len());
-        let mut apply_ctx = ApplyContext::new(self.host.as_ref());
+        let mut apply_ctx = ApplyContext::new(self.host.as_ref(), &self.resolved_ts);
         let mut committed_count = 0;
         for apply in applys {
             if apply.entries.is_empty() {
@@ -1467,6 +1562,8 @@ impl Runner {
 
                 if delegate.pending_remove {
                     delegate.destroy();
+                } else if results.iter().any(changes_region) {
+                    self.resolved_ts.update_region(&delegate.region);
                 }
 
                 applys_res.push(ApplyRes {
@@ -1479,6 +1576,7 @@ impl Runner {
             }
             if e.get().pending_remove {
                 e.remove();
+                self.resolved_ts.deregister(apply.region_id);
             }
         }
 
@@ -1493,6 +1591,8 @@ impl Runner {
             .write_opt(apply_ctx.wb.take().unwrap(), &write_opts)
             .unwrap_or_else(|e| panic!("failed to write to engine, error: {:?}", e));
 
+        apply_ctx.publish_txn_changes();
+
         // Call callbacks
         for (cb, resp) in apply_ctx.cbs.drain(..) {
             cb(resp);
@@ -1550,6 +1650,7 @@ impl Runner {
         let peer_id = s.id;
         let region_id = s.region.get_id();
         let term = s.term;
+        register_resolved_ts(&self.db, &self.resolved_ts, &s.region);
         let delegate = ApplyDelegate::from_registration(self.db.clone(), s);
         info!(
             "{} register to apply delegates at term {}",
@@ -1568,6 +1669,7 @@ impl Runner {
         // multiple times, the store may destroy wrong target peer.
         if let Some(mut meta) = self.delegates.remove(&d.region_id) {
             info!("{} remove from apply delegates", meta.tag);
+            self.resolved_ts.deregister(d.region_id);
             meta.destroy();
             self.notifier.send(TaskRes::Destroy(meta)).unwrap();
         }
@@ -1606,7 +1708,8 @@ mod tests {
     use kvproto::raft_cmdpb::CmdType;
 
     use super::*;
-    use storage::{ALL_CFS, CF_WRITE};
+    use storage::{make_key, ALL_CFS, CF_WRITE};
+    use storage::mvcc::LockType;
     use util::collections::HashMap;
 
     pub fn create_tmp_engine(path: &str) -> (TempDir, Arc<DB>) {
@@ -1624,6 +1727,7 @@ mod tests {
             delegates: HashMap::default(),
             notifier: tx,
             sync_log: false,
+            resolved_ts: ResolvedTsTracker::new(),
             tag: "".to_owned(),
         }
     }
@@ -1909,7 +2013,8 @@ mod tests {
             .capture_resp(&mut delegate, tx.clone())
             .build();
         let host = CoprocessorHost::new();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let tracker = ResolvedTsTracker::new();
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         let res = delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1936,7 +2041,7 @@ mod tests {
             .put_cf(CF_LOCK, b"k1", b"v1")
             .epoch(1, 3)
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1959,7 +2064,7 @@ mod tests {
             .epoch(1, 1)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1976,7 +2081,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2002,7 +2107,7 @@ mod tests {
         let lock_written_bytes = delegate.metrics.lock_cf_written_bytes;
         let delete_keys_hint = delegate.metrics.delete_keys_hint;
         let size_diff_hint = delegate.metrics.size_diff_hint;
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2026,7 +2131,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2040,7 +2145,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_range_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2057,7 +2162,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_range_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2078,7 +2183,7 @@ mod tests {
                 .build();
             entries.push(put_entry);
         }
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &tracker);
         delegate.handle_raft_committed_entries(&mut apply_ctx, entries);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2092,4 +2197,68 @@ mod tests {
             WRITE_BATCH_MAX_KEYS as u64 + 8
         );
     }
+
+    #[test]
+    fn test_resolved_ts() {
+        let (tx, rx) = mpsc::channel();
+        let (_tmp, db) = create_tmp_engine("apply-resolved-ts");
+        let host = Arc::new(CoprocessorHost::new());
+        let mut runner = new_runner(db.clone(), host, tx);
+
+        let lock = |ts| Lock::new(LockType::Put, b"k1".to_vec(), ts, 0, None, 0).to_bytes();
+        let write = |tp, start_ts| Write::new(tp, start_ts, None).to_bytes();
+        let (k1, k2) = (make_key(b"k1"), make_key(b"k2"));
+
+        // The locks written before the registration are scanned.
+        let handle = rocksdb::get_cf_handle(&db, CF_LOCK).unwrap();
+        db.put_cf(handle, &keys::data_key(k1.encoded()), &lock(10))
+            .unwrap();
+        let mut reg = Registration::default();
+        reg.id = 1;
+        reg.region.set_id(2);
+        reg.region.set_end_key(make_key(b"k3").encoded().to_vec());
+        reg.region.mut_region_epoch().set_version(2);
+        reg.region.mut_peers().push(PeerMeta::new());
+        reg.term = 1;
+        runner.run(Task::Registration(reg));
+        assert_eq!(runner.resolved_ts.resolved_ts(2), Some(0));
+
+        let entries = vec![
+            EntryBuilder::new(1, 1)
+                .put_cf(CF_LOCK, k2.encoded(), &lock(15))
+                .put_cf(CF_WRITE, k1.append_ts(20).encoded(), &write(WriteType::Put, 8))
+                .epoch(1, 2)
+                .build(),
+        ];
+        runner.run(Task::applies(vec![Apply::new(2, 1, entries)]));
+        assert_eq!(runner.resolved_ts.resolved_ts(2), Some(9));
+
+        let entries = vec![
+            EntryBuilder::new(2, 1)
+                .delete_cf(CF_LOCK, k1.encoded())
+                .put_cf(CF_WRITE, k1.append_ts(25).encoded(), &write(WriteType::Put, 10))
+                .epoch(1, 2)
+                .build(),
+            // Rollbacks are not commits.
+            EntryBuilder::new(3, 1)
+                .delete_cf(CF_LOCK, k2.encoded())
+                .put_cf(CF_WRITE, k2.append_ts(15).encoded(), &write(WriteType::Rollback, 15))
+                .epoch(1, 2)
+                .build(),
+            // The changes of a failed command are discarded.
+            EntryBuilder::new(4, 1)
+                .put_cf(CF_WRITE, k2.append_ts(30).encoded(), &write(WriteType::Put, 28))
+                .put(make_key(b"k9").encoded(), b"v")
+                .epoch(1, 2)
+                .build(),
+        ];
+        runner.run(Task::applies(vec![Apply::new(2, 1, entries)]));
+        assert_eq!(runner.resolved_ts.resolved_ts(2), Some(25));
+
+        for _ in 0..2 {
+            rx.try_recv().unwrap();
+        }
+        runner.run(Task::destroy(2));
+        assert_eq!(runner.resolved_ts.resolved_ts(2), None);
+    }
 }
@@ -0,0 +1,450 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A built-in GC manager which collects the garbage of all regions on the store.
+//!
+//! Every `gc_interval` the manager gets a safe point from a `GcSafePointProvider`, like the
+//! `PdSafePointProvider` which is based on the TSO of PD, then walks all the regions of the
+//! store and sends a `Command::Gc` for each of them. Regions whose `MvccProperties` show
+//! nothing to collect are skipped without touching raft, and regions which are not led by this
+//! store are rejected by raftstore and skipped as well, so every region is collected by its
+//! leader only. The IO of GC is throttled by sleeping after each region in proportion to its
+//! approximate size.
+//!
+//! If the GC compaction filter is enabled, the manager only advances the `GcSafePoint` of the
+//! filter and leaves the stale versions to RocksDB compaction.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{Builder, JoinHandle};
+use std::time::Duration;
+
+use kvproto::kvrpcpb::{Context, IsolationLevel};
+use kvproto::metapb::Region;
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+use futures::Future;
+use protobuf;
+use rocksdb::DB;
+use time;
+
+use pd::PdClient;
+use raftstore::coprocessor::RegionSnapshot;
+use raftstore::store::keys;
+use raftstore::store::engine::Iterable;
+use raftstore::store::util as store_util;
+use storage::{self, Config as StorageConfig, Statistics, Storage, CF_RAFT};
+use storage::engine::Error as EngineError;
+use storage::mvcc::{Error as MvccError, GcSafePoint, MvccReader};
+use storage::txn::Error as TxnError;
+use util::time::Instant;
+use super::Result;
+use super::metrics::*;
+
+// The physical part of a timestamp is shifted by 18 bits, see the TSO of PD.
+const TSO_PHYSICAL_SHIFT_BITS: u64 = 18;
+
+/// Composes a timestamp in the same format as the TSO of PD.
+pub fn compose_ts(physical_ms: u64, logical: u64) -> u64 {
+    (physical_ms << TSO_PHYSICAL_SHIFT_BITS) + logical
+}
+
+/// Returns the safe point which is `lifetime` before the physical time `now_ms`.
+fn safe_point_before(now_ms: u64, lifetime: Duration) -> u64 {
+    let lifetime_ms =
+        lifetime.as_secs() * 1000 + u64::from(lifetime.subsec_nanos()) / 1_000_000;
+    compose_ts(now_ms.saturating_sub(lifetime_ms), 0)
+}
+
+/// Provides the safe point under which the old versions can be collected.
+pub trait GcSafePointProvider: Send + 'static {
+    fn get_safe_point(&self) -> Result<u64>;
+}
+
+/// `LocalSafePointProvider` derives the safe point from the local clock: every version which
+/// was overwritten more than `lifetime` ago can be collected. `lifetime` must be much longer
+/// than the longest transaction and the clock skew between PD and the store.
+pub struct LocalSafePointProvider {
+    lifetime: Duration,
+}
+
+impl LocalSafePointProvider {
+    pub fn new(lifetime: Duration) -> LocalSafePointProvider {
+        LocalSafePointProvider { lifetime: lifetime }
+    }
+}
+
+impl GcSafePointProvider for LocalSafePointProvider {
+    fn get_safe_point(&self) -> Result<u64> {
+        let now = time::get_time();
+        let now_ms = now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000;
+        Ok(safe_point_before(now_ms, self.lifetime))
+    }
+}
+
+/// `PdSafePointProvider` derives the safe point from a timestamp allocated by PD, so all the
+/// stores agree on the clock and the clock skew doesn't matter. The PD in use doesn't keep a
+/// safe point for the cluster yet, so `lifetime` is applied to the timestamp like above.
+pub struct PdSafePointProvider<C: PdClient> {
+    pd_client: Arc<C>,
+    lifetime: Duration,
+}
+
+impl<C: PdClient> PdSafePointProvider<C> {
+    pub fn new(pd_client: Arc<C>, lifetime: Duration) -> PdSafePointProvider<C> {
+        PdSafePointProvider {
+            pd_client: pd_client,
+            lifetime: lifetime,
+        }
+    }
+}
+
+impl<C: PdClient + 'static> GcSafePointProvider for PdSafePointProvider<C> {
+    fn get_safe_point(&self) -> Result<u64> {
+        let ts = self.pd_client.get_tso().wait()?;
+        Ok(safe_point_before(ts >> TSO_PHYSICAL_SHIFT_BITS, self.lifetime))
+    }
+}
+
+/// Loads all the normal regions on the store from the kv engine.
+pub fn load_regions(db: &DB) -> Result<Vec<Region>> {
+    let mut regions = vec![];
+    db.scan_cf(
+        CF_RAFT,
+        keys::REGION_META_MIN_KEY,
+        keys::REGION_META_MAX_KEY,
+        false,
+        &mut |key, value| {
+            let (_, suffix) = keys::decode_region_meta_key(key)?;
+            if suffix != keys::REGION_STATE_SUFFIX {
+                return Ok(true);
+            }
+            let mut local_state = protobuf::parse_from_bytes::<RegionLocalState>(value)?;
+            if local_state.get_state() == PeerState::Normal {
+                regions.push(local_state.take_region());
+            }
+            Ok(true)
+        },
+    )?;
+    Ok(regions)
+}
+
+/// Returns how long to sleep after `bytes` were collected in `elapsed`, so that GC doesn't
+/// process more than `max_bytes_per_sec` on average. Zero `max_bytes_per_sec` means no limit.
+fn throttle_duration(bytes: u64, max_bytes_per_sec: u64, elapsed: Duration) -> Option<Duration> {
+    if max_bytes_per_sec == 0 {
+        return None;
+    }
+    let expected_ms = bytes.saturating_mul(1000) / max_bytes_per_sec;
+    let expected = Duration::from_millis(expected_ms);
+    if expected > elapsed {
+        Some(expected - elapsed)
+    } else {
+        None
+    }
+}
+
+fn is_region_error(err: &storage::Error) -> bool {
+    match *err {
+        storage::Error::Engine(EngineError::Request(_)) |
+        storage::Error::Txn(TxnError::Engine(EngineError::Request(_))) |
+        storage::Error::Txn(TxnError::Mvcc(MvccError::Engine(EngineError::Request(_)))) => true,
+        _ => false,
+    }
+}
+
+struct Runner<P: GcSafePointProvider> {
+    store_id: u64,
+    engine: Arc<DB>,
+    storage: Storage,
+    provider: P,
+    interval: Duration,
+    ratio_threshold: f64,
+    max_bytes_per_sec: u64,
+    compaction_filter_safe_point: Option<Arc<GcSafePoint>>,
+    last_safe_point: u64,
+    rx: Receiver<bool>,
+}
+
+impl<P: GcSafePointProvider> Runner<P> {
+    /// Waits for `dur`, returns false if the manager is stopped in the meantime.
+    fn wait(&self, dur: Duration) -> bool {
+        match self.rx.recv_timeout(dur) {
+            Err(RecvTimeoutError::Timeout) => true,
+            _ => false,
+        }
+    }
+
+    fn run(&mut self) {
+        while self.wait(self.interval) {
+            let safe_point = match self.provider.get_safe_point() {
+                Ok(ts) => ts,
+                Err(e) => {
+                    error!("gc manager failed to get safe point: {:?}", e);
+                    continue;
+                }
+            };
+            if safe_point <= self.last_safe_point {
+                continue;
+            }
+            GC_MANAGER_SAFE_POINT_GAUGE.set(safe_point as f64);
+            if let Some(ref filter_safe_point) = self.compaction_filter_safe_point {
+                if let Err(e) = filter_safe_point.update(safe_point) {
+                    error!("gc manager failed to update safe point: {:?}", e);
+                    continue;
+                }
+            } else if !self.gc_regions(safe_point) {
+                return;
+            }
+            self.last_safe_point = safe_point;
+        }
+    }
+
+    /// Collects the garbage of all regions, returns false if the manager is stopped.
+    fn gc_regions(&mut self, safe_point: u64) -> bool {
+        let regions = match load_regions(&self.engine) {
+            Ok(regions) => regions,
+            Err(e) => {
+                error!("gc manager failed to load regions: {:?}", e);
+                return true;
+            }
+        };
+        info!(
+            "gc manager starts to gc {} regions with safe point {}",
+            regions.len(),
+            safe_point
+        );
+        let timer = GC_MANAGER_ROUND_HISTOGRAM.start_coarse_timer();
+        GC_MANAGER_PROGRESS_GAUGE.set(0.0);
+        for (i, region) in regions.iter().enumerate() {
+            let t = Instant::now_coarse();
+            if self.gc_region(region, safe_point) {
+                let bytes = store_util::get_region_approximate_size(&self.engine, region)
+                    .unwrap_or(0);
+                if let Some(dur) = throttle_duration(bytes, self.max_bytes_per_sec, t.elapsed()) {
+                    if !self.wait(dur) {
+                        return false;
+                    }
+                }
+            }
+            GC_MANAGER_PROGRESS_GAUGE.set((i + 1) as f64 / regions.len() as f64);
+            if let Err(mpsc::TryRecvError::Disconnected) = self.rx.try_recv() {
+                return false;
+            }
+        }
+        timer.observe_duration();
+        true
+    }
+
+    /// Collects the garbage of `region`, returns true if GC has been done.
+    fn gc_region(&self, region: &Region, safe_point: u64) -> bool {
+        let peer = match store_util::find_peer(region, self.store_id) {
+            Some(peer) => peer.clone(),
+            None => return false,
+        };
+        let snapshot = RegionSnapshot::from_raw(self.engine.clone(), region.clone());
+        let mut statistics = Statistics::default();
+        let need_gc = {
+            let reader = MvccReader::new(
+                &snapshot,
+                &mut statistics,
+                None,
+                false,
+                None,
+                IsolationLevel::SI,
+            );
+            reader.need_gc(safe_point, self.ratio_threshold)
+        };
+        if !need_gc {
+            GC_MANAGER_REGION_COUNTER
+                .with_label_values(&["skipped"])
+                .inc();
+            return false;
+        }
+
+        let mut ctx = Context::new();
+        ctx.set_region_id(region.get_id());
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        ctx.set_peer(peer);
+        let (tx, rx) = mpsc::channel();
+        let res = self.storage
+            .async_gc(ctx, safe_point, box move |res| tx.send(res).unwrap())
+            .and_then(|_| rx.recv().unwrap());
+        match res {
+            Ok(_) => {
+                GC_MANAGER_REGION_COUNTER.with_label_values(&["gc"]).inc();
+                true
+            }
+            Err(ref e) if is_region_error(e) => {
+                // Mostly this store isn't the leader of the region.
+                GC_MANAGER_REGION_COUNTER
+                    .with_label_values(&["not_leader"])
+                    .inc();
+                false
+            }
+            Err(e) => {
+                warn!("gc manager failed to gc region {}: {:?}", region.get_id(), e);
+                GC_MANAGER_REGION_COUNTER
+                    .with_label_values(&["failed"])
+                    .inc();
+                false
+            }
+        }
+    }
+}
+
+/// `GcManager` runs GC of the regions on the store periodically in a background thread.
+pub struct GcManager<P: GcSafePointProvider> {
+    store_id: u64,
+    engine: Arc<DB>,
+    storage: Storage,
+    provider: Option<P>,
+    interval: Duration,
+    ratio_threshold: f64,
+    max_bytes_per_sec: u64,
+    compaction_filter_safe_point: Option<Arc<GcSafePoint>>,
+    handle: Option<JoinHandle<()>>,
+    sender: Option<Sender<bool>>,
+}
+
+impl<P: GcSafePointProvider> GcManager<P> {
+    pub fn new(
+        store_id: u64,
+        engine: Arc<DB>,
+        storage: Storage,
+        provider: P,
+        cfg: &StorageConfig,
+    ) -> GcManager<P> {
+        GcManager {
+            store_id: store_id,
+            engine: engine,
+            storage: storage,
+            provider: Some(provider),
+            interval: cfg.gc_interval.0,
+            ratio_threshold: cfg.gc_ratio_threshold,
+            max_bytes_per_sec: cfg.gc_max_bytes_per_sec.0,
+            compaction_filter_safe_point: None,
+            handle: None,
+            sender: None,
+        }
+    }
+
+    /// Leaves GC to the compaction filter sharing `safe_point`, see `storage::mvcc::GcSafePoint`.
+    pub fn set_compaction_filter_safe_point(&mut self, safe_point: Arc<GcSafePoint>) {
+        self.compaction_filter_safe_point = Some(safe_point);
+    }
+
+    pub fn start(&mut self) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let mut runner = Runner {
+            store_id: self.store_id,
+            engine: self.engine.clone(),
+            storage: self.storage.clone(),
+            provider: self.provider.take().unwrap(),
+            interval: self.interval,
+            ratio_threshold: self.ratio_threshold,
+            max_bytes_per_sec: self.max_bytes_per_sec,
+            compaction_filter_safe_point: self.compaction_filter_safe_point.clone(),
+            last_safe_point: 0,
+            rx: rx,
+        };
+        let h = Builder::new()
+            .name(thd_name!("gc-manager"))
+            .spawn(move || runner.run())?;
+        self.sender = Some(tx);
+        self.handle = Some(h);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        let h = match self.handle.take() {
+            Some(h) => h,
+            None => return,
+        };
+        drop(self.sender.take().unwrap());
+        if let Err(e) = h.join() {
+            error!("join gc manager failed {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+    use rocksdb::WriteBatch;
+    use tempdir::TempDir;
+
+    use raftstore::store::{keys, Mutable};
+    use storage::{ALL_CFS, CF_RAFT};
+    use util::rocksdb;
+    use super::*;
+
+    #[test]
+    fn test_local_safe_point_provider() {
+        let provider = LocalSafePointProvider::new(Duration::from_secs(600));
+        let safe_point = provider.get_safe_point().unwrap();
+        let now = time::get_time().sec as u64 * 1000;
+        assert!(safe_point <= compose_ts(now - 600 * 1000, 0));
+        assert!(safe_point >= compose_ts(now - 601 * 1000, 0));
+        assert_eq!(compose_ts(1, 2), (1 << 18) + 2);
+    }
+
+    #[test]
+    fn test_safe_point_before() {
+        let lifetime = Duration::from_millis(1500);
+        assert_eq!(safe_point_before(2000, lifetime), compose_ts(500, 0));
+        assert_eq!(safe_point_before(1000, lifetime), 0);
+    }
+
+    #[test]
+    fn test_load_regions() {
+        let path = TempDir::new("test_gc_manager_load_regions").unwrap();
+        let db = Arc::new(
+            rocksdb::new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap(),
+        );
+        let handle = rocksdb::get_cf_handle(&db, CF_RAFT).unwrap();
+        let wb = WriteBatch::new();
+        for (id, state) in vec![
+            (1, PeerState::Normal),
+            (2, PeerState::Tombstone),
+            (3, PeerState::Applying),
+            (4, PeerState::Normal),
+        ] {
+            let mut local_state = RegionLocalState::new();
+            local_state.mut_region().set_id(id);
+            local_state.set_state(state);
+            wb.put_msg_cf(handle, &keys::region_state_key(id), &local_state)
+                .unwrap();
+        }
+        db.write(wb).unwrap();
+        let ids: Vec<u64> = load_regions(&db)
+            .unwrap()
+            .iter()
+            .map(|r| r.get_id())
+            .collect();
+        assert_eq!(ids, vec![1, 4]);
+    }
+
+    #[test]
+    fn test_throttle_duration() {
+        let ms = Duration::from_millis;
+        assert_eq!(throttle_duration(1024, 0, ms(0)), None);
+        assert_eq!(throttle_duration(1024, 1024, ms(0)), Some(ms(1000)));
+        assert_eq!(throttle_duration(1024, 1024, ms(400)), Some(ms(600)));
+        assert_eq!(throttle_duration(1024, 1024, ms(1000)), None);
+        assert_eq!(throttle_duration(512, 1024, ms(100)), Some(ms(400)));
+    }
+}
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{Counter, CounterVec, Histogram, HistogramVec};
+use prometheus::{Counter, CounterVec, Gauge, Histogram, HistogramVec};
 
 lazy_static! {
     pub static ref SEND_SNAP_HISTOGRAM: Histogram =
@@ -60,4 +60,29 @@ lazy_static! {
             "Total number of reporting failure messages",
             &["type", "store_id"]
         ).unwrap();
+
+    pub static ref GC_MANAGER_REGION_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_gc_manager_region_total",
+            "Total number of regions handled by the gc manager",
+            &["type"]
+        ).unwrap();
+
+    pub static ref GC_MANAGER_SAFE_POINT_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_gc_manager_safe_point",
+            "Safe point used by the current gc round"
+        ).unwrap();
+
+    pub static ref GC_MANAGER_PROGRESS_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_gc_manager_progress",
+            "Ratio of regions handled in the current gc round"
+        ).unwrap();
+
+    pub static ref GC_MANAGER_ROUND_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_gc_manager_round_duration_seconds",
+            "Bucketed histogram of gc round duration"
+        ).unwrap();
 }
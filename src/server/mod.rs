@@ -24,6 +24,7 @@ pub mod transport;
 pub mod node;
 pub mod resolve;
 pub mod snap;
+pub mod gc_manager;
 
 pub use self::config::{Config, DEFAULT_CLUSTER_ID, DEFAULT_LISTENING_ADDR};
 pub use self::errors::{Error, Result};
@@ -32,5 +33,9 @@ pub use self::transport::{ServerRaftStoreRouter, ServerTransport};
 pub use self::node::{create_raft_storage, Node};
 pub use self::resolve::{PdStoreAddrResolver, StoreAddrResolver};
 pub use self::raft_client::RaftClient;
+pub use self::service::extpb;
+pub use self::service::CdcService;
+pub use self::gc_manager::{GcManager, GcSafePointProvider, LocalSafePointProvider,
+                           PdSafePointProvider};
 
 pub type OnResponse = Box<FnBox(Response) + Send>;
@@ -21,13 +21,15 @@ use mio::EventLoop;
 use rocksdb::DB;
 
 use pd::{Error as PdError, PdClient, PdTask, INVALID_ID};
+use cdc::CdcHub;
 use kvproto::raft_serverpb::StoreIdent;
 use kvproto::metapb;
 use protobuf::RepeatedField;
 use util::transport::SendCh;
 use util::worker::FutureWorker;
-use raftstore::store::{self, keys, Config as StoreConfig, Engines, Msg, Peekable, SignificantMsg,
-                       SnapManager, Store, StoreChannel, Transport};
+use raftstore::store::{self, keys, Config as StoreConfig, Engines, Msg, Peekable,
+                       ResolvedTsTracker, SignificantMsg, SnapManager, Store, StoreChannel,
+                       Transport};
 use super::Result;
 use server::Config as ServerConfig;
 use storage::{Config as StorageConfig, RaftKv, Storage};
@@ -36,12 +38,18 @@ use super::transport::RaftStoreRouter;
 const MAX_CHECK_CLUSTER_BOOTSTRAPPED_RETRY_COUNT: u64 = 60;
 const CHECK_CLUSTER_BOOTSTRAPPED_RETRY_SECONDS: u64 = 3;
 
-pub fn create_raft_storage<S>(router: S, db: Arc<DB>, cfg: &StorageConfig) -> Result<Storage>
+pub fn create_raft_storage<S>(
+    router: S,
+    db: Arc<DB>,
+    resolved_ts: ResolvedTsTracker,
+    cfg: &StorageConfig,
+) -> Result<Storage>
 where
     S: RaftStoreRouter + 'static,
 {
-    let engine = box RaftKv::new(db, router);
-    let store = Storage::from_engine(engine, cfg)?;
+    let engine = box RaftKv::new(db.clone(), router, resolved_ts);
+    let mut store = Storage::from_engine(engine, cfg)?;
+    store.set_flow_control_db(db);
     Ok(store)
 }
 
@@ -73,6 +81,8 @@ pub struct Node<C: PdClient + 'static> {
     store_cfg: StoreConfig,
     store_handle: Option<thread::JoinHandle<()>>,
     ch: SendCh<Msg>,
+    resolved_ts: ResolvedTsTracker,
+    cdc: CdcHub,
 
     pd_client: Arc<C>,
 }
@@ -86,6 +96,8 @@ where
         cfg: &ServerConfig,
         store_cfg: &StoreConfig,
         pd_client: Arc<C>,
+        resolved_ts: ResolvedTsTracker,
+        cdc: CdcHub,
     ) -> Node<C>
     where
         T: Transport + 'static,
@@ -115,6 +127,8 @@ where
             store_handle: None,
             pd_client: pd_client,
             ch: ch,
+            resolved_ts: resolved_ts,
+            cdc: cdc,
         }
     }
 
@@ -330,6 +344,8 @@ where
         let cfg = self.store_cfg.clone();
         let pd_client = self.pd_client.clone();
         let store = self.store.clone();
+        let resolved_ts = self.resolved_ts.clone();
+        let cdc = self.cdc.clone();
         let sender = event_loop.channel();
 
         let (tx, rx) = mpsc::channel();
@@ -348,6 +364,8 @@ where
                 pd_client,
                 snap_mgr,
                 pd_worker,
+                resolved_ts,
+                cdc,
             ) {
                 Err(e) => panic!("construct store {} err {:?}", store_id, e),
                 Ok(s) => s,
@@ -21,11 +21,12 @@ use kvproto::debugpb_grpc::create_debug;
 
 use util::worker::{FutureScheduler, Worker};
 use storage::Storage;
-use raftstore::store::{Engines, SnapManager};
+use raftstore::store::{Engines, ResolvedTsTracker, SnapManager};
 
 use super::{Config, Result};
 use coprocessor::{EndPointHost, EndPointTask};
 use super::service::*;
+use super::service::extpb::{create_change_data, create_debug_ext, create_tikv_ext};
 use super::transport::{RaftStoreRouter, ServerTransport};
 use super::resolve::StoreAddrResolver;
 use super::snap::{Runner as SnapHandler, Task as SnapTask};
@@ -64,6 +65,8 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
         snap_mgr: SnapManager,
         pd_scheduler: FutureScheduler<PdTask>,
         debug_engines: Option<Engines>,
+        resolved_ts: ResolvedTsTracker,
+        cdc_service: Option<CdcService>,
     ) -> Result<Server<T, S>> {
         let env = Arc::new(
             EnvBuilder::new()
@@ -94,9 +97,15 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             let mut sb = ServerBuilder::new(env.clone())
                 .bind(ip, addr.port())
                 .channel_args(channel_args)
-                .register_service(create_tikv(kv_service));
+                .register_service(create_tikv(kv_service.clone()))
+                .register_service(create_tikv_ext(kv_service));
             if let Some(engines) = debug_engines {
-                sb = sb.register_service(create_debug(DebugService::new(engines)));
+                let debug_service = DebugService::new(engines, resolved_ts, storage.clone());
+                sb = sb.register_service(create_debug(debug_service.clone()))
+                    .register_service(create_debug_ext(debug_service));
+            }
+            if let Some(cdc_service) = cdc_service {
+                sb = sb.register_service(create_change_data(cdc_service));
             }
             sb.build()?
         };
@@ -254,6 +263,8 @@ mod tests {
             SnapManager::new("", None),
             pd_worker.scheduler(),
             None,
+            ResolvedTsTracker::new(),
+            None,
         ).unwrap();
         *addr.lock().unwrap() = Some(server.listening_addr());
 
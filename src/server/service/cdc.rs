@@ -0,0 +1,102 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use grpc::{Error as GrpcError, RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink,
+           WriteFlags};
+use futures::{future, stream, Future, Stream};
+use futures_cpupool::{Builder, CpuPool};
+use rocksdb::DB;
+
+use cdc::{self, CdcHub, RowChange};
+use raftstore::Error;
+use super::extpb;
+
+/// Serves the subscriptions of `CdcHub` to the clients.
+#[derive(Clone)]
+pub struct Service {
+    hub: CdcHub,
+    engine: Arc<DB>,
+    // For the incremental scans.
+    pool: CpuPool,
+}
+
+impl Service {
+    pub fn new(hub: CdcHub, engine: Arc<DB>) -> Service {
+        let pool = Builder::new()
+            .name_prefix(thd_name!("cdc-scan"))
+            .pool_size(1)
+            .create();
+        Service {
+            hub: hub,
+            engine: engine,
+            pool: pool,
+        }
+    }
+
+    fn error_to_grpc_error(e: Error) -> GrpcError {
+        let code = match e {
+            Error::RegionNotFound(_) => RpcStatusCode::NotFound,
+            _ => RpcStatusCode::Aborted,
+        };
+        GrpcError::RpcFailure(RpcStatus::new(code, Some(format!("{:?}", e))))
+    }
+}
+
+fn new_event(change: RowChange) -> extpb::ChangeDataEvent {
+    extpb::ChangeDataEvent {
+        key: change.key,
+        value: change.value,
+        start_ts: change.start_ts,
+        commit_ts: change.commit_ts,
+    }
+}
+
+impl extpb::ChangeData for Service {
+    fn event_feed(
+        &self,
+        _: RpcContext,
+        req: extpb::ChangeDataRequest,
+        sink: ServerStreamingSink<extpb::ChangeDataEvent>,
+    ) {
+        const TAG: &'static str = "cdc_event_feed";
+
+        let region_id = req.region_id;
+        // Subscribe before the scan, so the rows committed during the scan are not missed.
+        let (id, changes) = self.hub.subscribe(region_id);
+        let (hub, engine) = (self.hub.clone(), self.engine.clone());
+        let future = future::lazy(move || {
+            let mut rows = vec![];
+            let res = cdc::incremental_scan(&engine, region_id, req.checkpoint_ts, |c| {
+                rows.push(c)
+            });
+            res.map(|_| rows)
+        }).map_err(Service::error_to_grpc_error)
+            .and_then(|rows| {
+                stream::iter_ok(rows)
+                    .chain(changes.map_err(Service::error_to_grpc_error))
+                    .map(|c| (new_event(c), WriteFlags::default()))
+                    .forward(sink)
+                    .map(|_| ())
+            })
+            .then(move |res| {
+                hub.unsubscribe(region_id, id);
+                if let Err(e) = res {
+                    error!("{} failed: {:?}", TAG, e);
+                }
+                Ok::<_, ()>(())
+            });
+        self.pool.spawn(future).forget();
+    }
+}
@@ -11,31 +11,65 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::PathBuf;
+use std::time::Duration;
+
 use grpc::{Error as GrpcError, WriteFlags};
 use grpc::{RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink, UnarySink};
 use futures::{future, stream, Future, Stream};
-use futures_cpupool::{Builder, CpuPool};
+use futures::sync::oneshot;
+use futures_cpupool::{Builder, CpuFuture, CpuPool};
 use kvproto::debugpb_grpc;
 use kvproto::debugpb::*;
 use fail;
 
-use raftstore::store::Engines;
-use raftstore::store::debug::{Debugger, Error};
+use import::ImportModeSwitcher;
+use raftstore::store::{Engines, ResolvedTsTracker};
+use raftstore::store::debug::{BackupManifest, Debugger, Error};
+use storage::Storage;
+use util::to_hex;
+use super::extpb;
 
 #[derive(Clone)]
 pub struct Service {
     pool: CpuPool,
     debugger: Debugger,
+    storage: Storage,
 }
 
 impl Service {
-    pub fn new(engines: Engines) -> Service {
+    pub fn new(engines: Engines, resolved_ts: ResolvedTsTracker, storage: Storage) -> Service {
         let pool = Builder::new()
             .name_prefix(thd_name!("debugger"))
             .pool_size(1)
             .create();
-        let debugger = Debugger::new(engines);
-        Service { pool, debugger }
+        let switcher = ImportModeSwitcher::new(engines.kv_engine.clone());
+        let mut debugger = Debugger::new(engines);
+        debugger.set_resolved_ts(resolved_ts);
+        debugger.set_import_mode_switcher(switcher);
+        Service {
+            pool,
+            debugger,
+            storage,
+        }
+    }
+
+    /// Backs up the versions visible at `ts` in the raw key range [start, end) of the regions
+    /// on the running store into `dir`, see `Debugger::backup`. The regions whose resolved ts
+    /// hasn't reached `ts` fail the backup.
+    ///
+    /// The backup runs in the pool of the service, so it doesn't run along with the other
+    /// debug requests. It's served by the `Backup` RPC of `extpb::DebugExt`.
+    pub fn backup(
+        &self,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        ts: u64,
+        dir: PathBuf,
+    ) -> CpuFuture<BackupManifest, Error> {
+        let debugger = self.debugger.clone();
+        self.pool
+            .spawn_fn(move || debugger.backup(&start, &end, ts, &dir))
     }
 
     fn handle_response<F, P>(&self, ctx: RpcContext, sink: UnarySink<P>, resp: F, tag: &'static str)
@@ -293,3 +327,114 @@ impl debugpb_grpc::Debug for Service {
         self.handle_response(ctx, sink, f, TAG);
     }
 }
+
+impl extpb::DebugExt for Service {
+    fn hot_keys(
+        &self,
+        ctx: RpcContext,
+        req: extpb::HotKeysRequest,
+        sink: UnarySink<extpb::HotKeysResponse>,
+    ) {
+        const TAG: &'static str = "debug_hot_keys";
+
+        let (tx, rx) = oneshot::channel();
+        let res = self.storage
+            .async_get_hot_keys(req.limit as usize, box move |keys| {
+                let _ = tx.send(keys);
+            })
+            .map_err(|e| Error::Other(box_err!("{:?}", e)));
+        let f = future::result(res)
+            .and_then(|_| rx.map_err(|e| Error::Other(box_err!("{:?}", e))))
+            .and_then(|keys| keys.map_err(|e| Error::Other(box_err!("{:?}", e))))
+            .map(|keys| {
+                let keys = keys.into_iter()
+                    .map(|(key, wait)| extpb::HotKey {
+                        key: to_hex(key.encoded()),
+                        latch_wait_secs: wait,
+                    })
+                    .collect();
+                extpb::HotKeysResponse { keys: keys }
+            });
+
+        self.handle_response(ctx, sink, f, TAG);
+    }
+
+    fn resolved_ts(
+        &self,
+        ctx: RpcContext,
+        req: extpb::ResolvedTsRequest,
+        sink: UnarySink<extpb::ResolvedTsResponse>,
+    ) {
+        const TAG: &'static str = "debug_resolved_ts";
+
+        let debugger = self.debugger.clone();
+        let f = self.pool.spawn_fn(move || {
+            debugger
+                .resolved_ts(req.region_id)
+                .map(|ts| extpb::ResolvedTsResponse { resolved_ts: ts })
+        });
+
+        self.handle_response(ctx, sink, f, TAG);
+    }
+
+    fn backup(
+        &self,
+        ctx: RpcContext,
+        req: extpb::BackupRequest,
+        sink: UnarySink<extpb::BackupResponse>,
+    ) {
+        const TAG: &'static str = "debug_backup";
+
+        let f = Service::backup(
+            self,
+            req.start_key,
+            req.end_key,
+            req.backup_ts,
+            PathBuf::from(req.path),
+        ).map(|manifest| extpb::BackupResponse { manifest: manifest });
+
+        self.handle_response(ctx, sink, f, TAG);
+    }
+
+    fn import_mode(
+        &self,
+        ctx: RpcContext,
+        _: extpb::ImportModeRequest,
+        sink: UnarySink<extpb::ImportModeResponse>,
+    ) {
+        const TAG: &'static str = "debug_import_mode";
+
+        let debugger = self.debugger.clone();
+        let f = self.pool.spawn_fn(move || {
+            debugger
+                .is_import_mode()
+                .map(|import| extpb::ImportModeResponse { import: import })
+        });
+
+        self.handle_response(ctx, sink, f, TAG);
+    }
+
+    fn switch_import_mode(
+        &self,
+        ctx: RpcContext,
+        req: extpb::SwitchImportModeRequest,
+        sink: UnarySink<extpb::SwitchImportModeResponse>,
+    ) {
+        const TAG: &'static str = "debug_switch_import_mode";
+
+        let debugger = self.debugger.clone();
+        let f = self.pool.spawn_fn(move || {
+            if req.import && req.timeout_secs == 0 {
+                return Err(Error::InvalidArgument(
+                    "import mode timeout must be positive".to_owned(),
+                ));
+            }
+            let timeout = Duration::from_secs(req.timeout_secs);
+            debugger
+                .switch_import_mode(req.import, timeout)
+                .map(|_| extpb::SwitchImportModeResponse {})
+        });
+
+        self.handle_response(ctx, sink, f, TAG);
+    }
+}
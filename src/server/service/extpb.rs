@@ -0,0 +1,781 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The RPCs which the kvproto in use doesn't define yet.
+//!
+//! They are served by the same gRPC server as the services of kvproto, under the `tikvext`
+//! package. The requests and the new responses are encoded in JSON, and the kvproto messages
+//! they carry, like `kvrpcpb::Context`, are embedded in their protobuf encoding, where an
+//! empty one stands for the default message. A response which kvproto already has, like the
+//! one of a prewrite, is encoded in protobuf as it is.
+
+use protobuf::{self, Message, MessageStatic, ProtobufResult};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use grpc::{self, CallOption, Channel, Client, ClientSStreamReceiver, Marshaller, Method,
+           MethodType, RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink,
+           ServiceBuilder, UnarySink};
+use grpc::{pb_de, pb_ser};
+use kvproto::kvrpcpb;
+use raftstore::store::debug::BackupManifest;
+
+fn json_ser<T: Serialize>(t: &T, buf: &mut Vec<u8>) {
+    serde_json::to_writer(buf, t).unwrap()
+}
+
+fn json_de<T: DeserializeOwned>(buf: &[u8]) -> grpc::Result<T> {
+    serde_json::from_slice(buf).map_err(|e| {
+        let status = RpcStatus::new(RpcStatusCode::InvalidArgument, Some(format!("{}", e)));
+        grpc::Error::RpcFailure(status)
+    })
+}
+
+/// Encodes a kvproto message to be embedded in a message here.
+pub fn encode<M: Message>(m: &M) -> Vec<u8> {
+    m.write_to_bytes().unwrap()
+}
+
+/// Decodes a kvproto message embedded in a message here.
+pub fn decode<M: Message + MessageStatic>(buf: &[u8]) -> ProtobufResult<M> {
+    protobuf::parse_from_bytes(buf)
+}
+
+macro_rules! json_method {
+    ($ty:ident, $name:expr) => {
+        Method {
+            ty: MethodType::$ty,
+            name: $name,
+            req_mar: Marshaller { ser: json_ser, de: json_de },
+            resp_mar: Marshaller { ser: json_ser, de: json_de },
+        }
+    }
+}
+
+macro_rules! pb_resp_method {
+    ($ty:ident, $name:expr) => {
+        Method {
+            ty: MethodType::$ty,
+            name: $name,
+            req_mar: Marshaller { ser: json_ser, de: json_de },
+            resp_mar: Marshaller { ser: pb_ser, de: pb_de },
+        }
+    }
+}
+
+/// A `kvrpcpb::PrewriteRequest` with the options of pessimistic transactions.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PrewriteRequest {
+    // The encoded `kvrpcpb::PrewriteRequest`.
+    pub request: Vec<u8>,
+    // Non-zero for pessimistic transactions.
+    pub for_update_ts: u64,
+    // Whether each mutation is protected by a pessimistic lock.
+    pub is_pessimistic_lock: Vec<bool>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PessimisticLockRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub keys: Vec<Vec<u8>>,
+    pub primary_lock: Vec<u8>,
+    pub start_version: u64,
+    pub for_update_ts: u64,
+    pub lock_ttl: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PessimisticLockResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    // The encoded `kvrpcpb::KeyError`s.
+    pub errors: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PessimisticRollbackRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub keys: Vec<Vec<u8>>,
+    pub start_version: u64,
+    pub for_update_ts: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PessimisticRollbackResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    // The encoded `kvrpcpb::KeyError`, empty if there is no error.
+    pub error: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TxnHeartBeatRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub primary_lock: Vec<u8>,
+    pub start_version: u64,
+    // The lock TTL is raised to it, but never lowered.
+    pub advise_lock_ttl: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TxnHeartBeatResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    // The encoded `kvrpcpb::KeyError`, empty if there is no error.
+    pub error: Vec<u8>,
+    // The TTL of the lock after the heart beat.
+    pub lock_ttl: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CheckTxnStatusRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub primary_key: Vec<u8>,
+    pub lock_ts: u64,
+    // The primary lock is rolled back if it has expired at the ts.
+    pub current_ts: u64,
+}
+
+/// The transaction is alive if `lock_ttl` is not 0, committed if `commit_version` is not 0, or
+/// rolled back otherwise.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CheckTxnStatusResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    // The encoded `kvrpcpb::KeyError`, empty if there is no error.
+    pub error: Vec<u8>,
+    pub lock_ttl: u64,
+    pub commit_version: u64,
+}
+
+/// Scans a range from one snapshot, the pairs are streamed back in batches.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScanStreamRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub start_key: Vec<u8>,
+    // Empty if the range is unbounded.
+    pub end_key: Vec<u8>,
+    pub batch_size: u64,
+    pub version: u64,
+    pub key_only: bool,
+}
+
+/// A batch of a streaming scan. The stream ends after a response with an error.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScanStreamResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    // The encoded `kvrpcpb::KeyError`, empty if there is no error.
+    pub error: Vec<u8>,
+    // The encoded `kvrpcpb::KvPair`s, a locked key comes with its error.
+    pub pairs: Vec<Vec<u8>>,
+}
+
+/// A `kvrpcpb::RawPutRequest` with the TTL of the pair.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawPutRequest {
+    // The encoded `kvrpcpb::RawPutRequest`.
+    pub request: Vec<u8>,
+    // In seconds, 0 if the pair never expires.
+    pub ttl: u64,
+}
+
+/// A `kvrpcpb::RawScanRequest` with the order of the scan.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawScanRequest {
+    // The encoded `kvrpcpb::RawScanRequest`.
+    pub request: Vec<u8>,
+    // Scans backward from the start key, which is excluded, if true.
+    pub reverse: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawBatchPutRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    // Empty for the default column family, which is the only one raw writes can go to.
+    pub cf: String,
+    pub pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    // In seconds, 0 if the pairs never expire.
+    pub ttl: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawBatchDeleteRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub cf: String,
+    pub keys: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawDeleteRangeRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub cf: String,
+    pub start_key: Vec<u8>,
+    pub end_key: Vec<u8>,
+}
+
+/// The response of the raw writes above.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawWriteResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawCompareAndSwapRequest {
+    // The encoded `kvrpcpb::Context`.
+    pub context: Vec<u8>,
+    pub cf: String,
+    pub key: Vec<u8>,
+    // None if the key is expected to be absent.
+    pub previous_value: Option<Vec<u8>>,
+    pub value: Vec<u8>,
+    // In seconds, 0 if the pair never expires.
+    pub ttl: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RawCompareAndSwapResponse {
+    // The encoded `errorpb::Error`, empty if there is no region error.
+    pub region_error: Vec<u8>,
+    pub error: String,
+    // The value observed before the swap.
+    pub previous_value: Option<Vec<u8>>,
+    pub succeed: bool,
+}
+
+const METHOD_TIKV_EXT_KV_PREWRITE: Method<PrewriteRequest, kvrpcpb::PrewriteResponse> =
+    pb_resp_method!(Unary, "/tikvext.TikvExt/KvPrewrite");
+
+const METHOD_TIKV_EXT_KV_PESSIMISTIC_LOCK: Method<
+    PessimisticLockRequest,
+    PessimisticLockResponse,
+> = json_method!(Unary, "/tikvext.TikvExt/KvPessimisticLock");
+
+const METHOD_TIKV_EXT_KV_PESSIMISTIC_ROLLBACK: Method<
+    PessimisticRollbackRequest,
+    PessimisticRollbackResponse,
+> = json_method!(Unary, "/tikvext.TikvExt/KvPessimisticRollback");
+
+const METHOD_TIKV_EXT_KV_TXN_HEART_BEAT: Method<TxnHeartBeatRequest, TxnHeartBeatResponse> =
+    json_method!(Unary, "/tikvext.TikvExt/KvTxnHeartBeat");
+
+const METHOD_TIKV_EXT_KV_CHECK_TXN_STATUS: Method<
+    CheckTxnStatusRequest,
+    CheckTxnStatusResponse,
+> = json_method!(Unary, "/tikvext.TikvExt/KvCheckTxnStatus");
+
+const METHOD_TIKV_EXT_KV_SCAN_STREAM: Method<ScanStreamRequest, ScanStreamResponse> =
+    json_method!(ServerStreaming, "/tikvext.TikvExt/KvScanStream");
+
+const METHOD_TIKV_EXT_RAW_PUT: Method<RawPutRequest, kvrpcpb::RawPutResponse> =
+    pb_resp_method!(Unary, "/tikvext.TikvExt/RawPut");
+
+const METHOD_TIKV_EXT_RAW_SCAN: Method<RawScanRequest, kvrpcpb::RawScanResponse> =
+    pb_resp_method!(Unary, "/tikvext.TikvExt/RawScan");
+
+const METHOD_TIKV_EXT_RAW_BATCH_PUT: Method<RawBatchPutRequest, RawWriteResponse> =
+    json_method!(Unary, "/tikvext.TikvExt/RawBatchPut");
+
+const METHOD_TIKV_EXT_RAW_BATCH_DELETE: Method<RawBatchDeleteRequest, RawWriteResponse> =
+    json_method!(Unary, "/tikvext.TikvExt/RawBatchDelete");
+
+const METHOD_TIKV_EXT_RAW_DELETE_RANGE: Method<RawDeleteRangeRequest, RawWriteResponse> =
+    json_method!(Unary, "/tikvext.TikvExt/RawDeleteRange");
+
+const METHOD_TIKV_EXT_RAW_COMPARE_AND_SWAP: Method<
+    RawCompareAndSwapRequest,
+    RawCompareAndSwapResponse,
+> = json_method!(Unary, "/tikvext.TikvExt/RawCompareAndSwap");
+
+pub struct TikvExtClient {
+    client: Client,
+}
+
+impl TikvExtClient {
+    pub fn new(channel: Channel) -> TikvExtClient {
+        TikvExtClient {
+            client: Client::new(channel),
+        }
+    }
+
+    pub fn kv_prewrite(&self, req: PrewriteRequest) -> grpc::Result<kvrpcpb::PrewriteResponse> {
+        self.client
+            .unary_call(&METHOD_TIKV_EXT_KV_PREWRITE, req, CallOption::default())
+    }
+
+    pub fn kv_pessimistic_lock(
+        &self,
+        req: PessimisticLockRequest,
+    ) -> grpc::Result<PessimisticLockResponse> {
+        self.client.unary_call(
+            &METHOD_TIKV_EXT_KV_PESSIMISTIC_LOCK,
+            req,
+            CallOption::default(),
+        )
+    }
+
+    pub fn kv_pessimistic_rollback(
+        &self,
+        req: PessimisticRollbackRequest,
+    ) -> grpc::Result<PessimisticRollbackResponse> {
+        self.client.unary_call(
+            &METHOD_TIKV_EXT_KV_PESSIMISTIC_ROLLBACK,
+            req,
+            CallOption::default(),
+        )
+    }
+
+    pub fn kv_txn_heart_beat(
+        &self,
+        req: TxnHeartBeatRequest,
+    ) -> grpc::Result<TxnHeartBeatResponse> {
+        self.client.unary_call(
+            &METHOD_TIKV_EXT_KV_TXN_HEART_BEAT,
+            req,
+            CallOption::default(),
+        )
+    }
+
+    pub fn kv_check_txn_status(
+        &self,
+        req: CheckTxnStatusRequest,
+    ) -> grpc::Result<CheckTxnStatusResponse> {
+        self.client.unary_call(
+            &METHOD_TIKV_EXT_KV_CHECK_TXN_STATUS,
+            req,
+            CallOption::default(),
+        )
+    }
+
+    pub fn kv_scan_stream(
+        &self,
+        req: ScanStreamRequest,
+    ) -> ClientSStreamReceiver<ScanStreamResponse> {
+        self.client.server_streaming(
+            &METHOD_TIKV_EXT_KV_SCAN_STREAM,
+            req,
+            CallOption::default(),
+        )
+    }
+
+    pub fn raw_put(&self, req: RawPutRequest) -> grpc::Result<kvrpcpb::RawPutResponse> {
+        self.client
+            .unary_call(&METHOD_TIKV_EXT_RAW_PUT, req, CallOption::default())
+    }
+
+    pub fn raw_scan(&self, req: RawScanRequest) -> grpc::Result<kvrpcpb::RawScanResponse> {
+        self.client
+            .unary_call(&METHOD_TIKV_EXT_RAW_SCAN, req, CallOption::default())
+    }
+
+    pub fn raw_batch_put(&self, req: RawBatchPutRequest) -> grpc::Result<RawWriteResponse> {
+        self.client
+            .unary_call(&METHOD_TIKV_EXT_RAW_BATCH_PUT, req, CallOption::default())
+    }
+
+    pub fn raw_batch_delete(&self, req: RawBatchDeleteRequest) -> grpc::Result<RawWriteResponse> {
+        self.client
+            .unary_call(&METHOD_TIKV_EXT_RAW_BATCH_DELETE, req, CallOption::default())
+    }
+
+    pub fn raw_delete_range(&self, req: RawDeleteRangeRequest) -> grpc::Result<RawWriteResponse> {
+        self.client
+            .unary_call(&METHOD_TIKV_EXT_RAW_DELETE_RANGE, req, CallOption::default())
+    }
+
+    pub fn raw_compare_and_swap(
+        &self,
+        req: RawCompareAndSwapRequest,
+    ) -> grpc::Result<RawCompareAndSwapResponse> {
+        self.client.unary_call(
+            &METHOD_TIKV_EXT_RAW_COMPARE_AND_SWAP,
+            req,
+            CallOption::default(),
+        )
+    }
+}
+
+pub trait TikvExt {
+    fn kv_prewrite(
+        &self,
+        ctx: RpcContext,
+        req: PrewriteRequest,
+        sink: UnarySink<kvrpcpb::PrewriteResponse>,
+    );
+    fn kv_pessimistic_lock(
+        &self,
+        ctx: RpcContext,
+        req: PessimisticLockRequest,
+        sink: UnarySink<PessimisticLockResponse>,
+    );
+    fn kv_pessimistic_rollback(
+        &self,
+        ctx: RpcContext,
+        req: PessimisticRollbackRequest,
+        sink: UnarySink<PessimisticRollbackResponse>,
+    );
+    fn kv_txn_heart_beat(
+        &self,
+        ctx: RpcContext,
+        req: TxnHeartBeatRequest,
+        sink: UnarySink<TxnHeartBeatResponse>,
+    );
+    fn kv_check_txn_status(
+        &self,
+        ctx: RpcContext,
+        req: CheckTxnStatusRequest,
+        sink: UnarySink<CheckTxnStatusResponse>,
+    );
+    fn kv_scan_stream(
+        &self,
+        ctx: RpcContext,
+        req: ScanStreamRequest,
+        sink: ServerStreamingSink<ScanStreamResponse>,
+    );
+    fn raw_put(
+        &self,
+        ctx: RpcContext,
+        req: RawPutRequest,
+        sink: UnarySink<kvrpcpb::RawPutResponse>,
+    );
+    fn raw_scan(
+        &self,
+        ctx: RpcContext,
+        req: RawScanRequest,
+        sink: UnarySink<kvrpcpb::RawScanResponse>,
+    );
+    fn raw_batch_put(
+        &self,
+        ctx: RpcContext,
+        req: RawBatchPutRequest,
+        sink: UnarySink<RawWriteResponse>,
+    );
+    fn raw_batch_delete(
+        &self,
+        ctx: RpcContext,
+        req: RawBatchDeleteRequest,
+        sink: UnarySink<RawWriteResponse>,
+    );
+    fn raw_delete_range(
+        &self,
+        ctx: RpcContext,
+        req: RawDeleteRangeRequest,
+        sink: UnarySink<RawWriteResponse>,
+    );
+    fn raw_compare_and_swap(
+        &self,
+        ctx: RpcContext,
+        req: RawCompareAndSwapRequest,
+        sink: UnarySink<RawCompareAndSwapResponse>,
+    );
+}
+
+pub fn create_tikv_ext<S: TikvExt + Send + Clone + 'static>(s: S) -> grpc::Service {
+    let mut builder = ServiceBuilder::new();
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_TIKV_EXT_KV_PREWRITE, move |ctx, req, resp| {
+        instance.kv_prewrite(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_KV_PESSIMISTIC_LOCK,
+        move |ctx, req, resp| instance.kv_pessimistic_lock(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_KV_PESSIMISTIC_ROLLBACK,
+        move |ctx, req, resp| instance.kv_pessimistic_rollback(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_KV_TXN_HEART_BEAT,
+        move |ctx, req, resp| instance.kv_txn_heart_beat(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_KV_CHECK_TXN_STATUS,
+        move |ctx, req, resp| instance.kv_check_txn_status(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_server_streaming_handler(
+        &METHOD_TIKV_EXT_KV_SCAN_STREAM,
+        move |ctx, req, resp| instance.kv_scan_stream(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_TIKV_EXT_RAW_PUT, move |ctx, req, resp| {
+        instance.raw_put(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_TIKV_EXT_RAW_SCAN, move |ctx, req, resp| {
+        instance.raw_scan(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_RAW_BATCH_PUT,
+        move |ctx, req, resp| instance.raw_batch_put(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_RAW_BATCH_DELETE,
+        move |ctx, req, resp| instance.raw_batch_delete(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_RAW_DELETE_RANGE,
+        move |ctx, req, resp| instance.raw_delete_range(ctx, req, resp),
+    );
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_TIKV_EXT_RAW_COMPARE_AND_SWAP,
+        move |ctx, req, resp| instance.raw_compare_and_swap(ctx, req, resp),
+    );
+    builder.build()
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HotKeysRequest {
+    pub limit: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HotKey {
+    // The upper case hex of the encoded key, which is the user key itself for raw commands.
+    pub key: String,
+    // The decayed time commands have waited for the latch of the key.
+    pub latch_wait_secs: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HotKeysResponse {
+    // The hottest first.
+    pub keys: Vec<HotKey>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResolvedTsRequest {
+    pub region_id: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResolvedTsResponse {
+    pub resolved_ts: u64,
+}
+
+/// Backs up a raw key range of the regions on the store, see `Debugger::backup`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BackupRequest {
+    pub start_key: Vec<u8>,
+    // Empty if the range is unbounded.
+    pub end_key: Vec<u8>,
+    pub backup_ts: u64,
+    // The directory on the server to save the SST files and the manifest.
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BackupResponse {
+    pub manifest: BackupManifest,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ImportModeRequest {}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ImportModeResponse {
+    pub import: bool,
+}
+
+/// Enters or renews the import mode of the KV engine for `timeout_secs`, or switches it back
+/// to the normal mode, see `import::ImportModeSwitcher`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SwitchImportModeRequest {
+    pub import: bool,
+    pub timeout_secs: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SwitchImportModeResponse {}
+
+const METHOD_DEBUG_EXT_HOT_KEYS: Method<HotKeysRequest, HotKeysResponse> =
+    json_method!(Unary, "/tikvext.DebugExt/HotKeys");
+
+const METHOD_DEBUG_EXT_RESOLVED_TS: Method<ResolvedTsRequest, ResolvedTsResponse> =
+    json_method!(Unary, "/tikvext.DebugExt/ResolvedTs");
+
+const METHOD_DEBUG_EXT_BACKUP: Method<BackupRequest, BackupResponse> =
+    json_method!(Unary, "/tikvext.DebugExt/Backup");
+
+const METHOD_DEBUG_EXT_IMPORT_MODE: Method<ImportModeRequest, ImportModeResponse> =
+    json_method!(Unary, "/tikvext.DebugExt/ImportMode");
+
+const METHOD_DEBUG_EXT_SWITCH_IMPORT_MODE: Method<
+    SwitchImportModeRequest,
+    SwitchImportModeResponse,
+> = json_method!(Unary, "/tikvext.DebugExt/SwitchImportMode");
+
+pub struct DebugExtClient {
+    client: Client,
+}
+
+impl DebugExtClient {
+    pub fn new(channel: Channel) -> DebugExtClient {
+        DebugExtClient {
+            client: Client::new(channel),
+        }
+    }
+
+    pub fn hot_keys(&self, req: HotKeysRequest) -> grpc::Result<HotKeysResponse> {
+        self.client
+            .unary_call(&METHOD_DEBUG_EXT_HOT_KEYS, req, CallOption::default())
+    }
+
+    pub fn resolved_ts(&self, req: ResolvedTsRequest) -> grpc::Result<ResolvedTsResponse> {
+        self.client
+            .unary_call(&METHOD_DEBUG_EXT_RESOLVED_TS, req, CallOption::default())
+    }
+
+    pub fn backup(&self, req: BackupRequest) -> grpc::Result<BackupResponse> {
+        self.client
+            .unary_call(&METHOD_DEBUG_EXT_BACKUP, req, CallOption::default())
+    }
+
+    pub fn import_mode(&self, req: ImportModeRequest) -> grpc::Result<ImportModeResponse> {
+        self.client
+            .unary_call(&METHOD_DEBUG_EXT_IMPORT_MODE, req, CallOption::default())
+    }
+
+    pub fn switch_import_mode(
+        &self,
+        req: SwitchImportModeRequest,
+    ) -> grpc::Result<SwitchImportModeResponse> {
+        self.client
+            .unary_call(&METHOD_DEBUG_EXT_SWITCH_IMPORT_MODE, req, CallOption::default())
+    }
+}
+
+pub trait DebugExt {
+    fn hot_keys(&self, ctx: RpcContext, req: HotKeysRequest, sink: UnarySink<HotKeysResponse>);
+    fn resolved_ts(
+        &self,
+        ctx: RpcContext,
+        req: ResolvedTsRequest,
+        sink: UnarySink<ResolvedTsResponse>,
+    );
+    fn backup(&self, ctx: RpcContext, req: BackupRequest, sink: UnarySink<BackupResponse>);
+    fn import_mode(
+        &self,
+        ctx: RpcContext,
+        req: ImportModeRequest,
+        sink: UnarySink<ImportModeResponse>,
+    );
+    fn switch_import_mode(
+        &self,
+        ctx: RpcContext,
+        req: SwitchImportModeRequest,
+        sink: UnarySink<SwitchImportModeResponse>,
+    );
+}
+
+pub fn create_debug_ext<S: DebugExt + Send + Clone + 'static>(s: S) -> grpc::Service {
+    let mut builder = ServiceBuilder::new();
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_DEBUG_EXT_HOT_KEYS, move |ctx, req, resp| {
+        instance.hot_keys(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_DEBUG_EXT_RESOLVED_TS, move |ctx, req, resp| {
+        instance.resolved_ts(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_DEBUG_EXT_BACKUP, move |ctx, req, resp| {
+        instance.backup(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(&METHOD_DEBUG_EXT_IMPORT_MODE, move |ctx, req, resp| {
+        instance.import_mode(ctx, req, resp)
+    });
+    let instance = s.clone();
+    builder = builder.add_unary_handler(
+        &METHOD_DEBUG_EXT_SWITCH_IMPORT_MODE,
+        move |ctx, req, resp| instance.switch_import_mode(ctx, req, resp),
+    );
+    builder.build()
+}
+
+/// Subscribes the rows committed in a region after `checkpoint_ts`, see `cdc::CdcHub`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChangeDataRequest {
+    pub region_id: u64,
+    pub checkpoint_ts: u64,
+}
+
+/// A row committed in the subscribed region. The rows caught up by the incremental scan come
+/// first, in the order of the keys, then the ones applied afterwards, in the order they are
+/// applied.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChangeDataEvent {
+    pub key: Vec<u8>,
+    // `None` if the row is deleted.
+    pub value: Option<Vec<u8>>,
+    pub start_ts: u64,
+    pub commit_ts: u64,
+}
+
+const METHOD_CHANGE_DATA_EVENT_FEED: Method<ChangeDataRequest, ChangeDataEvent> =
+    json_method!(ServerStreaming, "/tikvext.ChangeData/EventFeed");
+
+pub struct ChangeDataClient {
+    client: Client,
+}
+
+impl ChangeDataClient {
+    pub fn new(channel: Channel) -> ChangeDataClient {
+        ChangeDataClient {
+            client: Client::new(channel),
+        }
+    }
+
+    pub fn event_feed(&self, req: ChangeDataRequest) -> ClientSStreamReceiver<ChangeDataEvent> {
+        self.client
+            .server_streaming(&METHOD_CHANGE_DATA_EVENT_FEED, req, CallOption::default())
+    }
+}
+
+pub trait ChangeData {
+    fn event_feed(
+        &self,
+        ctx: RpcContext,
+        req: ChangeDataRequest,
+        sink: ServerStreamingSink<ChangeDataEvent>,
+    );
+}
+
+pub fn create_change_data<S: ChangeData + Send + Clone + 'static>(s: S) -> grpc::Service {
+    let mut builder = ServiceBuilder::new();
+    let instance = s.clone();
+    builder = builder.add_server_streaming_handler(
+        &METHOD_CHANGE_DATA_EVENT_FEED,
+        move |ctx, req, resp| instance.event_feed(ctx, req, resp),
+    );
+    builder.build()
+}
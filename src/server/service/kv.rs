@@ -17,9 +17,11 @@ use std::io::Write;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use mio::Token;
-use grpc::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus, RpcStatusCode, UnarySink};
-use futures::{future, Future, Stream};
+use grpc::{ClientStreamingSink, Error as GrpcError, RequestStream, RpcContext, RpcStatus,
+           RpcStatusCode, ServerStreamingSink, UnarySink, WriteFlags};
+use futures::{future, stream, Future, Stream};
 use futures::sync::oneshot;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
 use protobuf::RepeatedField;
 use kvproto::tikvpb_grpc;
 use kvproto::raft_serverpb::*;
@@ -29,7 +31,7 @@ use kvproto::errorpb::{Error as RegionError, ServerIsBusy};
 
 use util::worker::Scheduler;
 use util::buf::PipeBuffer;
-use storage::{self, Key, Mutation, Options, Storage, Value};
+use storage::{self, Key, Mutation, Options, Storage, TxnStatus, Value};
 use storage::txn::Error as TxnError;
 use storage::mvcc::{Error as MvccError, Write as MvccWrite, WriteType};
 use storage::engine::Error as EngineError;
@@ -37,10 +39,12 @@ use server::transport::RaftStoreRouter;
 use server::snap::Task as SnapTask;
 use server::metrics::*;
 use server::Error;
+use super::extpb;
 use raftstore::store::Msg as StoreMessage;
 use coprocessor::{EndPointTask, RequestTask};
 
 const SCHEDULER_IS_BUSY: &'static str = "scheduler is busy";
+const SCAN_STREAM_POOL_SIZE: usize = 2;
 
 #[derive(Clone)]
 pub struct Service<T: RaftStoreRouter + 'static> {
@@ -52,6 +56,8 @@ pub struct Service<T: RaftStoreRouter + 'static> {
     ch: T,
     // For handling snapshot.
     snap_scheduler: Scheduler<SnapTask>,
+    // For reading the batches of streaming scans.
+    scan_stream_pool: CpuPool,
     token: Arc<AtomicUsize>, // TODO: remove it.
 }
 
@@ -67,6 +73,10 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
             end_point_scheduler: end_point_scheduler,
             ch: ch,
             snap_scheduler: snap_scheduler,
+            scan_stream_pool: CpuPoolBuilder::new()
+                .name_prefix(thd_name!("scan-stream"))
+                .pool_size(SCAN_STREAM_POOL_SIZE)
+                .create(),
             token: Arc::new(AtomicUsize::new(1)),
         }
     }
@@ -81,6 +91,220 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
         let status = RpcStatus::new(code, Some(format!("{}", err)));
         ctx.spawn(sink.fail(status).map_err(|_| ()));
     }
+
+    /// Prewrites the mutations of `req` with `options`, whose lock TTL and constraint check are
+    /// taken from `req`.
+    fn prewrite(
+        &self,
+        ctx: RpcContext,
+        mut req: PrewriteRequest,
+        mut options: Options,
+        sink: UnarySink<PrewriteResponse>,
+        label: &'static str,
+    ) {
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let mutations = req.take_mutations()
+            .into_iter()
+            .map(|mut x| match x.get_op() {
+                Op::Put => Mutation::Put((Key::from_raw(x.get_key()), x.take_value())),
+                Op::Del => Mutation::Delete(Key::from_raw(x.get_key())),
+                Op::Lock => Mutation::Lock(Key::from_raw(x.get_key())),
+                _ => panic!("mismatch Op in prewrite mutations"),
+            })
+            .collect();
+        options.lock_ttl = req.get_lock_ttl();
+        options.skip_constraint_check = req.get_skip_constraint_check();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_prewrite(
+            req.take_context(),
+            mutations,
+            req.take_primary_lock(),
+            req.get_start_version(),
+            options,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = PrewriteResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_errors(RepeatedField::from_vec(extract_key_errors(v)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    /// Puts the pair of `req`, which expires after `ttl` seconds unless `ttl` is 0.
+    fn raw_put_with_ttl(
+        &self,
+        ctx: RpcContext,
+        mut req: RawPutRequest,
+        ttl: u64,
+        sink: UnarySink<RawPutResponse>,
+        label: &'static str,
+    ) {
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_put(
+            req.take_context(),
+            String::new(),
+            req.take_key(),
+            req.take_value(),
+            ttl,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawPutResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    /// Scans the pairs from the start key of `req`, backward from it if `reverse` is true.
+    fn raw_scan_with_order(
+        &self,
+        ctx: RpcContext,
+        mut req: RawScanRequest,
+        reverse: bool,
+        sink: UnarySink<RawScanResponse>,
+        label: &'static str,
+    ) {
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_scan(
+            req.take_context(),
+            String::new(),
+            req.take_start_key(),
+            req.get_limit() as usize,
+            reverse,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawScanResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_kvs(RepeatedField::from_vec(extract_kv_pairs(v)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    /// Writes raw keys with `write`, which is given the decoded `context` of the request.
+    fn raw_write<F>(
+        &self,
+        ctx: RpcContext,
+        context: &[u8],
+        sink: UnarySink<extpb::RawWriteResponse>,
+        label: &'static str,
+        write: F,
+    ) where
+        F: FnOnce(&Storage, Context, storage::Callback<()>) -> storage::Result<()>,
+    {
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(context) {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+
+        let (cb, future) = make_callback();
+        match write(&self.storage, context, cb) {
+            Ok(_) => {}
+            Err(e @ storage::Error::InvalidCf(_)) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+                return;
+            }
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = extpb::RawWriteResponse::default();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.region_error = extpb::encode(&err);
+                } else if let Err(e) = v {
+                    resp.error = format!("{}", e);
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
 }
 
 fn make_callback<T: Debug + Send + 'static>() -> (Box<FnBox(T) + Send>, oneshot::Receiver<T>) {
@@ -101,6 +325,7 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
             req.take_context(),
             Key::from_raw(req.get_key()),
             req.get_version(),
+            Options::default(),
             cb,
         );
         if let Err(e) = res {
@@ -181,60 +406,10 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
     fn kv_prewrite(
         &self,
         ctx: RpcContext,
-        mut req: PrewriteRequest,
+        req: PrewriteRequest,
         sink: UnarySink<PrewriteResponse>,
     ) {
-        let label = "kv_prewrite";
-        let timer = GRPC_MSG_HISTOGRAM_VEC
-            .with_label_values(&[label])
-            .start_coarse_timer();
-
-        let mutations = req.take_mutations()
-            .into_iter()
-            .map(|mut x| match x.get_op() {
-                Op::Put => Mutation::Put((Key::from_raw(x.get_key()), x.take_value())),
-                Op::Del => Mutation::Delete(Key::from_raw(x.get_key())),
-                Op::Lock => Mutation::Lock(Key::from_raw(x.get_key())),
-                _ => panic!("mismatch Op in prewrite mutations"),
-            })
-            .collect();
-        let mut options = Options::default();
-        options.lock_ttl = req.get_lock_ttl();
-        options.skip_constraint_check = req.get_skip_constraint_check();
-
-        let (cb, future) = make_callback();
-        let res = self.storage.async_prewrite(
-            req.take_context(),
-            mutations,
-            req.take_primary_lock(),
-            req.get_start_version(),
-            options,
-            cb,
-        );
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
-            .map(|v| {
-                let mut resp = PrewriteResponse::new();
-                if let Some(err) = extract_region_error(&v) {
-                    resp.set_region_error(err);
-                } else {
-                    resp.set_errors(RepeatedField::from_vec(extract_key_errors(v)));
-                }
-                resp
-            })
-            .and_then(|res| sink.success(res).map_err(Error::from))
-            .map(|_| timer.observe_duration())
-            .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
-                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
-            });
-
-        ctx.spawn(future);
+        self.prewrite(ctx, req, Options::default(), sink, "kv_prewrite");
     }
 
     fn kv_commit(&self, ctx: RpcContext, mut req: CommitRequest, sink: UnarySink<CommitResponse>) {
@@ -279,8 +454,12 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_import(&self, _: RpcContext, _: ImportRequest, _: UnarySink<ImportResponse>) {
-        unimplemented!();
+    fn kv_import(&self, ctx: RpcContext, _: ImportRequest, sink: UnarySink<ImportResponse>) {
+        // Imported data has to reach all the replicas through a Raft command, which the
+        // protocol doesn't have, and the request doesn't carry the region to import into.
+        let msg = "import is not supported by the protocol".to_owned();
+        let status = RpcStatus::new(RpcStatusCode::Unimplemented, Some(msg));
+        ctx.spawn(sink.fail(status).map_err(|_| ()));
     }
 
     fn kv_cleanup(
@@ -349,7 +528,7 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let (cb, future) = make_callback();
         let res = self.storage
-            .async_batch_get(req.take_context(), keys, req.get_version(), cb);
+            .async_batch_get(req.take_context(), keys, req.get_version(), Options::default(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -596,7 +775,7 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let (cb, future) = make_callback();
         let res = self.storage
-            .async_raw_get(req.take_context(), req.take_key(), cb);
+            .async_raw_get(req.take_context(), String::new(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -627,19 +806,28 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_scan(&self, ctx: RpcContext, mut req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
-        let label = "raw_scan";
+    fn raw_scan(&self, ctx: RpcContext, req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
+        self.raw_scan_with_order(ctx, req, false, sink, "raw_scan");
+    }
+
+    fn raw_put(&self, ctx: RpcContext, req: RawPutRequest, sink: UnarySink<RawPutResponse>) {
+        self.raw_put_with_ttl(ctx, req, 0, sink, "raw_put");
+    }
+
+    fn raw_delete(
+        &self,
+        ctx: RpcContext,
+        mut req: RawDeleteRequest,
+        sink: UnarySink<RawDeleteResponse>,
+    ) {
+        let label = "raw_delete";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage.async_raw_scan(
-            req.take_context(),
-            req.take_start_key(),
-            req.get_limit() as usize,
-            cb,
-        );
+        let res = self.storage
+            .async_raw_delete(req.take_context(), String::new(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -648,11 +836,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = RawScanResponse::new();
+                let mut resp = RawDeleteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else {
-                    resp.set_kvs(RepeatedField::from_vec(extract_kv_pairs(v)));
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
                 }
                 resp
             })
@@ -666,15 +854,15 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_put(&self, ctx: RpcContext, mut req: RawPutRequest, sink: UnarySink<RawPutResponse>) {
-        let label = "raw_put";
+    fn coprocessor(&self, ctx: RpcContext, req: Request, sink: UnarySink<Response>) {
+        let label = "coprocessor";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_put(req.take_context(), req.take_key(), req.take_value(), cb);
+        let res = self.end_point_scheduler
+            .schedule(EndPointTask::Request(RequestTask::new(req, cb)));
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -682,15 +870,6 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
 
         let future = future
             .map_err(Error::from)
-            .map(|v| {
-                let mut resp = RawPutResponse::new();
-                if let Some(err) = extract_region_error(&v) {
-                    resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    resp.set_error(format!("{}", e));
-                }
-                resp
-            })
             .and_then(|res| sink.success(res).map_err(Error::from))
             .map(|_| timer.observe_duration())
             .map_err(move |e| {
@@ -701,73 +880,7 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_delete(
-        &self,
-        ctx: RpcContext,
-        mut req: RawDeleteRequest,
-        sink: UnarySink<RawDeleteResponse>,
-    ) {
-        let label = "raw_delete";
-        let timer = GRPC_MSG_HISTOGRAM_VEC
-            .with_label_values(&[label])
-            .start_coarse_timer();
-
-        let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_delete(req.take_context(), req.take_key(), cb);
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
-            .map(|v| {
-                let mut resp = RawDeleteResponse::new();
-                if let Some(err) = extract_region_error(&v) {
-                    resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    resp.set_error(format!("{}", e));
-                }
-                resp
-            })
-            .and_then(|res| sink.success(res).map_err(Error::from))
-            .map(|_| timer.observe_duration())
-            .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
-                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
-            });
-
-        ctx.spawn(future);
-    }
-
-    fn coprocessor(&self, ctx: RpcContext, req: Request, sink: UnarySink<Response>) {
-        let label = "coprocessor";
-        let timer = GRPC_MSG_HISTOGRAM_VEC
-            .with_label_values(&[label])
-            .start_coarse_timer();
-
-        let (cb, future) = make_callback();
-        let res = self.end_point_scheduler
-            .schedule(EndPointTask::Request(RequestTask::new(req, cb)));
-        if let Err(e) = res {
-            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
-            return;
-        }
-
-        let future = future
-            .map_err(Error::from)
-            .and_then(|res| sink.success(res).map_err(Error::from))
-            .map(|_| timer.observe_duration())
-            .map_err(move |e| {
-                debug!("{} failed: {:?}", label, e);
-                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
-            });
-
-        ctx.spawn(future);
-    }
-
-    fn raft(
+    fn raft(
         &self,
         ctx: RpcContext,
         stream: RequestStream<RaftMessage>,
@@ -976,6 +1089,489 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
     }
 }
 
+impl<T: RaftStoreRouter + 'static> extpb::TikvExt for Service<T> {
+    fn kv_prewrite(
+        &self,
+        ctx: RpcContext,
+        req: extpb::PrewriteRequest,
+        sink: UnarySink<PrewriteResponse>,
+    ) {
+        let prewrite_req = match extpb::decode(&req.request) {
+            Ok(r) => r,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+        let mut options = Options::default();
+        options.for_update_ts = req.for_update_ts;
+        options.is_pessimistic_lock = req.is_pessimistic_lock;
+        self.prewrite(ctx, prewrite_req, options, sink, "kv_ext_prewrite");
+    }
+
+    fn kv_pessimistic_lock(
+        &self,
+        ctx: RpcContext,
+        req: extpb::PessimisticLockRequest,
+        sink: UnarySink<extpb::PessimisticLockResponse>,
+    ) {
+        let label = "kv_pessimistic_lock";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(&req.context) {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+        let keys = req.keys.iter().map(|k| Key::from_raw(k)).collect();
+        let mut options = Options::default();
+        options.lock_ttl = req.lock_ttl;
+        options.for_update_ts = req.for_update_ts;
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_acquire_pessimistic_lock(
+            context,
+            keys,
+            req.primary_lock,
+            req.start_version,
+            options,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = extpb::PessimisticLockResponse::default();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.region_error = extpb::encode(&err);
+                } else {
+                    resp.errors = extract_key_errors(v)
+                        .iter()
+                        .map(extpb::encode)
+                        .collect();
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_pessimistic_rollback(
+        &self,
+        ctx: RpcContext,
+        req: extpb::PessimisticRollbackRequest,
+        sink: UnarySink<extpb::PessimisticRollbackResponse>,
+    ) {
+        let label = "kv_pessimistic_rollback";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(&req.context) {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+        let keys = req.keys.iter().map(|k| Key::from_raw(k)).collect();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_pessimistic_rollback(
+            context,
+            keys,
+            req.start_version,
+            req.for_update_ts,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = extpb::PessimisticRollbackResponse::default();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.region_error = extpb::encode(&err);
+                } else if let Err(e) = v {
+                    resp.error = extpb::encode(&extract_key_error(&e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_txn_heart_beat(
+        &self,
+        ctx: RpcContext,
+        req: extpb::TxnHeartBeatRequest,
+        sink: UnarySink<extpb::TxnHeartBeatResponse>,
+    ) {
+        let label = "kv_txn_heart_beat";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(&req.context) {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_txn_heart_beat(
+            context,
+            Key::from_raw(&req.primary_lock),
+            req.start_version,
+            req.advise_lock_ttl,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = extpb::TxnHeartBeatResponse::default();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.region_error = extpb::encode(&err);
+                } else {
+                    match v {
+                        Ok(ttl) => resp.lock_ttl = ttl,
+                        Err(e) => resp.error = extpb::encode(&extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_check_txn_status(
+        &self,
+        ctx: RpcContext,
+        req: extpb::CheckTxnStatusRequest,
+        sink: UnarySink<extpb::CheckTxnStatusResponse>,
+    ) {
+        let label = "kv_check_txn_status";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(&req.context) {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_check_txn_status(
+            context,
+            Key::from_raw(&req.primary_key),
+            req.lock_ts,
+            req.current_ts,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = extpb::CheckTxnStatusResponse::default();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.region_error = extpb::encode(&err);
+                } else {
+                    match v {
+                        Ok(TxnStatus::Alive { ttl }) => resp.lock_ttl = ttl,
+                        Ok(TxnStatus::Committed { commit_ts }) => resp.commit_version = commit_ts,
+                        Ok(TxnStatus::RolledBack) => {}
+                        Err(e) => resp.error = extpb::encode(&extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_scan_stream(
+        &self,
+        ctx: RpcContext,
+        req: extpb::ScanStreamRequest,
+        sink: ServerStreamingSink<extpb::ScanStreamResponse>,
+    ) {
+        let label = "kv_scan_stream";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(&req.context) {
+            Ok(c) => c,
+            Err(e) => {
+                let status = RpcStatus::new(RpcStatusCode::InvalidArgument, Some(format!("{}", e)));
+                ctx.spawn(sink.fail(status).map_err(|_| ()));
+                return;
+            }
+        };
+        let end_key = if req.end_key.is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(&req.end_key))
+        };
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_scan_stream(
+            context,
+            Key::from_raw(&req.start_key),
+            end_key,
+            req.batch_size as usize,
+            req.version,
+            req.key_only,
+            cb,
+        );
+        if let Err(e) = res {
+            let status = RpcStatus::new(RpcStatusCode::ResourceExhausted, Some(format!("{}", e)));
+            ctx.spawn(sink.fail(status).map_err(|_| ()));
+            return;
+        }
+
+        // A batch is read only when the sink can take it, so a slow client slows down the scan
+        // instead of having the batches buffered.
+        let future = future
+            .map_err(Error::from)
+            .and_then(move |v| {
+                let responses: Box<Iterator<Item = extpb::ScanStreamResponse> + Send> = match v {
+                    Ok(scan) => box scan.map(|batch| {
+                        let mut resp = extpb::ScanStreamResponse::default();
+                        match batch {
+                            Ok(pairs) => {
+                                let pairs = pairs.into_iter().map(|r| r.map_err(From::from));
+                                resp.pairs = extract_kv_pairs(Ok(pairs.collect()))
+                                    .iter()
+                                    .map(extpb::encode)
+                                    .collect()
+                            }
+                            Err(e) => {
+                                resp.error = extpb::encode(&extract_key_error(&e.into()))
+                            }
+                        }
+                        resp
+                    }),
+                    Err(e) => {
+                        let mut resp = extpb::ScanStreamResponse::default();
+                        let res: storage::Result<()> = Err(e);
+                        if let Some(err) = extract_region_error(&res) {
+                            resp.region_error = extpb::encode(&err);
+                        } else if let Err(ref e) = res {
+                            resp.error = extpb::encode(&extract_key_error(e));
+                        }
+                        box Some(resp).into_iter()
+                    }
+                };
+                stream::iter_ok::<_, GrpcError>(responses.map(|resp| (resp, WriteFlags::default())))
+                    .forward(sink)
+                    .map_err(Error::from)
+            })
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        self.scan_stream_pool.spawn(future).forget();
+    }
+
+    fn raw_put(
+        &self,
+        ctx: RpcContext,
+        req: extpb::RawPutRequest,
+        sink: UnarySink<RawPutResponse>,
+    ) {
+        let put_req = match extpb::decode(&req.request) {
+            Ok(r) => r,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+        self.raw_put_with_ttl(ctx, put_req, req.ttl, sink, "raw_ext_put");
+    }
+
+    fn raw_scan(
+        &self,
+        ctx: RpcContext,
+        req: extpb::RawScanRequest,
+        sink: UnarySink<RawScanResponse>,
+    ) {
+        let scan_req = match extpb::decode(&req.request) {
+            Ok(r) => r,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+        self.raw_scan_with_order(ctx, scan_req, req.reverse, sink, "raw_ext_scan");
+    }
+
+    fn raw_batch_put(
+        &self,
+        ctx: RpcContext,
+        req: extpb::RawBatchPutRequest,
+        sink: UnarySink<extpb::RawWriteResponse>,
+    ) {
+        let extpb::RawBatchPutRequest {
+            context,
+            cf,
+            pairs,
+            ttl,
+        } = req;
+        self.raw_write(ctx, &context, sink, "raw_batch_put", move |storage, c, cb| {
+            storage.async_raw_batch_put(c, cf, pairs, ttl, cb)
+        });
+    }
+
+    fn raw_batch_delete(
+        &self,
+        ctx: RpcContext,
+        req: extpb::RawBatchDeleteRequest,
+        sink: UnarySink<extpb::RawWriteResponse>,
+    ) {
+        let extpb::RawBatchDeleteRequest { context, cf, keys } = req;
+        self.raw_write(ctx, &context, sink, "raw_batch_delete", move |storage, c, cb| {
+            storage.async_raw_batch_delete(c, cf, keys, cb)
+        });
+    }
+
+    fn raw_delete_range(
+        &self,
+        ctx: RpcContext,
+        req: extpb::RawDeleteRangeRequest,
+        sink: UnarySink<extpb::RawWriteResponse>,
+    ) {
+        let extpb::RawDeleteRangeRequest {
+            context,
+            cf,
+            start_key,
+            end_key,
+        } = req;
+        self.raw_write(ctx, &context, sink, "raw_delete_range", move |storage, c, cb| {
+            storage.async_raw_delete_range(c, cf, start_key, end_key, cb)
+        });
+    }
+
+    fn raw_compare_and_swap(
+        &self,
+        ctx: RpcContext,
+        req: extpb::RawCompareAndSwapRequest,
+        sink: UnarySink<extpb::RawCompareAndSwapResponse>,
+    ) {
+        let label = "raw_compare_and_swap";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let context = match extpb::decode(&req.context) {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+        };
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_compare_and_swap(
+            context,
+            req.cf,
+            req.key,
+            req.previous_value,
+            req.value,
+            req.ttl,
+            cb,
+        );
+        match res {
+            Ok(_) => {}
+            Err(e @ storage::Error::InvalidCf(_)) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::InvalidArgument);
+                return;
+            }
+            Err(e) => {
+                self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+                return;
+            }
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = extpb::RawCompareAndSwapResponse::default();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.region_error = extpb::encode(&err);
+                } else {
+                    match v {
+                        Ok((previous_value, succeed)) => {
+                            resp.previous_value = previous_value;
+                            resp.succeed = succeed;
+                        }
+                        Err(e) => resp.error = format!("{}", e),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+}
+
 fn extract_region_error<T>(res: &storage::Result<T>) -> Option<RegionError> {
     use storage::Error;
     match *res {
@@ -1022,10 +1618,15 @@ fn extract_key_error(err: &storage::Error) -> KeyError {
             key_error.set_locked(lock_info);
         }
         storage::Error::Txn(TxnError::Mvcc(MvccError::WriteConflict { .. })) |
-        storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound { .. })) => {
+        storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound { .. })) |
+        storage::Error::Txn(TxnError::CommitTsExpired { .. }) => {
             warn!("txn conflicts: {:?}", err);
             key_error.set_retryable(format!("{:?}", err));
         }
+        storage::Error::Txn(TxnError::Mvcc(MvccError::Deadlock { .. })) => {
+            warn!("txn deadlocks: {:?}", err);
+            key_error.set_abort(format!("{:?}", err));
+        }
         _ => {
             error!("txn aborts: {:?}", err);
             key_error.set_abort(format!("{:?}", err));
@@ -13,6 +13,9 @@
 
 mod kv;
 mod debug;
+mod cdc;
+pub mod extpb;
 
 pub use self::kv::Service as KvService;
 pub use self::debug::Service as DebugService;
+pub use self::cdc::Service as CdcService;
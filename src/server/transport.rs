@@ -47,6 +47,16 @@ pub trait RaftStoreRouter: Send + Clone {
         self.try_send(StoreMsg::new_raft_cmd(req, cb))
     }
 
+    // Send a read RaftCmdRequest at read_ts to local store, which may be served by a follower.
+    fn send_follower_read(
+        &self,
+        req: RaftCmdRequest,
+        read_ts: u64,
+        cb: Callback,
+    ) -> RaftStoreResult<()> {
+        self.try_send(StoreMsg::new_follower_read_cmd(req, read_ts, cb))
+    }
+
     // Send a batch of RaftCmdRequests to local store.
     fn send_batch_commands(
         &self,
@@ -15,7 +15,7 @@ use std::error::Error;
 
 use sys_info;
 
-use util::config;
+use util::config::{self, ReadableDuration, ReadableSize};
 
 pub const DEFAULT_DATA_DIR: &'static str = "";
 pub const DEFAULT_ROCKSDB_SUB_DIR: &'static str = "db";
@@ -29,6 +29,23 @@ const DEFAULT_SCHED_CONCURRENCY: usize = 102400;
 // on average, hence using the 10_000 as the default value here.
 const DEFAULT_SCHED_TOO_BUSY_THRESHOLD: usize = 10_000;
 
+const DEFAULT_SCHED_WAIT_FOR_LOCK_TIMEOUT_MILLIS: u64 = 0;
+
+// Keep it well below the default `raftstore.raft-entry-max-size`.
+const DEFAULT_SCHED_MAX_PROPOSAL_SIZE_MB: u64 = 4;
+
+const DEFAULT_GC_INTERVAL_MINUTES: u64 = 10;
+
+// Throttle the writes before RocksDB stalls them, which happens at 20 L0 files and 64GB
+// pending compaction bytes by default.
+const DEFAULT_FLOW_CONTROL_SOFT_L0_FILES: u64 = 12;
+const DEFAULT_FLOW_CONTROL_HARD_L0_FILES: u64 = 20;
+const DEFAULT_FLOW_CONTROL_SOFT_PENDING_COMPACTION_BYTES_GB: u64 = 16;
+const DEFAULT_FLOW_CONTROL_HARD_PENDING_COMPACTION_BYTES_GB: u64 = 64;
+const DEFAULT_FLOW_CONTROL_SOFT_APPLY_DURATION_MILLIS: u64 = 500;
+const DEFAULT_FLOW_CONTROL_HARD_APPLY_DURATION_MILLIS: u64 = 2000;
+const DEFAULT_FLOW_CONTROL_MAX_DELAY_MILLIS: u64 = 1000;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
@@ -40,6 +57,28 @@ pub struct Config {
     pub scheduler_concurrency: usize,
     pub scheduler_worker_pool_size: usize,
     pub scheduler_too_busy_threshold: usize,
+    pub scheduler_wait_for_lock_timeout: ReadableDuration,
+    // Larger prewrites are split into several Raft proposals, zero disables the splitting.
+    pub scheduler_max_proposal_size: ReadableSize,
+    pub enable_ttl: bool,
+    // Zero disables the built-in GC manager.
+    pub gc_safe_point_lifetime: ReadableDuration,
+    pub gc_interval: ReadableDuration,
+    pub gc_max_bytes_per_sec: ReadableSize,
+    // Drops the stale versions in compaction instead of writing deletions through raft.
+    pub gc_enable_compaction_filter: bool,
+    // Throttles the writes gradually between the soft and hard limits below.
+    pub flow_control_enable: bool,
+    pub flow_control_soft_l0_files: u64,
+    pub flow_control_hard_l0_files: u64,
+    pub flow_control_soft_pending_compaction_bytes: ReadableSize,
+    pub flow_control_hard_pending_compaction_bytes: ReadableSize,
+    pub flow_control_soft_apply_duration: ReadableDuration,
+    pub flow_control_hard_apply_duration: ReadableDuration,
+    pub flow_control_max_delay: ReadableDuration,
+    // Allows the snapshot reads asking for it to be served below the resolved ts of a region by
+    // the local replica.
+    pub enable_stale_read: bool,
 }
 
 impl Default for Config {
@@ -53,6 +92,34 @@ impl Default for Config {
             scheduler_concurrency: DEFAULT_SCHED_CONCURRENCY,
             scheduler_worker_pool_size: if total_cpu >= 16 { 8 } else { 4 },
             scheduler_too_busy_threshold: DEFAULT_SCHED_TOO_BUSY_THRESHOLD,
+            scheduler_wait_for_lock_timeout: ReadableDuration::millis(
+                DEFAULT_SCHED_WAIT_FOR_LOCK_TIMEOUT_MILLIS,
+            ),
+            scheduler_max_proposal_size: ReadableSize::mb(DEFAULT_SCHED_MAX_PROPOSAL_SIZE_MB),
+            enable_ttl: false,
+            gc_safe_point_lifetime: ReadableDuration::secs(0),
+            gc_interval: ReadableDuration::minutes(DEFAULT_GC_INTERVAL_MINUTES),
+            gc_max_bytes_per_sec: ReadableSize(0),
+            gc_enable_compaction_filter: false,
+            flow_control_enable: true,
+            flow_control_soft_l0_files: DEFAULT_FLOW_CONTROL_SOFT_L0_FILES,
+            flow_control_hard_l0_files: DEFAULT_FLOW_CONTROL_HARD_L0_FILES,
+            flow_control_soft_pending_compaction_bytes: ReadableSize::gb(
+                DEFAULT_FLOW_CONTROL_SOFT_PENDING_COMPACTION_BYTES_GB,
+            ),
+            flow_control_hard_pending_compaction_bytes: ReadableSize::gb(
+                DEFAULT_FLOW_CONTROL_HARD_PENDING_COMPACTION_BYTES_GB,
+            ),
+            flow_control_soft_apply_duration: ReadableDuration::millis(
+                DEFAULT_FLOW_CONTROL_SOFT_APPLY_DURATION_MILLIS,
+            ),
+            flow_control_hard_apply_duration: ReadableDuration::millis(
+                DEFAULT_FLOW_CONTROL_HARD_APPLY_DURATION_MILLIS,
+            ),
+            flow_control_max_delay: ReadableDuration::millis(
+                DEFAULT_FLOW_CONTROL_MAX_DELAY_MILLIS,
+            ),
+            enable_stale_read: false,
         }
     }
 }
@@ -62,6 +129,43 @@ impl Config {
         if self.data_dir != DEFAULT_DATA_DIR {
             self.data_dir = config::canonicalize_path(&self.data_dir)?
         }
+        if self.gc_safe_point_lifetime.0.as_secs() > 0 && self.gc_interval.0.as_secs() == 0 {
+            return Err(box_err!("storage.gc-interval should be greater than 0"));
+        }
+        if self.gc_enable_compaction_filter {
+            if self.gc_safe_point_lifetime.0.as_secs() == 0 {
+                return Err(box_err!(
+                    "storage.gc-enable-compaction-filter requires storage.gc-safe-point-lifetime"
+                ));
+            }
+            if self.enable_ttl {
+                return Err(box_err!(
+                    "storage.gc-enable-compaction-filter can't be used with storage.enable-ttl"
+                ));
+            }
+        }
+        if self.flow_control_enable {
+            if self.flow_control_soft_l0_files >= self.flow_control_hard_l0_files {
+                return Err(box_err!(
+                    "storage.flow-control-soft-l0-files should be less than \
+                     storage.flow-control-hard-l0-files"
+                ));
+            }
+            if self.flow_control_soft_pending_compaction_bytes.0
+                >= self.flow_control_hard_pending_compaction_bytes.0
+            {
+                return Err(box_err!(
+                    "storage.flow-control-soft-pending-compaction-bytes should be less than \
+                     storage.flow-control-hard-pending-compaction-bytes"
+                ));
+            }
+            if self.flow_control_soft_apply_duration.0 >= self.flow_control_hard_apply_duration.0 {
+                return Err(box_err!(
+                    "storage.flow-control-soft-apply-duration should be less than \
+                     storage.flow-control-hard-apply-duration"
+                ));
+            }
+        }
         Ok(())
     }
 }
@@ -22,6 +22,7 @@ use rocksdb::TablePropertiesCollection;
 use storage::{CfName, Key, Value, CF_DEFAULT, CF_LOCK, CF_WRITE};
 use kvproto::kvrpcpb::Context;
 use kvproto::errorpb::Error as ErrorHeader;
+use kvproto::metapb::Region;
 
 mod rocksdb;
 pub mod raftkv;
@@ -68,6 +69,24 @@ pub enum Modify {
 pub trait Engine: Send + Debug {
     fn async_write(&self, ctx: &Context, batch: Vec<Modify>, callback: Callback<()>) -> Result<()>;
     fn async_snapshot(&self, ctx: &Context, callback: Callback<Box<Snapshot>>) -> Result<()>;
+    /// Takes a snapshot for reading at `read_ts`, which may be served by any replica whose data
+    /// at `read_ts` is known to be complete. Falls back to `async_snapshot` otherwise.
+    fn async_stale_snapshot(
+        &self,
+        ctx: &Context,
+        _read_ts: u64,
+        callback: Callback<Box<Snapshot>>,
+    ) -> Result<()> {
+        self.async_snapshot(ctx, callback)
+    }
+    /// Records a read at `ts` in the region of `ctx`, so that the one-phase commits in the
+    /// region commit after it.
+    fn update_max_read_ts(&self, _ctx: &Context, _ts: u64) {}
+    /// Returns the max ts of the reads in the region of `ctx` served by any replica, or `None`
+    /// if it's unknown. Engines without replicas leave the tracking to the storage.
+    fn max_read_ts(&self, _ctx: &Context) -> Option<u64> {
+        Some(0)
+    }
     /// Snapshots are token by `Context`s, the results are send to the `on_finished` callback,
     /// with the same order. If a read-index is occurred, a `None` is placed in the corresponding
     /// slot, and the caller is responsible for reissuing it again, in `async_snapshot`.
@@ -135,6 +154,10 @@ pub trait Snapshot: Send {
     fn get_properties_cf(&self, _: CfName) -> Result<TablePropertiesCollection> {
         Err(Error::RocksDb("no user properties".to_owned()))
     }
+    /// Returns the region the snapshot is limited to, `None` if it covers the whole engine.
+    fn get_region(&self) -> Option<&Region> {
+        None
+    }
     fn clone(&self) -> Box<Snapshot>;
 }
 
@@ -263,6 +286,14 @@ impl Statistics {
         self.write.add(&other.write);
         self.data.add(&other.data);
     }
+    pub fn mut_cf_statistics(&mut self, cf: &str) -> &mut CFStatistics {
+        match cf {
+            CF_DEFAULT => &mut self.data,
+            CF_LOCK => &mut self.lock,
+            CF_WRITE => &mut self.write,
+            _ => unreachable!(),
+        }
+    }
 }
 
 #[derive(Default)]
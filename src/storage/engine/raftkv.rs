@@ -13,7 +13,7 @@
 
 
 use server::transport::RaftStoreRouter;
-use raftstore::store;
+use raftstore::store::{self, keys, ResolvedTsTracker};
 use raftstore::errors::Error as RaftServerError;
 use raftstore::coprocessor::{RegionIterator, RegionSnapshot};
 use raftstore::store::engine::{Peekable, Snapshot as EngineSnapshot};
@@ -23,6 +23,8 @@ use kvproto::raft_cmdpb::{CmdType, DeleteRangeRequest, DeleteRequest, PutRequest
                           RaftCmdResponse, RaftRequestHeader, Request, Response};
 use kvproto::errorpb;
 use kvproto::kvrpcpb::Context;
+use kvproto::metapb::Region;
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
 
 use std::sync::Arc;
 use std::fmt::{self, Debug, Formatter};
@@ -35,7 +37,7 @@ use protobuf::RepeatedField;
 use storage::engine;
 use super::{BatchCallback, Callback, CbContext, Cursor, Engine, Iterator as EngineIterator,
             Modify, ScanMode, Snapshot};
-use storage::{CfName, Key, Value, CF_DEFAULT};
+use storage::{CfName, Key, Value, CF_DEFAULT, CF_RAFT};
 use super::metrics::*;
 use raftstore::store::engine::IterOption;
 
@@ -116,6 +118,7 @@ impl From<RaftServerError> for engine::Error {
 pub struct RaftKv<S: RaftStoreRouter + 'static> {
     db: Arc<DB>,
     router: S,
+    resolved_ts: ResolvedTsTracker,
 }
 
 enum CmdRes {
@@ -163,20 +166,55 @@ fn on_result(
 
 impl<S: RaftStoreRouter> RaftKv<S> {
     /// Create a RaftKv using specified configuration.
-    pub fn new(db: Arc<DB>, router: S) -> RaftKv<S> {
+    pub fn new(db: Arc<DB>, router: S, resolved_ts: ResolvedTsTracker) -> RaftKv<S> {
         RaftKv {
             db: db,
             router: router,
+            resolved_ts: resolved_ts,
         }
     }
 
-    fn call_command(&self, req: RaftCmdRequest, cb: Callback<CmdRes>) -> Result<()> {
+    /// Takes a snapshot of the local replica if its data at `read_ts` is complete.
+    fn stale_snapshot(&self, ctx: &Context, read_ts: u64) -> Option<RegionSnapshot> {
+        let (region_id, epoch) = (ctx.get_region_id(), ctx.get_region_epoch());
+        let region = match self.resolved_ts.region_for_read(region_id, epoch, read_ts) {
+            Some(region) => region,
+            None => return None,
+        };
+        // The data of the region may be being replaced by a snapshot, or cleaned up after the
+        // peer is destroyed, which is marked in the region state before touching the data.
+        let snap = EngineSnapshot::new(self.db.clone());
+        let state_key = keys::region_state_key(region_id);
+        match snap.get_msg_cf::<RegionLocalState>(CF_RAFT, &state_key) {
+            Ok(Some(ref state))
+                if state.get_state() == PeerState::Normal &&
+                    state.get_region().get_region_epoch().get_version() ==
+                        region.get_region_epoch().get_version() =>
+            {
+                Some(RegionSnapshot::from_snapshot(snap.into_sync(), region))
+            }
+            _ => None,
+        }
+    }
+
+    /// Sends the command to the raftstore, which serves it by a follower if it's a read at
+    /// the ts of `follower_read`.
+    fn call_command(
+        &self,
+        req: RaftCmdRequest,
+        follower_read: Option<u64>,
+        cb: Callback<CmdRes>,
+    ) -> Result<()> {
         let l = req.get_requests().len();
         let db = self.db.clone();
-        self.router.send_command(req, box move |resp| {
+        let cb: store::Callback = box move |resp| {
             let (cb_ctx, res) = on_result(resp, l, db);
             cb((cb_ctx, res.map_err(Error::into)));
-        })?;
+        };
+        match follower_read {
+            Some(read_ts) => self.router.send_follower_read(req, read_ts, cb)?,
+            None => self.router.send_command(req, cb)?,
+        }
         Ok(())
     }
 
@@ -239,12 +277,64 @@ impl<S: RaftStoreRouter> RaftKv<S> {
         header
     }
 
-    fn exec_requests(&self, ctx: &Context, reqs: Vec<Request>, cb: Callback<CmdRes>) -> Result<()> {
+    fn exec_requests(
+        &self,
+        ctx: &Context,
+        reqs: Vec<Request>,
+        follower_read: Option<u64>,
+        cb: Callback<CmdRes>,
+    ) -> Result<()> {
         let header = self.new_request_header(ctx);
         let mut cmd = RaftCmdRequest::new();
         cmd.set_header(header);
         cmd.set_requests(RepeatedField::from_vec(reqs));
-        self.call_command(cmd, cb)
+        self.call_command(cmd, follower_read, cb)
+    }
+
+    /// Takes a snapshot by the raftstore, which may be served by a follower if `follower_read`
+    /// has the ts of the read.
+    fn snapshot(
+        &self,
+        ctx: &Context,
+        follower_read: Option<u64>,
+        cb: Callback<Box<Snapshot>>,
+    ) -> engine::Result<()> {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Snap);
+
+        ASYNC_REQUESTS_COUNTER_VEC
+            .with_label_values(&["snapshot", "all"])
+            .inc();
+        let req_timer = ASYNC_REQUESTS_DURATIONS_VEC
+            .with_label_values(&["snapshot"])
+            .start_coarse_timer();
+
+        self.exec_requests(ctx, vec![req], follower_read, box move |(cb_ctx, res)| match res {
+            Ok(CmdRes::Resp(r)) => cb((
+                cb_ctx,
+                Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into()),
+            )),
+            Ok(CmdRes::Snap(s)) => {
+                req_timer.observe_duration();
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["snapshot", "success"])
+                    .inc();
+                cb((cb_ctx, Ok(box s)))
+            }
+            Err(e) => {
+                let tag = get_tag_from_engine_error(&e);
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["snapshot", tag])
+                    .inc();
+                cb((cb_ctx, Err(e)))
+            }
+        }).map_err(|e| {
+                let tag = get_tag_from_error(&e);
+                ASYNC_REQUESTS_COUNTER_VEC
+                    .with_label_values(&["snapshot", tag])
+                    .inc();
+                e.into()
+            })
     }
 
     fn batch_exec_snap_requests(
@@ -329,7 +419,7 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             .with_label_values(&["write"])
             .start_coarse_timer();
 
-        self.exec_requests(ctx, reqs, box move |(cb_ctx, res)| match res {
+        self.exec_requests(ctx, reqs, None, box move |(cb_ctx, res)| match res {
             Ok(CmdRes::Resp(_)) => {
                 req_timer.observe_duration();
                 ASYNC_REQUESTS_COUNTER_VEC
@@ -359,42 +449,42 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
     }
 
     fn async_snapshot(&self, ctx: &Context, cb: Callback<Box<Snapshot>>) -> engine::Result<()> {
-        let mut req = Request::new();
-        req.set_cmd_type(CmdType::Snap);
+        self.snapshot(ctx, None, cb)
+    }
 
+    fn async_stale_snapshot(
+        &self,
+        ctx: &Context,
+        read_ts: u64,
+        cb: Callback<Box<Snapshot>>,
+    ) -> engine::Result<()> {
         ASYNC_REQUESTS_COUNTER_VEC
-            .with_label_values(&["snapshot", "all"])
+            .with_label_values(&["stale_snapshot", "all"])
             .inc();
-        let req_timer = ASYNC_REQUESTS_DURATIONS_VEC
-            .with_label_values(&["snapshot"])
-            .start_coarse_timer();
-
-        self.exec_requests(ctx, vec![req], box move |(cb_ctx, res)| match res {
-            Ok(CmdRes::Resp(r)) => cb((
-                cb_ctx,
-                Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into()),
-            )),
-            Ok(CmdRes::Snap(s)) => {
-                req_timer.observe_duration();
+        match self.stale_snapshot(ctx, read_ts) {
+            Some(s) => {
                 ASYNC_REQUESTS_COUNTER_VEC
-                    .with_label_values(&["snapshot", "success"])
+                    .with_label_values(&["stale_snapshot", "success"])
                     .inc();
-                cb((cb_ctx, Ok(box s)))
+                cb((CbContext::new(), Ok(box s)));
+                Ok(())
             }
-            Err(e) => {
-                let tag = get_tag_from_engine_error(&e);
+            None => {
                 ASYNC_REQUESTS_COUNTER_VEC
-                    .with_label_values(&["snapshot", tag])
+                    .with_label_values(&["stale_snapshot", "fallback"])
                     .inc();
-                cb((cb_ctx, Err(e)))
+                // The leader records the read, as one-phase commits must not miss it.
+                self.snapshot(ctx, Some(read_ts), cb)
             }
-        }).map_err(|e| {
-                let tag = get_tag_from_error(&e);
-                ASYNC_REQUESTS_COUNTER_VEC
-                    .with_label_values(&["snapshot", tag])
-                    .inc();
-                e.into()
-            })
+        }
+    }
+
+    fn update_max_read_ts(&self, ctx: &Context, ts: u64) {
+        self.resolved_ts.update_max_read_ts(ctx.get_region_id(), ts);
+    }
+
+    fn max_read_ts(&self, ctx: &Context) -> Option<u64> {
+        self.resolved_ts.max_read_ts(ctx.get_region_id())
     }
 
     fn async_batch_snapshot(
@@ -461,7 +551,11 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
     }
 
     fn clone(&self) -> Box<Engine> {
-        box RaftKv::new(self.db.clone(), self.router.clone())
+        box RaftKv::new(
+            self.db.clone(),
+            self.router.clone(),
+            self.resolved_ts.clone(),
+        )
     }
 }
 
@@ -498,6 +592,10 @@ impl Snapshot for RegionSnapshot {
         RegionSnapshot::get_properties_cf(self, cf).map_err(|e| e.into())
     }
 
+    fn get_region(&self) -> Option<&Region> {
+        Some(RegionSnapshot::get_region(self))
+    }
+
     fn clone(&self) -> Box<Snapshot> {
         Box::new(RegionSnapshot::clone(self))
     }
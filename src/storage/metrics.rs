@@ -63,6 +63,13 @@ lazy_static! {
             exponential_buckets(0.0005, 2.0, 20).unwrap()
         ).unwrap();
 
+    pub static ref SCHED_LATCH_CONTENTION_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_scheduler_latch_contention_duration_seconds",
+            "Bucketed histogram of the time commands wait for latches held by other commands",
+            exponential_buckets(0.0005, 2.0, 20).unwrap()
+        ).unwrap();
+
     pub static ref SCHED_TOO_BUSY_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_too_busy_total",
@@ -70,6 +77,32 @@ lazy_static! {
             &["type"]
         ).unwrap();
 
+    pub static ref SCHED_THROTTLE_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_throttle_total",
+            "Total count of commands throttled by flow control",
+            &["type", "result"]
+        ).unwrap();
+
+    pub static ref SCHED_FLOW_PRESSURE_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_scheduler_flow_pressure",
+            "Pressure of the storage used to throttle writes, from 0 to 1."
+        ).unwrap();
+
+    pub static ref SCHED_DEADLOCK_COUNTER: Counter =
+        register_counter!(
+            "tikv_scheduler_deadlock_total",
+            "Total number of deadlocks detected by scheduler."
+        ).unwrap();
+
+    pub static ref SCHED_LOCK_WAIT_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_lock_wait_total",
+            "Total count of commands waiting for locks",
+            &["type", "result"]
+        ).unwrap();
+
     pub static ref SCHED_COMMANDS_PRI_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_commands_pri_total",
@@ -21,6 +21,7 @@ use std::io::Error as IoError;
 use std::u64;
 use kvproto::kvrpcpb::{CommandPri, LockInfo};
 use kvproto::errorpb;
+use rocksdb::DB;
 use self::metrics::*;
 
 pub mod engine;
@@ -35,8 +36,9 @@ pub use self::engine::{new_local_engine, CFStatistics, Cursor, Engine, Error as
                        FlowStatistics, Modify, ScanMode, Snapshot, Statistics, StatisticsSummary,
                        TEMP_DIR};
 pub use self::engine::raftkv::RaftKv;
-pub use self::txn::{Msg, Scheduler, SnapshotStore, StoreScanner};
-pub use self::types::{make_key, Key, KvPair, MvccInfo, Value};
+pub use self::txn::{FlowController, Msg, ScanStream, Scheduler, SnapshotStore,
+                    StoreScanner};
+pub use self::types::{make_key, Key, KvPair, MvccInfo, TxnStatus, Value};
 pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 
 pub type CfName = &'static str;
@@ -52,6 +54,7 @@ pub const DATA_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE];
 // Short value max len must <= 255.
 pub const SHORT_VALUE_MAX_LEN: usize = 64;
 pub const SHORT_VALUE_PREFIX: u8 = b'v';
+pub const FOR_UPDATE_TS_PREFIX: u8 = b'f';
 
 pub fn is_short_value(value: &[u8]) -> bool {
     value.len() <= SHORT_VALUE_MAX_LEN
@@ -80,11 +83,17 @@ use kvproto::kvrpcpb::Context;
 pub enum StorageCb {
     Boolean(Callback<()>),
     Booleans(Callback<Vec<Result<()>>>),
+    // The results of a one-phase commit prewrite, and whether it's committed in one phase.
+    OnePcPrewrite(Callback<(Vec<Result<()>>, bool)>),
     SingleValue(Callback<Option<Value>>),
     KvPairs(Callback<Vec<Result<KvPair>>>),
     MvccInfoByKey(Callback<MvccInfo>),
     MvccInfoByStartTs(Callback<Option<(Key, MvccInfo)>>),
     Locks(Callback<Vec<LockInfo>>),
+    CompareAndSwap(Callback<(Option<Value>, bool)>),
+    LockTtl(Callback<u64>),
+    TxnStatus(Callback<TxnStatus>),
+    ScanStream(Callback<ScanStream>),
 }
 
 pub enum Command {
@@ -92,11 +101,13 @@ pub enum Command {
         ctx: Context,
         key: Key,
         start_ts: u64,
+        options: Options,
     },
     BatchGet {
         ctx: Context,
         keys: Vec<Key>,
         start_ts: u64,
+        options: Options,
     },
     Scan {
         ctx: Context,
@@ -105,6 +116,14 @@ pub enum Command {
         start_ts: u64,
         options: Options,
     },
+    ScanStream {
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        batch_size: usize,
+        start_ts: u64,
+        key_only: bool,
+    },
     Prewrite {
         ctx: Context,
         mutations: Vec<Mutation>,
@@ -112,6 +131,13 @@ pub enum Command {
         start_ts: u64,
         options: Options,
     },
+    AcquirePessimisticLock {
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    },
     Commit {
         ctx: Context,
         keys: Vec<Key>,
@@ -128,6 +154,27 @@ pub enum Command {
         keys: Vec<Key>,
         start_ts: u64,
     },
+    // Extends the TTL of the primary lock to keep a long running transaction alive.
+    TxnHeartBeat {
+        ctx: Context,
+        primary_key: Key,
+        start_ts: u64,
+        advise_ttl: u64,
+    },
+    PessimisticRollback {
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        for_update_ts: u64,
+    },
+    // Tells the status of the transaction by its primary key, and rolls it back if the primary
+    // lock has expired at `current_ts`.
+    CheckTxnStatus {
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+    },
     ScanLock { ctx: Context, max_ts: u64 },
     ResolveLock {
         ctx: Context,
@@ -143,11 +190,52 @@ pub enum Command {
         scan_key: Option<Key>,
         keys: Vec<Key>,
     },
-    RawGet { ctx: Context, key: Key },
+    RawGet {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+        enable_ttl: bool,
+    },
+    RawBatchGet {
+        ctx: Context,
+        cf: CfName,
+        keys: Vec<Key>,
+        enable_ttl: bool,
+    },
     RawScan {
         ctx: Context,
+        cf: CfName,
         start_key: Key,
         limit: usize,
+        enable_ttl: bool,
+        reverse: bool,
+    },
+    RawCompareAndSwap {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+        previous_value: Option<Value>,
+        value: Value,
+        ttl: u64,
+        enable_ttl: bool,
+    },
+    RawBatchPut {
+        ctx: Context,
+        cf: CfName,
+        pairs: Vec<(Key, Value)>,
+        ttl: u64,
+        enable_ttl: bool,
+    },
+    RawBatchDelete {
+        ctx: Context,
+        cf: CfName,
+        keys: Vec<Key>,
+    },
+    RawDeleteRange {
+        ctx: Context,
+        cf: CfName,
+        start_key: Key,
+        end_key: Key,
     },
     DeleteRange {
         ctx: Context,
@@ -194,6 +282,22 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::ScanStream {
+                ref ctx,
+                ref start_key,
+                ref end_key,
+                batch_size,
+                start_ts,
+                ..
+            } => write!(
+                f,
+                "kv::command::scan_stream {}..{:?}({}) @ {} | {:?}",
+                start_key,
+                end_key,
+                batch_size,
+                start_ts,
+                ctx
+            ),
             Command::Prewrite {
                 ref ctx,
                 ref mutations,
@@ -206,6 +310,20 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::AcquirePessimisticLock {
+                ref ctx,
+                ref keys,
+                start_ts,
+                ref options,
+                ..
+            } => write!(
+                f,
+                "kv::command::acquire_pessimistic_lock keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                options.for_update_ts,
+                ctx
+            ),
             Command::Commit {
                 ref ctx,
                 ref keys,
@@ -226,6 +344,32 @@ impl Display for Command {
                 start_ts,
                 ..
             } => write!(f, "kv::command::cleanup {} @ {} | {:?}", key, start_ts, ctx),
+            Command::CheckTxnStatus {
+                ref ctx,
+                ref primary_key,
+                lock_ts,
+                current_ts,
+            } => write!(
+                f,
+                "kv::command::check_txn_status {} @ {} curr({}) | {:?}",
+                primary_key,
+                lock_ts,
+                current_ts,
+                ctx
+            ),
+            Command::TxnHeartBeat {
+                ref ctx,
+                ref primary_key,
+                start_ts,
+                advise_ttl,
+            } => write!(
+                f,
+                "kv::command::txn_heart_beat {} @ {} ttl {} | {:?}",
+                primary_key,
+                start_ts,
+                advise_ttl,
+                ctx
+            ),
             Command::Rollback {
                 ref ctx,
                 ref keys,
@@ -238,6 +382,19 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::PessimisticRollback {
+                ref ctx,
+                ref keys,
+                start_ts,
+                for_update_ts,
+            } => write!(
+                f,
+                "kv::command::pessimistic_rollback keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                for_update_ts,
+                ctx
+            ),
             Command::ScanLock {
                 ref ctx, max_ts, ..
             } => write!(f, "kv::scan_lock {} | {:?}", max_ts, ctx),
@@ -265,18 +422,86 @@ impl Display for Command {
                 safe_point,
                 ctx
             ),
-            Command::RawGet { ref ctx, ref key } => {
-                write!(f, "kv::command::rawget {:?} | {:?}", key, ctx)
-            }
+            Command::RawGet {
+                ref ctx,
+                cf,
+                ref key,
+                ..
+            } => write!(f, "kv::command::rawget {:?} {} | {:?}", key, cf, ctx),
+            Command::RawBatchGet {
+                ref ctx,
+                cf,
+                ref keys,
+                ..
+            } => write!(
+                f,
+                "kv::command::raw_batch_get {} {} | {:?}",
+                keys.len(),
+                cf,
+                ctx
+            ),
             Command::RawScan {
                 ref ctx,
+                cf,
                 ref start_key,
                 limit,
+                reverse,
+                ..
             } => write!(
                 f,
-                "kv::command::rawscan {:?} {} | {:?}",
+                "kv::command::rawscan {:?} {} {} reverse: {} | {:?}",
                 start_key,
+                cf,
                 limit,
+                reverse,
+                ctx
+            ),
+            Command::RawCompareAndSwap {
+                ref ctx,
+                cf,
+                ref key,
+                ..
+            } => write!(
+                f,
+                "kv::command::raw_compare_and_swap {:?} {} | {:?}",
+                key,
+                cf,
+                ctx
+            ),
+            Command::RawBatchPut {
+                ref ctx,
+                cf,
+                ref pairs,
+                ..
+            } => write!(
+                f,
+                "kv::command::raw_batch_put {} {} | {:?}",
+                pairs.len(),
+                cf,
+                ctx
+            ),
+            Command::RawBatchDelete {
+                ref ctx,
+                cf,
+                ref keys,
+            } => write!(
+                f,
+                "kv::command::raw_batch_delete {} {} | {:?}",
+                keys.len(),
+                cf,
+                ctx
+            ),
+            Command::RawDeleteRange {
+                ref ctx,
+                cf,
+                ref start_key,
+                ref end_key,
+            } => write!(
+                f,
+                "kv::command::raw_delete_range [{:?}, {:?}) {} | {:?}",
+                start_key,
+                end_key,
+                cf,
                 ctx
             ),
             Command::DeleteRange {
@@ -318,8 +543,10 @@ impl Command {
             Command::Get { .. } |
             Command::BatchGet { .. } |
             Command::Scan { .. } |
+            Command::ScanStream { .. } |
             Command::ScanLock { .. } |
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
             Command::RawScan { .. } |
             // DeleteRange only called by DDL bg thread after table is dropped and
             // must guarantee that there is no other read or write on these keys, so
@@ -350,20 +577,43 @@ impl Command {
         !self.readonly() && self.priority() != CommandPri::High
     }
 
+    /// Returns true if the command can wait for a conflicting lock to be released instead of
+    /// returning `KeyIsLocked` immediately.
+    pub fn can_wait_for_lock(&self) -> bool {
+        match *self {
+            Command::Get { .. } |
+            Command::BatchGet { .. } |
+            Command::Scan { .. } |
+            Command::Prewrite { .. } |
+            Command::AcquirePessimisticLock { .. } => true,
+            _ => false,
+        }
+    }
+
     pub fn tag(&self) -> &'static str {
         match *self {
             Command::Get { .. } => "get",
             Command::BatchGet { .. } => "batch_get",
             Command::Scan { .. } => "scan",
+            Command::ScanStream { .. } => "scan_stream",
             Command::Prewrite { .. } => "prewrite",
+            Command::AcquirePessimisticLock { .. } => "acquire_pessimistic_lock",
             Command::Commit { .. } => "commit",
             Command::Cleanup { .. } => "cleanup",
             Command::Rollback { .. } => "rollback",
+            Command::TxnHeartBeat { .. } => "txn_heart_beat",
+            Command::CheckTxnStatus { .. } => "check_txn_status",
+            Command::PessimisticRollback { .. } => "pessimistic_rollback",
             Command::ScanLock { .. } => "scan_lock",
             Command::ResolveLock { .. } => "resolve_lock",
             Command::Gc { .. } => CMD_TAG_GC,
             Command::RawGet { .. } => "raw_get",
+            Command::RawBatchGet { .. } => "raw_batch_get",
             Command::RawScan { .. } => "raw_scan",
+            Command::RawCompareAndSwap { .. } => "raw_compare_and_swap",
+            Command::RawBatchPut { .. } => "raw_batch_put",
+            Command::RawBatchDelete { .. } => "raw_batch_delete",
+            Command::RawDeleteRange { .. } => "raw_delete_range",
             Command::DeleteRange { .. } => "delete_range",
             Command::Pause { .. } => "pause",
             Command::MvccByKey { .. } => "key_mvcc",
@@ -376,16 +626,26 @@ impl Command {
             Command::Get { start_ts, .. } |
             Command::BatchGet { start_ts, .. } |
             Command::Scan { start_ts, .. } |
+            Command::ScanStream { start_ts, .. } |
             Command::Prewrite { start_ts, .. } |
+            Command::AcquirePessimisticLock { start_ts, .. } |
             Command::Cleanup { start_ts, .. } |
             Command::Rollback { start_ts, .. } |
+            Command::TxnHeartBeat { start_ts, .. } |
+            Command::PessimisticRollback { start_ts, .. } |
             Command::ResolveLock { start_ts, .. } |
             Command::MvccByStartTs { start_ts, .. } => start_ts,
-            Command::Commit { lock_ts, .. } => lock_ts,
+            Command::Commit { lock_ts, .. } |
+            Command::CheckTxnStatus { lock_ts, .. } => lock_ts,
             Command::ScanLock { max_ts, .. } => max_ts,
             Command::Gc { safe_point, .. } => safe_point,
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
             Command::RawScan { .. } |
+            Command::RawCompareAndSwap { .. } |
+            Command::RawBatchPut { .. } |
+            Command::RawBatchDelete { .. } |
+            Command::RawDeleteRange { .. } |
             Command::DeleteRange { .. } |
             Command::Pause { .. } |
             Command::MvccByKey { .. } => 0,
@@ -397,15 +657,25 @@ impl Command {
             Command::Get { ref ctx, .. } |
             Command::BatchGet { ref ctx, .. } |
             Command::Scan { ref ctx, .. } |
+            Command::ScanStream { ref ctx, .. } |
             Command::Prewrite { ref ctx, .. } |
+            Command::AcquirePessimisticLock { ref ctx, .. } |
             Command::Commit { ref ctx, .. } |
             Command::Cleanup { ref ctx, .. } |
             Command::Rollback { ref ctx, .. } |
+            Command::TxnHeartBeat { ref ctx, .. } |
+            Command::CheckTxnStatus { ref ctx, .. } |
+            Command::PessimisticRollback { ref ctx, .. } |
             Command::ScanLock { ref ctx, .. } |
             Command::ResolveLock { ref ctx, .. } |
             Command::Gc { ref ctx, .. } |
             Command::RawGet { ref ctx, .. } |
+            Command::RawBatchGet { ref ctx, .. } |
             Command::RawScan { ref ctx, .. } |
+            Command::RawCompareAndSwap { ref ctx, .. } |
+            Command::RawBatchPut { ref ctx, .. } |
+            Command::RawBatchDelete { ref ctx, .. } |
+            Command::RawDeleteRange { ref ctx, .. } |
             Command::DeleteRange { ref ctx, .. } |
             Command::Pause { ref ctx, .. } |
             Command::MvccByKey { ref ctx, .. } |
@@ -418,15 +688,25 @@ impl Command {
             Command::Get { ref mut ctx, .. } |
             Command::BatchGet { ref mut ctx, .. } |
             Command::Scan { ref mut ctx, .. } |
+            Command::ScanStream { ref mut ctx, .. } |
             Command::Prewrite { ref mut ctx, .. } |
+            Command::AcquirePessimisticLock { ref mut ctx, .. } |
             Command::Commit { ref mut ctx, .. } |
             Command::Cleanup { ref mut ctx, .. } |
             Command::Rollback { ref mut ctx, .. } |
+            Command::TxnHeartBeat { ref mut ctx, .. } |
+            Command::CheckTxnStatus { ref mut ctx, .. } |
+            Command::PessimisticRollback { ref mut ctx, .. } |
             Command::ScanLock { ref mut ctx, .. } |
             Command::ResolveLock { ref mut ctx, .. } |
             Command::Gc { ref mut ctx, .. } |
             Command::RawGet { ref mut ctx, .. } |
+            Command::RawBatchGet { ref mut ctx, .. } |
             Command::RawScan { ref mut ctx, .. } |
+            Command::RawCompareAndSwap { ref mut ctx, .. } |
+            Command::RawBatchPut { ref mut ctx, .. } |
+            Command::RawBatchDelete { ref mut ctx, .. } |
+            Command::RawDeleteRange { ref mut ctx, .. } |
             Command::DeleteRange { ref mut ctx, .. } |
             Command::Pause { ref mut ctx, .. } |
             Command::MvccByKey { ref mut ctx, .. } |
@@ -443,15 +723,25 @@ impl Command {
             Command::Get { .. } |
             Command::RawGet { .. } |
             Command::RawScan { .. } |
+            Command::RawCompareAndSwap { .. } |
+            Command::RawDeleteRange { .. } |
             Command::Cleanup { .. } |
+            Command::TxnHeartBeat { .. } |
+            Command::CheckTxnStatus { .. } |
             Command::DeleteRange { .. } => 1,
             Command::Scan { limit, .. } => limit,
+            Command::ScanStream { batch_size, .. } => batch_size,
             Command::Gc { ref keys, .. } |
             Command::BatchGet { ref keys, .. } |
+            Command::RawBatchGet { ref keys, .. } |
+            Command::RawBatchDelete { ref keys, .. } |
+            Command::AcquirePessimisticLock { ref keys, .. } |
             Command::Commit { ref keys, .. } |
             Command::Rollback { ref keys, .. } |
+            Command::PessimisticRollback { ref keys, .. } |
             Command::ResolveLock { ref keys, .. } => keys.len(),
             Command::Prewrite { ref mutations, .. } => mutations.len(),
+            Command::RawBatchPut { ref pairs, .. } => pairs.len(),
         }
     }
 }
@@ -463,6 +753,19 @@ pub struct Options {
     pub lock_ttl: u64,
     pub skip_constraint_check: bool,
     pub key_only: bool,
+    // Non-zero for pessimistic transactions.
+    pub for_update_ts: u64,
+    // Whether each mutation of a pessimistic prewrite is protected by a pessimistic lock.
+    pub is_pessimistic_lock: Vec<bool>,
+    // Commits the transaction at `commit_ts` directly in prewrite. The prewrite must contain all
+    // the mutations of the transaction. They are set by `async_one_pc_prewrite`.
+    pub try_one_pc: bool,
+    pub commit_ts: u64,
+    // Scans backward from the start key, which is excluded, in descending order.
+    pub reverse_scan: bool,
+    // Serves the read by the local replica if its data at the read ts is complete. It takes
+    // effect only if the storage enables stale read.
+    pub stale_read: bool,
 }
 
 impl Options {
@@ -471,6 +774,12 @@ impl Options {
             lock_ttl: lock_ttl,
             skip_constraint_check: skip_constraint_check,
             key_only: key_only,
+            for_update_ts: 0,
+            is_pessimistic_lock: vec![],
+            try_one_pc: false,
+            commit_ts: 0,
+            reverse_scan: false,
+            stale_read: false,
         }
     }
 }
@@ -485,8 +794,13 @@ pub struct Storage {
     sendch: SyncSendCh<Msg>,
     handle: Arc<Mutex<StorageHandle>>,
 
+    // The RocksDB instance whose compaction stats are used by the flow control.
+    flow_control_db: Option<Arc<DB>>,
+
     // Storage configurations.
     gc_ratio_threshold: f64,
+    enable_ttl: bool,
+    enable_stale_read: bool,
 }
 
 impl Storage {
@@ -502,7 +816,10 @@ impl Storage {
                 handle: None,
                 receiver: Some(rx),
             })),
+            flow_control_db: None,
             gc_ratio_threshold: config.gc_ratio_threshold,
+            enable_ttl: config.enable_ttl,
+            enable_stale_read: config.enable_stale_read,
         })
     }
 
@@ -511,6 +828,12 @@ impl Storage {
         Storage::from_engine(engine, config)
     }
 
+    /// Sets the RocksDB instance the engine writes to, so the flow control can throttle the
+    /// writes when its compaction falls behind. It must be called before `start`.
+    pub fn set_flow_control_db(&mut self, db: Arc<DB>) {
+        self.flow_control_db = Some(db);
+    }
+
     pub fn start(&mut self, config: &Config) -> Result<()> {
         let mut handle = self.handle.lock().unwrap();
         if handle.handle.is_some() {
@@ -523,6 +846,10 @@ impl Storage {
         let sched_concurrency = config.scheduler_concurrency;
         let sched_worker_pool_size = config.scheduler_worker_pool_size;
         let sched_too_busy_threshold = config.scheduler_too_busy_threshold;
+        let wait_for_lock_timeout = config.scheduler_wait_for_lock_timeout.0;
+        let max_proposal_size = config.scheduler_max_proposal_size.0 as usize;
+        let flow_controller = FlowController::new(config, self.flow_control_db.clone());
+        let enable_stale_read = config.enable_stale_read;
         let ch = self.sendch.clone();
         let h = builder.spawn(move || {
             let mut sched = Scheduler::new(
@@ -531,6 +858,10 @@ impl Storage {
                 sched_concurrency,
                 sched_worker_pool_size,
                 sched_too_busy_threshold,
+                wait_for_lock_timeout,
+                max_proposal_size,
+                flow_controller,
+                enable_stale_read,
             );
             if let Err(e) = sched.run(rx) {
                 panic!("scheduler run err:{:?}", e);
@@ -571,17 +902,34 @@ impl Storage {
         Ok(())
     }
 
+    /// Gets at most `limit` keys whose latches are waited on the most recently, along with their
+    /// decayed latch wait in seconds, the hottest first.
+    pub fn async_get_hot_keys(
+        &self,
+        limit: usize,
+        callback: Callback<Vec<(Key, f64)>>,
+    ) -> Result<()> {
+        let cb = box move |keys| callback(Ok(keys));
+        box_try!(self.sendch.try_send(Msg::GetHotKeys {
+            limit: limit,
+            cb: cb,
+        }));
+        Ok(())
+    }
+
     pub fn async_get(
         &self,
         ctx: Context,
         key: Key,
         start_ts: u64,
+        options: Options,
         callback: Callback<Option<Value>>,
     ) -> Result<()> {
         let cmd = Command::Get {
             ctx: ctx,
             key: key,
             start_ts: start_ts,
+            options: options,
         };
         let tag = cmd.tag();
         self.send(cmd, StorageCb::SingleValue(callback))?;
@@ -594,12 +942,14 @@ impl Storage {
         ctx: Context,
         keys: Vec<Key>,
         start_ts: u64,
+        options: Options,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
         let cmd = Command::BatchGet {
             ctx: ctx,
             keys: keys,
             start_ts: start_ts,
+            options: options,
         };
         let tag = cmd.tag();
         self.send(cmd, StorageCb::KvPairs(callback))?;
@@ -629,6 +979,33 @@ impl Storage {
         Ok(())
     }
 
+    /// Scans `[start_key, end_key)` from one snapshot. The callback gets a `ScanStream`, which
+    /// reads at most `batch_size` pairs for each batch, until the end of the range.
+    #[allow(too_many_arguments)]
+    pub fn async_scan_stream(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        batch_size: usize,
+        start_ts: u64,
+        key_only: bool,
+        callback: Callback<ScanStream>,
+    ) -> Result<()> {
+        let cmd = Command::ScanStream {
+            ctx: ctx,
+            start_key: start_key,
+            end_key: end_key,
+            batch_size: batch_size,
+            start_ts: start_ts,
+            key_only: key_only,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::ScanStream(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_pause(&self, ctx: Context, duration: u64, callback: Callback<()>) -> Result<()> {
         let cmd = Command::Pause {
             ctx: ctx,
@@ -647,6 +1024,53 @@ impl Storage {
         options: Options,
         callback: Callback<Vec<Result<()>>>,
     ) -> Result<()> {
+        if options.for_update_ts != 0 && options.is_pessimistic_lock.len() != mutations.len() {
+            return Err(Error::InvalidArgument(format!(
+                "{} pessimistic lock flags for {} mutations",
+                options.is_pessimistic_lock.len(),
+                mutations.len()
+            )));
+        }
+        if options.try_one_pc {
+            return Err(Error::InvalidArgument(
+                "one-phase commit must be prewritten by async_one_pc_prewrite".to_owned(),
+            ));
+        }
+        let cmd = Command::Prewrite {
+            ctx: ctx,
+            mutations: mutations,
+            primary: primary,
+            start_ts: start_ts,
+            options: options,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::Booleans(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Prewrites all the mutations of a transaction and commits them at `commit_ts` directly.
+    /// If the max ts of the reads in the region is unknown, the mutations are prewritten in
+    /// two phases instead, and the callback gets `false` with the results, in which case the
+    /// transaction must be committed as usual.
+    pub fn async_one_pc_prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        commit_ts: u64,
+        mut options: Options,
+        callback: Callback<(Vec<Result<()>>, bool)>,
+    ) -> Result<()> {
+        if self.enable_stale_read {
+            // The stale reads can't see the one-phase commits, which write no locks.
+            return Err(Error::InvalidArgument(
+                "one-phase commit is not allowed with stale read enabled".to_owned(),
+            ));
+        }
+        options.try_one_pc = true;
+        options.commit_ts = commit_ts;
         let cmd = Command::Prewrite {
             ctx: ctx,
             mutations: mutations,
@@ -655,6 +1079,28 @@ impl Storage {
             options: options,
         };
         let tag = cmd.tag();
+        self.send(cmd, StorageCb::OnePcPrewrite(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_acquire_pessimistic_lock(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+        callback: Callback<Vec<Result<()>>>,
+    ) -> Result<()> {
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: ctx,
+            keys: keys,
+            primary: primary,
+            start_ts: start_ts,
+            options: options,
+        };
+        let tag = cmd.tag();
         self.send(cmd, StorageCb::Booleans(callback))?;
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
@@ -731,6 +1177,51 @@ impl Storage {
         Ok(())
     }
 
+    /// Extends the TTL of the primary lock of the transaction started at `start_ts` to
+    /// `advise_ttl` if it's larger, the callback gets the TTL of the lock after the update.
+    pub fn async_txn_heart_beat(
+        &self,
+        ctx: Context,
+        primary_key: Key,
+        start_ts: u64,
+        advise_ttl: u64,
+        callback: Callback<u64>,
+    ) -> Result<()> {
+        let cmd = Command::TxnHeartBeat {
+            ctx: ctx,
+            primary_key: primary_key,
+            start_ts: start_ts,
+            advise_ttl: advise_ttl,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::LockTtl(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Checks the status of the transaction started at `lock_ts` by its primary key. The
+    /// transaction is rolled back if the primary lock has expired at `current_ts`, so that a
+    /// client resolving its locks won't roll back a live transaction.
+    pub fn async_check_txn_status(
+        &self,
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+        callback: Callback<TxnStatus>,
+    ) -> Result<()> {
+        let cmd = Command::CheckTxnStatus {
+            ctx: ctx,
+            primary_key: primary_key,
+            lock_ts: lock_ts,
+            current_ts: current_ts,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::TxnStatus(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_rollback(
         &self,
         ctx: Context,
@@ -749,6 +1240,26 @@ impl Storage {
         Ok(())
     }
 
+    pub fn async_pessimistic_rollback(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        for_update_ts: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cmd = Command::PessimisticRollback {
+            ctx: ctx,
+            keys: keys,
+            start_ts: start_ts,
+            for_update_ts: for_update_ts,
+        };
+        let tag = cmd.tag();
+        self.send(cmd, StorageCb::Boolean(callback))?;
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_scan_lock(
         &self,
         ctx: Context,
@@ -799,66 +1310,244 @@ impl Storage {
         Ok(())
     }
 
+    /// Returns the column family of a raw request, an empty `cf` means the default one.
+    fn rawkv_cf(cf: &str) -> Result<CfName> {
+        if cf.is_empty() {
+            return Ok(CF_DEFAULT);
+        }
+        for c in DATA_CFS {
+            if cf == *c {
+                return Ok(*c);
+            }
+        }
+        Err(Error::InvalidCf(cf.to_owned()))
+    }
+
+    /// Returns the column family of a raw write, which must be the default one, as the others
+    /// keep the locks and the commit records of transactions.
+    fn rawkv_write_cf(cf: &str) -> Result<CfName> {
+        match Storage::rawkv_cf(cf)? {
+            CF_DEFAULT => Ok(CF_DEFAULT),
+            _ => Err(Error::InvalidCf(cf.to_owned())),
+        }
+    }
+
     pub fn async_raw_get(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<Option<Vec<u8>>>,
     ) -> Result<()> {
         let cmd = Command::RawGet {
             ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
             key: Key::from_encoded(key),
+            enable_ttl: self.enable_ttl,
         };
         self.send(cmd, StorageCb::SingleValue(callback))?;
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["get"]).inc();
         Ok(())
     }
 
-    pub fn async_raw_put(
+    /// Gets the values of `keys`, the keys which are not found are omitted in the result.
+    pub fn async_raw_batch_get(
         &self,
         ctx: Context,
-        key: Vec<u8>,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<Vec<Result<KvPair>>>,
+    ) -> Result<()> {
+        let cmd = Command::RawBatchGet {
+            ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+            enable_ttl: self.enable_ttl,
+        };
+        self.send(cmd, StorageCb::KvPairs(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_get"])
+            .inc();
+        Ok(())
+    }
+
+    /// Puts a raw key-value pair which expires after `ttl` seconds. A zero `ttl` means it never
+    /// expires, and a non-zero `ttl` requires `enable_ttl` in the config.
+    pub fn async_raw_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
         value: Vec<u8>,
+        ttl: u64,
         callback: Callback<()>,
     ) -> Result<()> {
-        try!(self.engine
-            .async_write(&ctx,
-                         vec![Modify::Put(CF_DEFAULT, Key::from_encoded(key), value)],
-                         box |(_, res): (_, engine::Result<_>)| {
-                             callback(res.map_err(Error::from))
-                         }));
+        self.raw_write_pairs(ctx, &cf, vec![(key, value)], ttl, callback)?;
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["put"]).inc();
         Ok(())
     }
 
+    /// Puts the raw key-value pairs of a region in one write, see `async_raw_put` for `ttl`.
+    pub fn async_raw_batch_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        pairs: Vec<KvPair>,
+        ttl: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        self.raw_write_pairs(ctx, &cf, pairs, ttl, callback)?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_put"])
+            .inc();
+        Ok(())
+    }
+
+    fn raw_write_pairs(
+        &self,
+        ctx: Context,
+        cf: &str,
+        pairs: Vec<KvPair>,
+        ttl: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = Storage::rawkv_write_cf(cf)?;
+        if !self.enable_ttl && ttl != 0 {
+            callback(Err(Error::TTLNotEnabled));
+            return Ok(());
+        }
+        let cmd = Command::RawBatchPut {
+            ctx: ctx,
+            cf: cf,
+            pairs: pairs
+                .into_iter()
+                .map(|(k, v)| (Key::from_encoded(k), v))
+                .collect(),
+            ttl: ttl,
+            enable_ttl: self.enable_ttl,
+        };
+        self.send(cmd, StorageCb::Boolean(callback))
+    }
+
     pub fn async_raw_delete(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<()>,
     ) -> Result<()> {
-        self.engine.async_write(
-            &ctx,
-            vec![Modify::Delete(CF_DEFAULT, Key::from_encoded(key))],
-            box |(_, res): (_, engine::Result<_>)| callback(res.map_err(Error::from)),
-        )?;
+        self.raw_delete_keys(ctx, &cf, vec![key], callback)?;
         RAWKV_COMMAND_COUNTER_VEC
             .with_label_values(&["delete"])
             .inc();
         Ok(())
     }
 
+    /// Deletes the raw keys of a region in one write.
+    pub fn async_raw_batch_delete(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        self.raw_delete_keys(ctx, &cf, keys, callback)?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_delete"])
+            .inc();
+        Ok(())
+    }
+
+    fn raw_delete_keys(
+        &self,
+        ctx: Context,
+        cf: &str,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cmd = Command::RawBatchDelete {
+            ctx: ctx,
+            cf: Storage::rawkv_write_cf(cf)?,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+        };
+        self.send(cmd, StorageCb::Boolean(callback))
+    }
+
+    /// Deletes the raw keys in [`start_key`, `end_key`). It's serialized with all the other
+    /// raw writes, as the keys it deletes are unknown.
+    pub fn async_raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cmd = Command::RawDeleteRange {
+            ctx: ctx,
+            cf: Storage::rawkv_write_cf(&cf)?,
+            start_key: Key::from_encoded(start_key),
+            end_key: Key::from_encoded(end_key),
+        };
+        self.send(cmd, StorageCb::Boolean(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["delete_range"])
+            .inc();
+        Ok(())
+    }
+
+    /// Puts `value` atomically if the current value of `key` equals `previous_value`, `None`
+    /// meaning the key doesn't exist, see `async_raw_put` for `ttl`.
+    ///
+    /// The callback gets the observed previous value and whether the value is swapped.
+    /// It's serialized with the other raw writes on the same key by the scheduler.
+    pub fn async_raw_compare_and_swap(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        previous_value: Option<Vec<u8>>,
+        value: Vec<u8>,
+        ttl: u64,
+        callback: Callback<(Option<Value>, bool)>,
+    ) -> Result<()> {
+        let cf = Storage::rawkv_write_cf(&cf)?;
+        if !self.enable_ttl && ttl != 0 {
+            callback(Err(Error::TTLNotEnabled));
+            return Ok(());
+        }
+        let cmd = Command::RawCompareAndSwap {
+            ctx: ctx,
+            cf: cf,
+            key: Key::from_encoded(key),
+            previous_value: previous_value,
+            value: value,
+            ttl: ttl,
+            enable_ttl: self.enable_ttl,
+        };
+        self.send(cmd, StorageCb::CompareAndSwap(callback))?;
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["compare_and_swap"])
+            .inc();
+        Ok(())
+    }
+
     pub fn async_raw_scan(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         limit: usize,
+        reverse: bool,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
         let cmd = Command::RawScan {
             ctx: ctx,
+            cf: Storage::rawkv_cf(&cf)?,
             start_key: Key::from_encoded(key),
             limit: limit,
+            enable_ttl: self.enable_ttl,
+            reverse: reverse,
         };
         self.send(cmd, StorageCb::KvPairs(callback))?;
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["scan"]).inc();
@@ -901,7 +1590,10 @@ impl Clone for Storage {
             engine: self.engine.clone(),
             sendch: self.sendch.clone(),
             handle: self.handle.clone(),
+            flow_control_db: self.flow_control_db.clone(),
             gc_ratio_threshold: self.gc_ratio_threshold,
+            enable_ttl: self.enable_ttl,
+            enable_stale_read: self.enable_stale_read,
         }
     }
 }
@@ -940,6 +1632,17 @@ quick_error! {
         SchedTooBusy {
             description("scheduler is too busy")
         }
+        InvalidCf(cf_name: String) {
+            description("invalid cf name")
+            display("invalid cf name: {}", cf_name)
+        }
+        TTLNotEnabled {
+            description("TTL is not enabled")
+        }
+        InvalidArgument(msg: String) {
+            description("invalid argument")
+            display("invalid argument: {}", msg)
+        }
     }
 }
 
@@ -965,6 +1668,8 @@ pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
 mod tests {
     use super::*;
     use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
+    use util::config::{ReadableDuration, ReadableSize};
     use kvproto::kvrpcpb::Context;
 
     fn expect_get_none(done: Sender<i32>, id: i32) -> Callback<Option<Value>> {
@@ -1006,6 +1711,27 @@ mod tests {
         })
     }
 
+    fn expect_locked_or_deadlock(
+        done: Sender<i32>,
+        deadlock: bool,
+        id: i32,
+    ) -> Callback<Vec<Result<()>>> {
+        Box::new(move |x: Result<Vec<Result<()>>>| {
+            let results = x.unwrap();
+            assert_eq!(results.len(), 1);
+            match results[0] {
+                Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::Deadlock { .. }))) => {
+                    assert!(deadlock)
+                }
+                Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked { .. }))) => {
+                    assert!(!deadlock)
+                }
+                ref res => panic!("unexpected result {:?}", res),
+            }
+            done.send(id).unwrap();
+        })
+    }
+
     fn expect_scan(
         done: Sender<i32>,
         pairs: Vec<Option<KvPair>>,
@@ -1041,6 +1767,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 100,
+                Options::default(),
                 expect_get_none(tx.clone(), 0),
             )
             .unwrap();
@@ -1071,6 +1798,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 100,
+                Options::default(),
                 expect_get_none(tx.clone(), 3),
             )
             .unwrap();
@@ -1080,6 +1808,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 4),
             )
             .unwrap();
@@ -1166,6 +1895,173 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_stale_read() {
+        let mut config = Config::default();
+        config.enable_stale_read = true;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
+                100,
+                101,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The reads ask for stale snapshots, which the local engine always serves itself.
+        let mut options = Options::default();
+        options.stale_read = true;
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                101,
+                options.clone(),
+                expect_get_val(tx.clone(), b"100".to_vec(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_batch_get(
+                Context::new(),
+                vec![make_key(b"x")],
+                100,
+                options,
+                expect_batch_get_vals(tx.clone(), vec![], 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The one-phase commits are refused.
+        let res = storage.async_one_pc_prewrite(
+            Context::new(),
+            vec![Mutation::Put((make_key(b"y"), b"110".to_vec()))],
+            b"y".to_vec(),
+            110,
+            111,
+            Options::default(),
+            expect_ok(tx.clone(), 4),
+        );
+        match res {
+            Err(Error::InvalidArgument(_)) => {}
+            res => panic!("expect invalid argument, got {:?}", res),
+        }
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_scan_stream() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let keys: Vec<&[u8]> = vec![b"a", b"b", b"c", b"d", b"e"];
+        storage
+            .async_prewrite(
+                Context::new(),
+                keys.iter()
+                    .map(|k| Mutation::Put((make_key(k), k.to_vec())))
+                    .collect(),
+                b"a".to_vec(),
+                1,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                keys.iter().map(|k| make_key(k)).collect(),
+                1,
+                2,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"cc"), b"cc".to_vec()))],
+                b"cc".to_vec(),
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        let (stream_tx, stream_rx) = channel();
+        storage
+            .async_scan_stream(
+                Context::new(),
+                make_key(b"b"),
+                Some(make_key(b"e")),
+                2,
+                15,
+                false,
+                Box::new(move |res: Result<ScanStream>| stream_tx.send(res.unwrap()).unwrap()),
+            )
+            .unwrap();
+        let stream = stream_rx.recv().unwrap();
+        // The locked key is returned as an error in its batch.
+        let batches: Vec<Vec<Option<KvPair>>> = stream
+            .map(|batch| batch.unwrap().into_iter().map(|r| r.ok()).collect())
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                vec![
+                    Some((b"b".to_vec(), b"b".to_vec())),
+                    Some((b"c".to_vec(), b"c".to_vec())),
+                ],
+                vec![None, Some((b"d".to_vec(), b"d".to_vec()))],
+            ]
+        );
+
+        // A batch ending at the range end is followed by no empty batch.
+        let (stream_tx, stream_rx) = channel();
+        storage
+            .async_scan_stream(
+                Context::new(),
+                make_key(b"d"),
+                None,
+                2,
+                15,
+                true,
+                Box::new(move |res: Result<ScanStream>| stream_tx.send(res.unwrap()).unwrap()),
+            )
+            .unwrap();
+        let stream = stream_rx.recv().unwrap();
+        let batches: Vec<Vec<Option<KvPair>>> = stream
+            .map(|batch| batch.unwrap().into_iter().map(|r| r.ok()).collect())
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                vec![
+                    Some((b"d".to_vec(), vec![])),
+                    Some((b"e".to_vec(), vec![])),
+                ],
+            ]
+        );
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_batch_get() {
         let config = Config::default();
@@ -1202,6 +2098,7 @@ mod tests {
                 Context::new(),
                 vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
                 5,
+                Options::default(),
                 expect_batch_get_vals(
                     tx.clone(),
                     vec![
@@ -1270,6 +2167,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 120,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 4),
             )
             .unwrap();
@@ -1278,6 +2176,7 @@ mod tests {
                 Context::new(),
                 make_key(b"y"),
                 120,
+                Options::default(),
                 expect_get_val(tx.clone(), b"101".to_vec(), 5),
             )
             .unwrap();
@@ -1309,6 +2208,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 100,
+                Options::default(),
                 expect_get_none(tx.clone(), 0),
             )
             .unwrap();
@@ -1380,6 +2280,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 105,
+                Options::default(),
                 expect_get_none(tx.clone(), 2),
             )
             .unwrap();
@@ -1387,6 +2288,392 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_split_prewrite() {
+        let mut config = Config::default();
+        config.scheduler_max_proposal_size = ReadableSize::kb(1);
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let keys: Vec<Key> = (0..100u8).map(|i| make_key(&[i])).collect();
+        let value = vec![b'v'; 256];
+        storage
+            .async_prewrite(
+                Context::new(),
+                keys.iter()
+                    .map(|k| Mutation::Put((k.clone(), value.clone())))
+                    .collect(),
+                vec![0],
+                100,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_txn_heart_beat(
+                Context::new(),
+                keys[0].clone(),
+                100,
+                3000,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(Context::new(), keys.clone(), 100, 110, expect_ok(tx.clone(), 2))
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_batch_get(
+                Context::new(),
+                keys.clone(),
+                120,
+                Options::default(),
+                expect_batch_get_vals(
+                    tx.clone(),
+                    (0..100u8).map(|i| Some((vec![i], value.clone()))).collect(),
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The transaction is committed, there is no lock to keep alive.
+        storage
+            .async_txn_heart_beat(
+                Context::new(),
+                keys[0].clone(),
+                100,
+                3000,
+                expect_fail(tx.clone(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_pessimistic_lock() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let (k, v) = (make_key(b"k"), b"v".to_vec());
+        let mut options = Options::default();
+        options.for_update_ts = 10;
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![k.clone()],
+                b"k".to_vec(),
+                10,
+                options.clone(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Pessimistic locks don't block reads.
+        storage
+            .async_get(
+                Context::new(),
+                k.clone(),
+                20,
+                Options::default(),
+                expect_get_none(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_pessimistic_rollback(
+                Context::new(),
+                vec![k.clone()],
+                10,
+                10,
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![k.clone()],
+                b"k".to_vec(),
+                10,
+                options.clone(),
+                expect_ok(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Every mutation of a pessimistic prewrite needs a flag.
+        let res = storage.async_prewrite(
+            Context::new(),
+            vec![Mutation::Put((k.clone(), v.clone()))],
+            b"k".to_vec(),
+            10,
+            options.clone(),
+            expect_ok(tx.clone(), 4),
+        );
+        match res {
+            Err(Error::InvalidArgument(_)) => {}
+            res => panic!("expect invalid argument, got {:?}", res),
+        }
+        options.is_pessimistic_lock = vec![true];
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((k.clone(), v.clone()))],
+                b"k".to_vec(),
+                10,
+                options,
+                expect_ok(tx.clone(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![k.clone()],
+                10,
+                20,
+                expect_ok(tx.clone(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(Context::new(), k, 20, Options::default(), expect_get_val(tx.clone(), v, 6))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut config = Config::default();
+        // Return the lock errors without waiting.
+        config.scheduler_wait_for_lock_timeout = ReadableDuration::millis(0);
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let (k1, k2) = (make_key(b"k1"), make_key(b"k2"));
+        fn acquire(storage: &Storage, key: &Key, ts: u64, cb: Callback<Vec<Result<()>>>) {
+            let mut options = Options::default();
+            options.for_update_ts = ts;
+            storage
+                .async_acquire_pessimistic_lock(
+                    Context::new(),
+                    vec![key.clone()],
+                    key.raw().unwrap(),
+                    ts,
+                    options,
+                    cb,
+                )
+                .unwrap();
+        }
+        acquire(&storage, &k1, 10, expect_ok(tx.clone(), 0));
+        rx.recv().unwrap();
+        acquire(&storage, &k2, 20, expect_ok(tx.clone(), 1));
+        rx.recv().unwrap();
+        // 10 waits for 20.
+        acquire(&storage, &k2, 10, expect_locked_or_deadlock(tx.clone(), false, 2));
+        rx.recv().unwrap();
+        // 20 waits for 10, 20 is the victim.
+        acquire(&storage, &k1, 20, expect_locked_or_deadlock(tx.clone(), true, 3));
+        rx.recv().unwrap();
+        // Still a deadlock when retried.
+        acquire(&storage, &k1, 20, expect_locked_or_deadlock(tx.clone(), true, 4));
+        rx.recv().unwrap();
+        storage
+            .async_pessimistic_rollback(
+                Context::new(),
+                vec![k2.clone()],
+                20,
+                20,
+                expect_ok(tx.clone(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // 10 gets the lock after 20 gives up, then 20 just waits for 10.
+        acquire(&storage, &k2, 10, expect_ok(tx.clone(), 6));
+        rx.recv().unwrap();
+        acquire(&storage, &k1, 20, expect_locked_or_deadlock(tx.clone(), false, 7));
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_deadlock_with_waiting() {
+        let mut config = Config::default();
+        config.scheduler_wait_for_lock_timeout = ReadableDuration::secs(10);
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let (k1, k2, k3) = (make_key(b"k1"), make_key(b"k2"), make_key(b"k3"));
+        fn acquire(storage: &Storage, keys: Vec<Key>, ts: u64, cb: Callback<Vec<Result<()>>>) {
+            let mut options = Options::default();
+            options.for_update_ts = ts;
+            let primary = keys[0].raw().unwrap();
+            storage
+                .async_acquire_pessimistic_lock(Context::new(), keys, primary, ts, options, cb)
+                .unwrap();
+        }
+        acquire(&storage, vec![k1.clone()], 10, expect_ok(tx.clone(), 0));
+        acquire(&storage, vec![k2.clone()], 20, expect_ok(tx.clone(), 1));
+        acquire(&storage, vec![k3.clone()], 30, expect_ok(tx.clone(), 2));
+        let mut ids: Vec<i32> = (0..3).map(|_| rx.recv().unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2]);
+        // 10 waits for 20.
+        acquire(&storage, vec![k2.clone()], 10, expect_ok(tx.clone(), 3));
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        // 20 is blocked by 30 on k3 and deadlocks with 10 on k1, it gets the deadlock error
+        // at once instead of waiting for 30.
+        let done = tx.clone();
+        let cb: Callback<Vec<Result<()>>> = Box::new(move |x: Result<Vec<Result<()>>>| {
+            let results = x.unwrap();
+            assert_eq!(results.len(), 2);
+            assert!(results.iter().any(|r| match *r {
+                Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::Deadlock { .. }))) => true,
+                _ => false,
+            }));
+            done.send(4).unwrap();
+        });
+        acquire(&storage, vec![k3.clone(), k1.clone()], 20, cb);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 4);
+        // 10 gets the lock once 20 gives up.
+        storage
+            .async_pessimistic_rollback(Context::new(), vec![k2], 20, 20, expect_ok(tx.clone(), 5))
+            .unwrap();
+        let mut ids: Vec<i32> = (0..2).map(|_| rx.recv().unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, vec![3, 5]);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_check_txn_status_wakes_up_waiters() {
+        let mut config = Config::default();
+        config.scheduler_wait_for_lock_timeout = ReadableDuration::secs(10);
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let k = make_key(b"k");
+        for (ts, id) in vec![(10, 0), (20, 1)] {
+            storage
+                .async_prewrite(
+                    Context::new(),
+                    vec![Mutation::Put((k.clone(), b"v".to_vec()))],
+                    b"k".to_vec(),
+                    ts,
+                    Options::default(),
+                    expect_ok(tx.clone(), id),
+                )
+                .unwrap();
+        }
+        assert_eq!(rx.recv().unwrap(), 0);
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        // The expired lock is rolled back, and the blocked prewrite goes on.
+        let done = tx.clone();
+        storage
+            .async_check_txn_status(
+                Context::new(),
+                k.clone(),
+                10,
+                100,
+                box move |x: Result<TxnStatus>| {
+                    assert_eq!(x.unwrap(), TxnStatus::RolledBack);
+                    done.send(2).unwrap();
+                },
+            )
+            .unwrap();
+        let mut ids: Vec<i32> = (0..2)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_lock() {
+        let mut config = Config::default();
+        config.scheduler_wait_for_lock_timeout = ReadableDuration::secs(10);
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let (k, v) = (make_key(b"k"), b"v".to_vec());
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((k.clone(), v.clone()))],
+                b"k".to_vec(),
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Both the read and the write are blocked by the lock.
+        storage
+            .async_get(
+                Context::new(),
+                k.clone(),
+                18,
+                Options::default(),
+                expect_get_val(tx.clone(), v.clone(), 1),
+            )
+            .unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Delete(k.clone())],
+                b"k".to_vec(),
+                20,
+                Options::default(),
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        // Committing the lock wakes them up.
+        storage
+            .async_commit(
+                Context::new(),
+                vec![k.clone()],
+                10,
+                15,
+                expect_ok(tx.clone(), 3),
+            )
+            .unwrap();
+        let mut ids: Vec<i32> = (0..3).map(|_| rx.recv().unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+        storage
+            .async_rollback(Context::new(), vec![k.clone()], 20, expect_ok(tx.clone(), 4))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+
+        // The blocked command gets the lock error once it has waited for too long.
+        config.scheduler_wait_for_lock_timeout = ReadableDuration::millis(100);
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((k.clone(), v.clone()))],
+                b"k".to_vec(),
+                10,
+                Options::default(),
+                expect_ok(tx.clone(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(Context::new(), k, 30, Options::default(), expect_fail(tx.clone(), 6))
+            .unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 6);
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_high_priority_get_put() {
         let config = Config::default();
@@ -1396,7 +2683,7 @@ mod tests {
         let mut ctx = Context::new();
         ctx.set_priority(CommandPri::High);
         storage
-            .async_get(ctx, make_key(b"x"), 100, expect_get_none(tx.clone(), 0))
+            .async_get(ctx, make_key(b"x"), 100, Options::default(), expect_get_none(tx.clone(), 0))
             .unwrap();
         rx.recv().unwrap();
         let mut ctx = Context::new();
@@ -1427,7 +2714,7 @@ mod tests {
         let mut ctx = Context::new();
         ctx.set_priority(CommandPri::High);
         storage
-            .async_get(ctx, make_key(b"x"), 100, expect_get_none(tx.clone(), 3))
+            .async_get(ctx, make_key(b"x"), 100, Options::default(), expect_get_none(tx.clone(), 3))
             .unwrap();
         rx.recv().unwrap();
         let mut ctx = Context::new();
@@ -1437,6 +2724,7 @@ mod tests {
                 ctx,
                 make_key(b"x"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 4),
             )
             .unwrap();
@@ -1456,6 +2744,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 100,
+                Options::default(),
                 expect_get_none(tx.clone(), 0),
             )
             .unwrap();
@@ -1492,6 +2781,7 @@ mod tests {
                 ctx,
                 make_key(b"x"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 4),
             )
             .unwrap();
@@ -1539,6 +2829,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 2),
             )
             .unwrap();
@@ -1548,6 +2839,7 @@ mod tests {
                 Context::new(),
                 make_key(b"y"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 3),
             )
             .unwrap();
@@ -1557,6 +2849,7 @@ mod tests {
                 Context::new(),
                 make_key(b"z"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 4),
             )
             .unwrap();
@@ -1577,6 +2870,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 101,
+                Options::default(),
                 expect_get_none(tx.clone(), 6),
             )
             .unwrap();
@@ -1586,6 +2880,7 @@ mod tests {
                 Context::new(),
                 make_key(b"y"),
                 101,
+                Options::default(),
                 expect_get_none(tx.clone(), 7),
             )
             .unwrap();
@@ -1595,6 +2890,7 @@ mod tests {
                 Context::new(),
                 make_key(b"z"),
                 101,
+                Options::default(),
                 expect_get_val(tx.clone(), b"100".to_vec(), 8),
             )
             .unwrap();
@@ -1615,10 +2911,30 @@ mod tests {
                 Context::new(),
                 make_key(b"z"),
                 101,
+                Options::default(),
                 expect_get_none(tx.clone(), 10),
             )
             .unwrap();
         rx.recv().unwrap();
         storage.stop().unwrap();
     }
+
+    #[test]
+    fn test_rawkv_cf() {
+        assert_eq!(Storage::rawkv_cf("").unwrap(), CF_DEFAULT);
+        for cf in DATA_CFS {
+            assert_eq!(Storage::rawkv_cf(cf).unwrap(), *cf);
+        }
+        assert!(Storage::rawkv_cf(CF_RAFT).is_err());
+        assert!(Storage::rawkv_cf("unknown").is_err());
+    }
+
+    #[test]
+    fn test_rawkv_write_cf() {
+        assert_eq!(Storage::rawkv_write_cf("").unwrap(), CF_DEFAULT);
+        assert_eq!(Storage::rawkv_write_cf(CF_DEFAULT).unwrap(), CF_DEFAULT);
+        assert!(Storage::rawkv_write_cf(CF_LOCK).is_err());
+        assert!(Storage::rawkv_write_cf(CF_WRITE).is_err());
+        assert!(Storage::rawkv_write_cf(CF_RAFT).is_err());
+    }
 }
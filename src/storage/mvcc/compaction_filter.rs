@@ -0,0 +1,498 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MVCC garbage collection in compaction.
+//!
+//! Instead of deleting the stale versions through raft, `WriteCompactionFilter` drops them when
+//! RocksDB compacts the write column family, using the safe point kept in `GcSafePoint`.
+//!
+//! For every key, the latest `Put` or `Delete` version committed before the safe point is kept
+//! and all the older versions are dropped, as well as the `Rollback` and `Lock` records before
+//! the safe point. The value in the default column family that a dropped record refers to is
+//! deleted along with it. RocksDB shares one filter among all the compactions of a column
+//! family and doesn't tell when a compaction starts, so the filter never drops a version unless
+//! it has checked that a newer version before the safe point is still in the DB.
+//!
+//! `DefaultCompactionFilter` drops the values left in the default column family, which are
+//! older than a version committed before the safe point. Raw keys share the default column
+//! family but have no write records, so they are never dropped.
+
+use std::cell::RefCell;
+use std::sync::{Arc, RwLock, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use rocksdb::{CompactionFilter, Writable, DB};
+
+use raftstore::store::keys;
+use raftstore::store::engine::{Iterable, Peekable};
+use storage::{Key, CF_DEFAULT, CF_WRITE};
+use storage::types::split_encoded_key_on_ts;
+use util::codec::number::{NumberDecoder, NumberEncoder};
+use util::collections::HashMap;
+use util::rocksdb::get_cf_handle;
+use super::{Write, WriteType};
+
+pub const GC_COMPACTION_FILTER_NAME: &'static str = "tikv.gc-compaction-filter";
+
+/// `GcSafePoint` is the safe point used by the GC compaction filters of a store.
+///
+/// The safe point never goes backward and is persisted in the kv engine, so the filters pick it
+/// up again after a restart.
+#[derive(Default)]
+pub struct GcSafePoint {
+    safe_point: RwLock<u64>,
+    db: RwLock<Option<Weak<DB>>>,
+}
+
+impl GcSafePoint {
+    pub fn new() -> GcSafePoint {
+        GcSafePoint::default()
+    }
+
+    /// Binds the kv engine to the safe point and loads the persisted safe point from it. The
+    /// filters drop nothing before the engine is bound.
+    pub fn bind(&self, db: &Arc<DB>) -> Result<(), String> {
+        let persisted = load_safe_point(db)?;
+        *self.db.write().unwrap() = Some(Arc::downgrade(db));
+        let mut safe_point = self.safe_point.write().unwrap();
+        if persisted > *safe_point {
+            *safe_point = persisted;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self) -> u64 {
+        *self.safe_point.read().unwrap()
+    }
+
+    /// Advances the safe point. A safe point not greater than the current one is ignored.
+    pub fn update(&self, safe_point: u64) -> Result<(), String> {
+        if safe_point <= self.get() {
+            return Ok(());
+        }
+        if let Some(db) = self.db() {
+            let mut value = Vec::with_capacity(8);
+            value.encode_u64(safe_point).unwrap();
+            db.put(keys::GC_SAFE_POINT_KEY, &value)?;
+        }
+        let mut current = self.safe_point.write().unwrap();
+        if safe_point > *current {
+            *current = safe_point;
+        }
+        Ok(())
+    }
+
+    fn db(&self) -> Option<Arc<DB>> {
+        self.db.read().unwrap().as_ref().and_then(|db| db.upgrade())
+    }
+}
+
+fn load_safe_point(db: &DB) -> Result<u64, String> {
+    match db.get_value(keys::GC_SAFE_POINT_KEY) {
+        Ok(Some(v)) => {
+            let mut v: &[u8] = &v;
+            v.decode_u64().map_err(|e| format!("{:?}", e))
+        }
+        Ok(None) => Ok(0),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+/// Returns the compaction filter of `cf`, which must be the write or the default column family.
+pub fn new_gc_compaction_filter(cf: &str, safe_point: Arc<GcSafePoint>) -> Box<CompactionFilter> {
+    match cf {
+        CF_WRITE => Box::new(WriteCompactionFilter::new(safe_point)),
+        CF_DEFAULT => Box::new(DefaultCompactionFilter::new(safe_point)),
+        _ => panic!("gc compaction filter is not supported on cf {}", cf),
+    }
+}
+
+// The versions of a key the current thread has visited, see `WriteCompactionFilter`.
+#[derive(Default)]
+struct WriteFilterState {
+    key: Vec<u8>,
+    last_ts: u64,
+    // Whether a `Put` or `Delete` version before the safe point has been kept.
+    kept: bool,
+    // Whether a newer `Put` or `Delete` version before the safe point is known to be in the DB.
+    verified: bool,
+}
+
+static WRITE_FILTER_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+thread_local! {
+    // filter id -> state, a compaction is always run by a single thread.
+    static WRITE_FILTER_STATES: RefCell<HashMap<usize, WriteFilterState>> =
+        RefCell::new(HashMap::default());
+}
+
+/// `WriteCompactionFilter` drops the stale write records and the values they refer to.
+///
+/// Versions of a key come newest first in a compaction. The first `Put` or `Delete` before the
+/// safe point is kept. Before dropping the versions after it, the filter checks the DB for a
+/// newer `Put` or `Delete` before the safe point, because the kept one may belong to another
+/// compaction which has been run by the same thread.
+pub struct WriteCompactionFilter {
+    id: usize,
+    safe_point: Arc<GcSafePoint>,
+}
+
+impl WriteCompactionFilter {
+    pub fn new(safe_point: Arc<GcSafePoint>) -> WriteCompactionFilter {
+        WriteCompactionFilter {
+            id: WRITE_FILTER_ID.fetch_add(1, Ordering::SeqCst),
+            safe_point: safe_point,
+        }
+    }
+
+    fn do_filter(&self, state: &mut WriteFilterState, key: &[u8], value: &[u8]) -> bool {
+        let safe_point = self.safe_point.get();
+        if safe_point == 0 || key.is_empty() || key[0] != keys::DATA_PREFIX {
+            return false;
+        }
+        let (user_key, commit_ts) = match split_encoded_key_on_ts(&key[1..]) {
+            Ok(res) => res,
+            Err(_) => return false,
+        };
+        if user_key != state.key.as_slice() || commit_ts >= state.last_ts {
+            // A new key, or the same key in a new compaction.
+            state.key.clear();
+            state.key.extend_from_slice(user_key);
+            state.kept = false;
+            state.verified = false;
+        }
+        state.last_ts = commit_ts;
+        if commit_ts > safe_point {
+            return false;
+        }
+        let write = match Write::parse(value) {
+            Ok(write) => write,
+            Err(_) => return false,
+        };
+        let filtered = match write.write_type {
+            WriteType::Rollback | WriteType::Lock => true,
+            WriteType::Put | WriteType::Delete => {
+                if !state.kept {
+                    state.kept = true;
+                    return false;
+                }
+                if !state.verified {
+                    state.verified = match self.safe_point.db() {
+                        Some(db) => has_newer_version(&db, user_key, commit_ts, safe_point),
+                        None => false,
+                    };
+                }
+                // Keeps it as the latest version if there is nothing newer in the DB.
+                state.verified
+            }
+        };
+        filtered && self.delete_value(user_key, &write)
+    }
+
+    /// Deletes the value in the default column family which the dropped `write` refers to. The
+    /// record is kept if the value can't be deleted, so that it's never left unreachable.
+    fn delete_value(&self, user_key: &[u8], write: &Write) -> bool {
+        // Only a `Put` without a short value has a value, a rollback deletes the value itself.
+        if write.write_type != WriteType::Put || write.short_value.is_some() {
+            return true;
+        }
+        let db = match self.safe_point.db() {
+            Some(db) => db,
+            None => return false,
+        };
+        let key = Key::from_encoded(user_key.to_vec()).append_ts(write.start_ts);
+        let res = get_cf_handle(&db, CF_DEFAULT)
+            .and_then(|handle| db.delete_cf(handle, &keys::data_key(key.encoded())));
+        res.is_ok()
+    }
+}
+
+impl CompactionFilter for WriteCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], value: &[u8]) -> bool {
+        WRITE_FILTER_STATES.with(|states| {
+            let mut states = states.borrow_mut();
+            let state = states.entry(self.id).or_insert_with(Default::default);
+            self.do_filter(state, key, value)
+        })
+    }
+}
+
+// The value of a key the current thread has visited, see `DefaultCompactionFilter`.
+#[derive(Default)]
+struct DefaultFilterState {
+    key: Vec<u8>,
+    last_ts: u64,
+    // The start ts of a `Put` or `Delete` version before the safe point found in the DB.
+    newer_start_ts: Option<u64>,
+}
+
+static DEFAULT_FILTER_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+thread_local! {
+    // filter id -> state, a compaction is always run by a single thread.
+    static DEFAULT_FILTER_STATES: RefCell<HashMap<usize, DefaultFilterState>> =
+        RefCell::new(HashMap::default());
+}
+
+/// `DefaultCompactionFilter` drops the values which are no longer visible at the safe point.
+///
+/// A value is dropped if a `Put` or `Delete` version of its key, started after the value and
+/// committed before the safe point, is in the DB. The write filter never drops the latest such
+/// version, so the value can't be read at or after the safe point. A value without such a
+/// version, including every raw value, is kept.
+pub struct DefaultCompactionFilter {
+    id: usize,
+    safe_point: Arc<GcSafePoint>,
+}
+
+impl DefaultCompactionFilter {
+    pub fn new(safe_point: Arc<GcSafePoint>) -> DefaultCompactionFilter {
+        DefaultCompactionFilter {
+            id: DEFAULT_FILTER_ID.fetch_add(1, Ordering::SeqCst),
+            safe_point: safe_point,
+        }
+    }
+
+    fn do_filter(&self, state: &mut DefaultFilterState, key: &[u8]) -> bool {
+        let safe_point = self.safe_point.get();
+        if safe_point == 0 || key.is_empty() || key[0] != keys::DATA_PREFIX {
+            return false;
+        }
+        let (user_key, start_ts) = match split_encoded_key_on_ts(&key[1..]) {
+            Ok(res) => res,
+            Err(_) => return false,
+        };
+        if user_key != state.key.as_slice() || start_ts >= state.last_ts {
+            // A new key, or the same key in a new compaction.
+            state.key.clear();
+            state.key.extend_from_slice(user_key);
+            state.newer_start_ts = None;
+        }
+        state.last_ts = start_ts;
+        if start_ts >= safe_point {
+            return false;
+        }
+        if let Some(ts) = state.newer_start_ts {
+            // Values of a key come newest first.
+            return ts > start_ts;
+        }
+        let db = match self.safe_point.db() {
+            Some(db) => db,
+            None => return false,
+        };
+        // The latest version may be the commit of the value itself.
+        state.newer_start_ts = match newer_version_start_ts(&db, user_key, start_ts, safe_point) {
+            Some(ts) if ts > start_ts => Some(ts),
+            _ => None,
+        };
+        state.newer_start_ts.is_some()
+    }
+}
+
+impl CompactionFilter for DefaultCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], _: &[u8]) -> bool {
+        DEFAULT_FILTER_STATES.with(|states| {
+            let mut states = states.borrow_mut();
+            let state = states.entry(self.id).or_insert_with(Default::default);
+            self.do_filter(state, key)
+        })
+    }
+}
+
+/// Checks whether there is a `Put` or `Delete` version of `user_key` committed in
+/// (`commit_ts`, `safe_point`].
+fn has_newer_version(db: &DB, user_key: &[u8], commit_ts: u64, safe_point: u64) -> bool {
+    newer_version_start_ts(db, user_key, commit_ts, safe_point).is_some()
+}
+
+/// Returns the start ts of the latest `Put` or `Delete` version of `user_key` committed in
+/// (`ts`, `safe_point`].
+fn newer_version_start_ts(db: &DB, user_key: &[u8], ts: u64, safe_point: u64) -> Option<u64> {
+    let key = Key::from_encoded(user_key.to_vec());
+    let start = keys::data_key(key.append_ts(safe_point).encoded());
+    let end = keys::data_key(key.append_ts(ts).encoded());
+    let mut found = None;
+    let res = db.scan_cf(CF_WRITE, &start, &end, false, &mut |_, value| {
+        match Write::parse(value) {
+            Ok(ref w) if w.write_type == WriteType::Put || w.write_type == WriteType::Delete => {
+                found = Some(w.start_ts);
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    });
+    match res {
+        Ok(_) => found,
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rocksdb::{Writable, DB};
+    use tempdir::TempDir;
+
+    use raftstore::store::keys;
+    use raftstore::store::engine::Peekable;
+    use storage::{make_key, ALL_CFS, CF_DEFAULT, CF_LOCK, CF_WRITE};
+    use storage::mvcc::{Lock, LockType, Write, WriteType};
+    use util::rocksdb::{self, CFOptions};
+    use super::*;
+
+    fn new_engine(path: &TempDir, safe_point: Arc<GcSafePoint>) -> Arc<DB> {
+        let mut cfs_opts = vec![];
+        for cf in ALL_CFS {
+            let mut cf_opts = CFOptions::new(cf, ::rocksdb::ColumnFamilyOptions::new());
+            if *cf == CF_WRITE || *cf == CF_DEFAULT {
+                cf_opts.enable_gc_compaction_filter(safe_point.clone());
+            }
+            cfs_opts.push(cf_opts);
+        }
+        let db = rocksdb::new_engine_opt(
+            path.path().to_str().unwrap(),
+            ::rocksdb::DBOptions::new(),
+            cfs_opts,
+        ).unwrap();
+        let db = Arc::new(db);
+        safe_point.bind(&db).unwrap();
+        db
+    }
+
+    fn put_write(db: &DB, key: &[u8], commit_ts: u64, write: Write) {
+        let k = keys::data_key(make_key(key).append_ts(commit_ts).encoded());
+        let handle = rocksdb::get_cf_handle(db, CF_WRITE).unwrap();
+        db.put_cf(handle, &k, &write.to_bytes()).unwrap();
+    }
+
+    fn put_value(db: &DB, key: &[u8], start_ts: u64) {
+        let k = keys::data_key(make_key(key).append_ts(start_ts).encoded());
+        let handle = rocksdb::get_cf_handle(db, CF_DEFAULT).unwrap();
+        db.put_cf(handle, &k, b"v").unwrap();
+    }
+
+    fn put_lock(db: &DB, key: &[u8], start_ts: u64) {
+        let k = keys::data_key(make_key(key).encoded());
+        let lock = Lock::new(LockType::Put, key.to_vec(), start_ts, 0, None, 0);
+        let handle = rocksdb::get_cf_handle(db, CF_LOCK).unwrap();
+        db.put_cf(handle, &k, &lock.to_bytes()).unwrap();
+    }
+
+    fn compact(db: &DB) {
+        for cf in &[CF_WRITE, CF_DEFAULT] {
+            let handle = rocksdb::get_cf_handle(db, cf).unwrap();
+            rocksdb::compact_range(db, handle, None, None, false);
+        }
+    }
+
+    fn has_write(db: &DB, key: &[u8], commit_ts: u64) -> bool {
+        let k = keys::data_key(make_key(key).append_ts(commit_ts).encoded());
+        db.get_value_cf(CF_WRITE, &k).unwrap().is_some()
+    }
+
+    fn has_value(db: &DB, key: &[u8], start_ts: u64) -> bool {
+        let k = keys::data_key(make_key(key).append_ts(start_ts).encoded());
+        db.get_value_cf(CF_DEFAULT, &k).unwrap().is_some()
+    }
+
+    #[test]
+    fn test_gc_compaction_filter() {
+        let path = TempDir::new("test_gc_compaction_filter").unwrap();
+        let safe_point = Arc::new(GcSafePoint::new());
+        let db = new_engine(&path, safe_point.clone());
+
+        // k1: Put(5, 10) with value, Rollback(15), Put(20, 25), Put(30, 40) with value.
+        put_value(&db, b"k1", 5);
+        put_write(&db, b"k1", 10, Write::new(WriteType::Put, 5, None));
+        put_write(&db, b"k1", 15, Write::new(WriteType::Rollback, 15, None));
+        put_write(&db, b"k1", 25, Write::new(WriteType::Put, 20, Some(b"v".to_vec())));
+        put_value(&db, b"k1", 30);
+        put_write(&db, b"k1", 40, Write::new(WriteType::Put, 30, None));
+        // k2: Put(5, 10) with value, Delete(15, 20).
+        put_value(&db, b"k2", 5);
+        put_write(&db, b"k2", 10, Write::new(WriteType::Put, 5, None));
+        put_write(&db, b"k2", 20, Write::new(WriteType::Delete, 15, None));
+        // k3: a rolled back transaction and a value of a pending transaction.
+        put_write(&db, b"k3", 5, Write::new(WriteType::Rollback, 5, None));
+        put_value(&db, b"k3", 28);
+        put_lock(&db, b"k3", 28);
+
+        // Nothing is dropped without a safe point.
+        compact(&db);
+        assert!(has_write(&db, b"k1", 10));
+        assert!(has_value(&db, b"k1", 5));
+
+        safe_point.update(30).unwrap();
+        compact(&db);
+        assert!(!has_write(&db, b"k1", 10));
+        assert!(!has_value(&db, b"k1", 5));
+        assert!(!has_write(&db, b"k1", 15));
+        assert!(has_write(&db, b"k1", 25));
+        assert!(has_write(&db, b"k1", 40));
+        assert!(has_value(&db, b"k1", 30));
+        assert!(!has_write(&db, b"k2", 10));
+        assert!(!has_value(&db, b"k2", 5));
+        assert!(has_write(&db, b"k2", 20));
+        assert!(!has_write(&db, b"k3", 5));
+        assert!(has_value(&db, b"k3", 28));
+    }
+
+    #[test]
+    fn test_default_compaction_filter() {
+        let path = TempDir::new("test_default_compaction_filter").unwrap();
+        let safe_point = Arc::new(GcSafePoint::new());
+        let db = new_engine(&path, safe_point.clone());
+
+        // k1: a value left by Put(5, 10), which is older than Put(20, 25).
+        put_value(&db, b"k1", 5);
+        put_value(&db, b"k1", 20);
+        put_write(&db, b"k1", 25, Write::new(WriteType::Put, 20, None));
+        // k2: the latest value before the safe point, and a value committed after it.
+        put_value(&db, b"k2", 5);
+        put_write(&db, b"k2", 10, Write::new(WriteType::Put, 5, None));
+        put_value(&db, b"k2", 30);
+        put_write(&db, b"k2", 40, Write::new(WriteType::Put, 30, None));
+        // A raw key, which has no write records.
+        let raw_key = keys::data_key(b"raw_key_longer_than_ts");
+        let handle = rocksdb::get_cf_handle(&db, CF_DEFAULT).unwrap();
+        db.put_cf(handle, &raw_key, b"v").unwrap();
+
+        safe_point.update(35).unwrap();
+        let handle = rocksdb::get_cf_handle(&db, CF_DEFAULT).unwrap();
+        rocksdb::compact_range(&db, handle, None, None, false);
+        assert!(!has_value(&db, b"k1", 5));
+        assert!(has_value(&db, b"k1", 20));
+        assert!(has_value(&db, b"k2", 5));
+        assert!(has_value(&db, b"k2", 30));
+        assert!(db.get_value_cf(CF_DEFAULT, &raw_key).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_gc_safe_point_persisted() {
+        let path = TempDir::new("test_gc_safe_point_persisted").unwrap();
+        {
+            let safe_point = Arc::new(GcSafePoint::new());
+            let db = new_engine(&path, safe_point.clone());
+            assert_eq!(safe_point.get(), 0);
+            safe_point.update(20).unwrap();
+            // The safe point never goes backward.
+            safe_point.update(10).unwrap();
+            assert_eq!(safe_point.get(), 20);
+            drop(db);
+        }
+        let safe_point = Arc::new(GcSafePoint::new());
+        let _db = new_engine(&path, safe_point.clone());
+        assert_eq!(safe_point.get(), 20);
+    }
+}
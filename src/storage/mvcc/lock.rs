@@ -12,8 +12,8 @@
 // limitations under the License.
 
 use byteorder::ReadBytesExt;
-use storage::{Mutation, SHORT_VALUE_MAX_LEN, SHORT_VALUE_PREFIX};
-use util::codec::number::{MAX_VAR_U64_LEN, NumberDecoder, NumberEncoder};
+use storage::{Mutation, FOR_UPDATE_TS_PREFIX, SHORT_VALUE_MAX_LEN, SHORT_VALUE_PREFIX};
+use util::codec::number::{self, MAX_VAR_U64_LEN, NumberDecoder, NumberEncoder};
 use util::codec::bytes::{BytesEncoder, CompactBytesDecoder};
 use super::{Error, Result};
 use super::super::types::Value;
@@ -23,11 +23,13 @@ pub enum LockType {
     Put,
     Delete,
     Lock,
+    Pessimistic,
 }
 
 const FLAG_PUT: u8 = b'P';
 const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
+const FLAG_PESSIMISTIC: u8 = b'S';
 
 impl LockType {
     pub fn from_mutation(mutation: &Mutation) -> LockType {
@@ -43,6 +45,7 @@ impl LockType {
             FLAG_PUT => Some(LockType::Put),
             FLAG_DELETE => Some(LockType::Delete),
             FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
             _ => None,
         }
     }
@@ -52,6 +55,7 @@ impl LockType {
             LockType::Put => FLAG_PUT,
             LockType::Delete => FLAG_DELETE,
             LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
         }
     }
 }
@@ -63,6 +67,8 @@ pub struct Lock {
     pub ts: u64,
     pub ttl: u64,
     pub short_value: Option<Value>,
+    // The `for_update_ts` of a pessimistic transaction, 0 for optimistic transactions.
+    pub for_update_ts: u64,
 }
 
 impl Lock {
@@ -72,6 +78,7 @@ impl Lock {
         ts: u64,
         ttl: u64,
         short_value: Option<Value>,
+        for_update_ts: u64,
     ) -> Lock {
         Lock {
             lock_type: lock_type,
@@ -79,12 +86,14 @@ impl Lock {
             ts: ts,
             ttl: ttl,
             short_value: short_value,
+            for_update_ts: for_update_ts,
         }
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut b = Vec::with_capacity(
-            1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN + SHORT_VALUE_MAX_LEN + 2,
+            1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN + SHORT_VALUE_MAX_LEN +
+                2 + 1 + number::U64_SIZE,
         );
         b.push(self.lock_type.to_u8());
         b.encode_compact_bytes(&self.primary).unwrap();
@@ -95,6 +104,10 @@ impl Lock {
             b.push(v.len() as u8);
             b.extend_from_slice(v);
         }
+        if self.for_update_ts > 0 {
+            b.push(FOR_UPDATE_TS_PREFIX);
+            b.encode_u64(self.for_update_ts).unwrap();
+        }
         b
     }
 
@@ -107,28 +120,35 @@ impl Lock {
         let ts = b.decode_var_u64()?;
         let ttl = if b.is_empty() { 0 } else { b.decode_var_u64()? };
 
-        if b.is_empty() {
-            return Ok(Lock::new(lock_type, primary, ts, ttl, None));
-        }
-
-        let flag = b.read_u8()?;
-        assert_eq!(
-            flag,
-            SHORT_VALUE_PREFIX,
-            "invalid flag [{:?}] in write",
-            flag
-        );
-
-        let len = b.read_u8()?;
-        if len as usize != b.len() {
-            panic!(
-                "short value len [{}] not equal to content len [{}]",
-                len,
-                b.len()
-            );
+        let mut short_value = None;
+        let mut for_update_ts = 0;
+        while !b.is_empty() {
+            match b.read_u8()? {
+                SHORT_VALUE_PREFIX => {
+                    let len = b.read_u8()? as usize;
+                    if b.len() < len {
+                        panic!(
+                            "short value len [{}] is larger than content len [{}]",
+                            len,
+                            b.len()
+                        );
+                    }
+                    short_value = Some(b[..len].to_vec());
+                    b = &b[len..];
+                }
+                FOR_UPDATE_TS_PREFIX => for_update_ts = b.decode_u64()?,
+                flag => panic!("invalid flag [{:?}] in lock", flag),
+            }
         }
 
-        Ok(Lock::new(lock_type, primary, ts, ttl, Some(b.to_vec())))
+        Ok(Lock::new(
+            lock_type,
+            primary,
+            ts,
+            ttl,
+            short_value,
+            for_update_ts,
+        ))
     }
 }
 
@@ -191,13 +211,23 @@ mod tests {
     fn test_lock() {
         // Test `Lock::to_bytes()` and `Lock::parse()` works as a pair.
         let mut locks = vec![
-            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None),
+            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None, 0),
             Lock::new(
                 LockType::Delete,
                 b"pk".to_vec(),
                 1,
                 10,
                 Some(b"short_value".to_vec()),
+                0,
+            ),
+            Lock::new(LockType::Pessimistic, b"pk".to_vec(), 1, 10, None, 10),
+            Lock::new(
+                LockType::Put,
+                b"pk".to_vec(),
+                1,
+                10,
+                Some(b"short_value".to_vec()),
+                10,
             ),
         ];
         for (i, lock) in locks.drain(..).enumerate() {
@@ -215,6 +245,7 @@ mod tests {
             1,
             10,
             Some(b"short_value".to_vec()),
+            0,
         );
         let v = lock.to_bytes();
         assert!(Lock::parse(&v[..4]).is_err());
@@ -16,6 +16,7 @@ mod txn;
 mod lock;
 mod write;
 mod metrics;
+mod compaction_filter;
 
 use std::io;
 use std::error;
@@ -23,6 +24,8 @@ pub use self::txn::{MvccTxn, MAX_TXN_WRITE_SIZE};
 pub use self::reader::MvccReader;
 pub use self::lock::{Lock, LockType};
 pub use self::write::{Write, WriteType};
+pub use self::compaction_filter::{new_gc_compaction_filter, DefaultCompactionFilter, GcSafePoint,
+                                  WriteCompactionFilter, GC_COMPACTION_FILTER_NAME};
 use util::escape;
 
 quick_error! {
@@ -67,6 +70,23 @@ quick_error! {
              start_ts, conflict_ts, key, primary)
         }
         KeyVersion {description("bad format key(version)")}
+        PessimisticLockRolledBack { start_ts: u64, key: Vec<u8> } {
+            description("pessimistic lock already rolled back")
+            display("pessimistic lock already rolled back, start_ts:{}, key:{:?}", start_ts, key)
+        }
+        PessimisticLockNotFound { start_ts: u64, key: Vec<u8> } {
+            description("pessimistic lock not found")
+            display("pessimistic lock not found, start_ts:{}, key:{:?}", start_ts, key)
+        }
+        LockTypeNotMatch { start_ts: u64, key: Vec<u8> } {
+            description("lock type not match")
+            display("pessimistic lock can't be committed, start_ts:{}, key:{:?}", start_ts, key)
+        }
+        Deadlock { start_ts: u64, lock_ts: u64, lock_key: Vec<u8>, deadlock_key_hash: u64 } {
+            description("deadlock")
+            display("deadlock occurs between txn:{} and txn:{}, lock_key:{:?}, key_hash:{}",
+                    start_ts, lock_ts, lock_key, deadlock_key_hash)
+        }
         Other(err: Box<error::Error + Sync + Send>) {
             from()
             cause(err.as_ref())
@@ -115,6 +135,33 @@ impl Error {
                 primary: primary.to_owned(),
             }),
             Error::KeyVersion => Some(Error::KeyVersion),
+            Error::PessimisticLockRolledBack { start_ts, ref key } => {
+                Some(Error::PessimisticLockRolledBack {
+                    start_ts: start_ts,
+                    key: key.to_owned(),
+                })
+            }
+            Error::PessimisticLockNotFound { start_ts, ref key } => {
+                Some(Error::PessimisticLockNotFound {
+                    start_ts: start_ts,
+                    key: key.to_owned(),
+                })
+            }
+            Error::LockTypeNotMatch { start_ts, ref key } => Some(Error::LockTypeNotMatch {
+                start_ts: start_ts,
+                key: key.to_owned(),
+            }),
+            Error::Deadlock {
+                start_ts,
+                lock_ts,
+                ref lock_key,
+                deadlock_key_hash,
+            } => Some(Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                lock_key: lock_key.to_owned(),
+                deadlock_key_hash: deadlock_key_hash,
+            }),
             Error::Committed { commit_ts } => Some(Error::Committed {
                 commit_ts: commit_ts,
             }),
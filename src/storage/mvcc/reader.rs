@@ -14,9 +14,10 @@
 use storage::engine::{Cursor, ScanMode, Snapshot, Statistics};
 use storage::{Key, Value, CF_LOCK, CF_WRITE};
 use super::{Error, Result};
-use super::lock::Lock;
+use super::lock::{Lock, LockType};
 use super::write::{Write, WriteType};
 use raftstore::store::engine::IterOption;
+use raftstore::store::util;
 use std::u64;
 use kvproto::kvrpcpb::IsolationLevel;
 use util::properties::MvccProperties;
@@ -191,6 +192,10 @@ impl<'a> MvccReader<'a> {
 
     fn check_lock(&mut self, key: &Key, mut ts: u64) -> Result<Option<u64>> {
         if let Some(lock) = self.load_lock(key)? {
+            // Pessimistic locks carry no data and never block reads.
+            if lock.lock_type == LockType::Pessimistic {
+                return Ok(Some(ts));
+            }
             if lock.ts <= ts {
                 if ts == u64::MAX && key.raw()? == lock.primary {
                     // when ts==u64::MAX(which means to get latest committed version for
@@ -219,6 +224,11 @@ impl<'a> MvccReader<'a> {
             },
             IsolationLevel::RC => {}
         }
+        self.get_committed(key, ts)
+    }
+
+    /// Reads the latest version of `key` committed at or before `ts`, regardless of the lock.
+    fn get_committed(&mut self, key: &Key, mut ts: u64) -> Result<Option<Value>> {
         loop {
             match self.seek_write(key, ts)? {
                 Some((commit_ts, mut write)) => match write.write_type {
@@ -241,6 +251,47 @@ impl<'a> MvccReader<'a> {
         }
     }
 
+    /// Works like `get`, but instead of returning `KeyIsLocked`, resolves the lock in place if
+    /// the primary of the locking transaction is already committed or rolled back. The lock
+    /// is ignored if the transaction is rolled back or committed after `ts`, and the locked
+    /// value is returned if it's committed at or before `ts`. The lock is returned as is if the
+    /// primary is out of the region of the snapshot.
+    pub fn get_resolving_lock(&mut self, key: &Key, ts: u64) -> Result<Option<Value>> {
+        let err = match self.get(key, ts) {
+            Err(e @ Error::KeyIsLocked { .. }) => e,
+            res => return res,
+        };
+        let (primary, lock_ts) = match err {
+            Error::KeyIsLocked {
+                ref primary, ts, ..
+            } => (Key::from_raw(primary), ts),
+            _ => unreachable!(),
+        };
+        if let Some(region) = self.snapshot.get_region() {
+            if util::check_key_in_region(primary.encoded(), region).is_err() {
+                return Err(err);
+            }
+        }
+        match self.get_txn_commit_info(&primary, lock_ts)? {
+            Some((_, WriteType::Rollback)) => self.get_committed(key, ts),
+            Some((commit_ts, _)) if commit_ts > ts => self.get_committed(key, ts),
+            Some(_) => {
+                let mut lock = self.load_lock(key)?.unwrap();
+                match lock.lock_type {
+                    LockType::Put => match lock.short_value.take() {
+                        Some(_) if self.key_only => Ok(Some(vec![])),
+                        Some(v) => Ok(Some(v)),
+                        None => self.load_data(key, lock.ts).map(Some),
+                    },
+                    LockType::Delete => Ok(None),
+                    LockType::Lock | LockType::Pessimistic => self.get_committed(key, ts),
+                }
+            }
+            // The transaction is still in progress.
+            None => Err(err),
+        }
+    }
+
     pub fn get_txn_commit_info(
         &mut self,
         key: &Key,
@@ -12,8 +12,8 @@
 // limitations under the License.
 
 use std::fmt;
-use storage::{is_short_value, Key, Mutation, Options, Statistics, Value, CF_DEFAULT, CF_LOCK,
-              CF_WRITE};
+use storage::{is_short_value, Key, Mutation, Options, Statistics, TxnStatus, Value, CF_DEFAULT,
+              CF_LOCK, CF_WRITE};
 use storage::engine::{Modify, ScanMode, Snapshot};
 use super::reader::MvccReader;
 use super::lock::{Lock, LockType};
@@ -24,6 +24,13 @@ use kvproto::kvrpcpb::IsolationLevel;
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
 
+// The physical part of a timestamp is in milliseconds, the same unit as the lock TTL.
+const TSO_PHYSICAL_SHIFT_BITS: u64 = 18;
+
+fn extract_physical(ts: u64) -> u64 {
+    ts >> TSO_PHYSICAL_SHIFT_BITS
+}
+
 pub struct MvccTxn<'a> {
     reader: MvccReader<'a>,
     start_ts: u64,
@@ -77,8 +84,21 @@ impl<'a> MvccTxn<'a> {
         primary: Vec<u8>,
         ttl: u64,
         short_value: Option<Value>,
+        for_update_ts: u64,
     ) {
-        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value).to_bytes();
+        let lock = Lock::new(
+            lock_type,
+            primary,
+            self.start_ts,
+            ttl,
+            short_value,
+            for_update_ts,
+        );
+        self.put_lock(key, &lock);
+    }
+
+    fn put_lock(&mut self, key: Key, lock: &Lock) {
+        let lock = lock.to_bytes();
         self.write_size += CF_LOCK.len() + key.encoded().len() + lock.len();
         self.writes.push(Modify::Put(CF_LOCK, key, lock));
     }
@@ -158,7 +178,13 @@ impl<'a> MvccTxn<'a> {
             return Ok(());
         }
 
-        let short_value = if let Mutation::Put((_, ref value)) = mutation {
+        self.prewrite_key_value(&mutation, primary, options);
+        Ok(())
+    }
+
+    fn prewrite_key_value(&mut self, mutation: &Mutation, primary: &[u8], options: &Options) {
+        let key = mutation.key();
+        let short_value = if let Mutation::Put((_, ref value)) = *mutation {
             if is_short_value(value) {
                 Some(value.clone())
             } else {
@@ -168,18 +194,229 @@ impl<'a> MvccTxn<'a> {
             None
         };
 
+        // The value goes before the lock, so that the lock is never written without its value
+        // when the scheduler splits a large prewrite into several proposals.
+        if let Mutation::Put((_, ref value)) = *mutation {
+            if !is_short_value(value) {
+                let ts = self.start_ts;
+                self.put_value(key, ts, value.clone());
+            }
+        }
+
         self.lock_key(
             key.clone(),
-            LockType::from_mutation(&mutation),
+            LockType::from_mutation(mutation),
             primary.to_vec(),
             options.lock_ttl,
             short_value,
+            options.for_update_ts,
         );
+    }
 
-        if let Mutation::Put((_, ref value)) = mutation {
-            if !is_short_value(value) {
+    /// Prewrites and commits a mutation at `commit_ts` in one phase, without leaving a lock.
+    ///
+    /// The conflict checks are the same as `prewrite`. It's only correct if all the mutations of
+    /// the transaction are handled by the same `MvccTxn`, so that they are written atomically.
+    pub fn one_pc_prewrite(
+        &mut self,
+        mutation: Mutation,
+        primary: &[u8],
+        commit_ts: u64,
+        options: &Options,
+    ) -> Result<()> {
+        let key = mutation.key();
+        if !options.skip_constraint_check {
+            if let Some((commit, _)) = self.reader.seek_write(key, u64::max_value())? {
+                if commit >= self.start_ts {
+                    // The transaction may have been committed by a duplicated request.
+                    match self.reader.get_txn_commit_info(key, self.start_ts)? {
+                        Some((_, WriteType::Rollback)) | None => {}
+                        Some(_) => {
+                            info!(
+                                "duplicated one-phase commit with start_ts {}, ignore it.",
+                                self.start_ts
+                            );
+                            return Ok(());
+                        }
+                    }
+                    MVCC_CONFLICT_COUNTER
+                        .with_label_values(&["one_pc_write_conflict"])
+                        .inc();
+                    return Err(Error::WriteConflict {
+                        start_ts: self.start_ts,
+                        conflict_ts: commit,
+                        key: key.encoded().to_owned(),
+                        primary: primary.to_vec(),
+                    });
+                }
+            }
+        }
+        if let Some(lock) = self.reader.load_lock(key)? {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: key.raw()?,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            // The lock of the transaction itself, e.g. a pessimistic lock, is replaced by the
+            // committed record.
+            self.unlock_key(key.clone());
+        }
+
+        let short_value = match mutation {
+            Mutation::Put((_, ref value)) if is_short_value(value) => Some(value.clone()),
+            Mutation::Put((_, ref value)) => {
                 let ts = self.start_ts;
                 self.put_value(key, ts, value.clone());
+                None
+            }
+            _ => None,
+        };
+        let write = Write::new(
+            WriteType::from_lock_type(LockType::from_mutation(&mutation)).unwrap(),
+            self.start_ts,
+            short_value,
+        );
+        self.put_write(key, commit_ts, write.to_bytes());
+        Ok(())
+    }
+
+    /// Acquires a pessimistic lock on `key` at `for_update_ts`.
+    ///
+    /// The lock only records ownership of the key, readers are not blocked by it. It is
+    /// upgraded to a normal lock by `pessimistic_prewrite` later.
+    pub fn acquire_pessimistic_lock(
+        &mut self,
+        key: Key,
+        primary: &[u8],
+        lock_ttl: u64,
+        for_update_ts: u64,
+    ) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(&key)? {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: key.raw()?,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            if lock.lock_type != LockType::Pessimistic {
+                // The key has been prewritten, the request must be a stale retry.
+                info!(
+                    "key {} is prewritten by txn {}, ignore pessimistic lock request",
+                    key,
+                    self.start_ts
+                );
+                return Ok(());
+            }
+            if lock.for_update_ts >= for_update_ts {
+                return Ok(());
+            }
+            // Overwrite the lock with the larger for_update_ts.
+            self.lock_key(
+                key,
+                LockType::Pessimistic,
+                primary.to_vec(),
+                lock_ttl,
+                None,
+                for_update_ts,
+            );
+            return Ok(());
+        }
+
+        if let Some((commit_ts, _)) = self.reader.seek_write(&key, u64::max_value())? {
+            // Abort on writes committed after `for_update_ts`, the client should retry with
+            // a newer `for_update_ts`.
+            if commit_ts > for_update_ts {
+                MVCC_CONFLICT_COUNTER
+                    .with_label_values(&["acquire_pessimistic_lock_conflict"])
+                    .inc();
+                return Err(Error::WriteConflict {
+                    start_ts: self.start_ts,
+                    conflict_ts: commit_ts,
+                    key: key.encoded().to_owned(),
+                    primary: primary.to_vec(),
+                });
+            }
+        }
+        // The transaction may have been rolled back by others resolving its locks.
+        if let Some((_, WriteType::Rollback)) =
+            self.reader.get_txn_commit_info(&key, self.start_ts)?
+        {
+            return Err(Error::PessimisticLockRolledBack {
+                start_ts: self.start_ts,
+                key: key.raw()?,
+            });
+        }
+
+        self.lock_key(
+            key,
+            LockType::Pessimistic,
+            primary.to_vec(),
+            lock_ttl,
+            None,
+            for_update_ts,
+        );
+        Ok(())
+    }
+
+    /// Prewrites a mutation of a pessimistic transaction.
+    ///
+    /// If `is_pessimistic_lock` is true, the key must have been locked by
+    /// `acquire_pessimistic_lock`, which already checked write conflicts, so the lock is just
+    /// upgraded. Otherwise the key is prewritten without constraint check, because the
+    /// isolation is guaranteed by the pessimistic locks on the other keys.
+    pub fn pessimistic_prewrite(
+        &mut self,
+        mutation: Mutation,
+        primary: &[u8],
+        is_pessimistic_lock: bool,
+        options: &Options,
+    ) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(mutation.key())? {
+            if lock.ts != self.start_ts {
+                if is_pessimistic_lock {
+                    // The pessimistic lock is lost, it must have been resolved by others.
+                    return Err(Error::PessimisticLockNotFound {
+                        start_ts: self.start_ts,
+                        key: mutation.key().raw()?,
+                    });
+                }
+                return Err(Error::KeyIsLocked {
+                    key: mutation.key().raw()?,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            if lock.lock_type != LockType::Pessimistic {
+                info!(
+                    "duplicated prewrite with start_ts {}, ignore it.",
+                    self.start_ts
+                );
+                return Ok(());
+            }
+        } else if is_pessimistic_lock {
+            return Err(Error::PessimisticLockNotFound {
+                start_ts: self.start_ts,
+                key: mutation.key().raw()?,
+            });
+        }
+
+        self.prewrite_key_value(&mutation, primary, options);
+        Ok(())
+    }
+
+    /// Removes the pessimistic lock on `key` if it was acquired at or before `for_update_ts`.
+    pub fn pessimistic_rollback(&mut self, key: Key, for_update_ts: u64) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(&key)? {
+            if lock.lock_type == LockType::Pessimistic && lock.ts == self.start_ts &&
+                lock.for_update_ts <= for_update_ts
+            {
+                self.unlock_key(key);
             }
         }
         Ok(())
@@ -188,6 +425,13 @@ impl<'a> MvccTxn<'a> {
     pub fn commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
         let (lock_type, short_value) = match self.reader.load_lock(key)? {
             Some(ref mut lock) if lock.ts == self.start_ts => {
+                // A pessimistic lock has no data, it must be prewritten before committing.
+                if lock.lock_type == LockType::Pessimistic {
+                    return Err(Error::LockTypeNotMatch {
+                        start_ts: self.start_ts,
+                        key: key.raw()?,
+                    });
+                }
                 (lock.lock_type, lock.short_value.take())
             }
             _ => {
@@ -218,7 +462,7 @@ impl<'a> MvccTxn<'a> {
             }
         };
         let write = Write::new(
-            WriteType::from_lock_type(lock_type),
+            WriteType::from_lock_type(lock_type).unwrap(),
             self.start_ts,
             short_value,
         );
@@ -271,6 +515,62 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
+    /// Extends the TTL of the primary lock to `advise_ttl` if it's larger, returns the TTL of the
+    /// lock after the update.
+    pub fn txn_heart_beat(&mut self, primary_key: Key, advise_ttl: u64) -> Result<u64> {
+        match self.reader.load_lock(&primary_key)? {
+            Some(mut lock) if lock.ts == self.start_ts => {
+                if lock.ttl < advise_ttl {
+                    lock.ttl = advise_ttl;
+                    self.put_lock(primary_key, &lock);
+                }
+                Ok(lock.ttl)
+            }
+            _ => {
+                MVCC_CONFLICT_COUNTER
+                    .with_label_values(&["txn_heart_beat_lock_not_found"])
+                    .inc();
+                Err(Error::TxnLockNotFound {
+                    start_ts: self.start_ts,
+                    commit_ts: 0,
+                    key: primary_key.encoded().to_owned(),
+                })
+            }
+        }
+    }
+
+    /// Tells the status of the transaction by its primary key. If the primary lock has expired at
+    /// `current_ts`, or the transaction has never been prewritten, rolls it back.
+    pub fn check_txn_status(&mut self, primary_key: Key, current_ts: u64) -> Result<TxnStatus> {
+        let expire_at = match self.reader.load_lock(&primary_key)? {
+            Some(ref lock) if lock.ts == self.start_ts => extract_physical(lock.ts) + lock.ttl,
+            _ => {
+                return match self.reader.get_txn_commit_info(&primary_key, self.start_ts)? {
+                    Some((_, WriteType::Rollback)) => Ok(TxnStatus::RolledBack),
+                    Some((commit_ts, _)) => Ok(TxnStatus::Committed {
+                        commit_ts: commit_ts,
+                    }),
+                    None => {
+                        // Make sure the transaction can't be prewritten later.
+                        self.rollback(&primary_key)?;
+                        Ok(TxnStatus::RolledBack)
+                    }
+                };
+            }
+        };
+        let now = extract_physical(current_ts);
+        if expire_at > now {
+            return Ok(TxnStatus::Alive {
+                ttl: expire_at - now,
+            });
+        }
+        MVCC_CONFLICT_COUNTER
+            .with_label_values(&["check_txn_status_lock_expired"])
+            .inc();
+        self.rollback(&primary_key)?;
+        Ok(TxnStatus::RolledBack)
+    }
+
     pub fn gc(&mut self, key: &Key, safe_point: u64) -> Result<()> {
         let mut remove_older = false;
         let mut ts: u64 = u64::max_value();
@@ -338,9 +638,9 @@ mod tests {
     use tempdir::TempDir;
     use kvproto::kvrpcpb::{Context, IsolationLevel};
     use super::MvccTxn;
-    use super::super::MvccReader;
+    use super::super::{LockType, MvccReader, Result};
     use super::super::write::{Write, WriteType};
-    use storage::{make_key, Mutation, Options, ScanMode, Statistics, ALL_CFS, CF_WRITE,
+    use storage::{make_key, Mutation, Options, ScanMode, Statistics, TxnStatus, ALL_CFS, CF_WRITE,
                   SHORT_VALUE_MAX_LEN};
     use storage::engine::{self, Engine, TEMP_DIR};
 
@@ -850,6 +1150,131 @@ mod tests {
         must_get_rc(engine.as_ref(), key, 20, v1);
     }
 
+    #[test]
+    fn test_pessimistic_lock() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+
+        // Normal.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 1);
+        must_pessimistic_locked(engine.as_ref(), k, 1, 1);
+        // Pessimistic locks don't block reads.
+        must_get_none(engine.as_ref(), k, 3);
+        // Pessimistic locks can't be committed.
+        must_commit_err(engine.as_ref(), k, 1, 2);
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 1, 1, true);
+        must_locked(engine.as_ref(), k, 1);
+        must_commit(engine.as_ref(), k, 1, 2);
+        must_unlocked(engine.as_ref(), k);
+        must_get(engine.as_ref(), k, 3, v);
+
+        // Lock conflict.
+        must_prewrite_put(engine.as_ref(), k, v, k, 3);
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 4, 4);
+        must_rollback(engine.as_ref(), k, 3);
+        must_unlocked(engine.as_ref(), k);
+
+        // Write conflict, the client should retry with a newer for_update_ts.
+        must_prewrite_put(engine.as_ref(), k, v, k, 5);
+        must_commit(engine.as_ref(), k, 5, 7);
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 6, 6);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 6, 7);
+        must_pessimistic_locked(engine.as_ref(), k, 6, 7);
+        // Acquiring again with a larger for_update_ts updates the lock.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 6, 8);
+        must_pessimistic_locked(engine.as_ref(), k, 6, 8);
+        // Pessimistic rollback with a smaller for_update_ts is ignored.
+        must_pessimistic_rollback(engine.as_ref(), k, 6, 7);
+        must_pessimistic_locked(engine.as_ref(), k, 6, 8);
+        must_pessimistic_rollback(engine.as_ref(), k, 6, 8);
+        must_unlocked(engine.as_ref(), k);
+
+        // Prewrite a lost pessimistic lock.
+        must_pessimistic_prewrite_put_err(engine.as_ref(), k, v, k, 6, 8, true);
+        // Keys without pessimistic locks are prewritten without constraint check.
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 6, 8, false);
+        must_locked(engine.as_ref(), k, 6);
+        // Duplicated prewrite.
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 6, 8, false);
+        must_rollback(engine.as_ref(), k, 6);
+        must_unlocked(engine.as_ref(), k);
+
+        // Acquire a pessimistic lock of a rolled back transaction.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 6, 9);
+        must_unlocked(engine.as_ref(), k);
+
+        // Rollback a pessimistic lock.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 10, 10);
+        must_rollback(engine.as_ref(), k, 10);
+        must_unlocked(engine.as_ref(), k);
+        must_written(engine.as_ref(), k, 10, 10, WriteType::Rollback);
+        must_get(engine.as_ref(), k, 11, v);
+    }
+
+    #[test]
+    fn test_txn_heart_beat() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+
+        // No lock.
+        must_txn_heart_beat_err(engine.as_ref(), k, 5, 100);
+
+        must_prewrite_put(engine.as_ref(), k, v, k, 5);
+        must_locked_with_ttl(engine.as_ref(), k, 5, 0);
+        must_txn_heart_beat(engine.as_ref(), k, 5, 100, 100);
+        must_locked_with_ttl(engine.as_ref(), k, 5, 100);
+        // A smaller TTL doesn't shrink the lock.
+        must_txn_heart_beat(engine.as_ref(), k, 5, 90, 100);
+        must_locked_with_ttl(engine.as_ref(), k, 5, 100);
+        // The lock belongs to another transaction.
+        must_txn_heart_beat_err(engine.as_ref(), k, 6, 200);
+
+        // The value is still there after the heart beats.
+        must_commit(engine.as_ref(), k, 5, 10);
+        must_get(engine.as_ref(), k, 11, v);
+        must_txn_heart_beat_err(engine.as_ref(), k, 5, 200);
+    }
+
+    #[test]
+    fn test_check_txn_status() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let ts = |physical: u64| physical << super::TSO_PHYSICAL_SHIFT_BITS;
+        let (k, v) = (b"k1", b"v1");
+
+        // Never prewritten, it's rolled back so that the prewrite can't succeed later.
+        must_check_txn_status(engine.as_ref(), k, ts(5), ts(6), TxnStatus::RolledBack);
+        must_written(engine.as_ref(), k, ts(5), ts(5), WriteType::Rollback);
+
+        // The lock is alive.
+        must_prewrite_put(engine.as_ref(), k, v, k, ts(10));
+        must_txn_heart_beat(engine.as_ref(), k, ts(10), 100, 100);
+        must_check_txn_status(
+            engine.as_ref(),
+            k,
+            ts(10),
+            ts(50),
+            TxnStatus::Alive { ttl: 60 },
+        );
+        must_locked(engine.as_ref(), k, ts(10));
+
+        // The lock has expired.
+        must_check_txn_status(engine.as_ref(), k, ts(10), ts(110), TxnStatus::RolledBack);
+        must_unlocked(engine.as_ref(), k);
+        must_check_txn_status(engine.as_ref(), k, ts(10), ts(120), TxnStatus::RolledBack);
+
+        // Committed.
+        must_prewrite_put(engine.as_ref(), k, v, k, ts(20));
+        must_commit(engine.as_ref(), k, ts(20), ts(30));
+        must_check_txn_status(
+            engine.as_ref(),
+            k,
+            ts(20),
+            ts(200),
+            TxnStatus::Committed { commit_ts: ts(30) },
+        );
+        must_get(engine.as_ref(), k, ts(40), v);
+    }
+
     fn must_get(engine: &Engine, key: &[u8], ts: u64, expect: &[u8]) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -982,6 +1407,144 @@ mod tests {
         );
     }
 
+    fn must_acquire_pessimistic_lock(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.acquire_pessimistic_lock(make_key(key), pk, 0, for_update_ts)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_acquire_pessimistic_lock_err(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        assert!(
+            txn.acquire_pessimistic_lock(make_key(key), pk, 0, for_update_ts)
+                .is_err()
+        );
+    }
+
+    fn pessimistic_prewrite_put(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+        is_pessimistic_lock: bool,
+    ) -> Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let mut options = Options::default();
+        options.for_update_ts = for_update_ts;
+        txn.pessimistic_prewrite(
+            Mutation::Put((make_key(key), value.to_vec())),
+            pk,
+            is_pessimistic_lock,
+            &options,
+        )?;
+        engine.write(&ctx, txn.modifies()).unwrap();
+        Ok(())
+    }
+
+    fn must_pessimistic_prewrite_put(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+        is_pessimistic_lock: bool,
+    ) {
+        pessimistic_prewrite_put(
+            engine,
+            key,
+            value,
+            pk,
+            start_ts,
+            for_update_ts,
+            is_pessimistic_lock,
+        ).unwrap();
+    }
+
+    fn must_pessimistic_prewrite_put_err(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+        is_pessimistic_lock: bool,
+    ) {
+        assert!(
+            pessimistic_prewrite_put(
+                engine,
+                key,
+                value,
+                pk,
+                start_ts,
+                for_update_ts,
+                is_pessimistic_lock,
+            ).is_err()
+        );
+    }
+
+    fn must_pessimistic_rollback(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.pessimistic_rollback(make_key(key), for_update_ts)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
     fn must_commit(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -1044,6 +1607,67 @@ mod tests {
         assert!(txn.rollback(&make_key(key)).is_err());
     }
 
+    fn must_txn_heart_beat(
+        engine: &Engine,
+        key: &[u8],
+        start_ts: u64,
+        advise_ttl: u64,
+        expect_ttl: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let ttl = txn.txn_heart_beat(make_key(key), advise_ttl).unwrap();
+        assert_eq!(ttl, expect_ttl);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_txn_heart_beat_err(engine: &Engine, key: &[u8], start_ts: u64, advise_ttl: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        assert!(txn.txn_heart_beat(make_key(key), advise_ttl).is_err());
+    }
+
+    fn must_check_txn_status(
+        engine: &Engine,
+        key: &[u8],
+        lock_ts: u64,
+        current_ts: u64,
+        expect: TxnStatus,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            lock_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let status = txn.check_txn_status(make_key(key), current_ts).unwrap();
+        assert_eq!(status, expect);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
     fn must_gc(engine: &Engine, key: &[u8], safe_point: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -1075,6 +1699,39 @@ mod tests {
         assert_eq!(lock.ts, start_ts);
     }
 
+    fn must_locked_with_ttl(engine: &Engine, key: &[u8], start_ts: u64, ttl: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            None,
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.ttl, ttl);
+    }
+
+    fn must_pessimistic_locked(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            None,
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.lock_type, LockType::Pessimistic);
+        assert_eq!(lock.for_update_ts, for_update_ts);
+    }
+
     fn must_unlocked(engine: &Engine, key: &[u8]) {
         let snapshot = engine.snapshot(&Context::new()).unwrap();
         let mut statistics = Statistics::default();
@@ -0,0 +1,274 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deadlock detection for transactions waiting on locks.
+//!
+//! The detector maintains a wait-for graph whose vertices are transactions (identified by their
+//! `start_ts`) and whose edges are labelled by the hash of the key being waited for. Before a
+//! transaction starts waiting for a lock, the scheduler asks the detector whether the new edge
+//! would close a cycle. If so, the waiting transaction is chosen as the victim and gets a
+//! `Deadlock` error instead.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use storage::Key;
+use util::collections::{HashMap, HashSet};
+use util::time::Instant;
+
+/// Calculates the hash of `key` which is used to label the edges of the wait-for graph.
+pub fn gen_key_hash(key: &Key) -> u64 {
+    let mut s = DefaultHasher::new();
+    key.hash(&mut s);
+    s.finish()
+}
+
+/// The locks of one transaction that another transaction is waiting for.
+struct Locks {
+    ts: u64,
+    hashes: Vec<u64>,
+    last_detect_time: Instant,
+}
+
+impl Locks {
+    fn new(ts: u64, hash: u64) -> Locks {
+        Locks {
+            ts: ts,
+            hashes: vec![hash],
+            last_detect_time: Instant::now_coarse(),
+        }
+    }
+
+    /// Adds the key hash if it doesn't exist and refreshes the detect time.
+    fn push(&mut self, hash: u64) {
+        if !self.hashes.contains(&hash) {
+            self.hashes.push(hash);
+        }
+        self.last_detect_time = Instant::now_coarse();
+    }
+
+    /// Removes the key hash, returns true if there is no key left.
+    fn remove(&mut self, hash: u64) -> bool {
+        if let Some(idx) = self.hashes.iter().position(|h| *h == hash) {
+            self.hashes.remove(idx);
+        }
+        self.hashes.is_empty()
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.last_detect_time.elapsed() >= ttl
+    }
+}
+
+/// `DetectTable` is the wait-for graph, mapping a waiting transaction to the transactions that
+/// hold the locks it's waiting for.
+///
+/// Edges which have not been refreshed for `ttl` are considered stale and ignored, so that a
+/// transaction which disappeared without cleaning up can't cause false deadlocks forever.
+pub struct DetectTable {
+    wait_for_map: HashMap<u64, Vec<Locks>>,
+    ttl: Duration,
+}
+
+impl DetectTable {
+    /// Creates an empty wait-for graph.
+    pub fn new(ttl: Duration) -> DetectTable {
+        DetectTable {
+            wait_for_map: HashMap::default(),
+            ttl: ttl,
+        }
+    }
+
+    /// Checks whether `txn_ts` waiting for the lock of `lock_ts` on the key hashed to `lock_hash`
+    /// causes a deadlock.
+    ///
+    /// Returns the hash of the key on which the cycle is closed if a deadlock is detected;
+    /// otherwise the edge is added to the graph and `None` is returned.
+    pub fn detect(&mut self, txn_ts: u64, lock_ts: u64, lock_hash: u64) -> Option<u64> {
+        if txn_ts == lock_ts {
+            return None;
+        }
+        if let Some(deadlock_key_hash) = self.do_detect(txn_ts, lock_ts) {
+            return Some(deadlock_key_hash);
+        }
+        self.register(txn_ts, lock_ts, lock_hash);
+        None
+    }
+
+    /// Walks the graph from `wait_for_ts`, returns the key hash of the edge pointing back to
+    /// `txn_ts` if there is one.
+    fn do_detect(&mut self, txn_ts: u64, wait_for_ts: u64) -> Option<u64> {
+        let ttl = self.ttl;
+        let mut stack = vec![wait_for_ts];
+        let mut pushed: HashSet<u64> = HashSet::default();
+        pushed.insert(wait_for_ts);
+        while let Some(ts) = stack.pop() {
+            let empty = match self.wait_for_map.get_mut(&ts) {
+                Some(wait_for) => {
+                    wait_for.retain(|locks| !locks.is_expired(ttl));
+                    for locks in wait_for.iter() {
+                        if locks.ts == txn_ts {
+                            return Some(locks.hashes[0]);
+                        }
+                        if pushed.insert(locks.ts) {
+                            stack.push(locks.ts);
+                        }
+                    }
+                    wait_for.is_empty()
+                }
+                None => continue,
+            };
+            if empty {
+                self.wait_for_map.remove(&ts);
+            }
+        }
+        None
+    }
+
+    /// Adds the edge `txn_ts` -> `lock_ts` labelled by `lock_hash`.
+    fn register(&mut self, txn_ts: u64, lock_ts: u64, lock_hash: u64) {
+        let wait_for = self.wait_for_map.entry(txn_ts).or_insert_with(Vec::new);
+        for locks in wait_for.iter_mut() {
+            if locks.ts == lock_ts {
+                locks.push(lock_hash);
+                return;
+            }
+        }
+        wait_for.push(Locks::new(lock_ts, lock_hash));
+    }
+
+    /// Removes the edge `txn_ts` -> `lock_ts` labelled by `lock_hash`, typically because
+    /// `txn_ts` stops waiting for that key.
+    pub fn clean_up_wait_for(&mut self, txn_ts: u64, lock_ts: u64, lock_hash: u64) {
+        let mut empty = false;
+        if let Some(wait_for) = self.wait_for_map.get_mut(&txn_ts) {
+            if let Some(idx) = wait_for.iter().position(|locks| locks.ts == lock_ts) {
+                if wait_for[idx].remove(lock_hash) {
+                    wait_for.swap_remove(idx);
+                }
+            }
+            empty = wait_for.is_empty();
+        }
+        if empty {
+            self.wait_for_map.remove(&txn_ts);
+        }
+    }
+
+    /// Removes all the edges starting from `txn_ts`, typically because the transaction is no
+    /// longer waiting for anything.
+    pub fn clean_up(&mut self, txn_ts: u64) {
+        self.wait_for_map.remove(&txn_ts);
+    }
+
+    /// Returns the number of waiting transactions.
+    pub fn len(&self) -> usize {
+        self.wait_for_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wait_for_map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use storage::Key;
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let mut detect_table = DetectTable::new(Duration::from_secs(60));
+
+        // Deadlock: 1 -> 2 -> 1
+        assert_eq!(detect_table.detect(1, 2, 2), None);
+        assert_eq!(detect_table.detect(2, 1, 1).unwrap(), 2);
+        // The victim's edge is not added.
+        assert_eq!(detect_table.len(), 1);
+        // Deadlock: 1 -> 2 -> 3 -> 1
+        assert_eq!(detect_table.detect(2, 3, 3), None);
+        assert_eq!(detect_table.detect(3, 1, 1).unwrap(), 3);
+        detect_table.clean_up(2);
+        assert_eq!(detect_table.len(), 1);
+
+        // After cycle is broken, no deadlock.
+        assert_eq!(detect_table.detect(3, 1, 1), None);
+        assert_eq!(detect_table.len(), 2);
+
+        // Waiting for the same lock again has no effect.
+        assert_eq!(detect_table.detect(3, 1, 1), None);
+        assert_eq!(detect_table.len(), 2);
+
+        // Waiting for a transaction itself is not a deadlock.
+        assert_eq!(detect_table.detect(4, 4, 4), None);
+        assert_eq!(detect_table.len(), 2);
+
+        // Deadlock on multiple keys: 1 -> 3 (key 10), 3 -> 1 (keys 1, 11)
+        assert_eq!(detect_table.detect(3, 1, 11), None);
+        assert_eq!(detect_table.detect(1, 3, 10).unwrap(), 1);
+        detect_table.clean_up_wait_for(3, 1, 1);
+        assert_eq!(detect_table.detect(1, 3, 10).unwrap(), 11);
+        detect_table.clean_up_wait_for(3, 1, 11);
+        assert_eq!(detect_table.len(), 1);
+        assert_eq!(detect_table.detect(1, 3, 10), None);
+
+        // Cleaning up an unknown edge has no effect.
+        detect_table.clean_up_wait_for(1, 4, 10);
+        detect_table.clean_up_wait_for(5, 3, 10);
+        detect_table.clean_up(5);
+        assert_eq!(detect_table.len(), 1);
+        detect_table.clean_up(1);
+        assert!(detect_table.is_empty());
+    }
+
+    #[test]
+    fn test_detect_complex() {
+        let mut detect_table = DetectTable::new(Duration::from_secs(60));
+
+        // 1 -> 2 -> 3, 1 -> 4 -> 5
+        assert_eq!(detect_table.detect(1, 2, 2), None);
+        assert_eq!(detect_table.detect(2, 3, 3), None);
+        assert_eq!(detect_table.detect(1, 4, 4), None);
+        assert_eq!(detect_table.detect(4, 5, 5), None);
+        // 3 -> 4 has no cycle, 5 -> 1 closes 1 -> 4 -> 5 -> 1.
+        assert_eq!(detect_table.detect(3, 4, 4), None);
+        assert_eq!(detect_table.detect(5, 1, 1).unwrap(), 5);
+        // 5 -> 2 closes 2 -> 3 -> 4 -> 5 -> 2.
+        assert_eq!(detect_table.detect(5, 2, 2).unwrap(), 5);
+        detect_table.clean_up(4);
+        assert_eq!(detect_table.detect(5, 1, 1), None);
+    }
+
+    #[test]
+    fn test_detect_expire() {
+        let mut detect_table = DetectTable::new(Duration::from_millis(100));
+
+        assert_eq!(detect_table.detect(1, 2, 2), None);
+        thread::sleep(Duration::from_millis(200));
+        // The stale edge 1 -> 2 is ignored and removed.
+        assert_eq!(detect_table.detect(2, 1, 1), None);
+        assert_eq!(detect_table.len(), 1);
+        assert_eq!(detect_table.detect(1, 2, 2).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_gen_key_hash() {
+        let k1 = Key::from_raw(b"k1");
+        let k2 = Key::from_raw(b"k2");
+        assert_eq!(gen_key_hash(&k1), gen_key_hash(&k1.clone()));
+        assert_ne!(gen_key_hash(&k1), gen_key_hash(&k2));
+    }
+}
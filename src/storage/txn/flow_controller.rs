@@ -0,0 +1,395 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Write flow control of the scheduler.
+//!
+//! The flow controller measures the pressure of the underlying storage from the Raft apply lag,
+//! the pending compaction bytes and the L0 file counts of RocksDB. The pressure is in `[0, 1]` and
+//! lowers the number of running write KVs allowed for each priority class. Writes over the limit
+//! are queued here and admitted in FIFO order once the running writes drop below the limit, or
+//! handed back to be rejected with `SchedTooBusy` if they are delayed for too long.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use kvproto::kvrpcpb::CommandPri;
+use rocksdb::DB;
+
+use storage::Config;
+use storage::metrics::SCHED_FLOW_PRESSURE_GAUGE;
+use util::rocksdb::{self as rocksdb_util, engine_metrics};
+use util::time::{duration_to_sec, Instant};
+
+// The storage statistics are read from RocksDB at most once per interval.
+const REFRESH_INTERVAL_MILLIS: u64 = 1000;
+// The weight of the latest sample in the moving average of the apply durations.
+const APPLY_DURATION_EWMA_ALPHA: f64 = 0.2;
+// The share of the write limit taken away from each priority class under full pressure. Every
+// class keeps a part of the limit so that the writes always make progress.
+const LOW_PRI_THROTTLE_FACTOR: f64 = 0.9;
+const NORMAL_PRI_THROTTLE_FACTOR: f64 = 0.5;
+const MAX_THROTTLED_COMMANDS: usize = 4096;
+
+struct Throttled<T> {
+    payload: T,
+    deadline: Instant,
+}
+
+/// A metric which starts throttling the writes at `soft` and reaches full pressure at `hard`.
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    soft: f64,
+    hard: f64,
+}
+
+impl Limit {
+    fn new(soft: f64, hard: f64) -> Limit {
+        Limit {
+            soft: soft,
+            hard: hard,
+        }
+    }
+
+    fn pressure(&self, value: f64) -> f64 {
+        if value <= self.soft {
+            0.0
+        } else if value >= self.hard {
+            1.0
+        } else {
+            (value - self.soft) / (self.hard - self.soft)
+        }
+    }
+}
+
+/// `FlowController` decides how many write KVs the scheduler may run at the same time, and keeps
+/// the writes which are throttled.
+pub struct FlowController<T> {
+    enabled: bool,
+    db: Option<Arc<DB>>,
+    l0_files: Limit,
+    pending_compaction_bytes: Limit,
+    apply_duration: Limit,
+    max_delay: Duration,
+
+    // The moving average of the time to write a proposal, in seconds.
+    apply_duration_ewma: f64,
+    pressure: f64,
+    last_refresh: Option<Instant>,
+
+    normal_pri_cmds: VecDeque<Throttled<T>>,
+    low_pri_cmds: VecDeque<Throttled<T>>,
+}
+
+impl<T> FlowController<T> {
+    /// Creates a flow controller. Without a `db`, only the apply lag is taken into account.
+    pub fn new(cfg: &Config, db: Option<Arc<DB>>) -> FlowController<T> {
+        FlowController {
+            enabled: cfg.flow_control_enable,
+            db: db,
+            l0_files: Limit::new(
+                cfg.flow_control_soft_l0_files as f64,
+                cfg.flow_control_hard_l0_files as f64,
+            ),
+            pending_compaction_bytes: Limit::new(
+                cfg.flow_control_soft_pending_compaction_bytes.0 as f64,
+                cfg.flow_control_hard_pending_compaction_bytes.0 as f64,
+            ),
+            apply_duration: Limit::new(
+                duration_to_sec(cfg.flow_control_soft_apply_duration.0),
+                duration_to_sec(cfg.flow_control_hard_apply_duration.0),
+            ),
+            max_delay: cfg.flow_control_max_delay.0,
+            apply_duration_ewma: 0.0,
+            pressure: 0.0,
+            last_refresh: None,
+            normal_pri_cmds: VecDeque::new(),
+            low_pri_cmds: VecDeque::new(),
+        }
+    }
+
+    /// Records the time it took to write a proposal, which is how the Raft apply lag shows up in
+    /// the scheduler.
+    pub fn observe_apply_duration(&mut self, duration: Duration) {
+        let secs = duration_to_sec(duration);
+        self.apply_duration_ewma =
+            self.apply_duration_ewma * (1.0 - APPLY_DURATION_EWMA_ALPHA)
+                + secs * APPLY_DURATION_EWMA_ALPHA;
+    }
+
+    /// Updates the pressure if it hasn't been updated in the last refresh interval.
+    pub fn refresh(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(last_refresh) = self.last_refresh {
+            if last_refresh.elapsed() < Duration::from_millis(REFRESH_INTERVAL_MILLIS) {
+                return;
+            }
+        }
+        self.last_refresh = Some(Instant::now_coarse());
+        self.update_pressure();
+    }
+
+    fn update_pressure(&mut self) {
+        let mut pressure = self.apply_duration.pressure(self.apply_duration_ewma);
+        if let Some(ref db) = self.db {
+            let (l0_files, pending_compaction_bytes) = get_compaction_stats(db);
+            pressure = pressure
+                .max(self.l0_files.pressure(l0_files as f64))
+                .max(self.pending_compaction_bytes.pressure(pending_compaction_bytes as f64));
+        }
+        self.pressure = pressure;
+        SCHED_FLOW_PRESSURE_GAUGE.set(pressure);
+    }
+
+    /// Returns the max number of running write KVs for the commands of the priority, out of
+    /// `threshold` when there is no pressure. It's at least 1, so a write can always run alone.
+    pub fn write_kv_limit(&self, threshold: usize, priority: CommandPri) -> usize {
+        let factor = match priority {
+            CommandPri::Low => LOW_PRI_THROTTLE_FACTOR,
+            CommandPri::Normal => NORMAL_PRI_THROTTLE_FACTOR,
+            CommandPri::High => 0.0,
+        };
+        let limit = (threshold as f64 * (1.0 - self.pressure * factor)).round() as usize;
+        cmp::max(limit, 1)
+    }
+
+    /// Returns true if a new write of the priority must be throttled, either because the running
+    /// writes reach the limit or there are earlier writes of the priority throttled.
+    pub fn should_throttle(&self, running: usize, threshold: usize, priority: CommandPri) -> bool {
+        match self.queue(priority) {
+            Some(queue) => !queue.is_empty() || running >= self.write_kv_limit(threshold, priority),
+            None => false,
+        }
+    }
+
+    /// Throttles a write. Returns the payload back if too many writes are throttled already.
+    pub fn throttle(&mut self, priority: CommandPri, payload: T) -> Result<(), T> {
+        if self.len() >= MAX_THROTTLED_COMMANDS {
+            return Err(payload);
+        }
+        let deadline = Instant::now_coarse() + self.max_delay;
+        match self.queue_mut(priority) {
+            Some(queue) => queue.push_back(Throttled {
+                payload: payload,
+                deadline: deadline,
+            }),
+            None => return Err(payload),
+        }
+        Ok(())
+    }
+
+    /// Pops the earliest throttled write which may run now, normal priority first.
+    pub fn pop_admitted(&mut self, running: usize, threshold: usize) -> Option<T> {
+        for &priority in &[CommandPri::Normal, CommandPri::Low] {
+            if running >= self.write_kv_limit(threshold, priority) {
+                continue;
+            }
+            if let Some(t) = self.queue_mut(priority).and_then(|q| q.pop_front()) {
+                return Some(t.payload);
+            }
+        }
+        None
+    }
+
+    /// Removes and returns the writes which are delayed for longer than the max delay.
+    pub fn expire(&mut self) -> Vec<T> {
+        let now = Instant::now_coarse();
+        let mut expired = vec![];
+        for queue in &mut [&mut self.normal_pri_cmds, &mut self.low_pri_cmds] {
+            // The deadlines are in ascending order.
+            while queue.front().map_or(false, |t| t.deadline <= now) {
+                expired.push(queue.pop_front().unwrap().payload);
+            }
+        }
+        expired
+    }
+
+    /// Returns the duration until the earliest throttled write expires or the pressure should be
+    /// refreshed, `None` if there is no throttled write.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let now = Instant::now_coarse();
+        self.normal_pri_cmds
+            .front()
+            .into_iter()
+            .chain(self.low_pri_cmds.front())
+            .map(|t| if t.deadline > now {
+                t.deadline - now
+            } else {
+                Duration::from_millis(0)
+            })
+            .min()
+            .map(|timeout| {
+                cmp::min(timeout, Duration::from_millis(REFRESH_INTERVAL_MILLIS))
+            })
+    }
+
+    /// Returns the number of throttled writes.
+    pub fn len(&self) -> usize {
+        self.normal_pri_cmds.len() + self.low_pri_cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn queue(&self, priority: CommandPri) -> Option<&VecDeque<Throttled<T>>> {
+        match priority {
+            CommandPri::Normal => Some(&self.normal_pri_cmds),
+            CommandPri::Low => Some(&self.low_pri_cmds),
+            CommandPri::High => None,
+        }
+    }
+
+    fn queue_mut(&mut self, priority: CommandPri) -> Option<&mut VecDeque<Throttled<T>>> {
+        match priority {
+            CommandPri::Normal => Some(&mut self.normal_pri_cmds),
+            CommandPri::Low => Some(&mut self.low_pri_cmds),
+            CommandPri::High => None,
+        }
+    }
+}
+
+/// Returns the max L0 file count and the max pending compaction bytes among all the column
+/// families.
+fn get_compaction_stats(db: &DB) -> (u64, u64) {
+    let (mut l0_files, mut pending_compaction_bytes) = (0, 0);
+    for cf in db.cf_names() {
+        let handle = rocksdb_util::get_cf_handle(db, cf).unwrap();
+        if let Some(n) = rocksdb_util::get_cf_num_files_at_level(db, handle, 0) {
+            l0_files = cmp::max(l0_files, n);
+        }
+        if let Some(n) =
+            db.get_property_int_cf(handle, engine_metrics::ROCKSDB_PENDING_COMPACTION_BYTES)
+        {
+            pending_compaction_bytes = cmp::max(pending_compaction_bytes, n);
+        }
+    }
+    (l0_files, pending_compaction_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use kvproto::kvrpcpb::CommandPri;
+
+    use storage::Config;
+    use util::config::ReadableDuration;
+    use super::*;
+
+    fn new_flow_controller(max_delay: Duration) -> FlowController<&'static str> {
+        let mut cfg = Config::default();
+        cfg.flow_control_soft_apply_duration = ReadableDuration::millis(100);
+        cfg.flow_control_hard_apply_duration = ReadableDuration::millis(300);
+        cfg.flow_control_max_delay = ReadableDuration(max_delay);
+        FlowController::new(&cfg, None)
+    }
+
+    #[test]
+    fn test_limit_pressure() {
+        let limit = Limit::new(10.0, 20.0);
+        assert_eq!(limit.pressure(0.0), 0.0);
+        assert_eq!(limit.pressure(10.0), 0.0);
+        assert_eq!(limit.pressure(15.0), 0.5);
+        assert_eq!(limit.pressure(20.0), 1.0);
+        assert_eq!(limit.pressure(30.0), 1.0);
+    }
+
+    #[test]
+    fn test_write_kv_limit() {
+        let mut fc = new_flow_controller(Duration::from_secs(1));
+        for &pri in &[CommandPri::Low, CommandPri::Normal, CommandPri::High] {
+            assert_eq!(fc.write_kv_limit(100, pri), 100);
+            assert!(!fc.should_throttle(99, 100, pri));
+        }
+
+        // The apply lag moves towards 200ms, which is half way to the hard limit.
+        for _ in 0..100 {
+            fc.observe_apply_duration(Duration::from_millis(200));
+        }
+        fc.refresh();
+        assert!((fc.pressure - 0.5).abs() < 0.01);
+        assert_eq!(fc.write_kv_limit(100, CommandPri::Low), 55);
+        assert_eq!(fc.write_kv_limit(100, CommandPri::Normal), 75);
+        assert_eq!(fc.write_kv_limit(100, CommandPri::High), 100);
+        assert!(fc.should_throttle(60, 100, CommandPri::Low));
+        assert!(!fc.should_throttle(60, 100, CommandPri::Normal));
+        assert!(!fc.should_throttle(99, 100, CommandPri::High));
+
+        // The pressure is refreshed once per interval.
+        for _ in 0..100 {
+            fc.observe_apply_duration(Duration::from_secs(1));
+        }
+        fc.refresh();
+        assert!((fc.pressure - 0.5).abs() < 0.01);
+        fc.update_pressure();
+        assert_eq!(fc.pressure, 1.0);
+        assert_eq!(fc.write_kv_limit(100, CommandPri::Low), 10);
+        assert_eq!(fc.write_kv_limit(100, CommandPri::Normal), 50);
+        assert_eq!(fc.write_kv_limit(1, CommandPri::Low), 1);
+
+        for _ in 0..100 {
+            fc.observe_apply_duration(Duration::from_millis(1));
+        }
+        fc.update_pressure();
+        assert_eq!(fc.pressure, 0.0);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut fc = new_flow_controller(Duration::from_secs(60));
+        for _ in 0..100 {
+            fc.observe_apply_duration(Duration::from_secs(1));
+        }
+        fc.refresh();
+        assert!(fc.next_timeout().is_none());
+
+        fc.throttle(CommandPri::Low, "a").unwrap();
+        fc.throttle(CommandPri::Normal, "b").unwrap();
+        fc.throttle(CommandPri::Low, "c").unwrap();
+        assert_eq!(fc.throttle(CommandPri::High, "d"), Err("d"));
+        assert_eq!(fc.len(), 3);
+        assert!(fc.next_timeout().unwrap() <= Duration::from_millis(REFRESH_INTERVAL_MILLIS));
+        // The earlier writes go first.
+        assert!(fc.should_throttle(0, 100, CommandPri::Low));
+        assert!(!fc.should_throttle(0, 100, CommandPri::High));
+
+        assert_eq!(fc.pop_admitted(50, 100), None);
+        assert_eq!(fc.pop_admitted(10, 100), Some("b"));
+        assert_eq!(fc.pop_admitted(10, 100), None);
+        assert_eq!(fc.pop_admitted(9, 100), Some("a"));
+        assert_eq!(fc.pop_admitted(9, 100), Some("c"));
+        assert!(fc.is_empty());
+        assert!(fc.expire().is_empty());
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut fc = new_flow_controller(Duration::from_millis(100));
+        fc.throttle(CommandPri::Low, "a").unwrap();
+        fc.throttle(CommandPri::Normal, "b").unwrap();
+        assert!(fc.expire().is_empty());
+        thread::sleep(Duration::from_millis(200));
+        fc.throttle(CommandPri::Low, "c").unwrap();
+        assert_eq!(fc.next_timeout().unwrap(), Duration::from_millis(0));
+
+        assert_eq!(fc.expire(), vec!["b", "a"]);
+        assert_eq!(fc.len(), 1);
+        assert!(fc.next_timeout().unwrap() > Duration::from_millis(0));
+        assert_eq!(fc.pop_admitted(0, 100), Some("c"));
+    }
+}
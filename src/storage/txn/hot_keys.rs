@@ -0,0 +1,192 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of the keys whose latches are contended.
+//!
+//! Every time a command has waited for a latch held by another command, the scheduler adds the
+//! wait time to the key it was blocked on. The keys are counted by a Space-Saving sketch with a
+//! bounded number of counters, so a key keeps its counter as long as it's among the hottest ones.
+//! The counters decay exponentially, hence the sketch reflects the recent contention only.
+//! The hottest keys are served by the debug service rather than reported to Prometheus, as the
+//! keys are user data and would make the number of series unbounded.
+
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use storage::Key;
+use util::collections::HashMap;
+use util::time::{duration_to_sec, Instant};
+
+// The number of keys tracked by the sketch.
+const HOT_KEYS_CAPACITY: usize = 128;
+// The counters are halved every half life.
+const DECAY_HALF_LIFE_SECS: u64 = 60;
+const DECAY_INTERVAL_SECS: u64 = 1;
+// Counters below this are dropped after decaying.
+const MIN_LATCH_WAIT_SECS: f64 = 0.000_001;
+
+/// `HotKeys` tracks the keys which commands wait on the most.
+pub struct HotKeys {
+    // encoded key -> decayed latch wait in seconds
+    counters: HashMap<Vec<u8>, f64>,
+    capacity: usize,
+    last_decay: Instant,
+}
+
+impl HotKeys {
+    pub fn new() -> HotKeys {
+        HotKeys::with_capacity(HOT_KEYS_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> HotKeys {
+        HotKeys {
+            counters: HashMap::default(),
+            capacity: capacity,
+            last_decay: Instant::now_coarse(),
+        }
+    }
+
+    /// Adds the time a command waited for the latch of `key`.
+    pub fn observe(&mut self, key: &Key, wait: Duration) {
+        let wait = duration_to_sec(wait);
+        if let Some(counter) = self.counters.get_mut(key.encoded()) {
+            *counter += wait;
+            return;
+        }
+        if self.counters.len() < self.capacity {
+            self.counters.insert(key.encoded().clone(), wait);
+            return;
+        }
+        // Takes over the smallest counter, which over-estimates the new key at most by the
+        // value of the counter.
+        let (min_key, min_wait) = self.counters
+            .iter()
+            .min_by(|a, b| cmp_wait(*a.1, *b.1))
+            .map(|(k, w)| (k.clone(), *w))
+            .unwrap();
+        self.counters.remove(&min_key);
+        self.counters.insert(key.encoded().clone(), min_wait + wait);
+    }
+
+    /// Returns at most `limit` hottest keys along with their decayed latch wait in seconds, the
+    /// hottest first.
+    pub fn top(&self, limit: usize) -> Vec<(Key, f64)> {
+        let mut keys: Vec<_> = self.counters.iter().collect();
+        keys.sort_by(|a, b| cmp_wait(*b.1, *a.1));
+        keys.into_iter()
+            .take(limit)
+            .map(|(k, w)| (Key::from_encoded(k.clone()), *w))
+            .collect()
+    }
+
+    /// Decays the counters by the time elapsed since the last decay, at most once per decay
+    /// interval.
+    pub fn on_tick(&mut self) {
+        let elapsed = self.last_decay.elapsed();
+        if elapsed < Duration::from_secs(DECAY_INTERVAL_SECS) {
+            return;
+        }
+        self.last_decay = Instant::now_coarse();
+        self.decay(elapsed);
+    }
+
+    fn decay(&mut self, elapsed: Duration) {
+        let factor = 0.5f64.powf(duration_to_sec(elapsed) / DECAY_HALF_LIFE_SECS as f64);
+        for wait in self.counters.values_mut() {
+            *wait *= factor;
+        }
+        self.counters.retain(|_, wait| *wait >= MIN_LATCH_WAIT_SECS);
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+}
+
+fn cmp_wait(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use storage::make_key;
+    use super::*;
+
+    fn top_keys(hot_keys: &HotKeys, limit: usize) -> Vec<Vec<u8>> {
+        hot_keys
+            .top(limit)
+            .into_iter()
+            .map(|(k, _)| k.raw().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_top() {
+        let mut hot_keys = HotKeys::new();
+        assert!(hot_keys.is_empty());
+        assert!(hot_keys.top(10).is_empty());
+
+        hot_keys.observe(&make_key(b"a"), Duration::from_millis(10));
+        hot_keys.observe(&make_key(b"b"), Duration::from_millis(30));
+        hot_keys.observe(&make_key(b"c"), Duration::from_millis(20));
+        hot_keys.observe(&make_key(b"a"), Duration::from_millis(15));
+        assert_eq!(hot_keys.len(), 3);
+        assert_eq!(top_keys(&hot_keys, 10), vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()]);
+        assert_eq!(top_keys(&hot_keys, 1), vec![b"b".to_vec()]);
+
+        let top = hot_keys.top(1);
+        assert!((top[0].1 - 0.03).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut hot_keys = HotKeys::with_capacity(2);
+        hot_keys.observe(&make_key(b"a"), Duration::from_millis(100));
+        hot_keys.observe(&make_key(b"b"), Duration::from_millis(10));
+        // "c" takes over the counter of "b".
+        hot_keys.observe(&make_key(b"c"), Duration::from_millis(20));
+        assert_eq!(hot_keys.len(), 2);
+        assert_eq!(top_keys(&hot_keys, 10), vec![b"a".to_vec(), b"c".to_vec()]);
+        let top = hot_keys.top(10);
+        assert!((top[1].1 - 0.03).abs() < 1e-9);
+
+        // A key which is hammered gets into the sketch in the end.
+        for _ in 0..10 {
+            hot_keys.observe(&make_key(b"d"), Duration::from_millis(20));
+        }
+        assert_eq!(top_keys(&hot_keys, 1), vec![b"d".to_vec()]);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut hot_keys = HotKeys::new();
+        hot_keys.observe(&make_key(b"a"), Duration::from_secs(1));
+        hot_keys.observe(&make_key(b"b"), Duration::from_secs(4));
+
+        hot_keys.decay(Duration::from_secs(DECAY_HALF_LIFE_SECS));
+        let top = hot_keys.top(10);
+        assert!((top[0].1 - 2.0).abs() < 1e-9);
+        assert!((top[1].1 - 0.5).abs() < 1e-9);
+
+        hot_keys.decay(Duration::from_secs(DECAY_HALF_LIFE_SECS * 20));
+        assert_eq!(top_keys(&hot_keys, 10), vec![b"b".to_vec()]);
+        hot_keys.decay(Duration::from_secs(DECAY_HALF_LIFE_SECS * 2));
+        assert!(hot_keys.is_empty());
+    }
+}
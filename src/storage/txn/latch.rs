@@ -102,6 +102,11 @@ impl Latches {
         Lock::new(slots)
     }
 
+    /// Creates a lock which requires all the latches, for a command whose keys are unknown.
+    pub fn gen_lock_all(&self) -> Lock {
+        Lock::new((0..self.size).collect())
+    }
+
     /// Tries to acquire the latches specified by the `lock` for command with ID `who`.
     ///
     /// This method will enqueue the command ID into the waiting queues of the latches. A latch is
@@ -149,7 +154,7 @@ impl Latches {
     }
 
     /// Calculates the slot ID by hashing the `key`.
-    fn calc_slot<H>(&self, key: &H) -> usize
+    pub fn calc_slot<H>(&self, key: &H) -> usize
     where
         H: Hash,
     {
@@ -234,4 +239,29 @@ mod tests {
         assert_eq!(acquired_c, true);
 
     }
+
+    #[test]
+    fn test_lock_all() {
+        let mut latches = Latches::new(256);
+
+        let mut lock_a = Lock::new(vec![3]);
+        let mut lock_all = latches.gen_lock_all();
+        let mut lock_b = Lock::new(vec![1]);
+        let cid_a: u64 = 1;
+        let cid_all: u64 = 2;
+        let cid_b: u64 = 3;
+
+        assert!(latches.acquire(&mut lock_a, cid_a));
+        // all waits for a on slot 3, but has taken slot 1 already
+        assert!(!latches.acquire(&mut lock_all, cid_all));
+        assert!(!latches.acquire(&mut lock_b, cid_b));
+
+        let wakeup = latches.release(&lock_a, cid_a);
+        assert_eq!(wakeup, vec![cid_all]);
+        assert!(latches.acquire(&mut lock_all, cid_all));
+
+        let wakeup = latches.release(&lock_all, cid_all);
+        assert_eq!(wakeup, vec![cid_b]);
+        assert!(latches.acquire(&mut lock_b, cid_b));
+    }
 }
@@ -14,12 +14,18 @@
 mod store;
 mod scheduler;
 mod latch;
+mod deadlock;
+mod waiter_manager;
+mod flow_controller;
+mod hot_keys;
 
 use std::error;
 use std::io::Error as IoError;
 
 pub use self::scheduler::{Msg, Scheduler, GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
-pub use self::store::{SnapshotStore, StoreScanner};
+pub use self::flow_controller::FlowController;
+pub use self::hot_keys::HotKeys;
+pub use self::store::{ScanStream, SnapshotStore, StoreScanner};
 
 quick_error! {
     #[derive(Debug)]
@@ -61,6 +67,13 @@ quick_error! {
                         start_ts,
                         commit_ts)
         }
+        CommitTsExpired {start_ts: u64, commit_ts: u64, min_commit_ts: u64} {
+            description("commit_ts is expired")
+            display("commit_ts {} of txn {} is expired, min_commit_ts:{}",
+                        commit_ts,
+                        start_ts,
+                        min_commit_ts)
+        }
     }
 }
 
@@ -77,6 +90,15 @@ impl Error {
                 start_ts: start_ts,
                 commit_ts: commit_ts,
             }),
+            Error::CommitTsExpired {
+                start_ts,
+                commit_ts,
+                min_commit_ts,
+            } => Some(Error::CommitTsExpired {
+                start_ts: start_ts,
+                commit_ts: commit_ts,
+                min_commit_ts: min_commit_ts,
+            }),
             Error::Other(_) | Error::ProtoBuf(_) | Error::Io(_) => None,
         }
     }
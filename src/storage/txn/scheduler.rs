@@ -31,12 +31,13 @@
 //! is ensured by the transaction protocol implemented in the client library, which is transparent
 //! to the scheduler.
 
+use std::boxed::FnBox;
 use std::fmt::{self, Debug, Formatter};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::time::Duration;
 use std::thread;
 use std::hash::{Hash, Hasher};
-use std::u64;
+use std::{cmp, mem, u64};
 
 use prometheus::HistogramTimer;
 use kvproto::kvrpcpb::{CommandPri, Context, LockInfo};
@@ -45,19 +46,24 @@ use storage::{Command, Engine, Error as StorageError, Result as StorageResult, S
               Statistics, StatisticsSummary, StorageCb};
 use storage::mvcc::{Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn, Write, WriteType,
                     MAX_TXN_WRITE_SIZE};
-use storage::{Key, KvPair, MvccInfo, Value, CMD_TAG_GC};
+use storage::{CfName, Key, KvPair, MvccInfo, TxnStatus, Value, CMD_TAG_GC};
 use storage::engine::{self, Callback as EngineCallback, CbContext, Error as EngineError, Modify,
                       Result as EngineResult};
 use raftstore::store::engine::IterOption;
 use util::transport::{Error as TransportError, SyncSendCh};
 use util::threadpool::{Context as ThreadContext, ThreadPool, ThreadPoolBuilder};
-use util::time::SlowTimer;
+use util::time::{duration_to_sec, Instant, SlowTimer};
+use util::rocksdb::ttl;
 use util::collections::HashMap;
 
 use super::Result;
 use super::Error;
-use super::store::SnapshotStore;
+use super::store::{ScanStream, SnapshotStore};
 use super::latch::{Latches, Lock};
+use super::deadlock::{gen_key_hash, DetectTable};
+use super::waiter_manager::WaiterManager;
+use super::flow_controller::FlowController;
+use super::hot_keys::HotKeys;
 use super::super::metrics::*;
 
 // TODO: make it configurable.
@@ -65,6 +71,9 @@ pub const GC_BATCH_SIZE: usize = 512;
 
 pub const RESOLVE_LOCK_BATCH_SIZE: usize = 512;
 
+// Wait-for edges not refreshed within this duration are considered stale by the deadlock detector.
+const WAIT_FOR_ENTRY_TTL_MILLIS: u64 = 3000;
+
 /// Process result of a command.
 pub enum ProcessResult {
     Res,
@@ -74,6 +83,13 @@ pub enum ProcessResult {
     MvccStartTs { mvcc: Option<(Key, MvccInfo)> },
     Value { value: Option<Value> },
     Locks { locks: Vec<LockInfo> },
+    RawCompareAndSwapRes {
+        previous_value: Option<Value>,
+        succeed: bool,
+    },
+    LockTtl { ttl: u64 },
+    TxnStatus { status: TxnStatus },
+    ScanStream { stream: ScanStream },
     NextCommand { cmd: Command },
     Failed { err: StorageError },
 }
@@ -91,7 +107,11 @@ pub enum Msg {
         snapshot: EngineResult<Box<Snapshot>>,
     },
     BatchSnapshotFinished { batch: Vec<SnapshotResult> },
-    ReadFinished { cid: u64, pr: ProcessResult },
+    ReadFinished {
+        cid: u64,
+        cmd: Command,
+        pr: ProcessResult,
+    },
     WritePrepareFinished {
         cid: u64,
         cmd: Command,
@@ -106,6 +126,11 @@ pub enum Msg {
         cb_ctx: CbContext,
         result: EngineResult<()>,
     },
+    // Gets at most `limit` keys whose latches are waited on the most, see `HotKeys`.
+    GetHotKeys {
+        limit: usize,
+        cb: Box<FnBox(Vec<(Key, f64)>) + Send>,
+    },
 }
 
 /// Debug for messages.
@@ -130,6 +155,7 @@ impl Debug for Msg {
                 write!(f, "WritePrepareFailed [cid={}, err={:?}]", cid, err)
             }
             Msg::WriteFinished { cid, .. } => write!(f, "WriteFinished [cid={}]", cid),
+            Msg::GetHotKeys { limit, .. } => write!(f, "GetHotKeys [limit={}]", limit),
         }
     }
 }
@@ -147,6 +173,11 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
             ProcessResult::Failed { err } => cb(Err(err)),
             _ => panic!("process result mismatch"),
         },
+        StorageCb::OnePcPrewrite(cb) => match pr {
+            ProcessResult::MultiRes { results } => cb(Ok((results, true))),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
         StorageCb::SingleValue(cb) => match pr {
             ProcessResult::Value { value } => cb(Ok(value)),
             ProcessResult::Failed { err } => cb(Err(err)),
@@ -172,6 +203,29 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
             ProcessResult::Failed { err } => cb(Err(err)),
             _ => panic!("process result mismatch"),
         },
+        StorageCb::CompareAndSwap(cb) => match pr {
+            ProcessResult::RawCompareAndSwapRes {
+                previous_value,
+                succeed,
+            } => cb(Ok((previous_value, succeed))),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::LockTtl(cb) => match pr {
+            ProcessResult::LockTtl { ttl } => cb(Ok(ttl)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::TxnStatus(cb) => match pr {
+            ProcessResult::TxnStatus { status } => cb(Ok(status)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::ScanStream(cb) => match pr {
+            ProcessResult::ScanStream { stream } => cb(Ok(stream)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
     }
 }
 
@@ -186,8 +240,27 @@ pub struct RunningCtx {
     ts: u64,
     region_id: u64,
     latch_timer: Option<HistogramTimer>,
+    // The slot of the latch the command is blocked on, and when it started to wait.
+    latch_wait: Option<(usize, Instant)>,
     _timer: HistogramTimer,
     slow_timer: SlowTimer,
+    // The start_ts and key hashes of the locks released by the command, which are used to wake
+    // up the lock waiters once the command is written.
+    released_locks: Option<(u64, Vec<u64>)>,
+    // The chunks of a split write which are not proposed yet.
+    pending_writes: Option<PendingWrites>,
+    // When the proposal being written was sent to the engine.
+    write_start: Option<Instant>,
+}
+
+/// The rest of a write which is too large for one Raft proposal. The chunks are proposed one by
+/// one, and the process result is delivered after the last one is written.
+struct PendingWrites {
+    ctx: Context,
+    // In reverse order, the next chunk to propose is the last one.
+    chunks: Vec<Vec<Modify>>,
+    pr: ProcessResult,
+    rows: usize,
 }
 
 impl RunningCtx {
@@ -211,10 +284,14 @@ impl RunningCtx {
                     .with_label_values(&[tag])
                     .start_coarse_timer(),
             ),
+            latch_wait: None,
             _timer: SCHED_HISTOGRAM_VEC
                 .with_label_values(&[tag])
                 .start_coarse_timer(),
             slow_timer: SlowTimer::new(),
+            released_locks: None,
+            pending_writes: None,
+            write_start: None,
         }
     }
 }
@@ -246,11 +323,12 @@ fn make_engine_cb(
             cb_ctx: cb_ctx,
             result: result,
         }) {
-            Ok(_) => {
+            // Only the last chunk of a split write reports the rows.
+            Ok(_) => if rows > 0 {
                 KV_COMMAND_KEYWRITE_HISTOGRAM_VEC
                     .with_label_values(&[cmd])
                     .observe(rows as f64);
-            }
+            },
             e @ Err(TransportError::Closed) => info!("channel closed, err {:?}", e),
             Err(e) => {
                 panic!(
@@ -308,6 +386,20 @@ pub struct Scheduler {
     // write concurrency control
     latches: Latches,
 
+    // wait-for graph of transactions blocked by pessimistic locks
+    detector: DetectTable,
+
+    // commands parked until the locks blocking them are released
+    waiter_mgr: WaiterManager<LockWaiter>,
+
+    // the max start_ts of the snapshot reads received, one-phase commits must commit after it
+    // and the one tracked by the engine for the region
+    max_read_ts: u64,
+    // cid -> commit_ts of the running one-phase commit prewrites
+    one_pc_cmds: HashMap<u64, u64>,
+    // reads which must see the running one-phase commits
+    deferred_reads: Vec<(Command, StorageCb)>,
+
     // TODO: Dynamically calculate this value according to processing
     // speed of recent write requests.
     sched_too_busy_threshold: usize,
@@ -322,6 +414,186 @@ pub struct Scheduler {
 
     // used to control write flow
     running_write_kv_count: usize,
+    flow_controller: FlowController<ThrottledCmd>,
+
+    // keys whose latches are contended
+    hot_keys: HotKeys,
+
+    // prewrites larger than this are split into several proposals, 0 means never split
+    max_proposal_size: usize,
+
+    // whether the snapshot reads asking for it may be served by the local replica at a
+    // resolved ts
+    enable_stale_read: bool,
+}
+
+/// A command blocked by a lock, along with its callback and the result to deliver if it times out.
+type LockWaiter = (Command, StorageCb, ProcessResult);
+
+/// A write delayed by the flow control, along with its callback.
+type ThrottledCmd = (Command, StorageCb);
+
+/// Returns the `start_ts` and the key hash of the lock that blocks a command, if any.
+fn extract_blocking_lock(pr: &ProcessResult) -> Option<(u64, u64)> {
+    fn lock_of(err: &StorageError) -> Option<(u64, u64)> {
+        match *err {
+            StorageError::Txn(Error::Mvcc(MvccError::KeyIsLocked { ref key, ts, .. })) => {
+                Some((ts, gen_key_hash(&Key::from_raw(key))))
+            }
+            _ => None,
+        }
+    }
+    match *pr {
+        ProcessResult::Failed { ref err } => lock_of(err),
+        ProcessResult::MultiRes { ref results } => results
+            .iter()
+            .filter_map(|r| r.as_ref().err().and_then(lock_of))
+            .next(),
+        ProcessResult::MultiKvpairs { ref pairs } => pairs
+            .iter()
+            .filter_map(|r| r.as_ref().err().and_then(lock_of))
+            .next(),
+        _ => None,
+    }
+}
+
+/// Returns true if waiting for a lock would cause a deadlock for any key of the command.
+fn has_deadlock(pr: &ProcessResult) -> bool {
+    match *pr {
+        ProcessResult::MultiRes { ref results } => results.iter().any(|r| match *r {
+            Err(StorageError::Txn(Error::Mvcc(MvccError::Deadlock { .. }))) => true,
+            _ => false,
+        }),
+        _ => false,
+    }
+}
+
+/// Returns the commit_ts if the command is a one-phase commit prewrite.
+fn one_pc_commit_ts(cmd: &Command) -> Option<u64> {
+    match *cmd {
+        Command::Prewrite { ref options, .. } if options.try_one_pc => Some(options.commit_ts),
+        _ => None,
+    }
+}
+
+/// Turns a one-phase commit prewrite into a normal one, whose callback is told that the
+/// transaction is not committed yet.
+fn fall_back_to_two_pc(cmd: &mut Command, callback: StorageCb) -> StorageCb {
+    if let Command::Prewrite {
+        ref mut options, ..
+    } = *cmd
+    {
+        options.try_one_pc = false;
+    }
+    match callback {
+        StorageCb::OnePcPrewrite(cb) => {
+            StorageCb::Booleans(box move |res: StorageResult<_>| cb(res.map(|r| (r, false))))
+        }
+        cb => cb,
+    }
+}
+
+/// Returns true if the command reads a snapshot of transactional data at its `start_ts`.
+fn is_snapshot_read(cmd: &Command) -> bool {
+    match *cmd {
+        Command::Get { .. } |
+        Command::BatchGet { .. } |
+        Command::Scan { .. } |
+        Command::ScanStream { .. } => true,
+        _ => false,
+    }
+}
+
+/// Returns the approximate size of a modify in a Raft proposal.
+fn modify_size(m: &Modify) -> usize {
+    match *m {
+        Modify::Delete(cf, ref k) => cf.len() + k.encoded().len(),
+        Modify::Put(cf, ref k, ref v) => cf.len() + k.encoded().len() + v.len(),
+        Modify::DeleteRange(cf, ref start, ref end) => {
+            cf.len() + start.encoded().len() + end.encoded().len()
+        }
+    }
+}
+
+/// Splits the modifies of a prewrite into chunks no larger than `max_size`, unless a single
+/// modify exceeds it. Other commands, and one-phase commits which must be atomic, are always
+/// written in one chunk.
+///
+/// Every chunk is proposed only after the previous one is written. `MvccTxn` puts the value of a
+/// key before its lock, so a lock is never written without its value even if a chunk fails.
+fn split_modifies(cmd: &Command, modifies: Vec<Modify>, max_size: usize) -> Vec<Vec<Modify>> {
+    let can_split = match *cmd {
+        Command::Prewrite { ref options, .. } => !options.try_one_pc,
+        _ => false,
+    };
+    if !can_split || max_size == 0 {
+        return vec![modifies];
+    }
+    let mut chunks = vec![];
+    let mut chunk = vec![];
+    let mut chunk_size = 0;
+    for m in modifies {
+        let size = modify_size(&m);
+        if !chunk.is_empty() && chunk_size + size > max_size {
+            chunks.push(mem::replace(&mut chunk, vec![]));
+            chunk_size = 0;
+        }
+        chunk_size += size;
+        chunk.push(m);
+    }
+    chunks.push(chunk);
+    chunks
+}
+
+/// Returns the `start_ts` and the key hashes of the locks released by a command, if any.
+fn released_locks(cmd: &Command, pr: &ProcessResult) -> Option<(u64, Vec<u64>)> {
+    match *cmd {
+        // The primary lock is rolled back if it has expired.
+        Command::CheckTxnStatus {
+            lock_ts,
+            ref primary_key,
+            ..
+        } => match *pr {
+            ProcessResult::TxnStatus {
+                status: TxnStatus::RolledBack,
+            } => Some((lock_ts, vec![gen_key_hash(primary_key)])),
+            _ => None,
+        },
+        // The locks of the transaction itself, if any, are replaced by the committed records.
+        Command::Prewrite {
+            start_ts,
+            ref mutations,
+            ref options,
+            ..
+        } if options.try_one_pc => {
+            let key_hashes = mutations.iter().map(|m| gen_key_hash(m.key())).collect();
+            Some((start_ts, key_hashes))
+        }
+        Command::Commit {
+            lock_ts: ts,
+            ref keys,
+            ..
+        } |
+        Command::Rollback {
+            start_ts: ts,
+            ref keys,
+            ..
+        } |
+        Command::PessimisticRollback {
+            start_ts: ts,
+            ref keys,
+            ..
+        } |
+        Command::ResolveLock {
+            start_ts: ts,
+            ref keys,
+            ..
+        } => Some((ts, keys.iter().map(gen_key_hash).collect())),
+        Command::Cleanup {
+            start_ts, ref key, ..
+        } => Some((start_ts, vec![gen_key_hash(key)])),
+        _ => None,
+    }
 }
 
 // Make clippy happy.
@@ -369,6 +641,10 @@ impl Scheduler {
         concurrency: usize,
         worker_pool_size: usize,
         sched_too_busy_threshold: usize,
+        wait_for_lock_timeout: Duration,
+        max_proposal_size: usize,
+        flow_controller: FlowController<ThrottledCmd>,
+        enable_stale_read: bool,
     ) -> Scheduler {
         Scheduler {
             engine: engine,
@@ -380,6 +656,11 @@ impl Scheduler {
             schedch: schedch,
             id_alloc: 0,
             latches: Latches::new(concurrency),
+            detector: DetectTable::new(Duration::from_millis(WAIT_FOR_ENTRY_TTL_MILLIS)),
+            waiter_mgr: WaiterManager::new(wait_for_lock_timeout),
+            max_read_ts: 0,
+            one_pc_cmds: Default::default(),
+            deferred_reads: vec![],
             sched_too_busy_threshold: sched_too_busy_threshold,
             worker_pool: ThreadPoolBuilder::with_default_factory(thd_name!("sched-worker-pool"))
                 .thread_count(worker_pool_size)
@@ -389,6 +670,10 @@ impl Scheduler {
             ).build(),
             has_gc_command: false,
             running_write_kv_count: 0,
+            flow_controller: flow_controller,
+            hot_keys: HotKeys::new(),
+            max_proposal_size: max_proposal_size,
+            enable_stale_read: enable_stale_read,
         }
     }
 }
@@ -467,7 +752,8 @@ fn process_read(
                 },
             }
         }
-        // Scans a range starting with `start_key` up to `limit` rows from the snapshot.
+        // Scans a range starting with `start_key` up to `limit` rows from the snapshot, or the
+        // range ending before `start_key` backward if `options.reverse_scan` is set.
         Command::Scan {
             ref ctx,
             ref start_key,
@@ -482,9 +768,18 @@ fn process_read(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
+            let mode = if options.reverse_scan {
+                ScanMode::Backward
+            } else {
+                ScanMode::Forward
+            };
             let res = snap_store
-                .scanner(ScanMode::Forward, options.key_only, None, &mut statistics)
-                .and_then(|mut scanner| scanner.scan(start_key.clone(), limit))
+                .scanner(mode, options.key_only, None, &mut statistics)
+                .and_then(|mut scanner| if options.reverse_scan {
+                    scanner.reverse_scan(start_key.clone(), limit)
+                } else {
+                    scanner.scan(start_key.clone(), limit)
+                })
                 .and_then(|mut results| {
                     KV_COMMAND_KEYREAD_HISTOGRAM_VEC
                         .with_label_values(&[tag])
@@ -502,6 +797,25 @@ fn process_read(
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
+        Command::ScanStream {
+            ref ctx,
+            ref start_key,
+            ref end_key,
+            batch_size,
+            start_ts,
+            key_only,
+        } => ProcessResult::ScanStream {
+            stream: ScanStream::new(
+                snapshot,
+                start_ts,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+                key_only,
+                start_key.clone(),
+                end_key.clone(),
+                batch_size,
+            ),
+        },
         Command::MvccByKey { ref ctx, ref key } => {
             let mut reader = MvccReader::new(
                 snapshot.as_ref(),
@@ -690,22 +1004,59 @@ fn process_read(
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        Command::RawGet { ref key, .. } => {
+        Command::RawGet {
+            cf,
+            ref key,
+            enable_ttl,
+            ..
+        } => {
             KV_COMMAND_KEYREAD_HISTOGRAM_VEC
                 .with_label_values(&[tag])
                 .observe(1f64);
-            match snapshot.get(key) {
+            let now = ttl::current_ts();
+            match get_raw_value(snapshot.as_ref(), cf, key, enable_ttl, now) {
                 Ok(val) => ProcessResult::Value { value: val },
                 Err(e) => ProcessResult::Failed {
                     err: StorageError::from(e),
                 },
             }
         }
+        Command::RawBatchGet {
+            cf,
+            ref keys,
+            enable_ttl,
+            ..
+        } => {
+            KV_COMMAND_KEYREAD_HISTOGRAM_VEC
+                .with_label_values(&[tag])
+                .observe(keys.len() as f64);
+            let now = ttl::current_ts();
+            let mut pairs = vec![];
+            for key in keys {
+                match get_raw_value(snapshot.as_ref(), cf, key, enable_ttl, now) {
+                    Ok(Some(v)) => pairs.push(Ok((key.encoded().to_owned(), v))),
+                    Ok(None) => {}
+                    Err(e) => pairs.push(Err(StorageError::from(e))),
+                }
+            }
+            ProcessResult::MultiKvpairs { pairs: pairs }
+        }
         Command::RawScan {
+            cf,
             ref start_key,
             limit,
+            enable_ttl,
+            reverse,
             ..
-        } => match process_rawscan(snapshot, start_key, limit, &mut statistics) {
+        } => match process_rawscan(
+            snapshot,
+            cf,
+            start_key,
+            limit,
+            enable_ttl,
+            reverse,
+            &mut statistics,
+        ) {
             Ok(val) => ProcessResult::MultiKvpairs { pairs: val },
             Err(e) => ProcessResult::Failed {
                 err: StorageError::from(e),
@@ -718,27 +1069,82 @@ fn process_read(
         _ => panic!("unsupported read command"),
     };
 
-    if let Err(e) = ch.send(Msg::ReadFinished { cid: cid, pr: pr }) {
+    if let Err(e) = ch.send(Msg::ReadFinished {
+        cid: cid,
+        cmd: cmd,
+        pr: pr,
+    }) {
         // Todo: if this happens we need to clean up command's context
         panic!("send read finished failed, cid={}, err={:?}", cid, e);
     }
     statistics
 }
 
+/// Returns the user value of a raw value, `None` if it has expired at `now`. A value without
+/// the expire ts is an error when TTL is enabled.
+fn raw_value(value: Value, enable_ttl: bool, now: u64) -> Result<Option<Value>> {
+    if !enable_ttl {
+        Ok(Some(value))
+    } else if ttl::is_expired(&value, now) {
+        Ok(None)
+    } else {
+        Ok(Some(ttl::strip_expire_ts(&value)?.to_vec()))
+    }
+}
+
+/// Reads the user value of a raw key, `None` if it doesn't exist or has expired at `now`.
+fn get_raw_value(
+    snapshot: &Snapshot,
+    cf: CfName,
+    key: &Key,
+    enable_ttl: bool,
+    now: u64,
+) -> Result<Option<Value>> {
+    match snapshot.get_cf(cf, key)? {
+        Some(v) => raw_value(v, enable_ttl, now),
+        None => Ok(None),
+    }
+}
+
 fn process_rawscan(
     snapshot: Box<Snapshot>,
+    cf: CfName,
     start_key: &Key,
     limit: usize,
+    enable_ttl: bool,
+    reverse: bool,
     stats: &mut Statistics,
 ) -> Result<Vec<StorageResult<KvPair>>> {
-    let mut cursor = snapshot.iter(IterOption::default(), ScanMode::Forward)?;
-    if !cursor.seek(start_key, &mut stats.data)? {
+    let mode = if reverse {
+        ScanMode::Backward
+    } else {
+        ScanMode::Forward
+    };
+    let mut cursor = snapshot.iter_cf(cf, IterOption::default(), mode)?;
+    let found = if !reverse {
+        cursor.seek(start_key, stats.mut_cf_statistics(cf))?
+    } else if start_key.encoded().is_empty() {
+        // An empty start key scans backward from the very end.
+        cursor.seek_to_last(stats.mut_cf_statistics(cf))
+    } else {
+        cursor.reverse_seek(start_key, stats.mut_cf_statistics(cf))?
+    };
+    if !found {
         return Ok(vec![]);
     }
+    let now = ttl::current_ts();
     let mut pairs = vec![];
     while cursor.valid() && pairs.len() < limit {
-        pairs.push(Ok((cursor.key().to_owned(), cursor.value().to_owned())));
-        cursor.next(&mut stats.data);
+        match raw_value(cursor.value().to_owned(), enable_ttl, now) {
+            Ok(Some(v)) => pairs.push(Ok((cursor.key().to_owned(), v))),
+            Ok(None) => {}
+            Err(e) => pairs.push(Err(StorageError::from(e))),
+        }
+        if reverse {
+            cursor.prev(stats.mut_cf_statistics(cf));
+        } else {
+            cursor.next(stats.mut_cf_statistics(cf));
+        }
     }
     Ok(pairs)
 }
@@ -792,10 +1198,64 @@ fn process_write_impl(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
+            if options.try_one_pc && options.commit_ts <= start_ts {
+                return Err(Error::InvalidTxnTso {
+                    start_ts: start_ts,
+                    commit_ts: options.commit_ts,
+                });
+            }
             let mut locks = vec![];
             let rows = mutations.len();
-            for m in mutations {
-                match txn.prewrite(m.clone(), primary, options) {
+            for (i, m) in mutations.iter().enumerate() {
+                let res = if options.try_one_pc {
+                    txn.one_pc_prewrite(m.clone(), primary, options.commit_ts, options)
+                } else if options.for_update_ts == 0 {
+                    txn.prewrite(m.clone(), primary, options)
+                } else {
+                    let is_pessimistic_lock = options.is_pessimistic_lock[i];
+                    txn.pessimistic_prewrite(m.clone(), primary, is_pessimistic_lock, options)
+                };
+                match res {
+                    Ok(_) => {}
+                    e @ Err(MvccError::KeyIsLocked { .. }) => {
+                        locks.push(e.map_err(Error::from).map_err(StorageError::from));
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                }
+            }
+            if locks.is_empty() {
+                let pr = ProcessResult::MultiRes { results: vec![] };
+                (pr, txn.modifies(), rows)
+            } else {
+                // Skip write stage if some keys are locked.
+                let pr = ProcessResult::MultiRes { results: locks };
+                (pr, vec![], 0)
+            }
+        }
+        Command::AcquirePessimisticLock {
+            ref ctx,
+            ref keys,
+            ref primary,
+            start_ts,
+            ref options,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let mut locks = vec![];
+            let rows = keys.len();
+            for k in keys {
+                match txn.acquire_pessimistic_lock(
+                    k.clone(),
+                    primary,
+                    options.lock_ttl,
+                    options.for_update_ts,
+                ) {
                     Ok(_) => {}
                     e @ Err(MvccError::KeyIsLocked { .. }) => {
                         locks.push(e.map_err(Error::from).map_err(StorageError::from));
@@ -882,6 +1342,66 @@ fn process_write_impl(
             let pr = ProcessResult::Res;
             (pr, txn.modifies(), rows)
         }
+        Command::TxnHeartBeat {
+            ref ctx,
+            ref primary_key,
+            start_ts,
+            advise_ttl,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let ttl = txn.txn_heart_beat(primary_key.clone(), advise_ttl)?;
+
+            let pr = ProcessResult::LockTtl { ttl: ttl };
+            (pr, txn.modifies(), 1)
+        }
+        Command::CheckTxnStatus {
+            ref ctx,
+            ref primary_key,
+            lock_ts,
+            current_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                lock_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let status = txn.check_txn_status(primary_key.clone(), current_ts)?;
+
+            let pr = ProcessResult::TxnStatus { status: status };
+            (pr, txn.modifies(), 1)
+        }
+        Command::PessimisticRollback {
+            ref ctx,
+            ref keys,
+            start_ts,
+            for_update_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let rows = keys.len();
+            for k in keys {
+                txn.pessimistic_rollback(k.clone(), for_update_ts)?;
+            }
+
+            let pr = ProcessResult::Res;
+            (pr, txn.modifies(), rows)
+        }
         Command::ResolveLock {
             ref ctx,
             start_ts,
@@ -909,7 +1429,14 @@ fn process_write_impl(
             let rows = keys.len();
             for k in keys {
                 match commit_ts {
-                    Some(ts) => txn.commit(k, ts)?,
+                    Some(ts) => match txn.commit(k, ts) {
+                        // Pessimistic locks which were never prewritten hold no data, just
+                        // remove them when the transaction is committed.
+                        Err(MvccError::LockTypeNotMatch { .. }) => {
+                            txn.pessimistic_rollback(k.clone(), u64::MAX)?
+                        }
+                        res => res?,
+                    },
                     None => txn.rollback(k)?,
                 }
                 if txn.write_size() >= MAX_TXN_WRITE_SIZE {
@@ -971,6 +1498,63 @@ fn process_write_impl(
                 (pr, txn.modifies(), rows)
             }
         }
+        Command::RawCompareAndSwap {
+            cf,
+            ref key,
+            ref previous_value,
+            ref value,
+            ttl,
+            enable_ttl,
+            ..
+        } => {
+            let current = get_raw_value(snapshot, cf, key, enable_ttl, ttl::current_ts())?;
+            let succeed = current == *previous_value;
+            let mut modifies = vec![];
+            if succeed {
+                let mut value = value.clone();
+                if enable_ttl {
+                    ttl::append_expire_ts(&mut value, ttl);
+                }
+                modifies.push(Modify::Put(cf, key.clone(), value));
+            }
+            let pr = ProcessResult::RawCompareAndSwapRes {
+                previous_value: current,
+                succeed: succeed,
+            };
+            (pr, modifies, 1)
+        }
+        Command::RawBatchPut {
+            cf,
+            ref pairs,
+            ttl,
+            enable_ttl,
+            ..
+        } => {
+            let modifies = pairs
+                .iter()
+                .map(|&(ref key, ref value)| {
+                    let mut value = value.clone();
+                    if enable_ttl {
+                        ttl::append_expire_ts(&mut value, ttl);
+                    }
+                    Modify::Put(cf, key.clone(), value)
+                })
+                .collect();
+            (ProcessResult::Res, modifies, pairs.len())
+        }
+        Command::RawBatchDelete { cf, ref keys, .. } => {
+            let modifies = keys.iter().map(|k| Modify::Delete(cf, k.clone())).collect();
+            (ProcessResult::Res, modifies, keys.len())
+        }
+        Command::RawDeleteRange {
+            cf,
+            ref start_key,
+            ref end_key,
+            ..
+        } => {
+            let modifies = vec![Modify::DeleteRange(cf, start_key.clone(), end_key.clone())];
+            (ProcessResult::Res, modifies, 0)
+        }
         _ => panic!("unsupported write command"),
     };
 
@@ -1045,6 +1629,10 @@ impl Scheduler {
         }
         SCHED_WRITING_KV_GAUGE.set(self.running_write_kv_count as f64);
         SCHED_CONTEX_GAUGE.set(self.cmd_ctxs.len() as f64);
+        if self.one_pc_cmds.remove(&cid).is_some() {
+            // The one-phase commit is either written or failed, the reads can go on now.
+            self.schedule_deferred_reads();
+        }
         ctx
     }
 
@@ -1112,6 +1700,26 @@ impl Scheduler {
         self.release_lock(&ctx.lock, cid);
     }
 
+    /// Returns the ts to read at if the command asks for a stale snapshot and can be served by
+    /// one.
+    fn stale_read_ts(&self, cid: u64) -> Option<u64> {
+        if !self.enable_stale_read {
+            return None;
+        }
+        let cmd = self.cmd_ctxs[&cid].cmd.as_ref().unwrap();
+        let stale_read = match *cmd {
+            Command::Get { ref options, .. } |
+            Command::BatchGet { ref options, .. } |
+            Command::Scan { ref options, .. } => options.stale_read,
+            _ => false,
+        };
+        if stale_read && cmd.ts() != u64::MAX {
+            Some(cmd.ts())
+        } else {
+            None
+        }
+    }
+
     /// Extracts the context of a command.
     fn extract_context(&self, cid: u64) -> &Context {
         let ctx = &self.cmd_ctxs[&cid];
@@ -1129,7 +1737,57 @@ impl Scheduler {
     /// Note that once a command is ready to execute, the snapshot is always up-to-date during the
     /// execution because 1) all the conflicting commands (if any) must be in the waiting queues;
     /// 2) there may be non-conflicitng commands running concurrently, but it doesn't matter.
-    fn schedule_command(&mut self, cmd: Command, callback: StorageCb) {
+    fn schedule_command(&mut self, mut cmd: Command, mut callback: StorageCb) {
+        let mut one_pc_commit_ts = one_pc_commit_ts(&cmd);
+        if let Some(commit_ts) = one_pc_commit_ts {
+            let err = match self.engine.max_read_ts(cmd.get_context()) {
+                // The reads served by the other replicas may have missed the transaction, so
+                // it's prewritten in two phases instead.
+                None => {
+                    SCHED_STAGE_COUNTER_VEC
+                        .with_label_values(&[cmd.tag(), "one_pc_fallback"])
+                        .inc();
+                    callback = fall_back_to_two_pc(&mut cmd, callback);
+                    one_pc_commit_ts = None;
+                    None
+                }
+                Some(ts) => {
+                    let max_read_ts = cmp::max(ts, self.max_read_ts);
+                    // A read newer than `commit_ts` may have missed the transaction.
+                    if commit_ts <= max_read_ts {
+                        Some(Error::CommitTsExpired {
+                            start_ts: cmd.ts(),
+                            commit_ts: commit_ts,
+                            min_commit_ts: max_read_ts + 1,
+                        })
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(err) = err {
+                execute_callback(
+                    callback,
+                    ProcessResult::Failed {
+                        err: StorageError::from(err),
+                    },
+                );
+                return;
+            }
+        } else if is_snapshot_read(&cmd) {
+            let ts = cmd.ts();
+            if ts != u64::MAX {
+                self.engine.update_max_read_ts(cmd.get_context(), ts);
+                if ts > self.max_read_ts {
+                    self.max_read_ts = ts;
+                }
+            }
+            if self.one_pc_cmds.values().any(|commit_ts| *commit_ts <= ts) {
+                // The read must see the running one-phase commits, which write no locks.
+                self.deferred_reads.push((cmd, callback));
+                return;
+            }
+        }
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[cmd.tag(), "new"])
             .inc();
@@ -1141,42 +1799,97 @@ impl Scheduler {
         let lock = gen_command_lock(&self.latches, &cmd);
         let ctx = RunningCtx::new(cid, cmd, lock, callback);
         self.insert_ctx(ctx);
+        if let Some(commit_ts) = one_pc_commit_ts {
+            self.one_pc_cmds.insert(cid, commit_ts);
+        }
         self.lock_and_register_get_snapshot(cid);
     }
 
+    /// Schedules the reads deferred by one-phase commits again.
+    fn schedule_deferred_reads(&mut self) {
+        let reads: Vec<_> = self.deferred_reads.drain(..).collect();
+        for (cmd, cb) in reads {
+            self.schedule_command(cmd, cb);
+        }
+    }
+
     fn too_busy(&self) -> bool {
         self.running_write_kv_count >= self.sched_too_busy_threshold
     }
 
+    fn reject_too_busy(&self, cmd: &Command, callback: StorageCb) {
+        SCHED_TOO_BUSY_COUNTER_VEC
+            .with_label_values(&[cmd.tag()])
+            .inc();
+        execute_callback(
+            callback,
+            ProcessResult::Failed {
+                err: StorageError::SchedTooBusy,
+            },
+        );
+    }
+
     fn on_receive_new_cmd(&mut self, cmd: Command, callback: StorageCb) {
+        // Allow 1 GC command at the same time.
+        if cmd.tag() == CMD_TAG_GC && self.has_gc_command {
+            return self.reject_too_busy(&cmd, callback);
+        }
         // write flow control
-        if cmd.need_flow_control() && self.too_busy() {
-            SCHED_TOO_BUSY_COUNTER_VEC
-                .with_label_values(&[cmd.tag()])
+        if cmd.need_flow_control() {
+            if self.too_busy() {
+                return self.reject_too_busy(&cmd, callback);
+            }
+            let priority = cmd.priority();
+            if self.flow_controller.should_throttle(
+                self.running_write_kv_count,
+                self.sched_too_busy_threshold,
+                priority,
+            ) {
+                let tag = cmd.tag();
+                match self.flow_controller.throttle(priority, (cmd, callback)) {
+                    Ok(()) => SCHED_THROTTLE_COUNTER_VEC
+                        .with_label_values(&[tag, "throttled"])
+                        .inc(),
+                    Err((cmd, callback)) => self.reject_too_busy(&cmd, callback),
+                }
+                return;
+            }
+        }
+        self.schedule_command(cmd, callback);
+    }
+
+    /// Schedules the throttled writes which may run now, and rejects the ones delayed for too
+    /// long.
+    fn on_throttled_cmds(&mut self) {
+        while let Some((cmd, cb)) = self.flow_controller
+            .pop_admitted(self.running_write_kv_count, self.sched_too_busy_threshold)
+        {
+            if cmd.tag() == CMD_TAG_GC && self.has_gc_command {
+                self.reject_too_busy(&cmd, cb);
+                continue;
+            }
+            SCHED_THROTTLE_COUNTER_VEC
+                .with_label_values(&[cmd.tag(), "admitted"])
                 .inc();
-            execute_callback(
-                callback,
-                ProcessResult::Failed {
-                    err: StorageError::SchedTooBusy,
-                },
-            );
-            return;
+            self.schedule_command(cmd, cb);
         }
-        // Allow 1 GC command at the same time.
-        if cmd.tag() == CMD_TAG_GC && self.has_gc_command {
-            SCHED_TOO_BUSY_COUNTER_VEC
-                .with_label_values(&[cmd.tag()])
+        for (cmd, cb) in self.flow_controller.expire() {
+            SCHED_THROTTLE_COUNTER_VEC
+                .with_label_values(&[cmd.tag(), "timeout"])
                 .inc();
-            execute_callback(
-                callback,
-                ProcessResult::Failed {
-                    err: StorageError::SchedTooBusy,
-                },
-            );
-            return;
+            self.reject_too_busy(&cmd, cb);
+        }
+    }
 
+    /// Returns the duration until a lock waiter or a throttled write expires.
+    fn next_timeout(&self) -> Option<Duration> {
+        match (
+            self.waiter_mgr.next_timeout(),
+            self.flow_controller.next_timeout(),
+        ) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
         }
-        self.schedule_command(cmd, callback);
     }
 
     /// Tries to acquire all the required latches for a command.
@@ -1185,16 +1898,35 @@ impl Scheduler {
     fn acquire_lock(&mut self, cid: u64) -> bool {
         let ctx = &mut self.cmd_ctxs.get_mut(&cid).unwrap();
         assert_eq!(ctx.cid, cid);
+        if let Some((slot, wait_start)) = ctx.latch_wait.take() {
+            // The command is woken up by the release of the latch it was blocked on, so the
+            // key is looked up only once per wait.
+            let wait = wait_start.elapsed();
+            SCHED_LATCH_CONTENTION_HISTOGRAM.observe(duration_to_sec(wait));
+            if let Some(ref cmd) = ctx.cmd {
+                let latches = &self.latches;
+                if let Some(key) = command_latch_keys(cmd)
+                    .into_iter()
+                    .find(|key| latches.calc_slot(key) == slot)
+                {
+                    self.hot_keys.observe(key, wait);
+                }
+            }
+        }
         let ok = self.latches.acquire(&mut ctx.lock, cid);
         if ok {
             ctx.latch_timer.take();
+        } else {
+            let slot = ctx.lock.required_slots[ctx.lock.owned_count];
+            ctx.latch_wait = Some((slot, Instant::now()));
         }
         ok
     }
 
     /// Initiates an async operation to get a snapshot from the storage engine, then posts a
-    /// `SnapshotFinished` message back to the event loop when it finishes.
-    fn get_snapshot(&mut self, ctx: &Context, cids: Vec<u64>) {
+    /// `SnapshotFinished` message back to the event loop when it finishes. The snapshot may be
+    /// a stale one if `read_ts` is given.
+    fn get_snapshot(&mut self, ctx: &Context, read_ts: Option<u64>, cids: Vec<u64>) {
         for cid in &cids {
             SCHED_STAGE_COUNTER_VEC
                 .with_label_values(&[self.get_ctx_tag(*cid), "snapshot"])
@@ -1212,7 +1944,11 @@ impl Scheduler {
             Err(e) => panic!("send SnapshotFinish failed, err {:?}", e),
         };
 
-        if let Err(e) = self.engine.async_snapshot(ctx, cb) {
+        let res = match read_ts {
+            Some(ts) => self.engine.async_stale_snapshot(ctx, ts, cb),
+            None => self.engine.async_snapshot(ctx, cb),
+        };
+        if let Err(e) = res {
             for cid in cids {
                 SCHED_STAGE_COUNTER_VEC
                     .with_label_values(&[self.get_ctx_tag(cid), "async_snap_err"])
@@ -1328,7 +2064,7 @@ impl Scheduler {
     ///
     /// If a next command is present, continues to execute; otherwise, delivers the result to the
     /// callback.
-    fn on_read_finished(&mut self, cid: u64, pr: ProcessResult) {
+    fn on_read_finished(&mut self, cid: u64, cmd: Command, pr: ProcessResult) {
         debug!("read command(cid={}) finished", cid);
         let mut ctx = self.remove_ctx(cid);
         SCHED_STAGE_COUNTER_VEC
@@ -1341,12 +2077,51 @@ impl Scheduler {
                 .inc();
             self.schedule_command(cmd, cb);
         } else {
-            execute_callback(cb, pr);
+            self.finish_or_wait_for_lock(cmd, cb, pr);
         }
 
         self.release_lock(&ctx.lock, cid);
     }
 
+    /// Delivers the result to the callback, unless the command is blocked by a lock and allowed
+    /// to wait for it. In that case the command is parked and will be retried once the lock is
+    /// released, or get the result after waiting for too long. A command that would deadlock
+    /// on any of its keys never waits, so the `Deadlock` error is returned at once.
+    fn finish_or_wait_for_lock(&mut self, cmd: Command, cb: StorageCb, pr: ProcessResult) {
+        if self.waiter_mgr.is_enabled() && cmd.can_wait_for_lock() && !has_deadlock(&pr) {
+            let blocking_lock = extract_blocking_lock(&pr);
+            if let Some((lock_ts, key_hash)) = blocking_lock {
+                SCHED_LOCK_WAIT_COUNTER_VEC
+                    .with_label_values(&[cmd.tag(), "wait"])
+                    .inc();
+                self.waiter_mgr.wait_for(lock_ts, key_hash, (cmd, cb, pr));
+                return;
+            }
+        }
+        execute_callback(cb, pr);
+    }
+
+    /// Wakes up the commands waiting for the released locks to retry.
+    fn wake_up_lock_waiters(&mut self, lock_ts: u64, key_hashes: &[u64]) {
+        let waiters = self.waiter_mgr.wake_up(lock_ts, key_hashes);
+        for (cmd, cb, _) in waiters {
+            SCHED_LOCK_WAIT_COUNTER_VEC
+                .with_label_values(&[cmd.tag(), "wake_up"])
+                .inc();
+            self.schedule_command(cmd, cb);
+        }
+    }
+
+    /// Delivers the results to the commands which have waited for locks for too long.
+    fn on_wait_for_lock_timeout(&mut self) {
+        for (cmd, cb, pr) in self.waiter_mgr.expire() {
+            SCHED_LOCK_WAIT_COUNTER_VEC
+                .with_label_values(&[cmd.tag(), "timeout"])
+                .inc();
+            execute_callback(cb, pr);
+        }
+    }
+
     /// Event handler for the failure of write prepare.
     ///
     /// Write prepare failure typically means conflicting transactions are detected. Delivers the
@@ -1359,6 +2134,50 @@ impl Scheduler {
         self.finish_with_err(cid, e);
     }
 
+    /// Consults the deadlock detector for the keys that an `AcquirePessimisticLock` command failed
+    /// to lock. If waiting for a lock would cause a deadlock, the `KeyIsLocked` error is replaced
+    /// by a `Deadlock` error so that the transaction gives up as the victim.
+    fn detect_deadlock(&mut self, start_ts: u64, pr: ProcessResult) -> ProcessResult {
+        let mut results = match pr {
+            ProcessResult::MultiRes { results } => results,
+            pr => return pr,
+        };
+        if results.is_empty() {
+            // The transaction has locked all the keys, so it is not waiting for any lock.
+            self.detector.clean_up(start_ts);
+        }
+        let mut deadlocked = false;
+        for res in &mut results {
+            let deadlock = match *res {
+                Err(StorageError::Txn(Error::Mvcc(MvccError::KeyIsLocked {
+                    ref key, ts, ..
+                }))) => {
+                    let key_hash = gen_key_hash(&Key::from_raw(key));
+                    self.detector
+                        .detect(start_ts, ts, key_hash)
+                        .map(|deadlock_key_hash| MvccError::Deadlock {
+                            start_ts: start_ts,
+                            lock_ts: ts,
+                            lock_key: key.to_owned(),
+                            deadlock_key_hash: deadlock_key_hash,
+                        })
+                }
+                _ => None,
+            };
+            if let Some(e) = deadlock {
+                warn!("deadlock detected: {:?}", e);
+                SCHED_DEADLOCK_COUNTER.inc();
+                *res = Err(StorageError::from(Error::from(e)));
+                deadlocked = true;
+            }
+        }
+        if deadlocked {
+            // The transaction gives up as the victim instead of waiting for the other keys.
+            self.detector.clean_up(start_ts);
+        }
+        ProcessResult::MultiRes { results: results }
+    }
+
     /// Event handler for the success of write prepare.
     ///
     /// Initiates an async write operation on the storage engine, there'll be a `WriteFinished`
@@ -1367,20 +2186,63 @@ impl Scheduler {
         &mut self,
         cid: u64,
         cmd: Command,
-        pr: ProcessResult,
+        mut pr: ProcessResult,
         to_be_write: Vec<Modify>,
         rows: usize,
     ) {
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[self.get_ctx_tag(cid), "write"])
             .inc();
+        if let Command::AcquirePessimisticLock { start_ts, .. } = cmd {
+            pr = self.detect_deadlock(start_ts, pr);
+        }
+        if let Some((lock_ts, key_hashes)) = released_locks(&cmd, &pr) {
+            // The transaction is finished, so it can't be waiting for any lock.
+            self.detector.clean_up(lock_ts);
+            self.cmd_ctxs.get_mut(&cid).unwrap().released_locks = Some((lock_ts, key_hashes));
+        }
         if to_be_write.is_empty() {
+            if cmd.can_wait_for_lock() {
+                // Nothing is written if the command is blocked by locks, it may wait for them.
+                let mut ctx = self.remove_ctx(cid);
+                let cb = ctx.callback.take().unwrap();
+                self.finish_or_wait_for_lock(cmd, cb, pr);
+                self.release_lock(&ctx.lock, cid);
+                return;
+            }
             return self.on_write_finished(cid, pr, Ok(()));
         }
-        let engine_cb = make_engine_cb(cmd.tag(), cid, pr, self.schedch.clone(), rows);
-        if let Err(e) = self.engine
-            .async_write(cmd.get_context(), to_be_write, engine_cb)
-        {
+        let mut chunks = split_modifies(&cmd, to_be_write, self.max_proposal_size);
+        if chunks.len() == 1 {
+            return self.async_write(cid, cmd.get_context(), chunks.pop().unwrap(), pr, rows);
+        }
+        SCHED_STAGE_COUNTER_VEC
+            .with_label_values(&[self.get_ctx_tag(cid), "split_write"])
+            .inc();
+        chunks.reverse();
+        let first = chunks.pop().unwrap();
+        self.cmd_ctxs.get_mut(&cid).unwrap().pending_writes = Some(PendingWrites {
+            ctx: cmd.get_context().clone(),
+            chunks: chunks,
+            pr: pr,
+            rows: rows,
+        });
+        self.async_write(cid, cmd.get_context(), first, ProcessResult::Res, 0);
+    }
+
+    /// Initiates an async write operation on the storage engine, there'll be a `WriteFinished`
+    /// message when it finishes.
+    fn async_write(
+        &mut self,
+        cid: u64,
+        ctx: &Context,
+        modifies: Vec<Modify>,
+        pr: ProcessResult,
+        rows: usize,
+    ) {
+        let engine_cb = make_engine_cb(self.get_ctx_tag(cid), cid, pr, self.schedch.clone(), rows);
+        self.cmd_ctxs.get_mut(&cid).unwrap().write_start = Some(Instant::now_coarse());
+        if let Err(e) = self.engine.async_write(ctx, modifies, engine_cb) {
             SCHED_STAGE_COUNTER_VEC
                 .with_label_values(&[self.get_ctx_tag(cid), "async_write_err"])
                 .inc();
@@ -1388,8 +2250,39 @@ impl Scheduler {
         }
     }
 
+    /// Takes the next chunk of a split write, along with the process result and the rows to
+    /// report once it's written. Only the last chunk carries the real process result.
+    fn next_write_chunk(
+        &mut self,
+        cid: u64,
+    ) -> Option<(Context, Vec<Modify>, ProcessResult, usize)> {
+        let ctx = self.cmd_ctxs.get_mut(&cid).unwrap();
+        let mut pending = match ctx.pending_writes.take() {
+            Some(pending) => pending,
+            None => return None,
+        };
+        let chunk = pending.chunks.pop().unwrap();
+        if pending.chunks.is_empty() {
+            return Some((pending.ctx, chunk, pending.pr, pending.rows));
+        }
+        let write_ctx = pending.ctx.clone();
+        ctx.pending_writes = Some(pending);
+        Some((write_ctx, chunk, ProcessResult::Res, 0))
+    }
+
     /// Event handler for the success of write.
+    ///
+    /// If the write is split and there are chunks left, proposes the next one and keeps holding
+    /// the latches; otherwise delivers the result to the callback.
     fn on_write_finished(&mut self, cid: u64, pr: ProcessResult, result: EngineResult<()>) {
+        if let Some(write_start) = self.cmd_ctxs.get_mut(&cid).unwrap().write_start.take() {
+            self.flow_controller.observe_apply_duration(write_start.elapsed());
+        }
+        if result.is_ok() {
+            if let Some((ctx, chunk, pr, rows)) = self.next_write_chunk(cid) {
+                return self.async_write(cid, &ctx, chunk, pr, rows);
+            }
+        }
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[self.get_ctx_tag(cid), "write_finish"])
             .inc();
@@ -1397,7 +2290,12 @@ impl Scheduler {
         let mut ctx = self.remove_ctx(cid);
         let cb = ctx.callback.take().unwrap();
         let pr = match result {
-            Ok(()) => pr,
+            Ok(()) => {
+                if let Some((lock_ts, key_hashes)) = ctx.released_locks.take() {
+                    self.wake_up_lock_waiters(lock_ts, &key_hashes);
+                }
+                pr
+            }
             Err(e) => ProcessResult::Failed {
                 err: ::storage::Error::from(e),
             },
@@ -1427,6 +2325,11 @@ impl Scheduler {
     fn lock_and_register_get_snapshot(&mut self, cid: u64) {
         if self.acquire_lock(cid) {
             let ctx = self.extract_context(cid).clone();
+            if let Some(read_ts) = self.stale_read_ts(cid) {
+                // Stale reads are not batched as they may not go through the leader.
+                self.get_snapshot(&ctx, Some(read_ts), vec![cid]);
+                return;
+            }
             let group = self.grouped_cmds
                 .as_mut()
                 .unwrap()
@@ -1439,8 +2342,14 @@ impl Scheduler {
     pub fn run(&mut self, receiver: Receiver<Msg>) -> Result<()> {
         let mut msgs = Vec::with_capacity(CMD_BATCH_SIZE);
         loop {
-            let msg = box_try!(receiver.recv());
-            msgs.push(msg);
+            match self.next_timeout() {
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(msg) => msgs.push(msg),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(e) => return Err(Error::Other(box_err!("{:?}", e))),
+                },
+                None => msgs.push(box_try!(receiver.recv())),
+            }
             while let Ok(msg) = receiver.try_recv() {
                 msgs.push(msg);
                 if msgs.len() >= CMD_BATCH_SIZE {
@@ -1448,12 +2357,13 @@ impl Scheduler {
                 }
             }
 
+            self.flow_controller.refresh();
             for msg in msgs.drain(..) {
                 match msg {
                     Msg::Quit => return self.shutdown(),
                     Msg::RawCmd { cmd, cb } => self.on_receive_new_cmd(cmd, cb),
                     Msg::RetryGetSnapshots(tasks) => for (ctx, cids) in tasks {
-                        self.get_snapshot(&ctx, cids);
+                        self.get_snapshot(&ctx, None, cids);
                     },
                     Msg::SnapshotFinished {
                         cids,
@@ -1463,7 +2373,7 @@ impl Scheduler {
                     Msg::BatchSnapshotFinished { batch } => for (cids, cb_ctx, snapshot) in batch {
                         self.on_snapshot_finished(cids, cb_ctx, snapshot)
                     },
-                    Msg::ReadFinished { cid, pr } => self.on_read_finished(cid, pr),
+                    Msg::ReadFinished { cid, cmd, pr } => self.on_read_finished(cid, cmd, pr),
                     Msg::WritePrepareFinished {
                         cid,
                         cmd,
@@ -1475,9 +2385,14 @@ impl Scheduler {
                     Msg::WriteFinished {
                         cid, pr, result, ..
                     } => self.on_write_finished(cid, pr, result),
+                    Msg::GetHotKeys { limit, cb } => cb(self.hot_keys.top(limit)),
                 }
             }
 
+            self.on_wait_for_lock_timeout();
+            self.on_throttled_cmds();
+            self.hot_keys.on_tick();
+
             if self.grouped_cmds.as_ref().unwrap().is_empty() {
                 continue;
             }
@@ -1514,18 +2429,35 @@ const CMD_BATCH_SIZE: usize = 256;
 /// Generates the lock for a command.
 ///
 /// Basically, read-only commands require no latches, write commands require latches hashed
-/// by the referenced keys.
+/// by the referenced keys. A raw range deletion can't tell the keys it deletes, so it requires
+/// all the latches.
 pub fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
     match *cmd {
-        Command::Prewrite { ref mutations, .. } => {
-            let keys: Vec<&Key> = mutations.iter().map(|x| x.key()).collect();
-            latches.gen_lock(&keys)
-        }
+        Command::RawDeleteRange { .. } => latches.gen_lock_all(),
+        _ => latches.gen_lock(&command_latch_keys(cmd)),
+    }
+}
+
+/// Returns the keys whose latches a command requires, which are empty for read-only commands.
+fn command_latch_keys(cmd: &Command) -> Vec<&Key> {
+    match *cmd {
+        Command::Prewrite { ref mutations, .. } => mutations.iter().map(|x| x.key()).collect(),
+        Command::AcquirePessimisticLock { ref keys, .. } |
         Command::Commit { ref keys, .. } |
         Command::Rollback { ref keys, .. } |
-        Command::ResolveLock { ref keys, .. } => latches.gen_lock(keys),
-        Command::Cleanup { ref key, .. } => latches.gen_lock(&[key]),
-        _ => Lock::new(vec![]),
+        Command::PessimisticRollback { ref keys, .. } |
+        Command::RawBatchDelete { ref keys, .. } |
+        Command::ResolveLock { ref keys, .. } => keys.iter().collect(),
+        Command::RawBatchPut { ref pairs, .. } => pairs.iter().map(|&(ref k, _)| k).collect(),
+        Command::Cleanup { ref key, .. } |
+        Command::RawCompareAndSwap { ref key, .. } => vec![key],
+        Command::TxnHeartBeat {
+            ref primary_key, ..
+        } |
+        Command::CheckTxnStatus {
+            ref primary_key, ..
+        } => vec![primary_key],
+        _ => vec![],
     }
 }
 
@@ -1534,7 +2466,7 @@ mod tests {
     use super::*;
     use kvproto::kvrpcpb::Context;
     use storage::txn::latch::*;
-    use storage::{make_key, Command, Mutation, Options};
+    use storage::{make_key, Command, Mutation, Options, CF_DEFAULT};
 
     #[test]
     fn test_command_latches() {
@@ -1543,11 +2475,13 @@ mod tests {
                 ctx: Context::new(),
                 key: make_key(b"k"),
                 start_ts: 25,
+                options: Options::default(),
             },
             Command::BatchGet {
                 ctx: Context::new(),
                 keys: vec![make_key(b"k")],
                 start_ts: 25,
+                options: Options::default(),
             },
             Command::Scan {
                 ctx: Context::new(),
@@ -1556,6 +2490,14 @@ mod tests {
                 start_ts: 25,
                 options: Options::default(),
             },
+            Command::ScanStream {
+                ctx: Context::new(),
+                start_key: make_key(b"k"),
+                end_key: Some(make_key(b"z")),
+                batch_size: 100,
+                start_ts: 25,
+                key_only: false,
+            },
             Command::ScanLock {
                 ctx: Context::new(),
                 max_ts: 5,
@@ -1607,6 +2549,19 @@ mod tests {
                 keys: vec![make_key(b"k")],
                 start_ts: 10,
             },
+            Command::AcquirePessimisticLock {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                primary: b"k".to_vec(),
+                start_ts: 10,
+                options: Options::default(),
+            },
+            Command::PessimisticRollback {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                start_ts: 10,
+                for_update_ts: 10,
+            },
             Command::ResolveLock {
                 ctx: Context::new(),
                 start_ts: 10,
@@ -1614,6 +2569,45 @@ mod tests {
                 scan_key: None,
                 keys: vec![make_key(b"k")],
             },
+            Command::RawCompareAndSwap {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                key: make_key(b"k"),
+                previous_value: None,
+                value: b"v".to_vec(),
+                ttl: 0,
+                enable_ttl: false,
+            },
+            Command::RawBatchPut {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                pairs: vec![(make_key(b"k"), b"v".to_vec())],
+                ttl: 0,
+                enable_ttl: false,
+            },
+            Command::RawBatchDelete {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                keys: vec![make_key(b"k")],
+            },
+            Command::RawDeleteRange {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                start_key: make_key(b"a"),
+                end_key: make_key(b"z"),
+            },
+            Command::TxnHeartBeat {
+                ctx: Context::new(),
+                primary_key: make_key(b"k"),
+                start_ts: 10,
+                advise_ttl: 100,
+            },
+            Command::CheckTxnStatus {
+                ctx: Context::new(),
+                primary_key: make_key(b"k"),
+                lock_ts: 10,
+                current_ts: 20,
+            },
         ];
 
         let mut latches = Latches::new(1024);
@@ -1648,4 +2642,74 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_raw_value() {
+        let now = ttl::current_ts();
+        assert_eq!(raw_value(b"v".to_vec(), false, now).unwrap(), Some(b"v".to_vec()));
+        let mut v = b"v".to_vec();
+        ttl::append_expire_ts(&mut v, 100);
+        assert_eq!(raw_value(v.clone(), true, now).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(raw_value(v, true, now + 100).unwrap(), None);
+        // A value without the expire ts is malformed.
+        assert!(raw_value(b"v".to_vec(), true, now).is_err());
+    }
+
+    #[test]
+    fn test_split_modifies() {
+        let prewrite = Command::Prewrite {
+            ctx: Context::new(),
+            mutations: vec![],
+            primary: b"k".to_vec(),
+            start_ts: 10,
+            options: Options::default(),
+        };
+        let modifies = || {
+            (0..10)
+                .map(|i| Modify::Put(CF_DEFAULT, make_key(&[i]), vec![0; 100]))
+                .collect::<Vec<_>>()
+        };
+        let size = modify_size(&modifies()[0]);
+
+        let chunks = split_modifies(&prewrite, modifies(), size * 3);
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            vec![3, 3, 3, 1]
+        );
+        // The order of the modifies is kept.
+        let keys: Vec<_> = chunks
+            .into_iter()
+            .flat_map(|c| c)
+            .map(|m| match m {
+                Modify::Put(_, k, _) => k,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(keys, (0..10).map(|i| make_key(&[i])).collect::<Vec<_>>());
+
+        // A modify larger than the limit is still written.
+        assert_eq!(split_modifies(&prewrite, modifies(), 1).len(), 10);
+        // 0 means never split.
+        assert_eq!(split_modifies(&prewrite, modifies(), 0).len(), 1);
+
+        // One-phase commits and other commands are never split.
+        let mut options = Options::default();
+        options.try_one_pc = true;
+        options.commit_ts = 20;
+        let one_pc = Command::Prewrite {
+            ctx: Context::new(),
+            mutations: vec![],
+            primary: b"k".to_vec(),
+            start_ts: 10,
+            options: options,
+        };
+        assert_eq!(split_modifies(&one_pc, modifies(), size).len(), 1);
+        let commit = Command::Commit {
+            ctx: Context::new(),
+            keys: vec![],
+            lock_ts: 10,
+            commit_ts: 20,
+        };
+        assert_eq!(split_modifies(&commit, modifies(), size).len(), 1);
+    }
 }
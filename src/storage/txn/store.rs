@@ -47,7 +47,7 @@ impl<'a> SnapshotStore<'a> {
             None,
             self.isolation_level,
         );
-        let v = reader.get(key, self.start_ts)?;
+        let v = reader.get_resolving_lock(key, self.start_ts)?;
         Ok(v)
     }
 
@@ -67,7 +67,11 @@ impl<'a> SnapshotStore<'a> {
         );
         let mut results = Vec::with_capacity(keys.len());
         for k in keys {
-            results.push(reader.get(k, self.start_ts).map_err(Error::from));
+            results.push(
+                reader
+                    .get_resolving_lock(k, self.start_ts)
+                    .map_err(Error::from),
+            );
         }
         Ok(results)
     }
@@ -165,6 +169,103 @@ impl<'a> StoreScanner<'a> {
     }
 }
 
+/// A forward scan which reads a range from one snapshot in batches.
+///
+/// A batch is read only when it's asked for, so forwarding the batches to a gRPC sink applies
+/// the backpressure of the sink to the scan. The scan ends after an error or the last batch.
+pub struct ScanStream {
+    snapshot: Box<Snapshot>,
+    start_ts: u64,
+    isolation_level: IsolationLevel,
+    fill_cache: bool,
+    key_only: bool,
+    // The key to scan from, `None` if the scan is finished.
+    next_key: Option<Key>,
+    end_key: Option<Key>,
+    batch_size: usize,
+    statistics: Statistics,
+}
+
+impl ScanStream {
+    #[allow(too_many_arguments)]
+    pub fn new(
+        snapshot: Box<Snapshot>,
+        start_ts: u64,
+        isolation_level: IsolationLevel,
+        fill_cache: bool,
+        key_only: bool,
+        start_key: Key,
+        end_key: Option<Key>,
+        batch_size: usize,
+    ) -> ScanStream {
+        ScanStream {
+            snapshot: snapshot,
+            start_ts: start_ts,
+            isolation_level: isolation_level,
+            fill_cache: fill_cache,
+            key_only: key_only,
+            next_key: Some(start_key),
+            end_key: end_key,
+            batch_size: batch_size,
+            statistics: Statistics::default(),
+        }
+    }
+
+    /// Returns the statistics of all the batches read so far.
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    fn scan_batch(&mut self, start_key: Key) -> Result<Vec<Result<KvPair>>> {
+        let store = SnapshotStore::new(
+            self.snapshot.as_ref(),
+            self.start_ts,
+            self.isolation_level,
+            self.fill_cache,
+        );
+        let upper_bound = self.end_key.as_ref().map(|k| k.encoded().clone());
+        let pairs = {
+            let mut scanner = store.scanner(
+                ScanMode::Forward,
+                self.key_only,
+                upper_bound,
+                &mut self.statistics,
+            )?;
+            scanner.scan(start_key, self.batch_size)?
+        };
+        if pairs.len() == self.batch_size {
+            // There may be more keys in the range.
+            self.next_key = pairs.last().map(resume_key);
+        }
+        Ok(pairs)
+    }
+}
+
+impl Iterator for ScanStream {
+    type Item = Result<Vec<Result<KvPair>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start_key = match self.next_key.take() {
+            Some(key) => key,
+            None => return None,
+        };
+        match self.scan_batch(start_key) {
+            Ok(ref pairs) if pairs.is_empty() => None,
+            res => Some(res),
+        }
+    }
+}
+
+/// Returns the key to continue a scan from after the result.
+fn resume_key(result: &Result<KvPair>) -> Key {
+    let key = match *result {
+        Ok((ref key, _)) |
+        Err(Error::Mvcc(MvccError::KeyIsLocked { ref key, .. })) => key,
+        Err(ref e) => panic!("unexpected scan result: {:?}", e),
+    };
+    Key::from_raw(key).append_ts(0)
+}
+
 #[cfg(test)]
 mod test {
     use kvproto::kvrpcpb::{Context, IsolationLevel};
@@ -0,0 +1,185 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lock waiters for commands blocked by transaction locks.
+//!
+//! Instead of returning `KeyIsLocked` to the client immediately, the scheduler parks a blocked
+//! command here, keyed by the `start_ts` of the lock and the hash of the locked key. The command is
+//! woken up to retry once the lock is released by `Commit`, `Rollback` or `ResolveLock`, or handed
+//! back when it has waited for longer than the timeout.
+
+use std::time::Duration;
+
+use util::collections::HashMap;
+use util::time::Instant;
+
+struct Waiter<T> {
+    payload: T,
+    start_time: Instant,
+}
+
+/// `WaiterManager` keeps track of the waiters of every lock.
+pub struct WaiterManager<T> {
+    // (lock_ts, key_hash) -> waiters
+    wait_table: HashMap<(u64, u64), Vec<Waiter<T>>>,
+    timeout: Duration,
+    count: usize,
+}
+
+impl<T> WaiterManager<T> {
+    /// Creates a waiter manager. A zero `timeout` disables waiting.
+    pub fn new(timeout: Duration) -> WaiterManager<T> {
+        WaiterManager {
+            wait_table: HashMap::default(),
+            timeout: timeout,
+            count: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.timeout > Duration::from_millis(0)
+    }
+
+    /// Parks `payload` until the lock of `lock_ts` on the key hashed to `key_hash` is released.
+    pub fn wait_for(&mut self, lock_ts: u64, key_hash: u64, payload: T) {
+        let start_time = Instant::now_coarse();
+        self.wait_table
+            .entry((lock_ts, key_hash))
+            .or_insert_with(Vec::new)
+            .push(Waiter {
+                payload: payload,
+                start_time: start_time,
+            });
+        self.count += 1;
+    }
+
+    /// Removes and returns the waiters of the locks of `lock_ts` on the keys hashed to
+    /// `key_hashes`, in the order they started waiting.
+    pub fn wake_up(&mut self, lock_ts: u64, key_hashes: &[u64]) -> Vec<T> {
+        let mut woken = vec![];
+        if self.count == 0 {
+            return woken;
+        }
+        for hash in key_hashes {
+            if let Some(waiters) = self.wait_table.remove(&(lock_ts, *hash)) {
+                self.count -= waiters.len();
+                woken.extend(waiters.into_iter().map(|w| w.payload));
+            }
+        }
+        woken
+    }
+
+    /// Removes and returns the waiters which have waited for longer than the timeout.
+    pub fn expire(&mut self) -> Vec<T> {
+        let mut expired = vec![];
+        if self.count == 0 {
+            return expired;
+        }
+        let timeout = self.timeout;
+        for waiters in self.wait_table.values_mut() {
+            let mut i = 0;
+            while i < waiters.len() {
+                if waiters[i].start_time.elapsed() >= timeout {
+                    expired.push(waiters.remove(i).payload);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.wait_table.retain(|_, waiters| !waiters.is_empty());
+        self.count -= expired.len();
+        expired
+    }
+
+    /// Returns the duration until the earliest waiter expires, `None` if there is no waiter.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.wait_table
+            .values()
+            .flat_map(|waiters| waiters.iter())
+            .map(|w| w.start_time.elapsed())
+            .max()
+            .map(|elapsed| if elapsed >= self.timeout {
+                Duration::from_millis(0)
+            } else {
+                self.timeout - elapsed
+            })
+    }
+
+    /// Returns the number of waiters.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_wake_up() {
+        let mut waiter_mgr = WaiterManager::new(Duration::from_secs(60));
+        assert!(waiter_mgr.is_enabled());
+        assert!(waiter_mgr.next_timeout().is_none());
+
+        waiter_mgr.wait_for(10, 1, "a");
+        waiter_mgr.wait_for(10, 1, "b");
+        waiter_mgr.wait_for(10, 2, "c");
+        waiter_mgr.wait_for(20, 1, "d");
+        assert_eq!(waiter_mgr.len(), 4);
+        assert!(waiter_mgr.next_timeout().unwrap() <= Duration::from_secs(60));
+
+        // Nobody waits for these locks.
+        assert!(waiter_mgr.wake_up(30, &[1, 2]).is_empty());
+        assert!(waiter_mgr.wake_up(10, &[3]).is_empty());
+
+        assert_eq!(waiter_mgr.wake_up(10, &[1, 3]), vec!["a", "b"]);
+        assert_eq!(waiter_mgr.len(), 2);
+        assert_eq!(waiter_mgr.wake_up(20, &[1, 2]), vec!["d"]);
+        assert_eq!(waiter_mgr.wake_up(10, &[2]), vec!["c"]);
+        assert!(waiter_mgr.is_empty());
+        assert!(waiter_mgr.next_timeout().is_none());
+        assert!(waiter_mgr.expire().is_empty());
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut waiter_mgr = WaiterManager::new(Duration::from_millis(100));
+        waiter_mgr.wait_for(10, 1, "a");
+        waiter_mgr.wait_for(10, 2, "b");
+        assert!(waiter_mgr.expire().is_empty());
+        thread::sleep(Duration::from_millis(200));
+        waiter_mgr.wait_for(10, 1, "c");
+        assert_eq!(waiter_mgr.next_timeout().unwrap(), Duration::from_millis(0));
+
+        let mut expired = waiter_mgr.expire();
+        expired.sort();
+        assert_eq!(expired, vec!["a", "b"]);
+        assert_eq!(waiter_mgr.len(), 1);
+        assert!(waiter_mgr.next_timeout().unwrap() > Duration::from_millis(0));
+        assert_eq!(waiter_mgr.wake_up(10, &[1, 2]), vec!["c"]);
+        assert!(waiter_mgr.is_empty());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let waiter_mgr: WaiterManager<()> = WaiterManager::new(Duration::from_millis(0));
+        assert!(!waiter_mgr.is_enabled());
+    }
+}
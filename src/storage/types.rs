@@ -43,6 +43,16 @@ pub struct MvccInfo {
     pub values: Vec<(u64, bool, Value)>,
 }
 
+/// `TxnStatus` is the status of a transaction told by its primary key.
+/// Used by `CheckTxnStatus`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxnStatus {
+    /// The primary is still locked, and the lock expires in `ttl` milliseconds.
+    Alive { ttl: u64 },
+    Committed { commit_ts: u64 },
+    RolledBack,
+}
+
 /// The caller should ensure the key is a timestamped key.
 pub fn truncate_ts(key: &[u8]) -> &[u8] {
     &key[..key.len() - number::U64_SIZE]
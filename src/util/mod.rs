@@ -202,6 +202,24 @@ pub fn escape(data: &[u8]) -> String {
     unsafe { String::from_utf8_unchecked(escaped) }
 }
 
+/// Formats `data` as an upper case hex string.
+///
+/// # Examples
+///
+/// ```
+/// use tikv::util::to_hex;
+///
+/// assert_eq!("", to_hex(b""));
+/// assert_eq!("00617A", to_hex(b"\0az"));
+/// ```
+pub fn to_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for c in data {
+        hex.push_str(&format!("{:02X}", c));
+    }
+    hex
+}
+
 /// A function to unescape an escaped string to a byte array.
 ///
 /// # Panic
@@ -24,6 +24,7 @@ pub const ROCKSDB_ESTIMATE_NUM_KEYS: &'static str = "rocksdb.estimate-num-keys";
 pub const ROCKSDB_PENDING_COMPACTION_BYTES: &'static str = "rocksdb.\
                                                             estimate-pending-compaction-bytes";
 pub const ROCKSDB_COMPRESSION_RATIO_AT_LEVEL: &'static str = "rocksdb.compression-ratio-at-level";
+pub const ROCKSDB_NUM_FILES_AT_LEVEL: &'static str = "rocksdb.num-files-at-level";
 pub const ROCKSDB_NUM_SNAPSHOTS: &'static str = "rocksdb.num-snapshots";
 pub const ROCKSDB_OLDEST_SNAPSHOT_TIME: &'static str = "rocksdb.oldest-snapshot-time";
 
@@ -15,6 +15,7 @@ pub mod properties;
 pub mod event_listener;
 pub mod engine_metrics;
 pub mod metrics_flusher;
+pub mod ttl;
 
 pub use self::event_listener::EventListener;
 pub use self::metrics_flusher::MetricsFlusher;
@@ -25,12 +26,15 @@ use std::sync::Arc;
 use std::str::FromStr;
 
 use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK};
-use rocksdb::{ColumnFamilyOptions, CompactOptions, DBCompressionType, DBOptions, ReadOptions,
-              SliceTransform, Writable, WriteBatch, DB};
+use storage::mvcc::{new_gc_compaction_filter, GcSafePoint, GC_COMPACTION_FILTER_NAME};
+use rocksdb::{ColumnFamilyOptions, CompactOptions, DBCompressionType, DBOptions, EnvOptions,
+              ReadOptions, SliceTransform, SstFileWriter, Writable, WriteBatch, DB};
 use rocksdb::rocksdb::supported_compression;
 use util::rocksdb::engine_metrics::{ROCKSDB_COMPRESSION_RATIO_AT_LEVEL,
-                                    ROCKSDB_CUR_SIZE_ALL_MEM_TABLES, ROCKSDB_TOTAL_SST_FILES_SIZE};
+                                    ROCKSDB_CUR_SIZE_ALL_MEM_TABLES, ROCKSDB_NUM_FILES_AT_LEVEL,
+                                    ROCKSDB_TOTAL_SST_FILES_SIZE};
 use util::rocksdb;
+use util::rocksdb::ttl::{TTLCompactionFilter, TTL_COMPACTION_FILTER_NAME};
 
 pub use rocksdb::CFHandle;
 
@@ -56,6 +60,22 @@ pub fn get_cf_handle<'a>(db: &'a DB, cf: &str) -> Result<&'a CFHandle, String> {
         .ok_or_else(|| format!("cf {} not found.", cf))
 }
 
+/// Opens an SST file writer at `path` with the options of the cf, whose files can be ingested
+/// into the cf.
+pub fn new_sst_writer(db: &DB, cf: &str, path: &str) -> Result<SstFileWriter, String> {
+    let handle = get_cf_handle(db, cf)?;
+    let mut io_options = db.get_options_cf(handle).clone();
+    io_options.compression(get_fastest_supported_compression_type());
+    // in rocksdb 5.5.1, SstFileWriter will try to use bottommost_compression and
+    // compression_per_level first, so to make sure our specified compression type
+    // being used, we must set them empty or disabled.
+    io_options.compression_per_level(&[]);
+    io_options.bottommost_compression(DBCompressionType::Disable);
+    let mut writer = SstFileWriter::new(EnvOptions::new(), io_options);
+    writer.open(path)?;
+    Ok(writer)
+}
+
 pub fn open(path: &str, cfs: &[&str]) -> Result<DB, String> {
     let mut opts = DBOptions::new();
     opts.create_if_missing(false);
@@ -79,6 +99,8 @@ pub fn open_opt(
 pub struct CFOptions<'a> {
     cf: &'a str,
     options: ColumnFamilyOptions,
+    enable_ttl: bool,
+    gc_safe_point: Option<Arc<GcSafePoint>>,
 }
 
 impl<'a> CFOptions<'a> {
@@ -86,8 +108,26 @@ impl<'a> CFOptions<'a> {
         CFOptions {
             cf: cf,
             options: options,
+            enable_ttl: false,
+            gc_safe_point: None,
         }
     }
+
+    pub fn cf(&self) -> &str {
+        self.cf
+    }
+
+    /// Drops the expired values of the column family in compaction, all the values must be
+    /// encoded by `ttl::append_expire_ts`.
+    pub fn enable_ttl(&mut self) {
+        self.enable_ttl = true;
+    }
+
+    /// Drops the stale MVCC versions before `safe_point` in compaction, only the write and the
+    /// default column families are supported.
+    pub fn enable_gc_compaction_filter(&mut self, safe_point: Arc<GcSafePoint>) {
+        self.gc_safe_point = Some(safe_point);
+    }
 }
 
 pub fn new_engine(path: &str, cfs: &[&str]) -> Result<DB, String> {
@@ -188,7 +228,25 @@ fn check_and_open(
     Ok(db)
 }
 
-pub fn new_engine_opt(path: &str, opts: DBOptions, cfs_opts: Vec<CFOptions>) -> Result<DB, String> {
+pub fn new_engine_opt(
+    path: &str,
+    opts: DBOptions,
+    mut cfs_opts: Vec<CFOptions>,
+) -> Result<DB, String> {
+    for cf_opts in cfs_opts.iter_mut().filter(|x| x.enable_ttl) {
+        let f = Box::new(TTLCompactionFilter::default());
+        cf_opts
+            .options
+            .set_compaction_filter(TTL_COMPACTION_FILTER_NAME, false, f)?;
+    }
+    for cf_opts in &mut cfs_opts {
+        if let Some(safe_point) = cf_opts.gc_safe_point.take() {
+            let f = new_gc_compaction_filter(cf_opts.cf, safe_point);
+            cf_opts
+                .options
+                .set_compaction_filter(GC_COMPACTION_FILTER_NAME, false, f)?;
+        }
+    }
     check_and_open(path, opts, cfs_opts)
 }
 
@@ -224,6 +282,11 @@ pub fn get_engine_used_size(engine: Arc<DB>) -> u64 {
     used_size
 }
 
+pub fn get_cf_num_files_at_level(engine: &DB, handle: &CFHandle, level: usize) -> Option<u64> {
+    let prop = format!("{}{}", ROCKSDB_NUM_FILES_AT_LEVEL, level);
+    engine.get_property_int_cf(handle, &prop)
+}
+
 pub fn get_engine_compression_ratio_at_level(
     engine: &DB,
     handle: &CFHandle,
@@ -0,0 +1,126 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TTL for raw values.
+//!
+//! When TTL is enabled, every raw value is stored with the time it expires appended, encoded as
+//! a big-endian u64 of seconds since the unix epoch, and `0` means the value never expires.
+//! Expired values are invisible to reads and dropped physically by `TTLCompactionFilter`.
+
+use time;
+
+use rocksdb::CompactionFilter;
+use raftstore::store::keys;
+use util::codec::{Error, Result};
+use util::codec::number::{NumberDecoder, NumberEncoder, U64_SIZE};
+
+pub const TTL_COMPACTION_FILTER_NAME: &'static str = "tikv.ttl-compaction-filter";
+
+/// Returns the current time in seconds since the unix epoch.
+pub fn current_ts() -> u64 {
+    time::get_time().sec as u64
+}
+
+/// Appends the expire ts of a value which lives for `ttl` seconds from now to `value`. A zero
+/// `ttl` means the value never expires.
+pub fn append_expire_ts(value: &mut Vec<u8>, ttl: u64) {
+    let expire_ts = if ttl == 0 {
+        0
+    } else {
+        current_ts().saturating_add(ttl)
+    };
+    value.encode_u64(expire_ts).unwrap();
+}
+
+fn check_expire_ts(value_with_ttl: &[u8]) -> Result<()> {
+    if value_with_ttl.len() < U64_SIZE {
+        return Err(Error::InvalidDataType(
+            format!("value of length {} has no expire ts", value_with_ttl.len()),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the expire ts of a value encoded with `append_expire_ts`.
+pub fn get_expire_ts(value_with_ttl: &[u8]) -> Result<u64> {
+    check_expire_ts(value_with_ttl)?;
+    let mut ts = &value_with_ttl[value_with_ttl.len() - U64_SIZE..];
+    ts.decode_u64()
+}
+
+/// Returns the user value of a value encoded with `append_expire_ts`.
+pub fn strip_expire_ts(value_with_ttl: &[u8]) -> Result<&[u8]> {
+    check_expire_ts(value_with_ttl)?;
+    Ok(&value_with_ttl[..value_with_ttl.len() - U64_SIZE])
+}
+
+/// Checks whether a value encoded with `append_expire_ts` has expired at `now`.
+pub fn is_expired(value_with_ttl: &[u8], now: u64) -> bool {
+    match get_expire_ts(value_with_ttl) {
+        Ok(expire_ts) => expire_ts != 0 && expire_ts <= now,
+        // Keep the malformed values, they are not written by the raw TTL API.
+        Err(_) => false,
+    }
+}
+
+/// `TTLCompactionFilter` drops the expired raw values during compaction. The keys out of the
+/// data range, like the local ones, are never dropped.
+#[derive(Default)]
+pub struct TTLCompactionFilter;
+
+impl CompactionFilter for TTLCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], value: &[u8]) -> bool {
+        key.starts_with(keys::DATA_PREFIX_KEY) && is_expired(value, current_ts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expire_ts() {
+        let now = current_ts();
+
+        let mut v = b"value".to_vec();
+        append_expire_ts(&mut v, 0);
+        assert_eq!(get_expire_ts(&v).unwrap(), 0);
+        assert_eq!(strip_expire_ts(&v).unwrap(), b"value");
+        assert!(!is_expired(&v, now + 1000));
+
+        let mut v = b"".to_vec();
+        append_expire_ts(&mut v, 10);
+        let expire_ts = get_expire_ts(&v).unwrap();
+        assert!(expire_ts >= now + 10);
+        assert_eq!(strip_expire_ts(&v).unwrap(), b"");
+        assert!(!is_expired(&v, now));
+        assert!(is_expired(&v, expire_ts));
+
+        assert!(get_expire_ts(b"short").is_err());
+        assert!(strip_expire_ts(b"short").is_err());
+        assert!(!is_expired(b"short", now));
+    }
+
+    #[test]
+    fn test_ttl_compaction_filter() {
+        let mut filter = TTLCompactionFilter::default();
+        let mut live = b"v".to_vec();
+        append_expire_ts(&mut live, 100);
+        assert!(!filter.filter(0, b"zk", &live));
+        let mut expired = b"v".to_vec();
+        expired.encode_u64(current_ts() - 1).unwrap();
+        assert!(filter.filter(0, b"zk", &expired));
+        // The keys out of the data range are kept.
+        assert!(!filter.filter(0, b"k", &expired));
+    }
+}
@@ -102,6 +102,7 @@ fn test_serde_custom_tikv_config() {
         lock_cf_compact_bytes_threshold: ReadableSize::mb(123),
         consistency_check_interval: ReadableDuration::secs(12),
         report_region_flow_interval: ReadableDuration::minutes(12),
+        resolved_ts_advance_interval: ReadableDuration::secs(12),
         raft_store_max_leader_lease: ReadableDuration::secs(12),
         right_derive_when_split: false,
         allow_remove_leader: true,
@@ -312,6 +313,22 @@ fn test_serde_custom_tikv_config() {
         scheduler_concurrency: 123,
         scheduler_worker_pool_size: 1,
         scheduler_too_busy_threshold: 123,
+        scheduler_wait_for_lock_timeout: ReadableDuration::secs(2),
+        scheduler_max_proposal_size: ReadableSize::mb(2),
+        enable_ttl: true,
+        gc_safe_point_lifetime: ReadableDuration::minutes(12),
+        gc_interval: ReadableDuration::secs(12),
+        gc_max_bytes_per_sec: ReadableSize::mb(12),
+        gc_enable_compaction_filter: false,
+        flow_control_enable: false,
+        flow_control_soft_l0_files: 10,
+        flow_control_hard_l0_files: 30,
+        flow_control_soft_pending_compaction_bytes: ReadableSize::gb(10),
+        flow_control_hard_pending_compaction_bytes: ReadableSize::gb(100),
+        flow_control_soft_apply_duration: ReadableDuration::secs(1),
+        flow_control_hard_apply_duration: ReadableDuration::secs(3),
+        flow_control_max_delay: ReadableDuration::millis(500),
+        enable_stale_read: true,
     };
 
     let custom = read_file_in_project_dir("tests/config/test-custom.toml");
@@ -94,6 +94,19 @@ pub fn new_test_raft(
     ))
 }
 
+pub fn new_test_learner_raft(
+    id: u64,
+    peers: Vec<u64>,
+    learners: Vec<u64>,
+    election: usize,
+    heartbeat: usize,
+    storage: MemStorage,
+) -> Interface {
+    let mut config = new_test_config(id, peers, election, heartbeat);
+    config.learners = learners;
+    new_test_raft_with_config(&config, storage)
+}
+
 pub fn new_test_raft_with_prevote(
     id: u64,
     peers: Vec<u64>,
@@ -192,11 +205,13 @@ impl Interface {
     fn initial(&mut self, id: u64, ids: &[u64]) {
         if self.raft.is_some() {
             self.id = id;
+            let learners = self.learner_nodes();
             self.prs = RaftFlatMap::with_capacity(ids.len());
             for id in ids {
                 self.prs.insert(
                     *id,
                     Progress {
+                        is_learner: learners.contains(id),
                         ..Default::default()
                     },
                 );
@@ -2755,6 +2770,19 @@ fn test_restore() {
     assert!(!sm.restore(s));
 }
 
+#[test]
+fn test_restore_with_learner() {
+    // Learner 4 is promoted by the snapshot while learner 3 stays a learner.
+    let s = new_snapshot(11, 11, vec![1, 2, 4]);
+
+    let mut sm = new_test_learner_raft(3, vec![1, 2], vec![3, 4], 10, 1, new_storage());
+    assert!(sm.is_learner());
+    assert!(sm.restore(s));
+    assert_eq!(sm.nodes(), vec![1, 2, 4]);
+    assert_eq!(sm.learner_nodes(), vec![3]);
+    assert!(sm.is_learner());
+}
+
 #[test]
 fn test_restore_ignore_snapshot() {
     let previous_ents = vec![empty_entry(1, 1), empty_entry(1, 2), empty_entry(1, 3)];
@@ -2983,6 +3011,163 @@ fn test_remove_node() {
     assert!(r.nodes().is_empty());
 }
 
+#[test]
+fn test_add_learner() {
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.add_learner(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+    assert!(r.prs[&2].is_learner);
+
+    // a voter can't be demoted to a learner.
+    r.add_learner(1);
+    assert_eq!(r.learner_nodes(), vec![2]);
+
+    // add_node promotes the learner.
+    r.add_node(2);
+    assert_eq!(r.nodes(), vec![1, 2]);
+    assert!(r.learner_nodes().is_empty());
+
+    r.add_learner(3);
+    r.remove_node(3);
+    assert_eq!(r.nodes(), vec![1, 2]);
+    assert!(r.learner_nodes().is_empty());
+}
+
+// test_learner_election_timeout verifies that a learner never starts an election.
+#[test]
+fn test_learner_election_timeout() {
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n2.become_follower(1, INVALID_ID);
+    assert!(n2.is_learner());
+    assert!(!n2.promotable());
+
+    let timeout = n2.get_election_timeout();
+    n2.set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        n2.tick();
+    }
+    assert_eq!(n2.state, StateRole::Follower);
+
+    n2.step(new_message(2, 2, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+}
+
+// test_learner_promotion verifies that a learner can't campaign until it's promoted
+// to a voter.
+#[test]
+fn test_learner_promotion() {
+    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n1.become_follower(1, INVALID_ID);
+    n2.become_follower(1, INVALID_ID);
+    let mut network = Network::new(vec![Some(n1), Some(n2)]);
+    assert_eq!(network.peers[&1].state, StateRole::Follower);
+
+    network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(network.peers[&1].state, StateRole::Leader);
+    assert_eq!(network.peers[&2].state, StateRole::Follower);
+
+    network.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(network.peers[&1].state, StateRole::Leader);
+    assert_eq!(network.peers[&2].state, StateRole::Follower);
+
+    // the learner catches up with the leader.
+    network.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(
+        network.peers[&2].raft_log.last_index(),
+        network.peers[&1].raft_log.last_index()
+    );
+
+    network.peers.get_mut(&1).unwrap().add_node(2);
+    network.peers.get_mut(&2).unwrap().add_node(2);
+    assert!(!network.peers[&2].is_learner());
+
+    network.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(network.peers[&1].state, StateRole::Follower);
+    assert_eq!(network.peers[&2].state, StateRole::Leader);
+}
+
+// test_learner_vote_and_commit verifies that the leader neither asks a learner for its vote
+// nor counts it in the quorum, while it still replicates the log to the learner.
+#[test]
+fn test_learner_vote_and_commit() {
+    let mut r = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_to(), 2);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgRequestVote);
+
+    let n1 = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1, 2], vec![3], 10, 1, new_storage());
+    let n3 = new_test_learner_raft(3, vec![1, 2], vec![3], 10, 1, new_storage());
+    let mut network = Network::new(vec![Some(n1), Some(n2), Some(n3)]);
+    network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(network.peers[&1].state, StateRole::Leader);
+    let committed = network.peers[&1].raft_log.committed;
+    assert_eq!(network.peers[&3].raft_log.committed, committed);
+
+    // the proposal can't be committed by the leader and the learner.
+    network.isolate(2);
+    network.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(network.peers[&1].raft_log.committed, committed);
+    assert_eq!(
+        network.peers[&3].raft_log.last_index(),
+        network.peers[&1].raft_log.last_index()
+    );
+
+    network.recover();
+    network.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(network.peers[&1].raft_log.committed, committed + 1);
+    assert_eq!(network.peers[&3].raft_log.committed, committed + 1);
+}
+
+// test_learner_read_index verifies that the read index forwarded by a learner is answered
+// by a leader which is the only voter.
+#[test]
+fn test_learner_read_index() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut network = Network::new(vec![Some(n1), Some(n2)]);
+    network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    network.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    network.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+
+    let e = new_entry(0, 0, Some("ctx"));
+    network.send(vec![
+        new_message_with_entries(2, 2, MessageType::MsgReadIndex, vec![e]),
+    ]);
+    let committed = network.peers[&1].raft_log.committed;
+    let read_states: Vec<ReadState> = network
+        .peers
+        .get_mut(&2)
+        .unwrap()
+        .read_states
+        .drain(..)
+        .collect();
+    assert_eq!(read_states.len(), 1);
+    assert_eq!(read_states[0].index, committed);
+    assert_eq!(read_states[0].request_ctx, b"ctx".to_vec());
+}
+
+// test_leader_transfer_to_learner verifies that the leadership can't be transferred to
+// a learner.
+#[test]
+fn test_leader_transfer_to_learner() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut network = Network::new(vec![Some(n1), Some(n2)]);
+    network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    network.send(vec![new_message(2, 1, MessageType::MsgTransferLeader, 0)]);
+    assert_eq!(network.peers[&1].state, StateRole::Leader);
+    assert_eq!(network.peers[&1].lead_transferee, None);
+}
+
 #[test]
 fn test_promotable() {
     let id = 1u64;
@@ -22,6 +22,7 @@ use tempdir::TempDir;
 
 use super::cluster::{Cluster, Simulator};
 use tikv::server::Node;
+use tikv::cdc::CdcHub;
 use tikv::raftstore::store::*;
 use kvproto::metapb;
 use kvproto::raft_cmdpb::*;
@@ -164,6 +165,8 @@ impl Simulator for NodeCluster {
             &cfg.server,
             &cfg.raft_store,
             self.pd_client.clone(),
+            ResolvedTsTracker::new(),
+            CdcHub::new(),
         );
 
         let (snap_mgr, tmp) = if node_id == 0 ||
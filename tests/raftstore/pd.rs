@@ -417,6 +417,7 @@ pub fn bootstrap_with_first_region(pd_client: Arc<TestPdClient>) -> Result<()> {
 pub struct TestPdClient {
     cluster_id: u64,
     cluster: RwLock<Cluster>,
+    tso: AtomicUsize,
 }
 
 impl TestPdClient {
@@ -424,6 +425,7 @@ impl TestPdClient {
         TestPdClient {
             cluster_id: cluster_id,
             cluster: RwLock::new(Cluster::new(cluster_id)),
+            tso: AtomicUsize::new(1),
         }
     }
 
@@ -742,4 +744,11 @@ impl PdClient for TestPdClient {
         self.cluster.wl().split_count += 1;
         Box::new(ok(()))
     }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        if let Err(e) = self.check_bootstrap() {
+            return Box::new(err(e));
+        }
+        Box::new(ok(self.tso.fetch_add(1, Ordering::SeqCst) as u64))
+    }
 }
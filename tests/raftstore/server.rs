@@ -22,16 +22,17 @@ use tempdir::TempDir;
 
 use super::cluster::{Cluster, Simulator};
 use tikv::config::TiKvConfig;
-use tikv::server::{Server, ServerTransport};
+use tikv::server::{CdcService, Server, ServerTransport};
 use tikv::server::{create_raft_storage, Config, Node, PdStoreAddrResolver, RaftClient};
 use tikv::server::resolve::{self, Task as ResolveTask};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::transport::RaftStoreRouter;
 use tikv::raftstore::{store, Error, Result};
-use tikv::raftstore::store::{Engines, Msg as StoreMsg, SnapManager};
+use tikv::raftstore::store::{Engines, Msg as StoreMsg, ResolvedTsTracker, SnapManager};
 use tikv::util::transport::SendCh;
 use tikv::util::worker::{FutureWorker, Worker};
 use tikv::storage::{CfName, Engine};
+use tikv::cdc::CdcHub;
 use kvproto::raft_serverpb::{self, RaftMessage};
 use kvproto::raft_cmdpb::*;
 
@@ -112,13 +113,18 @@ impl Simulator for ServerCluster {
         let sim_router = SimulateTransport::new(raft_router);
 
         // Create storage.
-        let mut store =
-            create_raft_storage(sim_router.clone(), engines.kv_engine.clone(), &cfg.storage)
-                .unwrap();
+        let resolved_ts = ResolvedTsTracker::new();
+        let mut store = create_raft_storage(
+            sim_router.clone(),
+            engines.kv_engine.clone(),
+            resolved_ts.clone(),
+            &cfg.storage,
+        ).unwrap();
         store.start(&cfg.storage).unwrap();
         self.storages.insert(node_id, store.get_engine());
 
         // Create pd client, snapshot manager, server.
+        let cdc_hub = CdcHub::new();
         let (worker, resolver) = resolve::new_resolver(self.pd_client.clone()).unwrap();
         let snap_mgr = SnapManager::new(tmp_str, Some(store_sendch));
         let pd_worker = FutureWorker::new("test-pd-worker");
@@ -131,6 +137,8 @@ impl Simulator for ServerCluster {
             snap_mgr.clone(),
             pd_worker.scheduler(),
             Some(engines.clone()),
+            resolved_ts.clone(),
+            Some(CdcService::new(cdc_hub.clone(), engines.kv_engine.clone())),
         ).unwrap();
         let addr = server.listening_addr();
         cfg.server.addr = format!("{}", addr);
@@ -143,6 +151,8 @@ impl Simulator for ServerCluster {
             &cfg.server,
             &cfg.raft_store,
             self.pd_client.clone(),
+            resolved_ts,
+            cdc_hub,
         );
         node.start(
             event_loop,
@@ -14,8 +14,9 @@
 use std::sync::{mpsc, Arc};
 use std::path::Path;
 use tikv::raftstore::store::{bootstrap_store, create_event_loop, keys, Engines, Peekable,
-                             SnapManager};
+                             ResolvedTsTracker, SnapManager};
 use tikv::server::Node;
+use tikv::cdc::CdcHub;
 use tikv::storage::{ALL_CFS, CF_RAFT};
 use tikv::util::rocksdb;
 use tikv::util::worker::FutureWorker;
@@ -67,6 +68,8 @@ fn test_node_bootstrap_with_prepared_data() {
         &cfg.server,
         &cfg.raft_store,
         pd_client.clone(),
+        ResolvedTsTracker::new(),
+        CdcHub::new(),
     );
     let snap_mgr = SnapManager::new(tmp_mgr.path().to_str().unwrap(), Some(node.get_sendch()));
     let (_, snapshot_status_receiver) = mpsc::channel();
@@ -0,0 +1,193 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module contains test cases for the reads served by the followers with read index.
+
+use std::boxed::FnBox;
+use std::thread;
+use std::time::Duration;
+
+use kvproto::eraftpb::MessageType;
+use kvproto::metapb::{Peer, Region};
+use kvproto::kvrpcpb::Context;
+use kvproto::raft_cmdpb::{CmdType, RaftCmdResponse};
+use tikv::raftstore::{Error, Result};
+use tikv::raftstore::store::Msg;
+use tikv::util::HandyRwLock;
+use tikv::util::config::ReadableDuration;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::transport_simulate::*;
+use super::util::*;
+
+fn read_on_peer<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: Peer,
+    region: &Region,
+    key: &[u8],
+    follower_read: Option<u64>,
+    timeout: Duration,
+) -> Result<RaftCmdResponse> {
+    let mut request = new_request(
+        region.get_id(),
+        region.get_region_epoch().clone(),
+        vec![new_get_cmd(key)],
+        false,
+    );
+    request.mut_header().set_peer(peer.clone());
+    let read_ts = match follower_read {
+        Some(read_ts) => read_ts,
+        None => return cluster.call_command(request, timeout),
+    };
+
+    let sendch = cluster
+        .sim
+        .rl()
+        .get_store_sendch(peer.get_store_id())
+        .unwrap();
+    wait_op!(
+        |cb: Box<FnBox(RaftCmdResponse) + 'static + Send>| {
+            sendch
+                .try_send(Msg::new_follower_read_cmd(request, read_ts, cb))
+                .unwrap()
+        },
+        timeout
+    ).ok_or_else(|| Error::Timeout(format!("request timeout for {:?}", timeout)))
+}
+
+fn must_read_on_follower<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: Peer,
+    region: &Region,
+    key: &[u8],
+    value: &[u8],
+) {
+    let timeout = Duration::from_secs(5);
+    let mut resp = read_on_peer(cluster, peer, region, key, Some(0), timeout).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    assert_eq!(resp.get_responses().len(), 1);
+    assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Get);
+    assert_eq!(resp.mut_responses()[0].take_get().get_value(), value);
+}
+
+fn test_follower_read<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+    let region = cluster.get_region(b"k1");
+
+    // Only the reads sent as follower reads are served by the followers, even if they ask
+    // for the read quorum.
+    let timeout = Duration::from_secs(5);
+    let resp = read_on_peer(cluster, new_peer(3, 3), &region, b"k1", None, timeout).unwrap();
+    assert!(resp.get_header().get_error().has_not_leader());
+    let mut request = new_request(
+        region.get_id(),
+        region.get_region_epoch().clone(),
+        vec![new_get_cmd(b"k1")],
+        true,
+    );
+    request.mut_header().set_peer(new_peer(3, 3));
+    let resp = cluster.call_command(request, timeout).unwrap();
+    assert!(resp.get_header().get_error().has_not_leader());
+    must_read_on_follower(cluster, new_peer(3, 3), &region, b"k1", b"v1");
+
+    // Writes are still refused by the followers.
+    let mut request = new_request(
+        region.get_id(),
+        region.get_region_epoch().clone(),
+        vec![new_put_cmd(b"k2", b"v2")],
+        false,
+    );
+    request.mut_header().set_peer(new_peer(3, 3));
+    let sendch = cluster.sim.rl().get_store_sendch(3).unwrap();
+    let resp = wait_op!(
+        |cb: Box<FnBox(RaftCmdResponse) + 'static + Send>| {
+            sendch
+                .try_send(Msg::new_follower_read_cmd(request, 0, cb))
+                .unwrap()
+        },
+        timeout
+    ).unwrap();
+    assert!(resp.get_header().get_error().has_not_leader());
+
+    // The read index request forwarded to the leader is dropped, so the read expires.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(1, 3)
+            .direction(Direction::Send)
+            .msg_type(MessageType::MsgReadIndex),
+    ));
+    let resp = read_on_peer(cluster, new_peer(3, 3), &region, b"k1", Some(0), timeout).unwrap();
+    assert!(resp.get_header().get_error().has_stale_command(), "{:?}", resp);
+    cluster.clear_send_filters();
+    must_read_on_follower(cluster, new_peer(3, 3), &region, b"k1", b"v1");
+
+    // Peer 3 doesn't receive the log, so the read index of the leader is never applied
+    // by it and the read waits.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(1, 3).msg_type(MessageType::MsgAppend),
+    ));
+    cluster.must_put(b"k1", b"v2");
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+    let short_timeout = Duration::from_millis(500);
+    read_on_peer(cluster, new_peer(3, 3), &region, b"k1", Some(0), short_timeout).unwrap_err();
+
+    // The follower read sees the writes acknowledged before it once the log is applied.
+    cluster.clear_send_filters();
+    must_read_on_follower(cluster, new_peer(3, 3), &region, b"k1", b"v2");
+}
+
+#[test]
+fn test_node_follower_read() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_follower_read(&mut cluster);
+}
+
+#[test]
+fn test_server_follower_read() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_follower_read(&mut cluster);
+}
+
+#[test]
+fn test_server_follower_read_ts() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.cfg.raft_store.resolved_ts_advance_interval = ReadableDuration::millis(100);
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+    let region = cluster.get_region(b"k1");
+
+    let engine = cluster.sim.rl().storages[&1].clone();
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    for _ in 0..50 {
+        if engine.max_read_ts(&ctx).is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let max_read_ts = engine.max_read_ts(&ctx).unwrap();
+
+    // The leader records the ts of the read served by the follower.
+    let read_ts = max_read_ts + 1_000_000;
+    let timeout = Duration::from_secs(5);
+    let resp = read_on_peer(&mut cluster, new_peer(3, 3), &region, b"k1", Some(read_ts), timeout)
+        .unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    assert!(engine.max_read_ts(&ctx).unwrap() >= read_ts);
+}
@@ -24,7 +24,9 @@ use kvproto::coprocessor::*;
 use kvproto::{debugpb, eraftpb, metapb, raft_serverpb};
 use kvproto::tikvpb_grpc::TikvClient;
 use kvproto::debugpb_grpc::DebugClient;
+use tikv::server::extpb::{self, ChangeDataClient, DebugExtClient, TikvExtClient};
 use rocksdb::Writable;
+use tempdir::TempDir;
 use futures::{future, Future, Sink, Stream};
 use grpc::{ChannelBuilder, Environment, Error, RpcStatusCode};
 
@@ -145,6 +147,18 @@ fn must_kv_commit(
     assert!(!commit_resp.has_error(), "{:?}", commit_resp.get_error());
 }
 
+#[test]
+fn test_kv_import() {
+    let (_cluster, client, _) = must_new_cluster_and_kv_client();
+
+    match client.kv_import(ImportRequest::new()).unwrap_err() {
+        Error::RpcFailure(status) => {
+            assert_eq!(status.status, RpcStatusCode::Unimplemented);
+        }
+        _ => panic!("expect Unimplemented"),
+    }
+}
+
 #[test]
 fn test_mvcc_basic() {
     let (_cluster, client, ctx) = must_new_cluster_and_kv_client();
@@ -459,6 +473,326 @@ fn test_mvcc_resolve_lock_gc_and_delete() {
     assert!(del_resp.error.is_empty());
 }
 
+fn must_new_cluster_and_kv_ext_client() -> (Cluster<ServerCluster>, TikvExtClient, Context) {
+    let (cluster, leader, ctx) = must_new_cluster();
+
+    let addr = cluster.sim.rl().get_addr(leader.get_store_id());
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let client = TikvExtClient::new(channel);
+
+    (cluster, client, ctx)
+}
+
+fn must_kv_pessimistic_lock(
+    client: &TikvExtClient,
+    ctx: &Context,
+    key: Vec<u8>,
+    start_ts: u64,
+    for_update_ts: u64,
+) {
+    let mut lock_req = extpb::PessimisticLockRequest::default();
+    lock_req.context = extpb::encode(ctx);
+    lock_req.keys = vec![key.clone()];
+    lock_req.primary_lock = key;
+    lock_req.start_version = start_ts;
+    lock_req.for_update_ts = for_update_ts;
+    lock_req.lock_ttl = 3000;
+    let lock_resp = client.kv_pessimistic_lock(lock_req).unwrap();
+    assert!(lock_resp.region_error.is_empty());
+    assert!(lock_resp.errors.is_empty());
+}
+
+#[test]
+fn test_txn_heart_beat() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+    let k = b"key".to_vec();
+
+    let mut req = extpb::TxnHeartBeatRequest::default();
+    req.context = extpb::encode(&ctx);
+    req.primary_lock = k.clone();
+    req.start_version = 10;
+    req.advise_lock_ttl = 5000;
+    // There is no lock yet.
+    let resp = client.kv_txn_heart_beat(req.clone()).unwrap();
+    assert!(resp.region_error.is_empty());
+    assert!(!resp.error.is_empty());
+
+    must_kv_pessimistic_lock(&client, &ctx, k, 10, 10);
+    let resp = client.kv_txn_heart_beat(req.clone()).unwrap();
+    assert!(resp.error.is_empty());
+    assert_eq!(resp.lock_ttl, 5000);
+    // The TTL is never lowered.
+    req.advise_lock_ttl = 1000;
+    let resp = client.kv_txn_heart_beat(req).unwrap();
+    assert_eq!(resp.lock_ttl, 5000);
+}
+
+#[test]
+fn test_check_txn_status() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+    let k = b"key".to_vec();
+
+    must_kv_pessimistic_lock(&client, &ctx, k.clone(), 10, 10);
+    let mut req = extpb::CheckTxnStatusRequest::default();
+    req.context = extpb::encode(&ctx);
+    req.primary_key = k;
+    req.lock_ts = 10;
+    req.current_ts = 10;
+    let resp = client.kv_check_txn_status(req.clone()).unwrap();
+    assert!(resp.region_error.is_empty());
+    assert!(resp.error.is_empty());
+    assert!(resp.lock_ttl > 0);
+
+    // The lock expires 3000ms after its ts, then it's rolled back.
+    req.current_ts = 4000 << 18;
+    let resp = client.kv_check_txn_status(req.clone()).unwrap();
+    assert!(resp.error.is_empty());
+    assert_eq!((resp.lock_ttl, resp.commit_version), (0, 0));
+    let resp = client.kv_check_txn_status(req).unwrap();
+    assert_eq!((resp.lock_ttl, resp.commit_version), (0, 0));
+}
+
+#[test]
+fn test_pessimistic_lock() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+    let (k, v) = (b"key".to_vec(), b"value".to_vec());
+
+    // Lock and roll the lock back.
+    must_kv_pessimistic_lock(&client, &ctx, k.clone(), 10, 10);
+    let mut rollback_req = extpb::PessimisticRollbackRequest::default();
+    rollback_req.context = extpb::encode(&ctx);
+    rollback_req.keys = vec![k.clone()];
+    rollback_req.start_version = 10;
+    rollback_req.for_update_ts = 10;
+    let rollback_resp = client.kv_pessimistic_rollback(rollback_req).unwrap();
+    assert!(rollback_resp.region_error.is_empty());
+    assert!(rollback_resp.error.is_empty());
+
+    // Another transaction can lock the key now, and prewrite it on top of the lock.
+    must_kv_pessimistic_lock(&client, &ctx, k.clone(), 20, 20);
+    let mut mutation = Mutation::new();
+    mutation.op = Op::Put;
+    mutation.key = k.clone();
+    mutation.value = v.clone();
+    let mut prewrite_req = PrewriteRequest::new();
+    prewrite_req.set_context(ctx.clone());
+    prewrite_req.set_mutations(vec![mutation].into_iter().collect());
+    prewrite_req.primary_lock = k.clone();
+    prewrite_req.start_version = 20;
+    prewrite_req.lock_ttl = 3000;
+    let mut req = extpb::PrewriteRequest::default();
+    req.request = extpb::encode(&prewrite_req);
+    req.for_update_ts = 20;
+    req.is_pessimistic_lock = vec![true];
+    let prewrite_resp = client.kv_prewrite(req).unwrap();
+    assert!(
+        !prewrite_resp.has_region_error(),
+        "{:?}",
+        prewrite_resp.get_region_error()
+    );
+    assert!(
+        prewrite_resp.errors.is_empty(),
+        "{:?}",
+        prewrite_resp.get_errors()
+    );
+}
+
+#[test]
+fn test_kv_scan_stream() {
+    let (cluster, client, ctx) = must_new_cluster_and_kv_client();
+    let keys: Vec<_> = (0..5).map(|i| format!("key{}", i).into_bytes()).collect();
+    let muts = keys.iter()
+        .map(|k| {
+            let mut mutation = Mutation::new();
+            mutation.op = Op::Put;
+            mutation.key = k.clone();
+            mutation.value = b"value".to_vec();
+            mutation
+        })
+        .collect();
+    must_kv_prewrite(&client, ctx.clone(), muts, keys[0].clone(), 10);
+    must_kv_commit(&client, ctx.clone(), keys.clone(), 10, 11);
+
+    let addr = cluster.sim.rl().get_addr(ctx.get_peer().get_store_id());
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let ext_client = TikvExtClient::new(channel);
+
+    let mut req = extpb::ScanStreamRequest::default();
+    req.context = extpb::encode(&ctx);
+    req.start_key = keys[1].clone();
+    req.batch_size = 2;
+    req.version = 20;
+    let resps: Vec<_> = ext_client.kv_scan_stream(req).collect().wait().unwrap();
+    let mut scanned = vec![];
+    for resp in &resps {
+        assert!(resp.region_error.is_empty());
+        assert!(resp.error.is_empty());
+        assert!(resp.pairs.len() <= 2);
+        for pair in &resp.pairs {
+            let pair: KvPair = extpb::decode(pair).unwrap();
+            assert_eq!(pair.get_value(), b"value");
+            scanned.push(pair.get_key().to_vec());
+        }
+    }
+    assert_eq!(scanned, &keys[1..]);
+}
+
+#[test]
+fn test_change_data_event_feed() {
+    let (cluster, client, ctx) = must_new_cluster_and_kv_client();
+    let must_put = |k: &[u8], ts: u64| {
+        let mut mutation = Mutation::new();
+        mutation.op = Op::Put;
+        mutation.key = k.to_vec();
+        mutation.value = b"value".to_vec();
+        must_kv_prewrite(&client, ctx.clone(), vec![mutation], k.to_vec(), ts);
+        must_kv_commit(&client, ctx.clone(), vec![k.to_vec()], ts, ts + 1);
+    };
+    must_put(b"k1", 10);
+
+    let addr = cluster.sim.rl().get_addr(ctx.get_peer().get_store_id());
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let cdc_client = ChangeDataClient::new(channel);
+    let mut req = extpb::ChangeDataRequest::default();
+    req.region_id = ctx.get_region_id();
+    let mut events = cdc_client.event_feed(req).wait();
+
+    // The row committed before the subscription is caught up by the incremental scan, the
+    // other one is sent either by the scan or when it's applied.
+    must_put(b"k2", 20);
+    let event = events.next().unwrap().unwrap();
+    assert_eq!(event.key, b"k1");
+    assert_eq!(event.value, Some(b"value".to_vec()));
+    assert_eq!((event.start_ts, event.commit_ts), (10, 11));
+    let event = events.next().unwrap().unwrap();
+    assert_eq!(event.key, b"k2");
+    assert_eq!((event.start_ts, event.commit_ts), (20, 21));
+}
+
+#[test]
+fn test_rawkv_ttl() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+
+    // TTL is not enabled by default.
+    let mut put_req = RawPutRequest::new();
+    put_req.set_context(ctx.clone());
+    put_req.key = b"key".to_vec();
+    put_req.value = b"value".to_vec();
+    let mut req = extpb::RawPutRequest::default();
+    req.request = extpb::encode(&put_req);
+    req.ttl = 100;
+    let put_resp = client.raw_put(req.clone()).unwrap();
+    assert!(!put_resp.has_region_error());
+    assert!(!put_resp.error.is_empty());
+
+    // The pairs which never expire are still allowed.
+    req.ttl = 0;
+    let put_resp = client.raw_put(req).unwrap();
+    assert!(!put_resp.has_region_error());
+    assert!(put_resp.error.is_empty(), "{}", put_resp.error);
+}
+
+#[test]
+fn test_rawkv_reverse_scan() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+
+    let mut put_req = extpb::RawBatchPutRequest::default();
+    put_req.context = extpb::encode(&ctx);
+    put_req.pairs = vec![
+        (b"k1".to_vec(), b"v1".to_vec()),
+        (b"k2".to_vec(), b"v2".to_vec()),
+        (b"k3".to_vec(), b"v3".to_vec()),
+    ];
+    let put_resp = client.raw_batch_put(put_req).unwrap();
+    assert!(put_resp.error.is_empty(), "{}", put_resp.error);
+
+    let mut scan_req = RawScanRequest::new();
+    scan_req.set_context(ctx);
+    scan_req.set_start_key(b"k3".to_vec());
+    scan_req.set_limit(2);
+    let mut req = extpb::RawScanRequest::default();
+    req.request = extpb::encode(&scan_req);
+    req.reverse = true;
+    let scan_resp = client.raw_scan(req.clone()).unwrap();
+    assert!(!scan_resp.has_region_error());
+    let keys: Vec<_> = scan_resp.get_kvs().iter().map(|kv| kv.get_key()).collect();
+    let expect: Vec<&[u8]> = vec![b"k2", b"k1"];
+    assert_eq!(keys, expect);
+
+    req.reverse = false;
+    let scan_resp = client.raw_scan(req).unwrap();
+    let keys: Vec<_> = scan_resp.get_kvs().iter().map(|kv| kv.get_key()).collect();
+    let expect: Vec<&[u8]> = vec![b"k3"];
+    assert_eq!(keys, expect);
+}
+
+#[test]
+fn test_rawkv_batch_write() {
+    let (mut cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+
+    let mut put_req = extpb::RawBatchPutRequest::default();
+    put_req.context = extpb::encode(&ctx);
+    put_req.pairs = (1..6)
+        .map(|i| (format!("k{}", i).into_bytes(), b"v".to_vec()))
+        .collect();
+    let put_resp = client.raw_batch_put(put_req.clone()).unwrap();
+    assert!(put_resp.region_error.is_empty());
+    assert!(put_resp.error.is_empty(), "{}", put_resp.error);
+    assert_eq!(cluster.get(b"k3"), Some(b"v".to_vec()));
+
+    let mut delete_req = extpb::RawBatchDeleteRequest::default();
+    delete_req.context = extpb::encode(&ctx);
+    delete_req.keys = vec![b"k1".to_vec(), b"k5".to_vec()];
+    let delete_resp = client.raw_batch_delete(delete_req).unwrap();
+    assert!(delete_resp.error.is_empty(), "{}", delete_resp.error);
+    assert_eq!(cluster.get(b"k1"), None);
+
+    let mut delete_range_req = extpb::RawDeleteRangeRequest::default();
+    delete_range_req.context = extpb::encode(&ctx);
+    delete_range_req.start_key = b"k2".to_vec();
+    delete_range_req.end_key = b"k4".to_vec();
+    let delete_range_resp = client.raw_delete_range(delete_range_req).unwrap();
+    assert!(delete_range_resp.error.is_empty(), "{}", delete_range_resp.error);
+    assert_eq!(cluster.get(b"k2"), None);
+    assert_eq!(cluster.get(b"k3"), None);
+    assert_eq!(cluster.get(b"k4"), Some(b"v".to_vec()));
+
+    // Raw writes can't touch the column families of transactions.
+    put_req.cf = CF_LOCK.to_owned();
+    match client.raw_batch_put(put_req).unwrap_err() {
+        Error::RpcFailure(status) => {
+            assert_eq!(status.status, RpcStatusCode::InvalidArgument);
+        }
+        _ => panic!("expect InvalidArgument"),
+    }
+}
+
+#[test]
+fn test_rawkv_compare_and_swap() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_ext_client();
+
+    let mut req = extpb::RawCompareAndSwapRequest::default();
+    req.context = extpb::encode(&ctx);
+    req.key = b"key".to_vec();
+    req.previous_value = Some(b"v0".to_vec());
+    req.value = b"v1".to_vec();
+    let resp = client.raw_compare_and_swap(req.clone()).unwrap();
+    assert!(resp.region_error.is_empty());
+    assert!(resp.error.is_empty(), "{}", resp.error);
+    assert_eq!((resp.previous_value, resp.succeed), (None, false));
+
+    req.previous_value = None;
+    let resp = client.raw_compare_and_swap(req.clone()).unwrap();
+    assert_eq!((resp.previous_value, resp.succeed), (None, true));
+
+    req.value = b"v2".to_vec();
+    let resp = client.raw_compare_and_swap(req).unwrap();
+    assert_eq!((resp.previous_value, resp.succeed), (Some(b"v1".to_vec()), false));
+}
+
 #[test]
 fn test_raft() {
     let (_cluster, client, _) = must_new_cluster_and_kv_client();
@@ -744,7 +1078,7 @@ fn test_debug_scan_mvcc() {
         keys::data_key(b"meta_lock_2"),
     ];
     for k in &keys {
-        let v = Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None).to_bytes();
+        let v = Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None, 0).to_bytes();
         let cf_handle = engine.cf_handle(CF_LOCK).unwrap();
         engine.put_cf(cf_handle, k.as_slice(), &v).unwrap();
     }
@@ -764,3 +1098,103 @@ fn test_debug_scan_mvcc() {
     assert_eq!(keys.len(), 1);
     assert_eq!(keys[0], keys::data_key(b"meta_lock_1"));
 }
+
+#[test]
+fn test_debug_hot_keys() {
+    let (cluster, _, store_id) = must_new_cluster_and_debug_client();
+
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let client = DebugExtClient::new(channel);
+
+    // No command has waited for a latch yet.
+    let mut req = extpb::HotKeysRequest::default();
+    req.limit = 10;
+    let resp = client.hot_keys(req).unwrap();
+    assert!(resp.keys.is_empty());
+}
+
+#[test]
+fn test_debug_resolved_ts() {
+    let (cluster, _, store_id) = must_new_cluster_and_debug_client();
+
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let client = DebugExtClient::new(channel);
+
+    // The region is tracked once its peer is created.
+    let region_id = 1;
+    let req = extpb::ResolvedTsRequest {
+        region_id: region_id,
+    };
+    client.resolved_ts(req).unwrap();
+
+    let req = extpb::ResolvedTsRequest {
+        region_id: region_id + 1,
+    };
+    match client.resolved_ts(req).unwrap_err() {
+        Error::RpcFailure(status) => {
+            assert_eq!(status.status, RpcStatusCode::NotFound);
+        }
+        _ => panic!("expect NotFound"),
+    }
+}
+
+#[test]
+fn test_debug_backup() {
+    let (cluster, _, store_id) = must_new_cluster_and_debug_client();
+
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let client = DebugExtClient::new(channel);
+
+    let dir = TempDir::new("test_debug_backup").unwrap();
+    let mut req = extpb::BackupRequest::default();
+    req.path = dir.path().to_str().unwrap().to_owned();
+    // The backup ts must be positive.
+    match client.backup(req).unwrap_err() {
+        Error::RpcFailure(status) => {
+            assert_eq!(status.status, RpcStatusCode::InvalidArgument);
+        }
+        _ => panic!("expect InvalidArgument"),
+    }
+}
+
+#[test]
+fn test_debug_import_mode() {
+    let (cluster, _, store_id) = must_new_cluster_and_debug_client();
+
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    let client = DebugExtClient::new(channel);
+
+    let must_import_mode = |import: bool| {
+        let resp = client.import_mode(extpb::ImportModeRequest {}).unwrap();
+        assert_eq!(resp.import, import);
+    };
+    must_import_mode(false);
+
+    let mut req = extpb::SwitchImportModeRequest::default();
+    req.import = true;
+    req.timeout_secs = 600;
+    client.switch_import_mode(req.clone()).unwrap();
+    must_import_mode(true);
+
+    req.import = false;
+    client.switch_import_mode(req.clone()).unwrap();
+    must_import_mode(false);
+
+    // The import mode must have a timeout.
+    req.import = true;
+    req.timeout_secs = 0;
+    match client.switch_import_mode(req).unwrap_err() {
+        Error::RpcFailure(status) => {
+            assert_eq!(status.status, RpcStatusCode::InvalidArgument);
+        }
+        _ => panic!("expect InvalidArgument"),
+    }
+}
@@ -13,7 +13,7 @@
 
 use super::sync_storage::SyncStorage;
 use kvproto::kvrpcpb::{Context, LockInfo};
-use tikv::storage::{self, make_key, Key, KvPair, Mutation, Value};
+use tikv::storage::{self, make_key, Key, KvPair, Mutation, TxnStatus, Value};
 use tikv::storage::mvcc::{self, MAX_TXN_WRITE_SIZE};
 use tikv::storage::txn;
 use raftstore::cluster::Cluster;
@@ -275,6 +275,25 @@ impl AssertionStorage {
         assert_eq!(result, expect);
     }
 
+    pub fn reverse_scan_ok(
+        &self,
+        start_key: &[u8],
+        limit: usize,
+        ts: u64,
+        expect: Vec<Option<(&[u8], &[u8])>>,
+    ) {
+        let key_address = make_key(start_key);
+        let result = self.store
+            .reverse_scan(self.ctx.clone(), key_address, limit, false, ts)
+            .unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter().map(Result::ok).collect();
+        let expect: Vec<Option<KvPair>> = expect
+            .into_iter()
+            .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect();
+        assert_eq!(result, expect);
+    }
+
     pub fn scan_key_only_ok(
         &self,
         start_key: &[u8],
@@ -332,6 +351,48 @@ impl AssertionStorage {
         assert_eq!(expect_locks, locks);
     }
 
+    fn one_pc_prewrite(
+        &self,
+        mutations: Vec<Mutation>,
+        primary: &[u8],
+        start_ts: u64,
+        commit_ts: u64,
+    ) -> storage::Result<(Vec<storage::Result<()>>, bool)> {
+        self.store.one_pc_prewrite(
+            self.ctx.clone(),
+            mutations,
+            primary.to_vec(),
+            start_ts,
+            commit_ts,
+        )
+    }
+
+    pub fn one_pc_prewrite_ok(
+        &self,
+        mutations: Vec<Mutation>,
+        primary: &[u8],
+        start_ts: u64,
+        commit_ts: u64,
+    ) {
+        let (res, one_pc) = self.one_pc_prewrite(mutations, primary, start_ts, commit_ts)
+            .unwrap();
+        assert!(res.iter().all(|x| x.is_ok()));
+        assert!(one_pc);
+    }
+
+    pub fn one_pc_prewrite_err(
+        &self,
+        mutations: Vec<Mutation>,
+        primary: &[u8],
+        start_ts: u64,
+        commit_ts: u64,
+    ) {
+        assert!(
+            self.one_pc_prewrite(mutations, primary, start_ts, commit_ts)
+                .is_err()
+        );
+    }
+
     pub fn commit_ok(&self, keys: Vec<&[u8]>, start_ts: u64, commit_ts: u64) {
         let keys: Vec<Key> = keys.iter().map(|x| make_key(x)).collect();
         self.store
@@ -360,6 +421,34 @@ impl AssertionStorage {
         );
     }
 
+    pub fn txn_heart_beat_ok(&self, key: &[u8], start_ts: u64, advise_ttl: u64, expect_ttl: u64) {
+        let ttl = self.store
+            .txn_heart_beat(self.ctx.clone(), make_key(key), start_ts, advise_ttl)
+            .unwrap();
+        assert_eq!(ttl, expect_ttl);
+    }
+
+    pub fn txn_heart_beat_err(&self, key: &[u8], start_ts: u64, advise_ttl: u64) {
+        assert!(
+            self.store
+                .txn_heart_beat(self.ctx.clone(), make_key(key), start_ts, advise_ttl)
+                .is_err()
+        );
+    }
+
+    pub fn check_txn_status_ok(
+        &self,
+        key: &[u8],
+        lock_ts: u64,
+        current_ts: u64,
+        expect: TxnStatus,
+    ) {
+        let status = self.store
+            .check_txn_status(self.ctx.clone(), make_key(key), lock_ts, current_ts)
+            .unwrap();
+        assert_eq!(status, expect);
+    }
+
     pub fn rollback_ok(&self, keys: Vec<&[u8]>, start_ts: u64) {
         let keys: Vec<Key> = keys.iter().map(|x| make_key(x)).collect();
         self.store
@@ -421,14 +510,82 @@ impl AssertionStorage {
         assert_eq!(self.store.raw_get(self.ctx.clone(), key).unwrap(), value);
     }
 
+    pub fn raw_batch_get_ok(&self, cf: &str, keys: Vec<&[u8]>, expect: Vec<(&[u8], &[u8])>) {
+        let keys = keys.into_iter().map(|k| k.to_vec()).collect();
+        let result: Vec<KvPair> = self.store
+            .raw_batch_get(self.ctx.clone(), cf.to_owned(), keys)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.unwrap())
+            .collect();
+        let expect: Vec<KvPair> = expect
+            .into_iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        assert_eq!(result, expect);
+    }
+
     pub fn raw_put_ok(&self, key: Vec<u8>, value: Vec<u8>) {
         self.store.raw_put(self.ctx.clone(), key, value).unwrap();
     }
 
+    pub fn raw_put_with_ttl_ok(&self, key: Vec<u8>, value: Vec<u8>, ttl: u64) {
+        self.store
+            .raw_put_with_ttl(self.ctx.clone(), key, value, ttl)
+            .unwrap();
+    }
+
+    pub fn raw_batch_put_ok(&self, cf: &str, pairs: Vec<(&[u8], &[u8])>) {
+        let pairs = pairs
+            .into_iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        self.store
+            .raw_batch_put(self.ctx.clone(), cf.to_owned(), pairs)
+            .unwrap();
+    }
+
     pub fn raw_delete_ok(&self, key: Vec<u8>) {
         self.store.raw_delete(self.ctx.clone(), key).unwrap()
     }
 
+    pub fn raw_batch_delete_ok(&self, cf: &str, keys: Vec<&[u8]>) {
+        let keys = keys.into_iter().map(|k| k.to_vec()).collect();
+        self.store
+            .raw_batch_delete(self.ctx.clone(), cf.to_owned(), keys)
+            .unwrap();
+    }
+
+    pub fn raw_delete_range_ok(&self, cf: &str, start_key: &[u8], end_key: &[u8]) {
+        self.store
+            .raw_delete_range(
+                self.ctx.clone(),
+                cf.to_owned(),
+                start_key.to_vec(),
+                end_key.to_vec(),
+            )
+            .unwrap();
+    }
+
+    pub fn raw_compare_and_swap_ok(
+        &self,
+        key: &[u8],
+        previous_value: Option<&[u8]>,
+        value: &[u8],
+        expect: (Option<&[u8]>, bool),
+    ) {
+        let (previous_value, succeed) = self.store
+            .raw_compare_and_swap(
+                self.ctx.clone(),
+                key.to_vec(),
+                previous_value.map(|v| v.to_vec()),
+                value.to_vec(),
+            )
+            .unwrap();
+        assert_eq!(previous_value, expect.0.map(|v| v.to_vec()));
+        assert_eq!(succeed, expect.1);
+    }
+
     pub fn raw_scan_ok(&self, start_key: Vec<u8>, limit: usize, expect: Vec<(&[u8], &[u8])>) {
         let result: Vec<KvPair> = self.store
             .raw_scan(self.ctx.clone(), start_key, limit)
@@ -443,6 +600,25 @@ impl AssertionStorage {
         assert_eq!(result, expect);
     }
 
+    pub fn raw_reverse_scan_ok(
+        &self,
+        start_key: Vec<u8>,
+        limit: usize,
+        expect: Vec<(&[u8], &[u8])>,
+    ) {
+        let result: Vec<KvPair> = self.store
+            .raw_reverse_scan(self.ctx.clone(), start_key, limit)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.unwrap())
+            .collect();
+        let expect: Vec<KvPair> = expect
+            .into_iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        assert_eq!(result, expect);
+    }
+
     pub fn test_txn_store_gc(&self, key: &str) {
         let key_bytes = key.as_bytes();
         self.put_ok(key_bytes, b"v1", 5, 10);
@@ -14,7 +14,7 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use tikv::storage::{Engine, Key, KvPair, Mutation, Options, Result, Storage, Value};
+use tikv::storage::{Engine, Key, KvPair, Mutation, Options, Result, Storage, TxnStatus, Value};
 use tikv::storage::config::Config;
 use kvproto::kvrpcpb::{Context, LockInfo};
 
@@ -64,7 +64,7 @@ impl SyncStorage {
     pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
         wait_op!(|cb| {
             self.store
-                .async_get(ctx, key.to_owned(), start_ts, cb)
+                .async_get(ctx, key.to_owned(), start_ts, Options::default(), cb)
                 .unwrap()
         }).unwrap()
     }
@@ -78,7 +78,7 @@ impl SyncStorage {
     ) -> Result<Vec<Result<KvPair>>> {
         wait_op!(|cb| {
             self.store
-                .async_batch_get(ctx, keys.to_owned(), start_ts, cb)
+                .async_batch_get(ctx, keys.to_owned(), start_ts, Options::default(), cb)
                 .unwrap()
         }).unwrap()
     }
@@ -105,6 +105,23 @@ impl SyncStorage {
         }).unwrap()
     }
 
+    pub fn reverse_scan(
+        &self,
+        ctx: Context,
+        key: Key,
+        limit: usize,
+        key_only: bool,
+        start_ts: u64,
+    ) -> Result<Vec<Result<KvPair>>> {
+        let mut options = Options::new(0, false, key_only);
+        options.reverse_scan = true;
+        wait_op!(|cb| {
+            self.store
+                .async_scan(ctx, key, limit, start_ts, options, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
     pub fn prewrite(
         &self,
         ctx: Context,
@@ -119,6 +136,44 @@ impl SyncStorage {
         }).unwrap()
     }
 
+    pub fn prewrite_with_options(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    ) -> Result<Vec<Result<()>>> {
+        wait_op!(|cb| {
+            self.store
+                .async_prewrite(ctx, mutations, primary, start_ts, options, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn one_pc_prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        commit_ts: u64,
+    ) -> Result<(Vec<Result<()>>, bool)> {
+        wait_op!(|cb| {
+            self.store
+                .async_one_pc_prewrite(
+                    ctx,
+                    mutations,
+                    primary,
+                    start_ts,
+                    commit_ts,
+                    Options::default(),
+                    cb,
+                )
+                .unwrap()
+        }).unwrap()
+    }
+
     pub fn commit(
         &self,
         ctx: Context,
@@ -139,6 +194,34 @@ impl SyncStorage {
         }).unwrap()
     }
 
+    pub fn txn_heart_beat(
+        &self,
+        ctx: Context,
+        primary_key: Key,
+        start_ts: u64,
+        advise_ttl: u64,
+    ) -> Result<u64> {
+        wait_op!(|cb| {
+            self.store
+                .async_txn_heart_beat(ctx, primary_key, start_ts, advise_ttl, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn check_txn_status(
+        &self,
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+    ) -> Result<TxnStatus> {
+        wait_op!(|cb| {
+            self.store
+                .async_check_txn_status(ctx, primary_key, lock_ts, current_ts, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
     pub fn rollback(&self, ctx: Context, keys: Vec<Key>, start_ts: u64) -> Result<()> {
         wait_op!(|cb| {
             self.store.async_rollback(ctx, keys, start_ts, cb).unwrap()
@@ -162,15 +245,94 @@ impl SyncStorage {
     }
 
     pub fn raw_get(&self, ctx: Context, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        wait_op!(|cb| self.store.async_raw_get(ctx, key, cb).unwrap()).unwrap()
+        wait_op!(|cb| {
+            self.store
+                .async_raw_get(ctx, String::new(), key, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_batch_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_batch_get(ctx, cf, keys, cb)
+                .unwrap()
+        }).unwrap()
     }
 
     pub fn raw_put(&self, ctx: Context, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_put(ctx, key, value, cb).unwrap()).unwrap()
+        self.raw_put_with_ttl(ctx, key, value, 0)
+    }
+
+    pub fn raw_put_with_ttl(
+        &self,
+        ctx: Context,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: u64,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_put(ctx, String::new(), key, value, ttl, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_batch_put(&self, ctx: Context, cf: String, pairs: Vec<KvPair>) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_batch_put(ctx, cf, pairs, 0, cb)
+                .unwrap()
+        }).unwrap()
     }
 
     pub fn raw_delete(&self, ctx: Context, key: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_delete(ctx, key, cb).unwrap()).unwrap()
+        wait_op!(|cb| {
+            self.store
+                .async_raw_delete(ctx, String::new(), key, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_batch_delete(&self, ctx: Context, cf: String, keys: Vec<Vec<u8>>) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_batch_delete(ctx, cf, keys, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_delete_range(ctx, cf, start_key, end_key, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_compare_and_swap(
+        &self,
+        ctx: Context,
+        key: Vec<u8>,
+        previous_value: Option<Vec<u8>>,
+        value: Vec<u8>,
+    ) -> Result<(Option<Value>, bool)> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_compare_and_swap(ctx, String::new(), key, previous_value, value, 0, cb)
+                .unwrap()
+        }).unwrap()
     }
 
     pub fn raw_scan(
@@ -181,7 +343,20 @@ impl SyncStorage {
     ) -> Result<Vec<Result<KvPair>>> {
         wait_op!(|cb| {
             self.store
-                .async_raw_scan(ctx, start_key, limit, cb)
+                .async_raw_scan(ctx, String::new(), start_key, limit, false, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_reverse_scan(
+        &self,
+        ctx: Context,
+        start_key: Vec<u8>,
+        limit: usize,
+    ) -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_scan(ctx, String::new(), start_key, limit, true, cb)
                 .unwrap()
         }).unwrap()
     }
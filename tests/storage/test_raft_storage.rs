@@ -19,6 +19,7 @@ use tikv::util::HandyRwLock;
 use tikv::storage::{self, make_key, Engine, Mutation, Options, Storage};
 use tikv::storage::{engine, mvcc, txn};
 use tikv::storage::config::Config;
+use tikv::util::config::ReadableDuration;
 use kvproto::kvrpcpb::Context;
 use raftstore::server::new_server_cluster;
 use raftstore::cluster::Cluster;
@@ -109,6 +110,48 @@ fn test_raft_storage_rollback_before_prewrite() {
     }
 }
 
+#[test]
+fn test_raft_storage_one_pc_fallback() {
+    let mut cluster = new_server_cluster(0, 1);
+    // The max read ts of the region is never synced.
+    cluster.cfg.raft_store.resolved_ts_advance_interval = ReadableDuration::secs(0);
+    cluster.run();
+    assert_eq!(cluster.must_get(b""), None);
+    let region = cluster.get_region(b"");
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let engine = cluster.sim.rl().storages[&leader.get_id()].clone();
+    let storage = SyncStorage::from_engine(engine, &Config::default());
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx.set_peer(leader);
+
+    // The transaction is prewritten in two phases instead.
+    let key = make_key(b"key");
+    let (res, one_pc) = storage
+        .one_pc_prewrite(
+            ctx.clone(),
+            vec![Mutation::Put((key.clone(), b"value".to_vec()))],
+            b"key".to_vec(),
+            10,
+            11,
+        )
+        .unwrap();
+    assert!(res.iter().all(|r| r.is_ok()));
+    assert!(!one_pc);
+    assert_eq!(storage.scan_lock(ctx.clone(), 20).unwrap().len(), 1);
+    // The lock blocks the reads until the transaction is committed.
+    assert!(storage.get(ctx.clone(), &key, 20).is_err());
+
+    storage
+        .commit(ctx.clone(), vec![key.clone()], 10, 15)
+        .unwrap();
+    assert_eq!(
+        storage.get(ctx.clone(), &key, 20).unwrap().unwrap(),
+        b"value".to_vec()
+    );
+}
+
 #[test]
 fn test_raft_storage_store_not_match() {
     let (_cluster, storage, mut ctx) = new_raft_storage();
@@ -155,6 +198,54 @@ fn test_raft_storage_store_not_match() {
     assert!(storage.scan_lock(ctx.clone(), 20).is_err());
 }
 
+fn new_region_ctx(cluster: &mut Cluster<ServerCluster>, key: &[u8]) -> Context {
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx.set_peer(leader);
+    ctx
+}
+
+#[test]
+fn test_raft_storage_get_with_primary_in_other_region() {
+    let (mut cluster, storage, _) = new_raft_storage();
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, make_key(b"k2").encoded());
+    let (primary, secondary) = (make_key(b"k1"), make_key(b"k3"));
+    let primary_ctx = new_region_ctx(&mut cluster, primary.encoded());
+    let secondary_ctx = new_region_ctx(&mut cluster, secondary.encoded());
+    assert_ne!(primary_ctx.get_region_id(), secondary_ctx.get_region_id());
+
+    for (ctx, key) in vec![(&primary_ctx, &primary), (&secondary_ctx, &secondary)] {
+        storage
+            .prewrite(
+                ctx.clone(),
+                vec![Mutation::Put((key.clone(), b"v".to_vec()))],
+                b"k1".to_vec(),
+                10,
+            )
+            .unwrap();
+    }
+    storage
+        .commit(primary_ctx.clone(), vec![primary.clone()], 10, 15)
+        .unwrap();
+    // The commit record of the primary isn't in the region of the secondary, so the lock is
+    // returned to be resolved by the client.
+    match storage.get(secondary_ctx.clone(), &secondary, 20) {
+        Err(storage::Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked { .. }))) => {}
+        res => panic!("expect key is locked, got {:?}", res),
+    }
+    storage
+        .commit(secondary_ctx.clone(), vec![secondary.clone()], 10, 15)
+        .unwrap();
+    assert_eq!(
+        storage.get(secondary_ctx, &secondary, 20).unwrap(),
+        Some(b"v".to_vec())
+    );
+}
+
 #[test]
 fn test_engine_leader_change_twice() {
     let mut cluster = new_server_cluster(0, 3);
@@ -19,7 +19,8 @@ use std::thread;
 use rand::random;
 use super::sync_storage::SyncStorage;
 use kvproto::kvrpcpb::{Context, LockInfo};
-use tikv::storage::{self, make_key, Key, Mutation, Storage, ALL_CFS};
+use tikv::storage::{self, make_key, Key, Mutation, Storage, TxnStatus, ALL_CFS, CF_DEFAULT,
+                    CF_LOCK};
 use tikv::storage::engine::{self, Engine, EngineRocksdb, TEMP_DIR};
 use tikv::storage::txn::{GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
 use tikv::storage::mvcc::MAX_TXN_WRITE_SIZE;
@@ -55,6 +56,53 @@ fn test_txn_store_delete() {
     store.get_none(b"x", 21);
 }
 
+#[test]
+fn test_txn_store_one_pc() {
+    let store = AssertionStorage::default();
+    let long_value = vec![b'y'; 1024];
+    let mutations = || {
+        vec![
+            Mutation::Put((make_key(b"x"), b"x5".to_vec())),
+            Mutation::Put((make_key(b"y"), long_value.clone())),
+        ]
+    };
+    store.one_pc_prewrite_ok(mutations(), b"x", 5, 6);
+    // Duplicated requests are ignored.
+    store.one_pc_prewrite_ok(mutations(), b"x", 5, 6);
+    // No lock is left.
+    store.scan_lock_ok(100, vec![]);
+    store.get_none(b"x", 5);
+    store.get_ok(b"x", 6, b"x5");
+    store.get_ok(b"y", 6, &long_value);
+
+    // Conflicts with the committed write.
+    store.one_pc_prewrite_err(
+        vec![Mutation::Put((make_key(b"x"), b"x3".to_vec()))],
+        b"x",
+        3,
+        10,
+    );
+    store.get_ok(b"x", 10, b"x5");
+
+    // A read at 20 has been served, the transaction can't commit before it.
+    store.get_ok(b"x", 20, b"x5");
+    store.one_pc_prewrite_err(vec![Mutation::Delete(make_key(b"x"))], b"x", 15, 18);
+    store.get_ok(b"x", 30, b"x5");
+    store.one_pc_prewrite_ok(vec![Mutation::Delete(make_key(b"x"))], b"x", 15, 31);
+    store.get_ok(b"x", 30, b"x5");
+    store.get_none(b"x", 31);
+    store.scan_lock_ok(100, vec![]);
+
+    // commit_ts must be larger than start_ts.
+    store.one_pc_prewrite_err(
+        vec![Mutation::Put((make_key(b"z"), b"z".to_vec()))],
+        b"z",
+        40,
+        40,
+    );
+    store.get_none(b"z", 50);
+}
+
 #[test]
 fn test_txn_store_cleanup_rollback() {
     let store = AssertionStorage::default();
@@ -69,9 +117,66 @@ fn test_txn_store_cleanup_rollback() {
     );
     store.get_err(b"secondary", 10);
     store.rollback_ok(vec![b"primary"], 5);
+    // The lock of a rolled back transaction doesn't block reads.
+    store.get_ok(b"secondary", 10, b"s-0");
     store.cleanup_ok(b"primary", 5);
 }
 
+#[test]
+fn test_txn_store_heart_beat() {
+    let store = AssertionStorage::default();
+    store.txn_heart_beat_err(b"primary", 5, 100);
+    store.prewrite_ok(
+        vec![
+            Mutation::Put((make_key(b"primary"), b"p-5".to_vec())),
+            Mutation::Put((make_key(b"secondary"), b"s-5".to_vec())),
+        ],
+        b"primary",
+        5,
+    );
+    store.txn_heart_beat_ok(b"primary", 5, 100, 100);
+    store.txn_heart_beat_ok(b"primary", 5, 50, 100);
+    store.txn_heart_beat_err(b"primary", 6, 100);
+    store.commit_ok(vec![b"primary", b"secondary"], 5, 10);
+    store.get_ok(b"primary", 11, b"p-5");
+    store.txn_heart_beat_err(b"primary", 5, 200);
+}
+
+#[test]
+fn test_txn_store_check_txn_status() {
+    let store = AssertionStorage::default();
+    let ts = |physical: u64| physical << 18;
+    store.prewrite_ok(
+        vec![
+            Mutation::Put((make_key(b"primary"), b"p-5".to_vec())),
+            Mutation::Put((make_key(b"secondary"), b"s-5".to_vec())),
+        ],
+        b"primary",
+        ts(5),
+    );
+    store.txn_heart_beat_ok(b"primary", ts(5), 100, 100);
+    // The lock expires at 105.
+    store.check_txn_status_ok(b"primary", ts(5), ts(50), TxnStatus::Alive { ttl: 55 });
+    store.get_err(b"secondary", ts(60));
+    store.check_txn_status_ok(b"primary", ts(5), ts(105), TxnStatus::RolledBack);
+    store.get_none(b"primary", ts(110));
+    store.get_none(b"secondary", ts(110));
+    store.rollback_ok(vec![b"secondary"], ts(5));
+
+    store.prewrite_ok(
+        vec![Mutation::Put((make_key(b"primary"), b"p-10".to_vec()))],
+        b"primary",
+        ts(10),
+    );
+    store.commit_ok(vec![b"primary"], ts(10), ts(20));
+    store.check_txn_status_ok(
+        b"primary",
+        ts(10),
+        ts(200),
+        TxnStatus::Committed { commit_ts: ts(20) },
+    );
+}
+
 #[test]
 fn test_txn_store_cleanup_commit() {
     let store = AssertionStorage::default();
@@ -91,6 +196,40 @@ fn test_txn_store_cleanup_commit() {
     store.rollback_err(vec![b"primary"], 5);
 }
 
+#[test]
+fn test_txn_store_get_with_committed_primary() {
+    let store = AssertionStorage::default();
+    store.put_ok(b"k1", b"v1-0", 1, 2);
+    store.put_ok(b"k2", b"v2-0", 1, 2);
+    let long_value = vec![b'v'; 1024];
+    store.prewrite_ok(
+        vec![
+            Mutation::Put((make_key(b"primary"), b"p-5".to_vec())),
+            Mutation::Put((make_key(b"k1"), long_value.clone())),
+            Mutation::Delete(make_key(b"k2")),
+            Mutation::Put((make_key(b"k3"), b"v3-5".to_vec())),
+        ],
+        b"primary",
+        5,
+    );
+    store.get_err(b"k1", 12);
+    store.commit_ok(vec![b"primary"], 5, 10);
+    // The secondaries are still locked, but the primary tells they are committed at 10.
+    store.get_ok(b"k1", 12, &long_value);
+    store.get_none(b"k2", 12);
+    store.get_ok(b"k3", 12, b"v3-5");
+    store.batch_get_ok(
+        &[b"k1", b"k2", b"k3"],
+        12,
+        vec![long_value.as_slice(), b"v3-5"],
+    );
+    // Reads before the commit don't see the transaction.
+    store.get_ok(b"k1", 8, b"v1-0");
+    store.get_ok(b"k2", 8, b"v2-0");
+    store.get_none(b"k3", 8);
+    store.batch_get_ok(&[b"k1", b"k2", b"k3"], 8, vec![b"v1-0", b"v2-0"]);
+}
+
 #[test]
 fn test_txn_store_for_point_get_with_pk() {
     let store = AssertionStorage::default();
@@ -279,6 +418,84 @@ fn test_txn_store_scan() {
     check_v40();
 }
 
+#[test]
+fn test_txn_store_reverse_scan() {
+    let store = AssertionStorage::default();
+
+    // ver10: A(10) - B(_) - C(10) - D(_) - E(10)
+    store.put_ok(b"A", b"A10", 5, 10);
+    store.put_ok(b"C", b"C10", 5, 10);
+    store.put_ok(b"E", b"E10", 5, 10);
+    // ver20: A(10) - B(20) - C(10) - D(_) - E(10)
+    store.put_ok(b"B", b"B20", 15, 20);
+    // ver30: A(10) - B(20) - C(_) - D(_) - E(30)
+    store.delete_ok(b"C", 25, 30);
+    store.put_ok(b"E", b"E30", 25, 30);
+
+    store.reverse_scan_ok(b"F", 0, 10, vec![]);
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        10,
+        vec![
+            Some((b"E", b"E10")),
+            Some((b"C", b"C10")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    // The start key is excluded.
+    store.reverse_scan_ok(b"E", 1, 10, vec![Some((b"C", b"C10"))]);
+    store.reverse_scan_ok(b"A", 5, 10, vec![]);
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        20,
+        vec![
+            Some((b"E", b"E10")),
+            Some((b"C", b"C10")),
+            Some((b"B", b"B20")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        30,
+        vec![
+            Some((b"E", b"E30")),
+            Some((b"B", b"B20")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    store.reverse_scan_ok(b"C\x00", 1, 30, vec![Some((b"B", b"B20"))]);
+
+    // D is locked by a transaction started at 35.
+    store.prewrite_ok(vec![Mutation::Put((make_key(b"D"), b"D40".to_vec()))], b"D", 35);
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        30,
+        vec![
+            Some((b"E", b"E30")),
+            Some((b"B", b"B20")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    store.reverse_scan_ok(
+        b"F",
+        5,
+        40,
+        vec![
+            Some((b"E", b"E30")),
+            None,
+            Some((b"B", b"B20")),
+            Some((b"A", b"A10")),
+        ],
+    );
+    store.commit_ok(vec![b"D"], 35, 40);
+    store.reverse_scan_ok(b"E", 2, 40, vec![Some((b"D", b"D40")), Some((b"B", b"B20"))]);
+}
+
 #[test]
 fn test_txn_store_scan_key_only() {
     let store = AssertionStorage::default();
@@ -543,6 +760,122 @@ fn test_txn_store_rawkv() {
     );
     store.raw_scan_ok(b"".to_vec(), 0, vec![]);
     store.raw_scan_ok(b"k5".to_vec(), 1, vec![]);
+
+    store.raw_reverse_scan_ok(
+        b"".to_vec(),
+        5,
+        vec![(b"k3", b"v3"), (b"k2", b"v2"), (b"k1", b"v1")],
+    );
+    store.raw_reverse_scan_ok(b"k5".to_vec(), 1, vec![(b"k3", b"v3")]);
+    store.raw_reverse_scan_ok(b"k3".to_vec(), 5, vec![(b"k2", b"v2"), (b"k1", b"v1")]);
+    store.raw_reverse_scan_ok(b"k2\x00".to_vec(), 1, vec![(b"k2", b"v2")]);
+    store.raw_reverse_scan_ok(b"k1".to_vec(), 1, vec![]);
+    store.raw_reverse_scan_ok(b"".to_vec(), 0, vec![]);
+}
+
+#[test]
+fn test_txn_store_rawkv_batch() {
+    let store = AssertionStorage::default();
+    store.raw_batch_put_ok(
+        "",
+        vec![(b"k1", b"v1"), (b"k2", b"v2"), (b"k3", b"v3"), (b"k4", b"v4")],
+    );
+    store.raw_batch_get_ok(
+        "",
+        vec![b"k1", b"k5", b"k3"],
+        vec![(b"k1", b"v1"), (b"k3", b"v3")],
+    );
+    store.raw_batch_delete_ok("", vec![b"k1", b"k3", b"k5"]);
+    store.raw_scan_ok(b"".to_vec(), 5, vec![(b"k2", b"v2"), (b"k4", b"v4")]);
+    store.raw_delete_range_ok("", b"k2", b"k4");
+    store.raw_scan_ok(b"".to_vec(), 5, vec![(b"k4", b"v4")]);
+
+    store.raw_batch_get_ok(CF_DEFAULT, vec![b"k4", b"k5"], vec![(b"k4", b"v4")]);
+    store.raw_batch_get_ok(CF_LOCK, vec![b"k4", b"k5"], vec![]);
+}
+
+#[test]
+fn test_txn_store_rawkv_compare_and_swap() {
+    let store = AssertionStorage::default();
+    store.raw_compare_and_swap_ok(b"k", Some(b"v0"), b"v1", (None, false));
+    store.raw_get_ok(b"k".to_vec(), None);
+    store.raw_compare_and_swap_ok(b"k", None, b"v1", (None, true));
+    store.raw_get_ok(b"k".to_vec(), Some(b"v1".to_vec()));
+    store.raw_compare_and_swap_ok(b"k", None, b"v2", (Some(b"v1"), false));
+    store.raw_compare_and_swap_ok(b"k", Some(b"v0"), b"v2", (Some(b"v1"), false));
+    store.raw_compare_and_swap_ok(b"k", Some(b"v1"), b"v2", (Some(b"v1"), true));
+    store.raw_get_ok(b"k".to_vec(), Some(b"v2".to_vec()));
+
+    // Concurrent increments on the same key are not lost.
+    let (thread_count, times) = (4, 10);
+    let handles: Vec<_> = (0..thread_count)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                let mut expect = None;
+                for _ in 0..times {
+                    loop {
+                        let n = expect.as_ref().map_or(0, |v: &Vec<u8>| v[0]);
+                        let (prev, succeed) = store
+                            .store
+                            .raw_compare_and_swap(
+                                store.ctx.clone(),
+                                b"counter".to_vec(),
+                                expect.clone(),
+                                vec![n + 1],
+                            )
+                            .unwrap();
+                        if succeed {
+                            expect = Some(vec![n + 1]);
+                            break;
+                        }
+                        expect = prev;
+                    }
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    store.raw_get_ok(b"counter".to_vec(), Some(vec![thread_count * times]));
+}
+
+#[test]
+fn test_txn_store_rawkv_ttl() {
+    let mut config = Config::default();
+    config.enable_ttl = true;
+    let store = AssertionStorage {
+        store: SyncStorage::new(&config),
+        ctx: Context::new(),
+    };
+    store.raw_put_ok(b"k1".to_vec(), b"v1".to_vec());
+    store.raw_put_with_ttl_ok(b"k2".to_vec(), b"v2".to_vec(), 1);
+    store.raw_put_with_ttl_ok(b"k3".to_vec(), b"v3".to_vec(), 100);
+    store.raw_get_ok(b"k1".to_vec(), Some(b"v1".to_vec()));
+    store.raw_get_ok(b"k2".to_vec(), Some(b"v2".to_vec()));
+    store.raw_scan_ok(
+        b"".to_vec(),
+        5,
+        vec![(b"k1", b"v1"), (b"k2", b"v2"), (b"k3", b"v3")],
+    );
+
+    thread::sleep(Duration::from_secs(2));
+    store.raw_get_ok(b"k1".to_vec(), Some(b"v1".to_vec()));
+    store.raw_get_ok(b"k2".to_vec(), None);
+    store.raw_get_ok(b"k3".to_vec(), Some(b"v3".to_vec()));
+    // Expired keys are not counted in the limit.
+    store.raw_scan_ok(b"".to_vec(), 2, vec![(b"k1", b"v1"), (b"k3", b"v3")]);
+
+    // TTL is rejected if it's not enabled.
+    let store = AssertionStorage::default();
+    assert!(
+        store
+            .store
+            .raw_put_with_ttl(store.ctx.clone(), b"k".to_vec(), b"v".to_vec(), 100)
+            .is_err()
+    );
+    store.raw_get_ok(b"k".to_vec(), None);
 }
 
 #[test]